@@ -0,0 +1,48 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::dependency_graph::{FileNode, FileType, TargetLocation};
+
+type ClassifyFn = dyn Fn(&FileNode, &str) -> Option<(FileType, TargetLocation)> + Send + Sync;
+
+/// Decides the file type and target location of dependencies instead of the
+/// default rules, e.g. to keep a stylesheet linked instead of inlined, or to
+/// place data files in `assets/`.
+///
+/// The callback gets the importing file and the raw specifier, and returns
+/// `None` to leave the dependency to the other rules. Precedence:
+///
+/// 1. the classifier's result, which also reclassifies a file that is already
+///    in the graph
+/// 2. the location of the file if it is already in the graph
+/// 3. the default rules: stylesheets of components are inlined, images are
+///    assets and everything else is a dependency
+///
+/// A stylesheet linked from a template is inlined unless the classifier
+/// places it somewhere other than `TargetLocation::Omit`, in which case the
+/// link stays and points to the stylesheet's output.
+#[derive(Clone)]
+pub struct Classifier(Arc<ClassifyFn>);
+
+impl Classifier {
+    pub fn new(
+        classify: impl Fn(&FileNode, &str) -> Option<(FileType, TargetLocation)> + Send + Sync + 'static,
+    ) -> Self {
+        Self(Arc::new(classify))
+    }
+
+    /// Classify the dependency `specifier` of `importer`.
+    pub fn classify(
+        &self,
+        importer: &FileNode,
+        specifier: &str,
+    ) -> Option<(FileType, TargetLocation)> {
+        (self.0)(importer, specifier)
+    }
+}
+
+impl fmt::Debug for Classifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Classifier(..)")
+    }
+}
@@ -0,0 +1,116 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::dependency_graph::DependencyGraph;
+use crate::pipeline::display_path;
+
+/// One import on the way from a component to one of its dependencies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportStep {
+    /// File containing the import
+    pub from: PathBuf,
+    /// The import statement as written in the file
+    pub import_statement: String,
+}
+
+/// A file that ends up in a component's output through its imports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransitiveDependency {
+    /// Source path, relative to the Firefox root when the file lives inside it
+    pub source: PathBuf,
+    /// Path in the output, `None` if the file is inlined or omitted
+    pub dist_path: Option<PathBuf>,
+    /// Shortest chain of imports from the component to the file
+    pub import_chain: Vec<ImportStep>,
+}
+
+/// All transitive dependencies of a component, as returned by
+/// `component_dependencies`, sorted by source path.
+#[derive(Debug, Clone)]
+pub struct ComponentDependencies {
+    /// Name of the component
+    pub component: String,
+    pub dependencies: Vec<TransitiveDependency>,
+}
+
+impl ComponentDependencies {
+    pub(crate) fn new(dep_graph: &DependencyGraph, component: &str, firefox_root: &Path) -> Self {
+        let dependencies = dep_graph
+            .transitive_dependency_chains(component)
+            .into_iter()
+            .map(|(node, chain)| TransitiveDependency {
+                source: display_path(&node.path, firefox_root),
+                dist_path: node.get_dist_path(),
+                import_chain: chain
+                    .into_iter()
+                    .map(|(from, import_statement)| ImportStep {
+                        from: display_path(&from, firefox_root),
+                        import_statement,
+                    })
+                    .collect(),
+            })
+            .collect();
+        Self {
+            component: component.to_string(),
+            dependencies,
+        }
+    }
+
+    /// The same data as the text output, for `--format json`.
+    pub fn to_json(&self) -> serde_json::Value {
+        let dependencies: Vec<serde_json::Value> = self
+            .dependencies
+            .iter()
+            .map(|dependency| {
+                let chain: Vec<serde_json::Value> = dependency
+                    .import_chain
+                    .iter()
+                    .map(|step| {
+                        serde_json::json!({
+                            "from": step.from,
+                            "import_statement": step.import_statement,
+                        })
+                    })
+                    .collect();
+                serde_json::json!({
+                    "source": dependency.source,
+                    "dist_path": dependency.dist_path,
+                    "import_chain": chain,
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "component": self.component,
+            "dependencies": dependencies,
+        })
+    }
+}
+
+impl fmt::Display for ComponentDependencies {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Component: {}", self.component)?;
+        if self.dependencies.is_empty() {
+            writeln!(f, "  (no dependencies)")?;
+        }
+        for dependency in &self.dependencies {
+            match &dependency.dist_path {
+                Some(dist_path) => writeln!(
+                    f,
+                    "  {} -> {}",
+                    dependency.source.display(),
+                    dist_path.display()
+                )?,
+                None => writeln!(f, "  {} -> (omitted)", dependency.source.display())?,
+            }
+            for step in &dependency.import_chain {
+                writeln!(
+                    f,
+                    "    via {}: {}",
+                    step.from.display(),
+                    step.import_statement
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
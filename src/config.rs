@@ -1,4 +1,9 @@
+use mozcomp::{
+    BudgetBasis, Compression, CssTargets, IfdefUnknown, LitCompat, MissingFiles, MozCleanup,
+    PrivilegedImports, UnknownCssUrl,
+};
 use serde::Deserialize;
+use std::collections::HashMap;
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
@@ -6,4 +11,135 @@ pub struct Config {
     pub jar_paths: Vec<String>,
     pub mozbuild_paths: Vec<String>,
     pub component_paths: Vec<String>,
+    #[serde(default)]
+    pub rename_collisions: bool,
+    #[serde(default)]
+    pub preserve_structure: bool,
+    #[serde(default)]
+    pub precompress: Vec<Compression>,
+    #[serde(default = "default_precompress_threshold")]
+    pub precompress_threshold: u64,
+    #[serde(default)]
+    pub size_budget: Option<u64>,
+    #[serde(default)]
+    pub budget_basis: BudgetBasis,
+    #[serde(default)]
+    pub dedupe: bool,
+    #[serde(default = "default_true")]
+    pub component_local_assets: bool,
+    #[serde(default)]
+    pub graph_json: Option<String>,
+    #[serde(default)]
+    pub lit_compat: LitCompat,
+    #[serde(default)]
+    pub promote_root_sheets: bool,
+    #[serde(default)]
+    pub prune_unused_globals: bool,
+    #[serde(default, alias = "keep")]
+    pub keep_globals: Vec<String>,
+    #[serde(default)]
+    pub loader: bool,
+    #[serde(default)]
+    pub external: Vec<String>,
+    #[serde(default)]
+    pub typescript: bool,
+    #[serde(default)]
+    pub ignore_literals: Vec<String>,
+    #[serde(default, alias = "discover_jars")]
+    pub auto_discover_jars: bool,
+    #[serde(default = "default_jar_discovery_depth")]
+    pub jar_discovery_depth: usize,
+    #[serde(default = "default_jar_exclude")]
+    pub jar_exclude: Vec<String>,
+    #[serde(default)]
+    pub cache_dir: Option<String>,
+    #[serde(default)]
+    pub trace_attributes: bool,
+    #[serde(default)]
+    pub manifest_paths: Vec<String>,
+    #[serde(default)]
+    pub ifdef: HashMap<String, bool>,
+    #[serde(default)]
+    pub ifdef_unknown: IfdefUnknown,
+    #[serde(default)]
+    pub strict_mappings: bool,
+    #[serde(default)]
+    pub fallback_search: bool,
+    /// Prefixes of bare module specifiers mapped to directories relative to
+    /// the Firefox root
+    #[serde(default)]
+    pub module_roots: HashMap<String, String>,
+    #[serde(default = "default_extension_fallbacks")]
+    pub extension_fallbacks: Vec<String>,
+    #[serde(default)]
+    pub missing_files: MissingFiles,
+    #[serde(default)]
+    pub strict_case: bool,
+    #[serde(default = "default_true")]
+    pub follow_symlinks: bool,
+    /// Specifiers or prefixes replaced with a path or another specifier
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+    #[serde(default)]
+    pub privileged_imports: PrivilegedImports,
+    #[serde(default)]
+    pub inline_fetched_stylesheets: bool,
+    #[serde(default)]
+    pub flatten_imports: bool,
+    /// A browserslist query, or browsers mapped to the oldest version,
+    /// stylesheets are lowered for
+    #[serde(default, alias = "targets")]
+    pub css_targets: CssTargets,
+    #[serde(default)]
+    pub css_minify: bool,
+    #[serde(default)]
+    pub css_license_headers: bool,
+    #[serde(default)]
+    pub unknown_css_url: UnknownCssUrl,
+    /// Remove Firefox-only media features, pseudo-classes and properties
+    /// from stylesheets
+    #[serde(default)]
+    pub moz_cleanup: Option<MozCleanup>,
+    /// Prefixes of specifiers, like `brand://`, mapped to directories
+    /// relative to the Firefox root
+    #[serde(default)]
+    pub custom_schemes: HashMap<String, String>,
+    #[serde(default)]
+    pub trace_resolution: bool,
+    /// Additional sections with their own JAR manifests and entry points,
+    /// merged into one output with the top-level section
+    #[serde(default)]
+    pub sections: Vec<SectionConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SectionConfig {
+    #[serde(default)]
+    pub globals_stylesheets: Vec<String>,
+    #[serde(default)]
+    pub jar_paths: Vec<String>,
+    #[serde(default)]
+    pub mozbuild_paths: Vec<String>,
+    #[serde(default)]
+    pub component_paths: Vec<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_precompress_threshold() -> u64 {
+    1024
+}
+
+fn default_jar_discovery_depth() -> usize {
+    8
+}
+
+fn default_jar_exclude() -> Vec<String> {
+    vec!["third_party".to_string(), "testing".to_string()]
+}
+
+fn default_extension_fallbacks() -> Vec<String> {
+    vec![".mjs".to_string(), ".js".to_string(), ".css".to_string()]
 }
@@ -1,29 +1,78 @@
 use lightningcss::{
-    rules::CssRule,
-    stylesheet::{ParserOptions, StyleSheet},
-    values::url::Url,
+    error::ParserError,
+    properties::Property,
+    properties::custom::Variable,
+    rules::{CssRule, CssRuleList, Location, style::StyleRule},
+    stylesheet::{ParserOptions, PrinterOptions, StyleSheet},
+    traits::ToCss,
+    values::{image::Image, url::Url},
     visitor::{Visit, VisitTypes, Visitor},
 };
+use std::collections::HashSet;
 use std::fs;
 use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
 
+use super::{DependencyRef, DependencySet};
+use crate::dependency_graph::{ImportKind, SourceSpan};
 use crate::errors::{DependencyError, DependencyResult};
 
-pub fn dependencies_from_file(source_path: &PathBuf) -> DependencyResult<Vec<String>> {
+/// Collect the references of a stylesheet. Stylesheets that can't be parsed at
+/// all are searched for `url()` and `@import` with patterns instead.
+pub fn dependencies_from_file(source_path: &PathBuf) -> DependencyResult<Vec<DependencyRef>> {
     let css_content = fs::read_to_string(source_path)?;
-    dependencies_from_string(&css_content)
+    match dependencies_from_string(&css_content) {
+        Err(DependencyError::CssParse { message }) => {
+            eprintln!(
+                "Warning: {} can't be parsed ({}), its references are found by pattern matching",
+                source_path.display(),
+                message
+            );
+            Ok(dependencies_from_patterns(&css_content))
+        }
+        result => result,
+    }
 }
-pub fn dependencies_from_string(css_content: &String) -> DependencyResult<Vec<String>> {
-    // Parse the CSS using StyleSheet::parse
-    let mut stylesheet = StyleSheet::parse(
-        &css_content,
+
+/// Parse a stylesheet, skipping the rules and declarations lightningcss can't
+/// parse instead of failing. Returns the stylesheet and a `line:column:
+/// message` for every part that was skipped.
+pub(crate) fn parse_stylesheet(
+    css_content: &str,
+) -> Result<(StyleSheet<'_, 'static>, Vec<String>), String> {
+    let warnings = Arc::new(RwLock::new(Vec::new()));
+    let stylesheet = StyleSheet::parse(
+        css_content,
         ParserOptions {
+            error_recovery: true,
+            warnings: Some(warnings.clone()),
             ..Default::default()
         },
     )
-    .map_err(|e| DependencyError::CssParse {
-        message: format!("{:?}", e),
-    })?;
+    .map_err(|e| format!("{:?}", e))?;
+    let warnings = warnings
+        .read()
+        .map(|warnings| warnings.iter().map(warning_message).collect())
+        .unwrap_or_default();
+    Ok((stylesheet, warnings))
+}
+
+/// A parse error as `line:column: message`, with a 1-based line.
+fn warning_message(error: &lightningcss::error::Error<ParserError>) -> String {
+    match &error.loc {
+        Some(loc) => format!("{}:{}: {}", loc.line + 1, loc.column, error.kind),
+        None => error.kind.to_string(),
+    }
+}
+
+/// Collect the `url()` and `@import` references of a stylesheet. Their spans
+/// are empty and point at the start of the `url()` or `@import`.
+pub fn dependencies_from_string(css_content: &str) -> DependencyResult<Vec<DependencyRef>> {
+    let (mut stylesheet, warnings) =
+        parse_stylesheet(css_content).map_err(|message| DependencyError::CssParse { message })?;
+    for warning in warnings {
+        log::debug!("Skipped part of a stylesheet: {}", warning);
+    }
 
     // Create visitors to collect dependencies
     let mut url_visitor = UrlVisitor::new();
@@ -43,25 +92,197 @@ pub fn dependencies_from_string(css_content: &String) -> DependencyResult<Vec<St
             message: "Rule visiting failed".to_string(),
         })?;
 
-    // Combine and return all dependencies
-    let mut dependencies: Vec<String> = url_visitor
+    // Combine and return all dependencies, each once per kind
+    let span = |loc: Location| {
+        let offset = location_offset(css_content, loc);
+        SourceSpan::new(offset, offset)
+    };
+    let mut dependencies: DependencySet = url_visitor
         .dependencies
         .into_iter()
-        .filter(|dep| !dep.is_empty())
+        .filter(|(dep, _)| !dep.is_empty())
+        .map(|(dep, loc)| DependencyRef::new(dep, ImportKind::CssUrl, span(loc)))
         .collect();
 
     dependencies.extend(
         rule_visitor
             .dependencies
             .into_iter()
-            .filter(|dep| !dep.is_empty()),
+            .filter(|(dep, _)| !dep.is_empty())
+            .map(|(dep, loc)| DependencyRef::new(dep, ImportKind::CssImport, span(loc))),
     );
 
-    Ok(dependencies)
+    Ok(dependencies.into_vec())
+}
+
+/// Find the references of a stylesheet lightningcss can't parse with
+/// patterns, like `url("...")` and `@import "..."`.
+fn dependencies_from_patterns(css_content: &str) -> Vec<DependencyRef> {
+    // Matches don't overlap, so `@import url(...)` is only an import
+    let reference_regex =
+        regex::Regex::new(r#"@import\s+(?:url\(\s*)?["']?([^"')\s;]+)|url\(\s*["']?([^"')\s]+)"#)
+            .unwrap();
+    reference_regex
+        .captures_iter(css_content)
+        .filter_map(|captures| {
+            let start = captures.get(0)?.start() as u32;
+            let (url, kind) = match (captures.get(1), captures.get(2)) {
+                (Some(import), _) => (import.as_str(), ImportKind::CssImport),
+                (_, Some(url)) => (url.as_str(), ImportKind::CssUrl),
+                _ => return None,
+            };
+            let path = url.split(['?', '#']).next().unwrap_or(url);
+            (!is_passthrough_url(path))
+                .then(|| DependencyRef::new(path, kind, SourceSpan::new(start, start)))
+        })
+        .collect::<DependencySet>()
+        .into_vec()
+}
+
+/// Convert a lightningcss location (0-based line, 1-based column in UTF-16
+/// code units) into a byte offset.
+fn location_offset(css_content: &str, loc: Location) -> u32 {
+    let line_start: usize = css_content
+        .split_inclusive('\n')
+        .take(loc.line as usize)
+        .map(str::len)
+        .sum();
+    let mut offset = line_start;
+    let mut column = 1;
+    for c in css_content[line_start..].chars() {
+        if column >= loc.column as usize || c == '\n' {
+            break;
+        }
+        column += c.len_utf16();
+        offset += c.len_utf8();
+    }
+    offset as u32
+}
+
+/// How much of a stylesheet consists of `:root`/`html`-scoped custom
+/// property declarations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RootScope {
+    /// Only `:root`/`html` rules declaring custom properties, possibly nested
+    /// in conditional rules like `@media`
+    TokensOnly,
+    /// Some `:root`/`html` custom property rules next to other rules
+    Mixed,
+    /// No `:root`/`html` custom property rules
+    None,
+}
+
+pub fn root_scope_from_file(source_path: &PathBuf) -> DependencyResult<RootScope> {
+    let css_content = fs::read_to_string(source_path)?;
+    root_scope_from_string(&css_content)
+}
+
+/// Check whether a stylesheet only defines `:root`-scoped custom properties,
+/// which stop applying to the page once the sheet is inlined into a shadow root.
+pub fn root_scope_from_string(css_content: &str) -> DependencyResult<RootScope> {
+    let (stylesheet, _) =
+        parse_stylesheet(css_content).map_err(|message| DependencyError::CssParse { message })?;
+
+    let (tokens, other) = count_root_rules(&stylesheet.rules);
+    Ok(match (tokens, other) {
+        (0, _) => RootScope::None,
+        (_, 0) => RootScope::TokensOnly,
+        _ => RootScope::Mixed,
+    })
+}
+
+/// Count `:root` custom property rules and other rules, as (tokens, other).
+fn count_root_rules(rules: &CssRuleList<'_>) -> (usize, usize) {
+    let mut tokens = 0;
+    let mut other = 0;
+    for rule in &rules.0 {
+        let (t, o) = match rule {
+            CssRule::Style(style) if is_root_custom_property_rule(style) => (1, 0),
+            CssRule::Media(media) => count_root_rules(&media.rules),
+            CssRule::Supports(supports) => count_root_rules(&supports.rules),
+            CssRule::LayerBlock(layer) => count_root_rules(&layer.rules),
+            // These don't style anything by themselves
+            CssRule::LayerStatement(_) | CssRule::Ignored => (0, 0),
+            _ => (0, 1),
+        };
+        tokens += t;
+        other += o;
+    }
+    (tokens, other)
+}
+
+fn is_root_custom_property_rule(style: &StyleRule<'_>) -> bool {
+    let Ok(selectors) = style.selectors.to_css_string(PrinterOptions::default()) else {
+        return false;
+    };
+    let declarations = &style.declarations;
+    selectors
+        .split(',')
+        .all(|selector| matches!(selector.trim(), ":root" | "html"))
+        && style.rules.0.is_empty()
+        && declarations
+            .declarations
+            .iter()
+            .chain(declarations.important_declarations.iter())
+            .all(|property| matches!(property, Property::Custom(_)))
+}
+
+/// Custom properties a stylesheet defines and references.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CustomProperties {
+    /// Names of the custom properties declared in the sheet
+    pub defined: HashSet<String>,
+    /// Names of the custom properties read through `var()`
+    pub used: HashSet<String>,
+}
+
+pub fn custom_properties_from_file(source_path: &PathBuf) -> DependencyResult<CustomProperties> {
+    let css_content = fs::read_to_string(source_path)?;
+    custom_properties_from_string(&css_content)
+}
+
+pub fn custom_properties_from_string(css_content: &str) -> DependencyResult<CustomProperties> {
+    let (mut stylesheet, _) =
+        parse_stylesheet(css_content).map_err(|message| DependencyError::CssParse { message })?;
+
+    let mut visitor = CustomPropertyVisitor::default();
+    stylesheet
+        .visit(&mut visitor)
+        .map_err(|_| DependencyError::Extract {
+            message: "Custom property visiting failed".to_string(),
+        })?;
+    Ok(visitor.properties)
+}
+
+#[derive(Default)]
+struct CustomPropertyVisitor {
+    properties: CustomProperties,
+}
+
+impl<'i> Visitor<'i> for CustomPropertyVisitor {
+    type Error = ();
+
+    fn visit_property(&mut self, property: &mut Property<'i>) -> std::result::Result<(), ()> {
+        if let Property::Custom(custom) = property {
+            self.properties
+                .defined
+                .insert(custom.name.as_ref().to_string());
+        }
+        property.visit_children(self)
+    }
+
+    fn visit_variable(&mut self, var: &mut Variable<'i>) -> std::result::Result<(), ()> {
+        self.properties.used.insert(var.name.ident.0.to_string());
+        var.visit_children(self)
+    }
+
+    fn visit_types(&self) -> VisitTypes {
+        lightningcss::visit_types!(PROPERTIES | VARIABLES)
+    }
 }
 
 struct UrlVisitor {
-    dependencies: Vec<String>,
+    dependencies: Vec<(String, Location)>,
 }
 
 impl UrlVisitor {
@@ -71,42 +292,72 @@ impl UrlVisitor {
         }
     }
 
-    fn add_dependency(&mut self, url: &str) {
-        // Skip data URLs, HTTP(S) URLs, and other non-file protocols
-
-        if url.starts_with("data:")
-            || url.starts_with("http://")
-            || url.starts_with("https://")
-            || url.starts_with("//")
-        {
+    fn add_dependency(&mut self, url: &str, loc: Location) {
+        // Remove URL fragments and query parameters
+        let clean_url = url.split(['?', '#']).next().unwrap_or(url);
+        if is_passthrough_url(clean_url) {
             return;
         }
 
-        // Remove URL fragments and query parameters
-        let clean_url = url.split(['?', '#']).next().unwrap_or(url).to_string();
-
-        if !self.dependencies.contains(&clean_url) {
-            self.dependencies.push(clean_url);
-        }
+        self.dependencies.push((clean_url.to_string(), loc));
     }
 }
 
+/// Check whether the part of a `url()` before any query or fragment refers to
+/// something other than a file: nothing, like `url(#gradient)` pointing at an
+/// SVG element in the document, data, remote or `about:` URLs. These are left
+/// as they are.
+pub(crate) fn is_passthrough_url(base: &str) -> bool {
+    base.is_empty()
+        || ["data:", "http://", "https://", "//", "about:"]
+            .iter()
+            .any(|prefix| base.starts_with(prefix))
+}
+
 impl<'i> Visitor<'i> for UrlVisitor {
     type Error = ();
 
     fn visit_url(&mut self, url: &mut Url<'i>) -> std::result::Result<(), ()> {
         let url_str = url.url.to_string();
-        self.add_dependency(&url_str);
+        // url() locations count lines from 1, unlike rule locations
+        let loc = Location {
+            source_index: 0,
+            line: url.loc.line.saturating_sub(1),
+            column: url.loc.column,
+        };
+        self.add_dependency(&url_str, loc);
         Ok(())
     }
 
+    fn visit_image(&mut self, image: &mut Image<'i>) -> std::result::Result<(), ()> {
+        visit_image_set_options(image, self)
+    }
+
     fn visit_types(&self) -> VisitTypes {
-        lightningcss::visit_types!(URLS)
+        lightningcss::visit_types!(URLS | IMAGES)
+    }
+}
+
+/// Visit the images of an image.
+///
+/// lightningcss doesn't count the images of `image-set()` options as
+/// children of the set, so a visitor of URLs alone never reaches the URLs of
+/// `image-set(url("a-1x.png") 1x, url("a-2x.png") 2x)`.
+pub(crate) fn visit_image_set_options<'i, V: Visitor<'i>>(
+    image: &mut Image<'i>,
+    visitor: &mut V,
+) -> std::result::Result<(), V::Error> {
+    match image {
+        Image::ImageSet(image_set) => image_set
+            .options
+            .iter_mut()
+            .try_for_each(|option| option.image.visit(visitor)),
+        _ => image.visit_children(visitor),
     }
 }
 
 struct RuleVisitor {
-    dependencies: Vec<String>,
+    dependencies: Vec<(String, Location)>,
 }
 
 impl RuleVisitor {
@@ -116,7 +367,7 @@ impl RuleVisitor {
         }
     }
 
-    fn add_dependency(&mut self, url: &str) {
+    fn add_dependency(&mut self, url: &str, loc: Location) {
         // Skip data URLs, HTTP(S) URLs, and other non-file protocols
 
         if url.starts_with("data:")
@@ -130,9 +381,7 @@ impl RuleVisitor {
         // Remove URL fragments and query parameters
         let clean_url = url.split(['?', '#']).next().unwrap_or(url).to_string();
 
-        if !self.dependencies.contains(&clean_url) {
-            self.dependencies.push(clean_url);
-        }
+        self.dependencies.push((clean_url, loc));
     }
 }
 
@@ -142,7 +391,7 @@ impl<'i> Visitor<'i> for RuleVisitor {
     fn visit_rule(&mut self, rule: &mut CssRule<'i>) -> std::result::Result<(), ()> {
         if let CssRule::Import(import_rule) = rule {
             let url_str = import_rule.url.to_string();
-            self.add_dependency(&url_str);
+            self.add_dependency(&url_str, import_rule.loc);
         }
         Ok(())
     }
@@ -151,3 +400,278 @@ impl<'i> Visitor<'i> for RuleVisitor {
         lightningcss::visit_types!(RULES)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conditional_imports() {
+        let css = r#"@import url("./tokens.css") layer(tokens);
+@import "./wide.css" screen and (min-width: 600px);
+@import "./grid.css?v=1" supports(display: grid);
+"#;
+        let deps = dependencies_from_string(css).unwrap();
+        let specifiers: Vec<(&str, ImportKind, u32)> = deps
+            .iter()
+            .map(|dep| (dep.specifier.as_str(), dep.kind, dep.span.start))
+            .collect();
+        assert_eq!(
+            specifiers,
+            [
+                ("./tokens.css", ImportKind::CssImport, 0),
+                ("./wide.css", ImportKind::CssImport, 43),
+                ("./grid.css", ImportKind::CssImport, 95),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_image_set_urls() {
+        let css = r#".a { background-image: image-set(url("chrome://global/skin/a.png") 1x, url("chrome://global/skin/a@2x.png") 2x); }
+.b { background: -webkit-image-set("./b.png" 1x, "./b@2x.png" 2x) no-repeat; }
+"#;
+        let specifiers: Vec<String> = dependencies_from_string(css)
+            .unwrap()
+            .into_iter()
+            .map(|dep| dep.specifier)
+            .collect();
+        assert_eq!(
+            specifiers,
+            [
+                "chrome://global/skin/a.png",
+                "chrome://global/skin/a@2x.png",
+                "./b.png",
+                "./b@2x.png"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_custom_property_urls() {
+        let css = r#":root { --icon-info: url("chrome://global/skin/icons/info.svg"); }
+.a { background-image: var(--icon, var(--other, url("./fallback.svg"))); }
+"#;
+        let specifiers: Vec<String> = dependencies_from_string(css)
+            .unwrap()
+            .into_iter()
+            .map(|dep| dep.specifier)
+            .collect();
+        assert_eq!(
+            specifiers,
+            ["chrome://global/skin/icons/info.svg", "./fallback.svg"]
+        );
+    }
+
+    #[test]
+    fn test_cursor_and_mask_urls() {
+        let css = r#".a { cursor: url("chrome://global/skin/cursors/grab.png") 4 4, pointer; }
+.b { -webkit-mask-image: url("chrome://global/skin/icons/a.svg"); mask-image: url("chrome://global/skin/icons/b.svg"); }
+.c { -webkit-mask: url("./c.svg") no-repeat center; mask: url(d.svg) center / 16px; }
+"#;
+        let specifiers: Vec<String> = dependencies_from_string(css)
+            .unwrap()
+            .into_iter()
+            .map(|dep| dep.specifier)
+            .collect();
+        assert_eq!(
+            specifiers,
+            [
+                "chrome://global/skin/cursors/grab.png",
+                "chrome://global/skin/icons/a.svg",
+                "chrome://global/skin/icons/b.svg",
+                "./c.svg",
+                "d.svg"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fragment_only_urls_are_skipped() {
+        let css = r##".a { fill: url(#gradient); clip-path: url("#clip"); }
+.b { background: url(""), url(about:blank), url("./b.png#frag"); }
+"##;
+        let specifiers: Vec<String> = dependencies_from_string(css)
+            .unwrap()
+            .into_iter()
+            .map(|dep| dep.specifier)
+            .collect();
+        assert_eq!(specifiers, ["./b.png"]);
+        let specifiers: Vec<String> = dependencies_from_patterns(css)
+            .into_iter()
+            .map(|dep| dep.specifier)
+            .collect();
+        assert_eq!(specifiers, ["./b.png"]);
+    }
+
+    #[test]
+    fn test_nested_at_rule_urls() {
+        let css = r#"@namespace url(http://www.w3.org/1999/xhtml);
+@namespace xul url("http://www.mozilla.org/keymaster/gatekeeper/there.is.only.xul");
+@supports (-moz-appearance: none) {
+  @supports (display: grid) {
+    .a { background: url("chrome://global/skin/a.png"); }
+  }
+  @media (min-width: 600px) {
+    .b { list-style-image: url(./b.png); }
+  }
+}
+@-moz-document url-prefix() {
+  .c { background: url(./c.png); }
+}
+@container (width > 400px) {
+  @layer widgets {
+    @supports (display: grid) { .d { mask-image: url(./d.svg); } }
+  }
+}
+"#;
+        let specifiers: Vec<String> = dependencies_from_string(css)
+            .unwrap()
+            .into_iter()
+            .map(|dep| dep.specifier)
+            .collect();
+        assert_eq!(
+            specifiers,
+            [
+                "chrome://global/skin/a.png",
+                "./b.png",
+                "./c.png",
+                "./d.svg"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recovers_from_parse_errors() {
+        let css = r#".a { background: url("./a.svg"); }
+.b:-moz-window-inactive { -moz-default-appearance: none; }
+@media (-moz-platform: windows) { .c { background: url("./c.svg"); } }
+.d { color: red; } } .e { background: url("./e.svg"); }
+"#;
+        let specifiers: Vec<String> = dependencies_from_string(css)
+            .unwrap()
+            .into_iter()
+            .map(|dep| dep.specifier)
+            .collect();
+        // Parsing continues after the stray brace, but drops what follows
+        assert_eq!(specifiers, ["./a.svg", "./c.svg"]);
+        let (_, warnings) = parse_stylesheet(css).unwrap();
+        assert_eq!(warnings, ["4:20: Invalid empty selector"]);
+    }
+
+    #[test]
+    fn test_dependencies_from_patterns() {
+        let css = r#"@import url("./tokens.css?v=1") layer(tokens);
+@import './base.css';
+.a { background: url(./a.svg#flip), url("data:image/png;base64,AA"); }
+.b { background: url( 'https://example.com/b.png' ); }
+"#;
+        let deps: Vec<(String, ImportKind)> = dependencies_from_patterns(css)
+            .into_iter()
+            .map(|dep| (dep.specifier, dep.kind))
+            .collect();
+        assert_eq!(
+            deps,
+            [
+                ("./tokens.css".to_string(), ImportKind::CssImport),
+                ("./base.css".to_string(), ImportKind::CssImport),
+                ("./a.svg".to_string(), ImportKind::CssUrl),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dependencies_carry_import_kind() {
+        let css = r#"@import url("chrome://global/skin/design-system/tokens.css");
+.a { background-image: url("./icon.svg#frag"); }
+"#;
+        let deps: Vec<(String, ImportKind)> = dependencies_from_string(css)
+            .unwrap()
+            .into_iter()
+            .map(|dep| (dep.specifier, dep.kind))
+            .collect();
+        assert_eq!(
+            deps,
+            vec![
+                ("./icon.svg".to_string(), ImportKind::CssUrl),
+                (
+                    "chrome://global/skin/design-system/tokens.css".to_string(),
+                    ImportKind::CssImport
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dependencies_are_deduplicated() {
+        let css = r#".a { background: url("./a.svg"); }
+.b { background: url("./b.svg"); }
+.c { background: url("./a.svg#flip"); }
+"#;
+        let deps: Vec<(String, usize)> = dependencies_from_string(css)
+            .unwrap()
+            .into_iter()
+            .map(|dep| (dep.specifier, dep.references))
+            .collect();
+        assert_eq!(
+            deps,
+            vec![("./a.svg".to_string(), 2), ("./b.svg".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn test_dependency_spans_point_at_the_reference() {
+        let css =
+            "@import url(\"./a.css\");\n\n/* é */ .a {\n  background: url(\"./icon.svg\");\n}\n";
+        let deps = dependencies_from_string(css).unwrap();
+        let icon = deps
+            .iter()
+            .find(|dep| dep.specifier == "./icon.svg")
+            .unwrap()
+            .span;
+        assert_eq!(icon.line_col(css), (4, 15));
+        assert!(css[icon.start as usize..].starts_with("url("));
+        let import = deps
+            .iter()
+            .find(|dep| dep.specifier == "./a.css")
+            .unwrap()
+            .span;
+        assert_eq!(import.line_col(css), (1, 1));
+    }
+
+    #[test]
+    fn test_root_scope() {
+        let tokens = r#":root { --color-accent: blue; }
+@media (prefers-color-scheme: dark) {
+  :root, html { --color-accent: lightblue; }
+}
+"#;
+        assert_eq!(
+            root_scope_from_string(tokens).unwrap(),
+            RootScope::TokensOnly
+        );
+
+        let mixed = ":root { --size: 4px; }\n.button { padding: var(--size); }\n";
+        assert_eq!(root_scope_from_string(mixed).unwrap(), RootScope::Mixed);
+
+        let root_colors = ":root { color: red; }\n";
+        assert_eq!(
+            root_scope_from_string(root_colors).unwrap(),
+            RootScope::None
+        );
+    }
+
+    #[test]
+    fn test_custom_properties() {
+        let css = r#":root { --size: 4px; --gap: calc(var(--size) * 2); }
+.a { padding: var(--gap, var(--fallback)); margin: 1px; }
+"#;
+        let properties = custom_properties_from_string(css).unwrap();
+        let mut defined: Vec<_> = properties.defined.into_iter().collect();
+        defined.sort();
+        let mut used: Vec<_> = properties.used.into_iter().collect();
+        used.sort();
+        assert_eq!(defined, vec!["--gap", "--size"]);
+        assert_eq!(used, vec!["--fallback", "--gap", "--size"]);
+    }
+}
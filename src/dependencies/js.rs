@@ -1,25 +1,115 @@
-use std::path::PathBuf;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
 use oxc::{
     allocator::Allocator,
-    ast::ast::{ImportDeclaration, StringLiteral, TemplateElement},
+    ast::ast::{
+        Argument, ArrayExpression, ArrayExpressionElement, CallExpression, ExportAllDeclaration,
+        ExportNamedDeclaration, Expression, IfStatement, ImportDeclaration,
+        ImportDeclarationSpecifier, ImportExpression, LogicalOperator, NewExpression,
+        ObjectProperty, ObjectPropertyKind, StringLiteral, TaggedTemplateExpression,
+        TemplateElement, TemplateLiteral,
+    },
     ast_visit::Visit,
     parser::{Parser, ParserReturn},
-    span::SourceType,
+    span::{GetSpan, SourceType},
 };
 
+use super::{DependencyRef, DependencySet};
+use crate::dependency_graph::{ImportKind, SourceSpan};
 use crate::errors::{DependencyError, DependencyResult};
+use crate::utils::literals::{LiteralFilter, is_relative_asset};
+use crate::utils::preprocessor::Preprocessor;
 
-pub fn dependencies_from_file(source_path: &PathBuf) -> DependencyResult<Vec<String>> {
-    let source_text = std::fs::read_to_string(source_path)?;
-    let source_type = SourceType::from_path(source_path).unwrap();
-    dependencies_from_string(&source_text, source_type)
+/// A reference like `import()` whose specifier isn't a string literal, with
+/// the kind of expression.
+type ComputedReference = (SourceSpan, &'static str);
+
+/// A string literal that isn't followed, with the reason.
+type SkippedLiteral = (String, SourceSpan, &'static str);
+
+/// Scan a module for its dependencies. Sources that go through the build's
+/// preprocessor are preprocessed with `preprocessor` first. In `stories`,
+/// relative paths to assets in object properties and arrays, like the `args`
+/// of a story, are dependencies too.
+pub fn dependencies_from_file(
+    source_path: &PathBuf,
+    literals: &LiteralFilter,
+    preprocessor: Option<&Preprocessor>,
+    stories: bool,
+) -> DependencyResult<Vec<DependencyRef>> {
+    let mut source_text = std::fs::read_to_string(source_path)?;
+    if let Some(preprocessor) = preprocessor {
+        source_text = preprocessor
+            .process(&source_text, source_path)
+            .map_err(|e| DependencyError::Preprocess {
+                message: e.to_string(),
+            })?;
+    }
+    let source_type = source_type(source_path)?;
+    let parsed = parse_dependencies(&source_text, source_type, literals, stories)?;
+    for (span, expression) in parsed.computed_references {
+        let (line, column) = span.line_col(&source_text);
+        eprintln!(
+            "Warning: {}:{}:{}: {} with a computed specifier can't be followed, the file it loads isn't included",
+            source_path.display(),
+            line,
+            column,
+            expression
+        );
+    }
+    for span in parsed.split_links {
+        let (line, column) = span.line_col(&source_text);
+        eprintln!(
+            "Warning: {}:{}:{}: <link rel=\"stylesheet\"> is interrupted by a template expression, the stylesheet isn't inlined and needs manual handling",
+            source_path.display(),
+            line,
+            column
+        );
+    }
+    for (literal, span, reason) in parsed.skipped_literals {
+        let (line, column) = span.line_col(&source_text);
+        log::debug!(
+            "{}:{}:{}: Not following string literal '{}': {}",
+            source_path.display(),
+            line,
+            column,
+            literal,
+            reason
+        );
+    }
+    Ok(parsed.dependencies)
 }
 
-pub fn dependencies_from_string(
-    source_text: &String,
+/// Scan a module for its dependencies with the default literal filter.
+#[cfg(test)]
+fn dependencies_from_string(
+    source_text: &str,
     source_type: SourceType,
-) -> DependencyResult<Vec<String>> {
+) -> DependencyResult<Vec<DependencyRef>> {
+    parse_dependencies(source_text, source_type, &LiteralFilter::default(), false)
+        .map(|parsed| parsed.dependencies)
+}
+
+/// What scanning a module found besides its dependencies.
+struct ParsedDependencies {
+    dependencies: Vec<DependencyRef>,
+    /// References like `import()` whose specifier isn't a string literal
+    computed_references: Vec<ComputedReference>,
+    /// Stylesheet link tags in templates that an expression interrupts
+    split_links: Vec<SourceSpan>,
+    /// chrome:// and resource:// string literals that aren't dependencies,
+    /// with the reason
+    skipped_literals: Vec<SkippedLiteral>,
+}
+
+/// Scan a module for its dependencies.
+fn parse_dependencies(
+    source_text: &str,
+    source_type: SourceType,
+    literals: &LiteralFilter,
+    stories: bool,
+) -> DependencyResult<ParsedDependencies> {
     // Memory arena where AST nodes are allocated.
     let allocator = Allocator::default();
 
@@ -42,34 +132,331 @@ pub fn dependencies_from_string(
         });
     }
 
-    let mut visitor = DependencyVisitor::new();
+    let mut visitor = DependencyVisitor::new(literals, stories);
     visitor.visit_program(&program);
 
-    let dependencies: Vec<String> = visitor
+    let dependencies = visitor
         .dependencies
         .into_iter()
-        .filter(|dep| !dep.is_empty())
-        .collect();
+        .filter(|dep| !dep.specifier.is_empty())
+        .collect::<DependencySet>()
+        .into_vec();
+
+    Ok(ParsedDependencies {
+        dependencies,
+        computed_references: visitor.computed_references,
+        split_links: visitor.split_links,
+        skipped_literals: visitor.skipped_literals,
+    })
+}
+
+/// Whether an expression is `new URL(..., import.meta.url)`, which resolves its
+/// first argument relative to the module.
+pub(crate) fn is_module_url(expr: &NewExpression) -> bool {
+    let [_, Argument::StaticMemberExpression(base)] = expr.arguments.as_slice() else {
+        return false;
+    };
+    matches!(&expr.callee, Expression::Identifier(ident) if ident.name == "URL")
+        && base.property.name == "url"
+        && matches!(&base.object, Expression::MetaProperty(meta)
+            if meta.meta.name == "import" && meta.property.name == "meta")
+}
+
+/// Whether an import only imports TypeScript types, like
+/// `import type { A } from "./a.ts"` or `import { type A } from "./a.ts"`.
+fn is_type_only_import(decl: &ImportDeclaration) -> bool {
+    if decl.import_kind.is_type() {
+        return true;
+    }
+    decl.specifiers.as_ref().is_some_and(|specifiers| {
+        !specifiers.is_empty()
+            && specifiers.iter().all(|specifier| {
+                matches!(
+                    specifier,
+                    ImportDeclarationSpecifier::ImportSpecifier(specifier)
+                        if specifier.import_kind.is_type()
+                )
+            })
+    })
+}
+
+/// Whether a re-export only exports TypeScript types, like
+/// `export type { A } from "./a.ts"` or `export { type A } from "./a.ts"`.
+fn is_type_only_export(decl: &ExportNamedDeclaration) -> bool {
+    decl.export_kind.is_type()
+        || (!decl.specifiers.is_empty()
+            && decl
+                .specifiers
+                .iter()
+                .all(|specifier| specifier.export_kind.is_type()))
+}
+
+/// Matches an html attribute with a quoted value, which is in the first group
+/// for double and the second for single quotes.
+pub(crate) fn attribute_regex(name: &str) -> regex::Regex {
+    regex::Regex::new(&format!(r#"\b{}\s*=\s*(?:"([^"]*)"|'([^']*)')"#, name)).unwrap()
+}
 
-    Ok(dependencies)
+/// Byte ranges of the URLs of the quoted and unquoted `url()` tokens in a
+/// `style` attribute value.
+pub(crate) fn style_urls(value: &str) -> Vec<Range<usize>> {
+    let url_regex =
+        regex::Regex::new(r#"url\(\s*(?:"([^"]*)"|'([^']*)'|([^"'()\s]+))\s*\)"#).unwrap();
+    url_regex
+        .captures_iter(value)
+        .filter_map(|captures| captures.get(1).or(captures.get(2)).or(captures.get(3)))
+        .map(|url| url.range())
+        .collect()
 }
 
-struct DependencyVisitor {
-    dependencies: Vec<String>,
+/// Byte ranges of the URLs in a `srcset` attribute value like `a.png 1x,
+/// b.png 2x`. URLs can't contain whitespace but can contain commas, e.g. in
+/// data: URLs, so a comma only ends a candidate after its URL.
+pub(crate) fn srcset_urls(value: &str) -> Vec<Range<usize>> {
+    let bytes = value.as_bytes();
+    let mut urls = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        while i < bytes.len() && (bytes[i].is_ascii_whitespace() || bytes[i] == b',') {
+            i += 1;
+        }
+        let start = i;
+        while i < bytes.len() && !bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        // A URL ending in commas has no descriptors
+        let mut end = i;
+        while end > start && bytes[end - 1] == b',' {
+            end -= 1;
+        }
+        if end > start {
+            urls.push(start..end);
+        }
+        if end == i {
+            // Skip the descriptors, which end at the next comma outside of
+            // parentheses
+            let mut depth = 0usize;
+            while i < bytes.len() {
+                match bytes[i] {
+                    b'(' => depth += 1,
+                    b')' => depth = depth.saturating_sub(1),
+                    b',' if depth == 0 => break,
+                    _ => {}
+                }
+                i += 1;
+            }
+        }
+    }
+    urls
+}
+
+/// Whether a condition only holds in automation, like `Cu.isInAutomation` or
+/// `Cu.isInAutomation && debug`. Literals guarded by it are only loaded by
+/// tests.
+fn is_test_guard(expr: &Expression) -> bool {
+    match expr {
+        Expression::StaticMemberExpression(member) => member.property.name == "isInAutomation",
+        Expression::LogicalExpression(logical) if logical.operator == LogicalOperator::And => {
+            is_test_guard(&logical.left) || is_test_guard(&logical.right)
+        }
+        Expression::ParenthesizedExpression(inner) => is_test_guard(&inner.expression),
+        _ => false,
+    }
+}
+
+/// Whether an expression constructs a `Worker` or `SharedWorker`, whose first
+/// argument is the URL of its script.
+pub(crate) fn is_worker(expr: &NewExpression) -> bool {
+    matches!(&expr.callee, Expression::Identifier(ident)
+        if ident.name == "Worker" || ident.name == "SharedWorker")
+}
+
+/// The stylesheet URL of a `fetch("...css")` call, which loads a stylesheet
+/// for a constructed `CSSStyleSheet`, e.g. with `replaceSync`.
+pub(crate) fn stylesheet_fetch<'b, 'a>(
+    call: &'b CallExpression<'a>,
+) -> Option<&'b StringLiteral<'a>> {
+    let is_fetch = match &call.callee {
+        Expression::Identifier(ident) => ident.name == "fetch",
+        Expression::StaticMemberExpression(member) => {
+            member.property.name == "fetch"
+                && matches!(&member.object, Expression::Identifier(object)
+                    if object.name == "window" || object.name == "globalThis")
+        }
+        _ => false,
+    };
+    let Some(Argument::StringLiteral(literal)) = call.arguments.first().filter(|_| is_fetch) else {
+        return None;
+    };
+    let url = literal.value.as_str();
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let remote = ["http://", "https://", "data:", "blob:"]
+        .iter()
+        .any(|scheme| url.starts_with(scheme));
+    (path.ends_with(".css") && !remote).then_some(literal)
+}
+
+/// A `ChromeUtils` call that loads privileged modules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PrivilegedCall {
+    /// `ChromeUtils.importESModule("resource://...")`
+    ImportESModule,
+    /// `ChromeUtils.defineESModuleGetters(lazy, { Name: "resource://..." })`
+    DefineESModuleGetters,
+}
+
+/// The kind of `ChromeUtils` call that loads privileged modules, if `call` is
+/// one.
+pub(crate) fn privileged_call(call: &CallExpression) -> Option<PrivilegedCall> {
+    let Expression::StaticMemberExpression(callee) = &call.callee else {
+        return None;
+    };
+    if !matches!(&callee.object, Expression::Identifier(ident) if ident.name == "ChromeUtils") {
+        return None;
+    }
+    match callee.property.name.as_str() {
+        "importESModule" => Some(PrivilegedCall::ImportESModule),
+        "defineESModuleGetters" => Some(PrivilegedCall::DefineESModuleGetters),
+        _ => None,
+    }
 }
 
-impl DependencyVisitor {
-    fn new() -> Self {
+/// The module specifiers of a privileged call: the first argument of
+/// `importESModule`, the property values of `defineESModuleGetters`.
+pub(crate) fn privileged_specifiers<'b, 'a>(
+    call: &'b CallExpression<'a>,
+) -> Vec<&'b StringLiteral<'a>> {
+    match privileged_call(call) {
+        Some(PrivilegedCall::ImportESModule) => match call.arguments.first() {
+            Some(Argument::StringLiteral(literal)) => vec![literal],
+            _ => Vec::new(),
+        },
+        Some(PrivilegedCall::DefineESModuleGetters) => match call.arguments.get(1) {
+            Some(Argument::ObjectExpression(object)) => object
+                .properties
+                .iter()
+                .filter_map(|property| match property {
+                    ObjectPropertyKind::ObjectProperty(property) => match &property.value {
+                        Expression::StringLiteral(literal) => Some(&**literal),
+                        _ => None,
+                    },
+                    ObjectPropertyKind::SpreadProperty(_) => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        },
+        None => Vec::new(),
+    }
+}
+
+/// The source type of a module, from its extension.
+fn source_type(source_path: &Path) -> DependencyResult<SourceType> {
+    SourceType::from_path(source_path).map_err(|e| DependencyError::JsParse {
+        message: format!("{}: {}", source_path.display(), e),
+    })
+}
+
+/// Get the tag names a module registers with `customElements.define`.
+pub fn custom_element_tags_from_file(source_path: &PathBuf) -> DependencyResult<Vec<String>> {
+    let source_text = std::fs::read_to_string(source_path)?;
+    let source_type = source_type(source_path)?;
+    custom_element_tags_from_string(&source_text, source_type)
+}
+
+pub fn custom_element_tags_from_string(
+    source_text: &str,
+    source_type: SourceType,
+) -> DependencyResult<Vec<String>> {
+    let allocator = Allocator::default();
+    let ParserReturn {
+        program, panicked, ..
+    } = Parser::new(&allocator, source_text, source_type).parse();
+    if panicked {
+        return Err(DependencyError::JsPanicParse);
+    }
+
+    let mut visitor = CustomElementVisitor { tags: Vec::new() };
+    visitor.visit_program(&program);
+    Ok(visitor.tags)
+}
+
+struct CustomElementVisitor {
+    tags: Vec<String>,
+}
+
+impl<'a> Visit<'a> for CustomElementVisitor {
+    fn visit_call_expression(&mut self, call: &CallExpression<'a>) {
+        // customElements.define("tag", ...) or window.customElements.define(...)
+        if let Expression::StaticMemberExpression(callee) = &call.callee
+            && callee.property.name == "define"
+            && match &callee.object {
+                Expression::Identifier(ident) => ident.name == "customElements",
+                Expression::StaticMemberExpression(member) => {
+                    member.property.name == "customElements"
+                }
+                _ => false,
+            }
+            && let Some(Argument::StringLiteral(tag)) = call.arguments.first()
+        {
+            self.tags.push(tag.value.to_string());
+        }
+        oxc::ast_visit::walk::walk_call_expression(self, call);
+    }
+}
+
+struct DependencyVisitor<'f> {
+    dependencies: Vec<DependencyRef>,
+    /// Spans of `import()` and `new URL()` expressions with a computed
+    /// specifier, with the kind of expression
+    computed_references: Vec<ComputedReference>,
+    /// Spans of stylesheet link tags in templates that an expression
+    /// interrupts, like `<link rel="stylesheet" href="${url}">`
+    split_links: Vec<SourceSpan>,
+    literals: &'f LiteralFilter,
+    skipped_literals: Vec<SkippedLiteral>,
+    /// How many test-only guards like `if (Cu.isInAutomation)` the visitor
+    /// is inside of
+    test_guards: usize,
+    /// Whether relative asset paths in object properties and arrays are
+    /// dependencies, as in the `args` of stories
+    stories: bool,
+}
+
+impl<'f> DependencyVisitor<'f> {
+    fn new(literals: &'f LiteralFilter, stories: bool) -> Self {
         Self {
             dependencies: Vec::new(),
+            computed_references: Vec::new(),
+            split_links: Vec::new(),
+            literals,
+            skipped_literals: Vec::new(),
+            test_guards: 0,
+            stories,
+        }
+    }
+
+    /// Collect a string literal of a story that is a relative asset path.
+    fn extract_story_asset(&mut self, literal: &StringLiteral) {
+        if self.stories && is_relative_asset(&literal.value) {
+            self.extract_string_literal(literal, ImportKind::UrlLiteral);
         }
     }
 
-    fn extract_string_literal(&mut self, literal: &StringLiteral) {
-        self.dependencies.push(literal.value.to_string());
+    fn extract_string_literal(&mut self, literal: &StringLiteral, kind: ImportKind) {
+        let span = literal.span();
+        self.dependencies.push(DependencyRef::new(
+            literal.value.as_str(),
+            kind,
+            SourceSpan::new(span.start, span.end),
+        ));
+    }
+
+    /// Span of a regex match inside a template element starting at `offset`.
+    fn template_span(offset: u32, m: &regex::Match) -> SourceSpan {
+        SourceSpan::new(offset + m.start() as u32, offset + m.end() as u32)
     }
 
-    fn extract_css_links_from_html(&mut self, html_content: &str) {
+    fn extract_css_links_from_html(&mut self, html_content: &str, offset: u32) {
         // Use the same regex pattern as in transform.rs
         let link_tag_regex = regex::Regex::new(
             r#"<link[^>]*rel\s*=\s*[\"']stylesheet[\"'][^>]*href\s*=\s*[\"']([^\"']+)[\"'][^>]*/?>"#
@@ -79,16 +466,21 @@ impl DependencyVisitor {
             if let Some(href_match) = captures.get(1) {
                 let href = href_match.as_str().trim();
                 if !href.is_empty() {
-                    self.dependencies.push(href.to_string());
+                    self.dependencies.push(DependencyRef::new(
+                        href.to_string(),
+                        ImportKind::HtmlLink,
+                        Self::template_span(offset, &href_match),
+                    ));
                 }
             }
         }
     }
 
-    fn extract_any_link_from_html(&mut self, html_content: &str) {
-        let url_regex =
-            regex::Regex::new(r#"(?:src|href|iconsrc)\s*=\s*[\"']([^\"']+\.[a-zA-Z0-9]+)[\"']"#)
-                .unwrap();
+    fn extract_any_link_from_html(&mut self, html_content: &str, offset: u32) {
+        let url_regex = regex::Regex::new(
+            r#"(?:src|href|iconsrc)\s*=\s*[\"']([^\"'?#]+\.[a-zA-Z0-9]+)(?:[?#][^\"']*)?[\"']"#,
+        )
+        .unwrap();
         for captures in url_regex.captures_iter(html_content) {
             if let Some(url_match) = captures.get(1) {
                 let url = url_match.as_str().trim();
@@ -98,28 +490,850 @@ impl DependencyVisitor {
                         && !url.starts_with("https://")
                         && !url.starts_with("www."))
                 {
-                    self.dependencies.push(url.to_string());
+                    self.dependencies.push(DependencyRef::new(
+                        url.to_string(),
+                        ImportKind::TemplateAttribute,
+                        Self::template_span(offset, &url_match),
+                    ));
                 }
             }
         }
+        self.extract_attribute_urls(html_content, offset, "srcset", srcset_urls);
+        self.extract_attribute_urls(html_content, offset, "style", style_urls);
+    }
+
+    /// Collect the URLs of attributes that can hold several of them, like
+    /// `srcset` or the `url()` tokens of `style`. `urls` finds them in the
+    /// attribute value.
+    fn extract_attribute_urls(
+        &mut self,
+        html_content: &str,
+        offset: u32,
+        attribute: &str,
+        urls: fn(&str) -> Vec<Range<usize>>,
+    ) {
+        for captures in attribute_regex(attribute).captures_iter(html_content) {
+            let Some(value) = captures.get(1).or(captures.get(2)) else {
+                continue;
+            };
+            for range in urls(value.as_str()) {
+                let start = offset + (value.start() + range.start) as u32;
+                self.extract_template_url(&value.as_str()[range], start);
+            }
+        }
+    }
+
+    /// Collect a URL of a template that starts at `start`, unless it is
+    /// remote or doesn't name a file.
+    fn extract_template_url(&mut self, url: &str, start: u32) {
+        let path = url.split(['?', '#']).next().unwrap_or(url);
+        let file_name = path.rsplit('/').next().unwrap_or(path);
+        if !file_name.contains('.')
+            || ["http://", "https://", "www.", "data:", "blob:"]
+                .iter()
+                .any(|prefix| path.starts_with(prefix))
+        {
+            return;
+        }
+        self.dependencies.push(DependencyRef::new(
+            path,
+            ImportKind::TemplateAttribute,
+            SourceSpan::new(start, start + path.len() as u32),
+        ));
     }
 }
 
-impl<'a> Visit<'a> for DependencyVisitor {
+impl<'a> Visit<'a> for DependencyVisitor<'_> {
     fn visit_import_declaration(&mut self, decl: &ImportDeclaration<'a>) {
-        self.extract_string_literal(&decl.source);
+        // TypeScript imports of types only are gone from the output
+        if is_type_only_import(decl) {
+            return;
+        }
+        self.extract_string_literal(&decl.source, ImportKind::StaticImport);
+    }
+
+    fn visit_export_named_declaration(&mut self, decl: &ExportNamedDeclaration<'a>) {
+        // `export { a } from "./a.mjs"` re-exports, other exports declare
+        // something that may contain dependencies
+        match &decl.source {
+            Some(_) if is_type_only_export(decl) => {}
+            Some(source) => self.extract_string_literal(source, ImportKind::StaticImport),
+            None => oxc::ast_visit::walk::walk_export_named_declaration(self, decl),
+        }
+    }
+
+    fn visit_export_all_declaration(&mut self, decl: &ExportAllDeclaration<'a>) {
+        if decl.export_kind.is_type() {
+            return;
+        }
+        self.extract_string_literal(&decl.source, ImportKind::StaticImport);
+    }
+
+    fn visit_import_expression(&mut self, expr: &ImportExpression<'a>) {
+        if let Expression::StringLiteral(literal) = &expr.source {
+            self.extract_string_literal(literal, ImportKind::DynamicImport);
+            return;
+        }
+        let span = expr.span;
+        self.computed_references
+            .push((SourceSpan::new(span.start, span.end), "import()"));
+        oxc::ast_visit::walk::walk_import_expression(self, expr);
+    }
+
+    fn visit_call_expression(&mut self, call: &CallExpression<'a>) {
+        if let Some(literal) = stylesheet_fetch(call) {
+            self.extract_string_literal(literal, ImportKind::FetchedStylesheet);
+            return;
+        }
+        if privileged_call(call).is_some() {
+            for literal in privileged_specifiers(call) {
+                self.extract_string_literal(literal, ImportKind::PrivilegedImport);
+            }
+            return;
+        }
+        oxc::ast_visit::walk::walk_call_expression(self, call);
+    }
+
+    fn visit_new_expression(&mut self, expr: &NewExpression<'a>) {
+        if is_worker(expr) {
+            let script = match expr.arguments.first() {
+                Some(Argument::StringLiteral(literal)) => Some(literal),
+                // new Worker(new URL("./worker.mjs", import.meta.url))
+                Some(Argument::NewExpression(url)) if is_module_url(url) => {
+                    match url.arguments.first() {
+                        Some(Argument::StringLiteral(literal)) => Some(literal),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+            match script {
+                Some(literal) => self.extract_string_literal(literal, ImportKind::Worker),
+                None => {
+                    let span = expr.span;
+                    self.computed_references
+                        .push((SourceSpan::new(span.start, span.end), "new Worker()"));
+                }
+            }
+            // Options like `{ type: "module" }` have nothing to follow
+            return;
+        }
+        if is_module_url(expr) {
+            match expr.arguments.first() {
+                Some(Argument::StringLiteral(literal)) => {
+                    let url = literal.value.as_str();
+                    if !["http://", "https://", "data:", "blob:"]
+                        .iter()
+                        .any(|scheme| url.starts_with(scheme))
+                    {
+                        self.extract_string_literal(literal, ImportKind::ModuleUrl);
+                    }
+                    return;
+                }
+                _ => {
+                    let span = expr.span;
+                    self.computed_references
+                        .push((SourceSpan::new(span.start, span.end), "new URL()"));
+                }
+            }
+        }
+        oxc::ast_visit::walk::walk_new_expression(self, expr);
+    }
+
+    fn visit_template_literal(&mut self, template: &TemplateLiteral<'a>) {
+        // Link tags are only found within a single element, a tag that
+        // continues after an expression can't be followed
+        let rel_regex = regex::Regex::new(r#"rel\s*=\s*["']?(?:stylesheet|\$\{)"#).unwrap();
+        let quasis = &template.quasis;
+        let mut idx = 0;
+        while idx + 1 < quasis.len() {
+            let raw = quasis[idx].value.raw.as_str();
+            let Some(start) = raw
+                .rfind("<link")
+                .filter(|&start| !raw[start..].contains('>'))
+            else {
+                idx += 1;
+                continue;
+            };
+            let span_start = quasis[idx].span.start + start as u32;
+            // The tag with `${}` in place of the expressions, up to where it
+            // closes
+            let mut tag = raw[start..].to_string();
+            idx += 1;
+            while idx < quasis.len() {
+                let raw = quasis[idx].value.raw.as_str();
+                tag.push_str("${}");
+                match raw.find('>') {
+                    Some(end) => {
+                        tag.push_str(&raw[..end]);
+                        break;
+                    }
+                    None => {
+                        tag.push_str(raw);
+                        idx += 1;
+                    }
+                }
+            }
+            if rel_regex.is_match(&tag) {
+                let span_end = span_start + "<link".len() as u32;
+                self.split_links.push(SourceSpan::new(span_start, span_end));
+            }
+        }
+        oxc::ast_visit::walk::walk_template_literal(self, template);
+    }
+
+    fn visit_tagged_template_expression(&mut self, tagged: &TaggedTemplateExpression<'a>) {
+        // The `url()` tokens of Lit `css` templates, like custom properties
+        // holding icons
+        if matches!(&tagged.tag, Expression::Identifier(ident) if ident.name == "css") {
+            for quasi in &tagged.quasi.quasis {
+                let raw = quasi.value.raw.as_str();
+                for range in style_urls(raw) {
+                    let start = quasi.span.start + range.start as u32;
+                    self.extract_template_url(&raw[range], start);
+                }
+            }
+        }
+        oxc::ast_visit::walk::walk_tagged_template_expression(self, tagged);
     }
 
     fn visit_template_element(&mut self, element: &TemplateElement<'a>) {
         // If the template element contains HTML, extract CSS links
         let value = &element.value;
-        self.extract_css_links_from_html(&value.raw);
-        self.extract_any_link_from_html(&value.raw);
+        self.extract_css_links_from_html(&value.raw, element.span.start);
+        self.extract_any_link_from_html(&value.raw, element.span.start);
+    }
+
+    fn visit_if_statement(&mut self, stmt: &IfStatement<'a>) {
+        if !is_test_guard(&stmt.test) {
+            oxc::ast_visit::walk::walk_if_statement(self, stmt);
+            return;
+        }
+        self.visit_expression(&stmt.test);
+        self.test_guards += 1;
+        self.visit_statement(&stmt.consequent);
+        self.test_guards -= 1;
+        if let Some(alternate) = &stmt.alternate {
+            self.visit_statement(alternate);
+        }
+    }
+
+    fn visit_object_property(&mut self, property: &ObjectProperty<'a>) {
+        if let Expression::StringLiteral(literal) = &property.value {
+            self.extract_story_asset(literal);
+        }
+        oxc::ast_visit::walk::walk_object_property(self, property);
+    }
+
+    fn visit_array_expression(&mut self, array: &ArrayExpression<'a>) {
+        for element in &array.elements {
+            if let ArrayExpressionElement::StringLiteral(literal) = element {
+                self.extract_story_asset(literal);
+            }
+        }
+        oxc::ast_visit::walk::walk_array_expression(self, array);
     }
 
     fn visit_string_literal(&mut self, it: &StringLiteral<'a>) {
-        if it.value.starts_with("chrome://") || it.value.starts_with("resource://") {
-            self.extract_string_literal(it);
+        if !it.value.starts_with("chrome://") && !it.value.starts_with("resource://") {
+            return;
+        }
+        let reason = if self.test_guards > 0 {
+            Some("only used in automation")
+        } else {
+            self.literals.skip_reason(&it.value)
+        };
+        match reason {
+            Some(reason) => self.skipped_literals.push((
+                it.value.to_string(),
+                SourceSpan::new(it.span.start, it.span.end),
+                reason,
+            )),
+            None => self.extract_string_literal(it, ImportKind::UrlLiteral),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_css_template_urls() {
+        let source = r#"const styles = css`:host { --icon: url("chrome://global/skin/icons/info.svg"); }
+.a { background: var(--icon, url(./fallback.svg)) , url("https://example.com/a.png"); }`;
+const other = svg`<path style="fill: url(#gradient)" />`;
+"#;
+        let deps: Vec<(String, ImportKind, u32)> =
+            dependencies_from_string(source, SourceType::mjs())
+                .unwrap()
+                .into_iter()
+                .map(|dep| (dep.specifier, dep.kind, dep.span.start))
+                .collect();
+        let at = |specifier: &str| source.find(specifier).unwrap() as u32;
+        assert_eq!(
+            deps,
+            [
+                (
+                    "chrome://global/skin/icons/info.svg".to_string(),
+                    ImportKind::TemplateAttribute,
+                    at("chrome://")
+                ),
+                (
+                    "./fallback.svg".to_string(),
+                    ImportKind::TemplateAttribute,
+                    at("./fallback.svg")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_story_assets() {
+        let source = r#"export default { argTypes: { icon: { options: ["./a.svg", "./b.mjs"] } } };
+export const Default = () => {};
+Default.args = { icon: "./fixtures/avatar.png", label: "./label" };
+const other = "./c.png";
+"#;
+        let specifiers = |stories| -> Vec<String> {
+            parse_dependencies(
+                source,
+                SourceType::mjs(),
+                &LiteralFilter::default(),
+                stories,
+            )
+            .unwrap()
+            .dependencies
+            .into_iter()
+            .map(|dep| dep.specifier)
+            .collect()
+        };
+        assert_eq!(specifiers(true), ["./a.svg", "./fixtures/avatar.png"]);
+        assert!(specifiers(false).is_empty());
+    }
+
+    #[test]
+    fn test_dependencies_carry_import_kind() {
+        let source = r#"import { html } from "chrome://global/content/vendor/lit.all.mjs";
+const lazy = () => import("chrome://global/content/lazy.mjs");
+const icon = "chrome://global/skin/icons/close.svg";
+const tpl = html`<link rel="stylesheet" href="./moz-a.css" /><img src="./a.png" />`;
+"#;
+        let deps: Vec<(String, ImportKind)> = dependencies_from_string(source, SourceType::mjs())
+            .unwrap()
+            .into_iter()
+            .map(|dep| (dep.specifier, dep.kind))
+            .collect();
+        assert!(deps.contains(&(
+            "chrome://global/content/vendor/lit.all.mjs".to_string(),
+            ImportKind::StaticImport
+        )));
+        assert!(deps.contains(&(
+            "chrome://global/content/lazy.mjs".to_string(),
+            ImportKind::DynamicImport
+        )));
+        assert!(deps.contains(&(
+            "chrome://global/skin/icons/close.svg".to_string(),
+            ImportKind::UrlLiteral
+        )));
+        assert!(deps.contains(&("./moz-a.css".to_string(), ImportKind::HtmlLink)));
+        assert!(deps.contains(&("./a.png".to_string(), ImportKind::TemplateAttribute)));
+        // The dynamic import is not reported a second time as a literal
+        assert!(!deps.contains(&(
+            "chrome://global/content/lazy.mjs".to_string(),
+            ImportKind::UrlLiteral
+        )));
+    }
+
+    #[test]
+    fn test_type_only_imports_are_skipped() {
+        let source = r#"import type { A } from "./a.ts";
+import { type B } from "./b.ts";
+import { type C, c } from "./c.ts";
+import "./d.ts";
+export type { E } from "./e.ts";
+export { type F } from "./f.ts";
+export type * from "./g.ts";
+export { h } from "./h.ts";
+"#;
+        let deps: Vec<String> = dependencies_from_string(source, SourceType::ts())
+            .unwrap()
+            .into_iter()
+            .map(|dep| dep.specifier)
+            .collect();
+        assert_eq!(deps, vec!["./c.ts", "./d.ts", "./h.ts"]);
+    }
+
+    #[test]
+    fn test_dependencies_are_deduplicated() {
+        let source = r#"import { html } from "./lit.all.mjs";
+const tpl = html`
+  <img src="chrome://global/skin/icons/close.svg" />
+  <img src="./a.png" />
+  <img src="chrome://global/skin/icons/close.svg" />
+`;
+const icon = "chrome://global/skin/icons/close.svg";
+const other = html`<img src="chrome://global/skin/icons/close.svg" />`;
+"#;
+        let deps: Vec<(String, ImportKind, usize)> =
+            dependencies_from_string(source, SourceType::mjs())
+                .unwrap()
+                .into_iter()
+                .map(|dep| (dep.specifier, dep.kind, dep.references))
+                .collect();
+        assert_eq!(
+            deps,
+            vec![
+                ("./lit.all.mjs".to_string(), ImportKind::StaticImport, 1),
+                (
+                    "chrome://global/skin/icons/close.svg".to_string(),
+                    ImportKind::TemplateAttribute,
+                    3
+                ),
+                ("./a.png".to_string(), ImportKind::TemplateAttribute, 1),
+                (
+                    "chrome://global/skin/icons/close.svg".to_string(),
+                    ImportKind::UrlLiteral,
+                    1
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_string_literal_heuristic() {
+        let source = r#"const icon = "chrome://global/skin/icons/close.svg";
+const page = "chrome://browser/content/browser.xhtml";
+const label = "chrome://global/content/";
+const ignored = "chrome://global/skin/icons/legacy.png";
+if (Cu.isInAutomation && debug) {
+  loadFixture("chrome://mochitests/content/fixture.mjs");
+} else {
+  load("chrome://global/content/real.mjs");
+}
+"#;
+        let literals =
+            LiteralFilter::new(&["chrome://global/skin/icons/legacy.*".to_string()]).unwrap();
+        let parsed = parse_dependencies(source, SourceType::mjs(), &literals, false).unwrap();
+        let deps: Vec<&str> = parsed
+            .dependencies
+            .iter()
+            .map(|dep| dep.specifier.as_str())
+            .collect();
+        assert_eq!(
+            deps,
+            [
+                "chrome://global/skin/icons/close.svg",
+                "chrome://global/content/real.mjs"
+            ]
+        );
+        let skipped: Vec<(&str, &str)> = parsed
+            .skipped_literals
+            .iter()
+            .map(|(literal, _, reason)| (literal.as_str(), *reason))
+            .collect();
+        assert_eq!(
+            skipped,
+            [
+                (
+                    "chrome://browser/content/browser.xhtml",
+                    "not a file type mozcomp handles"
+                ),
+                (
+                    "chrome://global/content/",
+                    "not a file type mozcomp handles"
+                ),
+                (
+                    "chrome://global/skin/icons/legacy.png",
+                    "matches ignore_literals"
+                ),
+                (
+                    "chrome://mochitests/content/fixture.mjs",
+                    "only used in automation"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_srcset_urls() {
+        let value = "a.png 1x,b.png  2x , data:image/png;base64,AA== 3x, c.png, d.png";
+        let urls: Vec<&str> = srcset_urls(value)
+            .into_iter()
+            .map(|range| &value[range])
+            .collect();
+        assert_eq!(
+            urls,
+            [
+                "a.png",
+                "b.png",
+                "data:image/png;base64,AA==",
+                "c.png",
+                "d.png"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_srcset_dependencies() {
+        let source = r#"const tpl = html`<img srcset="chrome://global/skin/icons/a-1x.png 1x, ./a-2x.png?v=2 2x, https://example.com/a.png 3x" />`;
+"#;
+        let deps = dependencies_from_string(source, SourceType::mjs()).unwrap();
+        let urls: Vec<(&str, &ImportKind)> = deps
+            .iter()
+            .map(|dep| (dep.specifier.as_str(), &dep.kind))
+            .collect();
+        assert_eq!(
+            urls,
+            [
+                (
+                    "chrome://global/skin/icons/a-1x.png",
+                    &ImportKind::TemplateAttribute
+                ),
+                ("./a-2x.png", &ImportKind::TemplateAttribute),
+            ]
+        );
+        let span = deps[1].span;
+        assert_eq!(
+            &source[span.start as usize..span.end as usize],
+            "./a-2x.png"
+        );
+    }
+
+    #[test]
+    fn test_style_attribute_dependencies() {
+        let source = r#"const tpl = html`<div style="background-image: url('chrome://global/skin/icons/info.svg')"></div><span style='mask: url(./mask.svg#a) no-repeat, url("data:image/svg+xml,x.svg")'></span>`;
+"#;
+        let deps = dependencies_from_string(source, SourceType::mjs()).unwrap();
+        let urls: Vec<(&str, &ImportKind)> = deps
+            .iter()
+            .map(|dep| (dep.specifier.as_str(), &dep.kind))
+            .collect();
+        assert_eq!(
+            urls,
+            [
+                (
+                    "chrome://global/skin/icons/info.svg",
+                    &ImportKind::TemplateAttribute
+                ),
+                ("./mask.svg", &ImportKind::TemplateAttribute),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_link_tags() {
+        let source = r#"const tpl = html`<link rel="stylesheet" href="${this.stylesheetURL}" />
+<link rel="stylesheet" href="./static.css" media=${media}>
+<link rel="icon" href="${icon}">
+<link rel="stylesheet" href="./a.css" />`;
+"#;
+        let parsed =
+            parse_dependencies(source, SourceType::mjs(), &LiteralFilter::default(), false)
+                .unwrap();
+        let deps: Vec<(&str, &ImportKind)> = parsed
+            .dependencies
+            .iter()
+            .map(|dep| (dep.specifier.as_str(), &dep.kind))
+            .collect();
+        // A static href is still copied and rewritten as an attribute, but
+        // only complete tags are stylesheet links
+        assert_eq!(
+            deps,
+            [
+                ("./static.css", &ImportKind::TemplateAttribute),
+                ("./a.css", &ImportKind::HtmlLink),
+                ("./a.css", &ImportKind::TemplateAttribute),
+            ]
+        );
+        let locations: Vec<(usize, usize)> = parsed
+            .split_links
+            .iter()
+            .map(|span| span.line_col(source))
+            .collect();
+        assert_eq!(locations, [(1, 18), (2, 1)]);
+    }
+
+    #[test]
+    fn test_stylesheet_fetches() {
+        let source = r#"const sheet = new CSSStyleSheet();
+sheet.replaceSync(await (await fetch("chrome://global/skin/shared.css")).text());
+window.fetch("./local.css?v=1").then(r => r.text()).then(t => sheet.replace(t));
+this.shadowRoot.adoptedStyleSheets = [sheet];
+fetch("https://example.com/remote.css");
+fetch("chrome://global/content/data.json");
+"#;
+        let deps: Vec<(String, ImportKind)> = dependencies_from_string(source, SourceType::mjs())
+            .unwrap()
+            .into_iter()
+            .map(|dep| (dep.specifier, dep.kind))
+            .collect();
+        assert_eq!(
+            deps,
+            [
+                (
+                    "chrome://global/skin/shared.css".to_string(),
+                    ImportKind::FetchedStylesheet
+                ),
+                ("./local.css?v=1".to_string(), ImportKind::FetchedStylesheet),
+                (
+                    "chrome://global/content/data.json".to_string(),
+                    ImportKind::UrlLiteral
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_re_exports() {
+        let source = r#"export { default as MozButton } from "./moz-button.mjs";
+export * from "chrome://global/content/elements/named-deck.js";
+export * as panels from "./panels.mjs";
+export { helper } from "chrome://global/content/helper.mjs";
+export const icon = "chrome://global/skin/icons/close.svg";
+export { MozButton as default };
+"#;
+        let deps: Vec<(String, ImportKind)> = dependencies_from_string(source, SourceType::mjs())
+            .unwrap()
+            .into_iter()
+            .map(|dep| (dep.specifier, dep.kind))
+            .collect();
+        assert_eq!(
+            deps,
+            [
+                ("./moz-button.mjs".to_string(), ImportKind::StaticImport),
+                (
+                    "chrome://global/content/elements/named-deck.js".to_string(),
+                    ImportKind::StaticImport
+                ),
+                ("./panels.mjs".to_string(), ImportKind::StaticImport),
+                (
+                    "chrome://global/content/helper.mjs".to_string(),
+                    ImportKind::StaticImport
+                ),
+                (
+                    "chrome://global/skin/icons/close.svg".to_string(),
+                    ImportKind::UrlLiteral
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dynamic_imports() {
+        let source = r#"const panel = await import("./lazy-panel.mjs");
+import("chrome://global/content/megalist.mjs").then(m => m.init());
+const name = "other";
+import(`./${name}.mjs`);
+"#;
+        let ParsedDependencies {
+            dependencies: deps,
+            computed_references,
+            ..
+        } = parse_dependencies(source, SourceType::mjs(), &LiteralFilter::default(), false)
+            .unwrap();
+        let deps: Vec<(&str, &ImportKind)> = deps
+            .iter()
+            .map(|dep| (dep.specifier.as_str(), &dep.kind))
+            .collect();
+        assert_eq!(
+            deps,
+            [
+                ("./lazy-panel.mjs", &ImportKind::DynamicImport),
+                (
+                    "chrome://global/content/megalist.mjs",
+                    &ImportKind::DynamicImport
+                ),
+            ]
+        );
+        assert_eq!(computed_references.len(), 1);
+        assert_eq!(computed_references[0].0.line_col(source), (4, 1));
+    }
+
+    #[test]
+    fn test_privileged_imports() {
+        let source = r#"const { XPCOMUtils } = ChromeUtils.importESModule("resource://gre/modules/XPCOMUtils.sys.mjs");
+const lazy = {};
+ChromeUtils.defineESModuleGetters(lazy, {
+  PlacesUtils: "resource://gre/modules/PlacesUtils.sys.mjs",
+  UrlbarUtils: "resource:///modules/UrlbarUtils.sys.mjs",
+});
+const other = "resource://gre/modules/Other.sys.mjs";
+"#;
+        let deps: Vec<(String, ImportKind)> = dependencies_from_string(source, SourceType::mjs())
+            .unwrap()
+            .into_iter()
+            .map(|dep| (dep.specifier, dep.kind))
+            .collect();
+        assert_eq!(
+            deps,
+            [
+                (
+                    "resource://gre/modules/XPCOMUtils.sys.mjs".to_string(),
+                    ImportKind::PrivilegedImport
+                ),
+                (
+                    "resource://gre/modules/PlacesUtils.sys.mjs".to_string(),
+                    ImportKind::PrivilegedImport
+                ),
+                (
+                    "resource:///modules/UrlbarUtils.sys.mjs".to_string(),
+                    ImportKind::PrivilegedImport
+                ),
+                (
+                    "resource://gre/modules/Other.sys.mjs".to_string(),
+                    ImportKind::UrlLiteral
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dependencies_from_file_returns_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("moz-a.mjs");
+        let source = r#"import { a } from "./a.mjs";
+export { b } from "./b.mjs";
+import { c } from "./a.mjs";
+const lazy = await import("./a.mjs");
+"#;
+        std::fs::write(&path, source).unwrap();
+
+        let deps: Vec<(String, ImportKind, usize, (usize, usize))> =
+            dependencies_from_file(&path, &LiteralFilter::default(), None, false)
+                .unwrap()
+                .into_iter()
+                .map(|dep| {
+                    let position = dep.span.line_col(source);
+                    (dep.specifier, dep.kind, dep.references, position)
+                })
+                .collect();
+        // The span is where the specifier is first referenced
+        assert_eq!(
+            deps,
+            vec![
+                ("./a.mjs".to_string(), ImportKind::StaticImport, 2, (1, 19)),
+                ("./b.mjs".to_string(), ImportKind::StaticImport, 1, (2, 19)),
+                ("./a.mjs".to_string(), ImportKind::DynamicImport, 1, (4, 27)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_extensions_are_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("worker");
+        std::fs::write(&path, "onmessage = () => {};\n").unwrap();
+        let error = custom_element_tags_from_file(&path).unwrap_err();
+        assert!(
+            matches!(&error, DependencyError::JsParse { message } if message.contains("worker")),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn test_workers() {
+        let source = r#"const worker = new Worker("chrome://global/content/some-worker.js", { type: "module" });
+const shared = new SharedWorker(new URL("./shared-worker.mjs", import.meta.url));
+const computed = new Worker(workerUrl);
+"#;
+        let ParsedDependencies {
+            dependencies: deps,
+            computed_references,
+            ..
+        } = parse_dependencies(source, SourceType::mjs(), &LiteralFilter::default(), false)
+            .unwrap();
+        let deps: Vec<(&str, &ImportKind)> = deps
+            .iter()
+            .map(|dep| (dep.specifier.as_str(), &dep.kind))
+            .collect();
+        assert_eq!(
+            deps,
+            [
+                (
+                    "chrome://global/content/some-worker.js",
+                    &ImportKind::Worker
+                ),
+                ("./shared-worker.mjs", &ImportKind::Worker),
+            ]
+        );
+        assert_eq!(computed_references.len(), 1);
+        assert_eq!(computed_references[0].1, "new Worker()");
+    }
+
+    #[test]
+    fn test_module_urls() {
+        let source = r#"const warning = new URL("./icons/warning.svg", import.meta.url);
+const logo = new URL("chrome://branding/content/about-logo.svg", import.meta.url).href;
+const page = new URL("https://example.com/", import.meta.url);
+const other = new URL("./not-relative-to-the-module.svg", location.href);
+const computed = new URL(`./icons/${name}.svg`, import.meta.url);
+"#;
+        let ParsedDependencies {
+            dependencies: deps,
+            computed_references,
+            ..
+        } = parse_dependencies(source, SourceType::mjs(), &LiteralFilter::default(), false)
+            .unwrap();
+        let deps: Vec<(&str, &ImportKind)> = deps
+            .iter()
+            .map(|dep| (dep.specifier.as_str(), &dep.kind))
+            .collect();
+        assert_eq!(
+            deps,
+            [
+                ("./icons/warning.svg", &ImportKind::ModuleUrl),
+                (
+                    "chrome://branding/content/about-logo.svg",
+                    &ImportKind::ModuleUrl
+                ),
+            ]
+        );
+        assert_eq!(computed_references.len(), 1);
+        assert_eq!(computed_references[0].0.line_col(source), (5, 18));
+        assert_eq!(computed_references[0].1, "new URL()");
+    }
+
+    #[test]
+    fn test_template_attributes_drop_query_and_fragment() {
+        let source = r#"const tpl = html`<img src="chrome://global/skin/icons/arrow-down.svg#flip" /><moz-button iconsrc="./icon.svg?size=16"></moz-button>`;
+"#;
+        let deps = dependencies_from_string(source, SourceType::mjs()).unwrap();
+        let urls: Vec<&str> = deps.iter().map(|dep| dep.specifier.as_str()).collect();
+        assert_eq!(
+            urls,
+            ["chrome://global/skin/icons/arrow-down.svg", "./icon.svg"]
+        );
+    }
+
+    #[test]
+    fn test_dependency_spans_point_at_the_reference() {
+        let source = r#"import { html } from "./lit.all.mjs";
+
+const tpl = html`
+  <img src="./a.png" />`;
+"#;
+        let deps = dependencies_from_string(source, SourceType::mjs()).unwrap();
+        let span_of = |specifier: &str| {
+            deps.iter()
+                .find(|dep| dep.specifier == specifier)
+                .map(|dep| dep.span)
+                .unwrap()
+        };
+        assert_eq!(span_of("./lit.all.mjs").line_col(source), (1, 22));
+        let span = span_of("./a.png");
+        assert_eq!(span.line_col(source), (4, 13));
+        assert_eq!(&source[span.start as usize..span.end as usize], "./a.png");
+    }
+
+    #[test]
+    fn test_custom_element_tags() {
+        let source = r#"class MozA extends HTMLElement {}
+customElements.define("moz-a", MozA);
+window.customElements.define("moz-a-item", class extends HTMLElement {});
+other.define("not-a-tag", MozA);
+"#;
+        let tags = custom_element_tags_from_string(source, SourceType::mjs()).unwrap();
+        assert_eq!(tags, vec!["moz-a", "moz-a-item"]);
+    }
+}
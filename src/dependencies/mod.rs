@@ -1,2 +1,71 @@
 pub mod css;
 pub mod js;
+
+use std::collections::HashMap;
+
+use crate::dependency_graph::{ImportKind, SourceSpan};
+
+/// A reference from a source file to another file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DependencyRef {
+    /// The specifier as written in the source
+    pub specifier: String,
+    /// How the file is referenced
+    pub kind: ImportKind,
+    /// Where the specifier is first referenced in the source
+    pub span: SourceSpan,
+    /// How often the source references the specifier this way
+    pub references: usize,
+}
+
+impl DependencyRef {
+    pub fn new(specifier: impl Into<String>, kind: ImportKind, span: SourceSpan) -> Self {
+        Self {
+            specifier: specifier.into(),
+            kind,
+            span,
+            references: 1,
+        }
+    }
+}
+
+/// Dependencies in the order they are first referenced, each specifier once
+/// per kind. Later references only increase the count of the first one.
+#[derive(Debug, Default)]
+pub(crate) struct DependencySet {
+    dependencies: Vec<DependencyRef>,
+    index: HashMap<(String, ImportKind), usize>,
+}
+
+impl DependencySet {
+    pub(crate) fn insert(&mut self, dependency: DependencyRef) {
+        let key = (dependency.specifier.clone(), dependency.kind);
+        match self.index.get(&key) {
+            Some(&i) => self.dependencies[i].references += dependency.references,
+            None => {
+                self.index.insert(key, self.dependencies.len());
+                self.dependencies.push(dependency);
+            }
+        }
+    }
+
+    pub(crate) fn into_vec(self) -> Vec<DependencyRef> {
+        self.dependencies
+    }
+}
+
+impl Extend<DependencyRef> for DependencySet {
+    fn extend<I: IntoIterator<Item = DependencyRef>>(&mut self, iter: I) {
+        for dependency in iter {
+            self.insert(dependency);
+        }
+    }
+}
+
+impl FromIterator<DependencyRef> for DependencySet {
+    fn from_iter<I: IntoIterator<Item = DependencyRef>>(iter: I) -> Self {
+        let mut set = Self::default();
+        set.extend(iter);
+        set
+    }
+}
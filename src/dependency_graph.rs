@@ -1,13 +1,14 @@
-use petgraph::graph::{EdgeIndex, NodeIndex};
-use petgraph::visit::EdgeRef;
-use petgraph::{Directed, Direction, Graph};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use petgraph::stable_graph::{EdgeIndex, NodeIndex, StableGraph};
+use petgraph::visit::{EdgeRef, IntoEdgeReferences};
+use petgraph::{Directed, Direction};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::{Path, PathBuf};
 
 use crate::utils::file_utils;
 
 /// Represents the type of a file in the dependency graph.
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum FileType {
     /// A JavaScript with a moz-component
     JsComponent,
@@ -20,7 +21,7 @@ pub enum FileType {
 }
 
 /// Represents where a file should be placed in the output distribution.
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum TargetLocation {
     /// for the specific component foldeer
     Component(String),
@@ -34,6 +35,20 @@ pub enum TargetLocation {
     Omit,
 }
 
+impl TargetLocation {
+    /// How specific a placement is when the same file is claimed twice: a
+    /// component beats a global stylesheet, which beats a shared dependency or
+    /// asset, which beats an omitted file.
+    fn specificity(&self) -> u8 {
+        match self {
+            TargetLocation::Component(_) => 3,
+            TargetLocation::CssGlobal => 2,
+            TargetLocation::Asset | TargetLocation::Dependency => 1,
+            TargetLocation::Omit => 0,
+        }
+    }
+}
+
 /// Node representing a file in the dependency graph.
 #[derive(Debug, Clone)]
 pub struct FileNode {
@@ -43,6 +58,116 @@ pub struct FileNode {
     pub file_type: FileType,
     /// Where the file should be placed in the output
     pub target_location: TargetLocation,
+    /// File name to use in the output instead of the source file name,
+    /// set when resolving dist path collisions
+    pub dist_file_name: Option<String>,
+    /// Subdirectory inside the target folder, used for dependencies and assets
+    /// when the source directory structure is preserved
+    pub dist_subdir: Option<PathBuf>,
+    /// Dist path of the canonical copy, set when this file's output is identical
+    /// to another file's and it was merged into it
+    pub canonical_dist_path: Option<PathBuf>,
+    /// The chrome:// or resource:// URL the file was first imported through
+    pub original_url: Option<String>,
+    /// Other chrome:// or resource:// URLs that map to the same file
+    pub url_aliases: Vec<String>,
+    /// Set when a stylesheet linked by a component was promoted to a global
+    /// stylesheet instead of being inlined
+    pub promoted: bool,
+    /// Hash of the source content, set by `DependencyGraph::compute_hashes`
+    pub content_hash: Option<u64>,
+    /// Size of the source in bytes, set by `DependencyGraph::compute_hashes`
+    pub size: Option<u64>,
+    /// Set when a `Classifier` chose the file type and target location, which
+    /// the default rules then leave alone
+    pub classified: bool,
+    /// Set for empty placeholders of imports that couldn't be resolved; the
+    /// path doesn't exist
+    pub stub: bool,
+    /// Specifiers of imports of this file that couldn't be resolved, which
+    /// are left untouched in its output
+    pub unresolved_imports: Vec<String>,
+    /// Set for JS sources listed with `*` in a jar.mn file, whose
+    /// preprocessor directives are evaluated before they are parsed
+    pub preprocessed: bool,
+}
+
+/// How a file references one of its dependencies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ImportKind {
+    /// A JS `import ... from "..."` declaration
+    StaticImport,
+    /// A JS `import("...")` expression
+    DynamicImport,
+    /// A CSS `@import` rule
+    CssImport,
+    /// A CSS `url()` value
+    CssUrl,
+    /// A `<link rel="stylesheet">` inside an html template
+    HtmlLink,
+    /// A `src`/`href` attribute inside a template
+    TemplateAttribute,
+    /// A chrome:// or resource:// string literal in JS
+    UrlLiteral,
+    /// A JS `new URL("...", import.meta.url)` expression
+    ModuleUrl,
+    /// The script of a JS `new Worker(...)` or `new SharedWorker(...)`
+    Worker,
+    /// A module loaded with `ChromeUtils.importESModule` or
+    /// `ChromeUtils.defineESModuleGetters`
+    PrivilegedImport,
+    /// A stylesheet loaded with JS `fetch("...")`, e.g. to construct a
+    /// `CSSStyleSheet` for `adoptedStyleSheets`
+    FetchedStylesheet,
+}
+
+/// Counts and sizes describing a dependency graph, see
+/// `DependencyGraph::statistics`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GraphStatistics {
+    /// Number of files
+    pub node_count: usize,
+    /// Number of imports between files
+    pub edge_count: usize,
+    /// Number of files per file type
+    pub by_file_type: BTreeMap<FileType, usize>,
+    /// Number of files per target location
+    pub by_target_location: BTreeMap<TargetLocation, usize>,
+    /// Total on-disk size of the source files per target location, in bytes
+    pub bytes_by_target_location: BTreeMap<TargetLocation, u64>,
+    /// Whether the graph has circular imports
+    pub has_cycles: bool,
+    /// Files nothing imports, sorted
+    pub roots: Vec<PathBuf>,
+}
+
+/// Byte range of a dependency reference in the importing file's source.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct SourceSpan {
+    /// Byte offset of the start of the reference
+    pub start: u32,
+    /// Byte offset of the end of the reference
+    pub end: u32,
+}
+
+impl SourceSpan {
+    /// Create a new span.
+    pub fn new(start: u32, end: u32) -> Self {
+        Self { start, end }
+    }
+
+    /// Get the 1-based line and column of the start of the span in `source`.
+    /// Columns are counted in characters.
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let start = (self.start as usize).min(source.len());
+        let before = source
+            .get(..start)
+            .unwrap_or_else(|| &source[..source.floor_char_boundary(start)]);
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let column = before[line_start..].chars().count() + 1;
+        (line, column)
+    }
 }
 
 /// Edge representing an import relationship between files.
@@ -50,45 +175,95 @@ pub struct FileNode {
 pub struct ImportEdge {
     /// The import statement string
     pub import_statement: String,
+    /// How the dependency is referenced
+    pub kind: ImportKind,
+    /// Where the import statement appears in the importing file, if known
+    pub span: Option<SourceSpan>,
 }
 
 /// The main dependency graph structure, holding files and their relationships.
 pub struct DependencyGraph {
-    /// The underlying petgraph graph
-    graph: Graph<FileNode, ImportEdge, Directed>,
+    /// The underlying petgraph graph. A `StableGraph` keeps node indices valid
+    /// when files are removed, so `path_to_index` stays correct
+    graph: StableGraph<FileNode, ImportEdge, Directed>,
     /// Maps file paths to node indices for quick lookup
     path_to_index: HashMap<PathBuf, NodeIndex>,
 }
 
 impl FileNode {
+    /// Create a new file node.
+    pub fn new(path: PathBuf, file_type: FileType, target_location: TargetLocation) -> Self {
+        Self {
+            path,
+            file_type,
+            target_location,
+            dist_file_name: None,
+            dist_subdir: None,
+            canonical_dist_path: None,
+            original_url: None,
+            url_aliases: Vec::new(),
+            promoted: false,
+            content_hash: None,
+            size: None,
+            classified: false,
+            stub: false,
+            unresolved_imports: Vec::new(),
+            preprocessed: false,
+        }
+    }
+
     /// Get the output (dist) path for this file, or None if omitted.
     pub fn get_dist_path(&self) -> Option<PathBuf> {
         // early return if the target location is Omit
         if matches!(self.target_location, TargetLocation::Omit) {
             return None;
         }
-        let filename = match self.path.file_name() {
-            Some(name) => name.to_string_lossy().to_owned(),
-            None => return None,
+        if let Some(canonical) = &self.canonical_dist_path {
+            return Some(canonical.clone());
+        }
+        let filename = match (&self.dist_file_name, self.path.file_name()) {
+            (Some(name), _) => name.clone(),
+            (None, Some(name)) => {
+                let name = name.to_string_lossy();
+                // TypeScript sources are emitted as plain modules
+                match name
+                    .strip_suffix(".ts")
+                    .or_else(|| name.strip_suffix(".mts"))
+                {
+                    Some(stem) if self.file_type != FileType::OpaqueFile => format!("{stem}.mjs"),
+                    _ => name.into_owned(),
+                }
+            }
+            (None, None) => return None,
         };
 
+        let subdir = self.dist_subdir.clone().unwrap_or_default();
+
         match &self.target_location {
             TargetLocation::Component(name) => {
                 Some(PathBuf::from(format!("components/{}/{}", name, filename)))
             }
             TargetLocation::CssGlobal => Some(PathBuf::from(format!("styles/{}", filename))),
-            TargetLocation::Asset => Some(PathBuf::from(format!("assets/{}", filename))),
-            TargetLocation::Dependency => Some(PathBuf::from(format!("dependencies/{}", filename))),
+            TargetLocation::Asset => Some(Path::new("assets").join(subdir).join(filename)),
+            TargetLocation::Dependency => {
+                Some(Path::new("dependencies").join(subdir).join(filename))
+            }
             TargetLocation::Omit => None,
         }
     }
 }
 
+impl Default for DependencyGraph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DependencyGraph {
     /// Create a new empty dependency graph.
     pub fn new() -> Self {
         Self {
-            graph: Graph::new(),
+            graph: StableGraph::new(),
             path_to_index: HashMap::new(),
         }
     }
@@ -108,11 +283,7 @@ impl DependencyGraph {
         }
 
         // Create new node
-        let node = FileNode {
-            path: path.clone(),
-            file_type,
-            target_location,
-        };
+        let node = FileNode::new(path.clone(), file_type, target_location);
 
         let index = self.graph.add_node(node);
         self.path_to_index.insert(path, index);
@@ -121,11 +292,18 @@ impl DependencyGraph {
 
     /// Add a dependency (import) between two files. Both files must already exist in the graph.
     /// Returns the EdgeIndex for the new dependency, or an error if either file doesn't exist.
+    ///
+    /// If the same import (same statement and kind) between the two files was
+    /// already added, e.g. because the specifier appears twice in the source,
+    /// the existing edge is returned instead. Imports with a different
+    /// statement or kind get their own edge.
     pub fn add_dependency(
         &mut self,
         from_file: &PathBuf,
         to_file: &PathBuf,
         import_statement: &str,
+        kind: ImportKind,
+        span: Option<SourceSpan>,
     ) -> Result<EdgeIndex, DependencyGraphError> {
         let from_idx = self
             .path_to_index
@@ -139,19 +317,79 @@ impl DependencyGraph {
 
         // If the target is Omit and the source is not a JsComponent, mark as Dependency
         let from_file_type = self.graph[*from_idx].file_type.clone();
-        let to_target_location_is_omit =
-            self.graph[*to_idx].target_location == TargetLocation::Omit;
+        let to_target_location_is_omit = self.graph[*to_idx].target_location
+            == TargetLocation::Omit
+            && !self.graph[*to_idx].classified;
         if from_file_type != FileType::JsComponent && to_target_location_is_omit {
             self.graph[*to_idx].target_location = TargetLocation::Dependency;
         }
 
+        if let Some(existing) = self
+            .graph
+            .edges_connecting(*from_idx, *to_idx)
+            .find(|e| e.weight().import_statement == import_statement && e.weight().kind == kind)
+        {
+            return Ok(existing.id());
+        }
+
         let edge = ImportEdge {
             import_statement: import_statement.to_string(),
+            kind,
+            span,
         };
 
         Ok(self.graph.add_edge(*from_idx, *to_idx, edge))
     }
 
+    /// Remove a file and all of its import edges from the graph.
+    /// Returns the removed file node.
+    pub fn remove_file(&mut self, file_path: &PathBuf) -> Result<FileNode, DependencyGraphError> {
+        let node_idx = self
+            .path_to_index
+            .remove(file_path)
+            .ok_or_else(|| DependencyGraphError::FileNotFound(file_path.clone()))?;
+        self.graph
+            .remove_node(node_idx)
+            .ok_or_else(|| DependencyGraphError::FileNotFound(file_path.clone()))
+    }
+
+    /// Change the import statement of the edge from `from_file` to `to_file` that
+    /// currently uses `old_statement`. A file can import the same target through
+    /// several specifiers, so the old statement picks which of the edges to update.
+    pub fn update_import_statement(
+        &mut self,
+        from_file: &PathBuf,
+        to_file: &PathBuf,
+        old_statement: &str,
+        new_statement: &str,
+    ) -> Result<(), DependencyGraphError> {
+        let from_idx = *self
+            .path_to_index
+            .get(from_file)
+            .ok_or_else(|| DependencyGraphError::SourceFileNotFound(from_file.clone()))?;
+        let to_idx = *self
+            .path_to_index
+            .get(to_file)
+            .ok_or_else(|| DependencyGraphError::TargetFileNotFound(to_file.clone()))?;
+
+        let edge_indices: Vec<EdgeIndex> = self
+            .graph
+            .edges_connecting(from_idx, to_idx)
+            .filter(|edge| edge.weight().import_statement == old_statement)
+            .map(|edge| edge.id())
+            .collect();
+        if edge_indices.is_empty() {
+            return Err(DependencyGraphError::ImportNotFound(
+                from_file.clone(),
+                old_statement.to_string(),
+            ));
+        }
+        for edge_idx in edge_indices {
+            self.graph[edge_idx].import_statement = new_statement.to_string();
+        }
+        Ok(())
+    }
+
     /// Get a file node by its path, if it exists in the graph.
     pub fn get_file(&self, file_path: &PathBuf) -> Option<&FileNode> {
         self.path_to_index
@@ -164,11 +402,369 @@ impl DependencyGraph {
         self.graph.node_weights()
     }
 
+    /// Find the file that a chrome:// or resource:// URL resolved to, matching
+    /// both the original URL and its aliases.
+    pub fn find_by_chrome_url(&self, url: &str) -> Option<&FileNode> {
+        self.all_files().find(|f| {
+            f.original_url.as_deref() == Some(url) || f.url_aliases.iter().any(|a| a == url)
+        })
+    }
+
+    /// Record the internal URL a file was imported through. The first URL
+    /// becomes the original URL; any other URL that resolves to the same file is
+    /// added to the aliases, together with the given `aliases`.
+    pub fn record_url(
+        &mut self,
+        file_path: &PathBuf,
+        url: &str,
+        aliases: Vec<String>,
+    ) -> Result<(), DependencyGraphError> {
+        let node_idx = self
+            .path_to_index
+            .get(file_path)
+            .ok_or_else(|| DependencyGraphError::FileNotFound(file_path.clone()))?;
+        let node = &mut self.graph[*node_idx];
+
+        let original = node.original_url.get_or_insert_with(|| url.to_string());
+        let original = original.clone();
+        for alias in std::iter::once(url.to_string()).chain(aliases) {
+            if alias != original && !node.url_aliases.contains(&alias) {
+                node.url_aliases.push(alias);
+            }
+        }
+        node.url_aliases.sort();
+        Ok(())
+    }
+
+    /// Add an empty placeholder for an import that couldn't be resolved. It is
+    /// copied to the output like an opaque file.
+    pub fn add_stub(&mut self, path: PathBuf, target_location: TargetLocation) -> NodeIndex {
+        let index = self.add_file(path, FileType::OpaqueFile, target_location);
+        self.graph[index].stub = true;
+        index
+    }
+
+    /// Record an import of a file that couldn't be resolved, to leave it
+    /// untouched when the file is transformed.
+    pub fn record_unresolved_import(
+        &mut self,
+        file_path: &PathBuf,
+        specifier: &str,
+    ) -> Result<(), DependencyGraphError> {
+        let node_idx = self
+            .path_to_index
+            .get(file_path)
+            .ok_or_else(|| DependencyGraphError::FileNotFound(file_path.clone()))?;
+        let node = &mut self.graph[*node_idx];
+        if !node.unresolved_imports.iter().any(|s| s == specifier) {
+            node.unresolved_imports.push(specifier.to_string());
+        }
+        Ok(())
+    }
+
+    /// Export the graph as JSON, with all files and their dependencies.
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut files: Vec<&FileNode> = self.all_files().collect();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        let files: Vec<serde_json::Value> = files
+            .into_iter()
+            .map(|file| {
+                serde_json::json!({
+                    "path": file.path,
+                    "file_type": format!("{:?}", file.file_type),
+                    "target_location": format!("{:?}", file.target_location),
+                    "dist_path": file.get_dist_path().as_deref().map(file_utils::to_slash),
+                    "original_url": file.original_url,
+                    "url_aliases": file.url_aliases,
+                    "content_hash": file.content_hash.map(|hash| format!("{:016x}", hash)),
+                    "size": file.size,
+                })
+            })
+            .collect();
+
+        let mut dependencies: Vec<serde_json::Value> = self
+            .graph
+            .edge_references()
+            .map(|edge| {
+                serde_json::json!({
+                    "from": self.graph[edge.source()].path,
+                    "to": self.graph[edge.target()].path,
+                    "import_statement": edge.weight().import_statement,
+                    "kind": format!("{:?}", edge.weight().kind),
+                    "span": edge.weight().span.map(|span| [span.start, span.end]),
+                })
+            })
+            .collect();
+        dependencies.sort_by_key(|d| d.to_string());
+
+        serde_json::json!({
+            "files": files,
+            "dependencies": dependencies,
+        })
+    }
+
+    /// Get all files of the given type.
+    ///
+    /// This is a linear scan over all files, file types and target locations
+    /// change in place during the passes so they aren't indexed.
+    pub fn files_by_type(&self, file_type: FileType) -> impl Iterator<Item = &FileNode> {
+        self.all_files().filter(move |f| f.file_type == file_type)
+    }
+
+    /// Get all files placed at exactly the given target location, e.g. all
+    /// global stylesheets or the files of one component. Linear scan, like
+    /// `files_by_type`.
+    pub fn files_by_target<'a>(
+        &'a self,
+        target_location: &'a TargetLocation,
+    ) -> impl Iterator<Item = &'a FileNode> {
+        self.all_files()
+            .filter(move |f| &f.target_location == target_location)
+    }
+
+    /// Get the files of all components, regardless of the component name.
+    /// Linear scan, like `files_by_type`.
+    pub fn files_in_any_component(&self) -> impl Iterator<Item = &FileNode> {
+        self.all_files()
+            .filter(|f| matches!(f.target_location, TargetLocation::Component(_)))
+    }
+
+    /// Get the distinct names of all components in the graph, sorted.
+    pub fn components(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .files_in_any_component()
+            .filter_map(|f| match &f.target_location {
+                TargetLocation::Component(name) => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Extract the files of a single component and everything reachable from
+    /// them into a new graph. Shared dependencies are cloned into every
+    /// component's subgraph.
+    pub fn component_subgraph(&self, component_name: &str) -> DependencyGraph {
+        let roots: Vec<NodeIndex> = self
+            .graph
+            .node_indices()
+            .filter(|&idx| {
+                matches!(
+                    &self.graph[idx].target_location,
+                    TargetLocation::Component(name) if name == component_name
+                )
+            })
+            .collect();
+
+        let mut reachable = std::collections::HashSet::new();
+        let mut stack = roots;
+        while let Some(idx) = stack.pop() {
+            if reachable.insert(idx) {
+                stack.extend(self.graph.neighbors_directed(idx, Direction::Outgoing));
+            }
+        }
+
+        let mut subgraph = DependencyGraph::new();
+        let mut index_map = HashMap::new();
+        let mut nodes: Vec<NodeIndex> = reachable.into_iter().collect();
+        nodes.sort();
+        for idx in nodes {
+            let node = self.graph[idx].clone();
+            let path = node.path.clone();
+            let new_idx = subgraph.graph.add_node(node);
+            subgraph.path_to_index.insert(path, new_idx);
+            index_map.insert(idx, new_idx);
+        }
+        for edge in self.graph.edge_references() {
+            if let (Some(&from), Some(&to)) =
+                (index_map.get(&edge.source()), index_map.get(&edge.target()))
+            {
+                subgraph.graph.add_edge(from, to, edge.weight().clone());
+            }
+        }
+        subgraph
+    }
+
+    /// Get every file reachable from the JS files of a component, sorted by
+    /// path. The component's JS files themselves are not included.
+    pub fn transitive_dependencies(&self, component_name: &str) -> Vec<&FileNode> {
+        self.transitive_dependency_chains(component_name)
+            .into_iter()
+            .map(|(node, _)| node)
+            .collect()
+    }
+
+    /// Like `transitive_dependencies`, with the chain of imports each file was
+    /// first reached through, as (importing file, import statement) pairs
+    /// starting at one of the component's JS files.
+    ///
+    /// The search is breadth first and visits imports in order of target path
+    /// and statement, so every chain is a shortest one and the result doesn't
+    /// depend on the order files were added in. Cycles are visited once.
+    pub fn transitive_dependency_chains(
+        &self,
+        component_name: &str,
+    ) -> Vec<(&FileNode, Vec<(PathBuf, String)>)> {
+        let mut roots: Vec<NodeIndex> = self
+            .graph
+            .node_indices()
+            .filter(|&idx| {
+                let node = &self.graph[idx];
+                node.file_type == FileType::JsComponent
+                    && matches!(
+                        &node.target_location,
+                        TargetLocation::Component(name) if name == component_name
+                    )
+            })
+            .collect();
+        roots.sort_by(|a, b| self.graph[*a].path.cmp(&self.graph[*b].path));
+
+        // Maps each visited file to the file and edge it was reached through
+        let mut parents: HashMap<NodeIndex, Option<EdgeIndex>> =
+            roots.iter().map(|&idx| (idx, None)).collect();
+        let mut queue: VecDeque<NodeIndex> = roots.into_iter().collect();
+        while let Some(idx) = queue.pop_front() {
+            let mut edges: Vec<_> = self
+                .graph
+                .edges_directed(idx, Direction::Outgoing)
+                .collect();
+            edges.sort_by(|a, b| {
+                (&self.graph[a.target()].path, &a.weight().import_statement)
+                    .cmp(&(&self.graph[b.target()].path, &b.weight().import_statement))
+            });
+            for edge in edges {
+                if let Entry::Vacant(entry) = parents.entry(edge.target()) {
+                    entry.insert(Some(edge.id()));
+                    queue.push_back(edge.target());
+                }
+            }
+        }
+
+        let mut dependencies: Vec<(&FileNode, Vec<(PathBuf, String)>)> = parents
+            .iter()
+            .filter(|(_, parent)| parent.is_some())
+            .map(|(&idx, _)| {
+                let mut chain = Vec::new();
+                let mut current = idx;
+                while let Some(Some(edge)) = parents.get(&current) {
+                    let (from, _) = self.graph.edge_endpoints(*edge).unwrap();
+                    chain.push((
+                        self.graph[from].path.clone(),
+                        self.graph[*edge].import_statement.clone(),
+                    ));
+                    current = from;
+                }
+                chain.reverse();
+                (&self.graph[idx], chain)
+            })
+            .collect();
+        dependencies.sort_by(|a, b| a.0.path.cmp(&b.0.path));
+        dependencies
+    }
+
+    /// Merge another graph into this one. Files are unioned by path, keeping
+    /// the more specific target location, and all edges of `other` that this
+    /// graph doesn't have yet are added.
+    ///
+    /// Fails without changing this graph if both graphs claim the same file for
+    /// two different components, listing every such file.
+    pub fn merge(&mut self, other: DependencyGraph) -> Result<(), DependencyGraphError> {
+        let mut conflicts = Vec::new();
+        for node in other.all_files() {
+            if let Some(existing) = self.get_file(&node.path)
+                && let (TargetLocation::Component(first), TargetLocation::Component(second)) =
+                    (&existing.target_location, &node.target_location)
+                && first != second
+            {
+                conflicts.push((
+                    node.path.clone(),
+                    existing.target_location.clone(),
+                    node.target_location.clone(),
+                ));
+            }
+        }
+        if !conflicts.is_empty() {
+            return Err(DependencyGraphError::ConflictingTargets(conflicts));
+        }
+
+        let DependencyGraph {
+            graph: other_graph, ..
+        } = other;
+        let mut index_map = HashMap::new();
+        for idx in other_graph.node_indices() {
+            let node = other_graph[idx].clone();
+            let new_idx = match self.path_to_index.get(&node.path) {
+                Some(&existing_idx) => {
+                    let existing = &mut self.graph[existing_idx];
+                    if node.target_location.specificity() > existing.target_location.specificity() {
+                        existing.target_location = node.target_location;
+                    }
+                    if existing.original_url.is_none() {
+                        existing.original_url = node.original_url;
+                    } else if let Some(url) = node.original_url
+                        && existing.original_url.as_ref() != Some(&url)
+                        && !existing.url_aliases.contains(&url)
+                    {
+                        existing.url_aliases.push(url);
+                    }
+                    for alias in node.url_aliases {
+                        if existing.original_url.as_ref() != Some(&alias)
+                            && !existing.url_aliases.contains(&alias)
+                        {
+                            existing.url_aliases.push(alias);
+                        }
+                    }
+                    existing.url_aliases.sort();
+                    existing.promoted |= node.promoted;
+                    existing.stub &= node.stub;
+                    existing.preprocessed |= node.preprocessed;
+                    for specifier in node.unresolved_imports {
+                        if !existing.unresolved_imports.contains(&specifier) {
+                            existing.unresolved_imports.push(specifier);
+                        }
+                    }
+                    existing_idx
+                }
+                None => {
+                    let path = node.path.clone();
+                    let new_idx = self.graph.add_node(node);
+                    self.path_to_index.insert(path, new_idx);
+                    new_idx
+                }
+            };
+            index_map.insert(idx, new_idx);
+        }
+
+        for edge in other_graph.edge_references() {
+            let from = index_map[&edge.source()];
+            let to = index_map[&edge.target()];
+            let weight = edge.weight();
+            let exists = self.graph.edges_connecting(from, to).any(|e| {
+                e.weight().import_statement == weight.import_statement
+                    && e.weight().kind == weight.kind
+            });
+            if !exists {
+                self.graph.add_edge(from, to, weight.clone());
+            }
+        }
+        Ok(())
+    }
+
     /// Check if the graph has any circular dependencies.
     pub fn has_cycles(&self) -> bool {
         petgraph::algo::is_cyclic_directed(&self.graph)
     }
 
+    /// Sort the files so that every file comes before the files it imports.
+    /// Fails with one of the files on a cycle if the graph has cycles.
+    pub fn topological_sort(&self) -> Result<Vec<&FileNode>, DependencyGraphError> {
+        petgraph::algo::toposort(&self.graph, None)
+            .map(|order| order.into_iter().map(|idx| &self.graph[idx]).collect())
+            .map_err(|cycle| DependencyGraphError::Cycle(self.graph[cycle.node_id()].path.clone()))
+    }
+
     /// Get the number of files in the graph.
     pub fn file_count(&self) -> usize {
         self.graph.node_count()
@@ -179,23 +775,90 @@ impl DependencyGraph {
         self.graph.edge_count()
     }
 
+    /// Read every file once and store the hash and size of its content on its
+    /// node. Files that can't be read get no hash or size.
+    pub fn compute_hashes(&mut self) {
+        for node in self.graph.node_weights_mut() {
+            match std::fs::read(&node.path) {
+                Ok(content) => {
+                    node.content_hash = Some(file_utils::content_hash(&content));
+                    node.size = Some(content.len() as u64);
+                }
+                Err(e) => {
+                    log::debug!("Failed to read {:?} for hashing: {}", node.path, e);
+                    node.content_hash = None;
+                    node.size = None;
+                }
+            }
+        }
+    }
+
+    /// Compute counts and sizes describing the graph. Source sizes are read
+    /// from the file system; files that can't be read count as empty.
+    pub fn statistics(&self) -> GraphStatistics {
+        let mut stats = GraphStatistics {
+            node_count: self.file_count(),
+            edge_count: self.dependency_count(),
+            has_cycles: self.has_cycles(),
+            ..Default::default()
+        };
+
+        for (node_idx, file) in self.all_files_with_index() {
+            *stats
+                .by_file_type
+                .entry(file.file_type.clone())
+                .or_default() += 1;
+            *stats
+                .by_target_location
+                .entry(file.target_location.clone())
+                .or_default() += 1;
+            let size = std::fs::metadata(&file.path).map_or(0, |m| m.len());
+            *stats
+                .bytes_by_target_location
+                .entry(file.target_location.clone())
+                .or_default() += size;
+
+            if self
+                .graph
+                .edges_directed(node_idx, Direction::Incoming)
+                .next()
+                .is_none()
+            {
+                stats.roots.push(file.path.clone());
+            }
+        }
+        stats.roots.sort();
+
+        stats
+    }
+
     /// Print a debug representation of the entire dependency graph to stdout.
     pub fn debug_print(&self) {
+        let stats = self.statistics();
+
         println!("=== Dependency Graph Debug ===");
         println!(
-            "Files: {}, Dependencies: {}",
-            self.file_count(),
-            self.dependency_count()
+            "Files: {}, Dependencies: {}, Components: {}, Roots: {}",
+            stats.node_count,
+            stats.edge_count,
+            self.components().len(),
+            stats.roots.len()
         );
+        let by_file_type: Vec<String> = stats
+            .by_file_type
+            .iter()
+            .map(|(file_type, count)| format!("{:?}: {}", file_type, count))
+            .collect();
+        println!("File types: {}", by_file_type.join(", "));
 
-        if self.has_cycles() {
+        if stats.has_cycles {
             println!("⚠️  WARNING: Circular dependencies detected!");
         }
 
         println!();
 
         // Print all files grouped by target location
-        self.debug_print_files_by_target();
+        self.debug_print_files_by_target(&stats);
 
         println!();
 
@@ -206,25 +869,27 @@ impl DependencyGraph {
     }
 
     /// Print files grouped by their target location to stdout.
-    fn debug_print_files_by_target(&self) {
-        use std::collections::BTreeMap;
-
-        let mut by_target: BTreeMap<String, Vec<&FileNode>> = BTreeMap::new();
-
+    fn debug_print_files_by_target(&self, stats: &GraphStatistics) {
+        let mut by_target: BTreeMap<&TargetLocation, Vec<&FileNode>> = BTreeMap::new();
         for file in self.all_files() {
-            let target_key = match &file.target_location {
-                TargetLocation::Component(name) => format!("Component({})", name),
-                TargetLocation::CssGlobal => "CssGlobal".to_string(),
-                TargetLocation::Asset => "Asset".to_string(),
-                TargetLocation::Dependency => "Dependency".to_string(),
-                TargetLocation::Omit => "Omit".to_string(),
-            };
-            by_target.entry(target_key).or_default().push(file);
+            by_target
+                .entry(&file.target_location)
+                .or_default()
+                .push(file);
         }
 
         println!("📁 Files by Target Location:");
         for (target, files) in by_target {
-            println!("  {} ({} files):", target, files.len());
+            let target_key = match target {
+                TargetLocation::Component(name) => format!("Component({})", name),
+                other => format!("{:?}", other),
+            };
+            println!(
+                "  {} ({} files, {} bytes):",
+                target_key,
+                stats.by_target_location[target],
+                stats.bytes_by_target_location[target]
+            );
             for file in files {
                 let file_type_icon = match file.file_type {
                     FileType::JsComponent => "🧩",
@@ -233,11 +898,17 @@ impl DependencyGraph {
                     FileType::OpaqueFile => "📄",
                 };
                 println!(
-                    "    {} {} ({})",
+                    "    {} {} ({:?})",
                     file_type_icon,
                     file.path.display(),
-                    format!("{:?}", file.file_type)
+                    file.file_type
                 );
+                if let Some(url) = &file.original_url {
+                    println!("      🔗 {}", url);
+                    for alias in &file.url_aliases {
+                        println!("      🔗 {} (alias)", alias);
+                    }
+                }
             }
         }
     }
@@ -280,7 +951,7 @@ impl DependencyGraph {
                     FileType::CssFile => "🎨",
                     FileType::OpaqueFile => "📄",
                 };
-                println!("    └─ 📎 \"{}\"", import_stmt);
+                println!("    └─ 📎 \"{}\" ({:?})", import_stmt, edge.weight().kind);
                 println!("      -> {} {}", target_icon, target_node.path.display());
             }
         }
@@ -294,30 +965,168 @@ impl DependencyGraph {
             .collect()
     }
 
-    /// Get all outgoing dependencies from a file.
-    /// Returns a vector of (target_file_path, import_statement) tuples.
-    pub fn get_file_dependencies(
-        &self,
+    /// Find output paths that more than one source file would be written to.
+    /// Returns a sorted list of (dist_path, source_paths) tuples, with the
+    /// source paths of each collision sorted as well.
+    pub fn find_dist_collisions(&self) -> Vec<(PathBuf, Vec<PathBuf>)> {
+        let mut by_dist_path: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+        // Merged duplicates share the dist path of their canonical copy on purpose
+        for file in self.all_files().filter(|f| f.canonical_dist_path.is_none()) {
+            if let Some(dist_path) = file.get_dist_path() {
+                by_dist_path
+                    .entry(dist_path)
+                    .or_default()
+                    .push(file.path.clone());
+            }
+        }
+
+        by_dist_path
+            .into_iter()
+            .filter(|(_, sources)| sources.len() > 1)
+            .map(|(dist_path, mut sources)| {
+                sources.sort();
+                (dist_path, sources)
+            })
+            .collect()
+    }
+
+    /// Place dependencies and assets in the same subdirectory structure they
+    /// have relative to the Firefox root, e.g. `dependencies/toolkit/content/widgets/vendor/lit.all.mjs`
+    /// instead of `dependencies/lit.all.mjs`. Files outside the Firefox root stay flat.
+    pub fn preserve_source_structure(&mut self, firefox_root: &Path) {
+        let root = file_utils::make_relative_to_cwd(&firefox_root.to_path_buf());
+
+        for node in self.graph.node_weights_mut() {
+            if !matches!(
+                node.target_location,
+                TargetLocation::Dependency | TargetLocation::Asset
+            ) {
+                continue;
+            }
+
+            let path = file_utils::make_relative_to_cwd(&node.path);
+            let Some(relative) = pathdiff::diff_paths(&path, &root) else {
+                continue;
+            };
+            if relative
+                .components()
+                .any(|c| matches!(c, std::path::Component::ParentDir))
+            {
+                continue;
+            }
+            node.dist_subdir = relative.parent().map(Path::to_path_buf);
+        }
+    }
+
+    /// Move assets that live inside a component's source folder into that
+    /// component's output folder, so they stay next to the component instead of
+    /// ending up in `assets/`. Imports of them are then component-relative.
+    pub fn place_component_local_assets(&mut self) {
+        let component_dirs: Vec<(PathBuf, String)> = self
+            .all_files()
+            .filter_map(|f| match &f.target_location {
+                TargetLocation::Component(name) => f.path.parent().map(|dir| {
+                    (
+                        file_utils::make_relative_to_cwd(&dir.to_path_buf()),
+                        name.clone(),
+                    )
+                }),
+                _ => None,
+            })
+            .collect();
+
+        for node in self.graph.node_weights_mut() {
+            if node.target_location != TargetLocation::Asset {
+                continue;
+            }
+            let path = file_utils::make_relative_to_cwd(&node.path);
+            if let Some((_, name)) = component_dirs.iter().find(|(dir, _)| path.starts_with(dir)) {
+                node.target_location = TargetLocation::Component(name.clone());
+            }
+        }
+    }
+
+    /// Mark a file as a duplicate of the file written to `canonical_dist_path`.
+    /// The file is not written itself, and imports of it resolve to the canonical copy.
+    pub fn mark_duplicate(
+        &mut self,
         file_path: &PathBuf,
-    ) -> Result<Vec<(PathBuf, String)>, DependencyGraphError> {
+        canonical_dist_path: PathBuf,
+    ) -> Result<(), DependencyGraphError> {
         let node_idx = self
             .path_to_index
             .get(file_path)
             .ok_or_else(|| DependencyGraphError::FileNotFound(file_path.clone()))?;
+        self.graph[*node_idx].canonical_dist_path = Some(canonical_dist_path);
+        Ok(())
+    }
 
-        let dependencies = self
-            .graph
-            .edges_directed(*node_idx, Direction::Outgoing)
-            .map(|edge| {
-                let target_node = &self.graph[edge.target()];
-                (
-                    target_node.path.clone(),
-                    edge.weight().import_statement.clone(),
-                )
-            })
-            .collect();
+    /// Set the file type and target location a `Classifier` chose for a file.
+    pub fn reclassify(
+        &mut self,
+        file_path: &PathBuf,
+        file_type: FileType,
+        target_location: TargetLocation,
+    ) -> Result<(), DependencyGraphError> {
+        let node_idx = self
+            .path_to_index
+            .get(file_path)
+            .ok_or_else(|| DependencyGraphError::FileNotFound(file_path.clone()))?;
+        let node = &mut self.graph[*node_idx];
+        node.file_type = file_type;
+        node.target_location = target_location;
+        node.classified = true;
+        Ok(())
+    }
+
+    /// Emit a component-linked stylesheet as a global stylesheet instead of
+    /// inlining it. Links to it are removed from the components' templates.
+    pub fn promote_to_global(&mut self, file_path: &PathBuf) -> Result<(), DependencyGraphError> {
+        let node_idx = self
+            .path_to_index
+            .get(file_path)
+            .ok_or_else(|| DependencyGraphError::FileNotFound(file_path.clone()))?;
+        let node = &mut self.graph[*node_idx];
+        node.target_location = TargetLocation::CssGlobal;
+        node.promoted = true;
+        Ok(())
+    }
 
-        Ok(dependencies)
+    /// Mark a file as going through the build's preprocessor.
+    pub fn mark_preprocessed(&mut self, file_path: &PathBuf) -> Result<(), DependencyGraphError> {
+        let node_idx = self
+            .path_to_index
+            .get(file_path)
+            .ok_or_else(|| DependencyGraphError::FileNotFound(file_path.clone()))?;
+        self.graph[*node_idx].preprocessed = true;
+        Ok(())
+    }
+
+    /// Override the output file name of a file, e.g. to resolve a dist path collision.
+    pub fn set_dist_file_name(
+        &mut self,
+        file_path: &PathBuf,
+        file_name: String,
+    ) -> Result<(), DependencyGraphError> {
+        let node_idx = self
+            .path_to_index
+            .get(file_path)
+            .ok_or_else(|| DependencyGraphError::FileNotFound(file_path.clone()))?;
+        self.graph[*node_idx].dist_file_name = Some(file_name);
+        Ok(())
+    }
+
+    /// Write a file under its own name again, undoing `set_dist_file_name`.
+    pub fn reset_dist_file_name(
+        &mut self,
+        file_path: &PathBuf,
+    ) -> Result<(), DependencyGraphError> {
+        let node_idx = self
+            .path_to_index
+            .get(file_path)
+            .ok_or_else(|| DependencyGraphError::FileNotFound(file_path.clone()))?;
+        self.graph[*node_idx].dist_file_name = None;
+        Ok(())
     }
 
     /// Helper function to get dependencies and compute relative paths for import replacement.
@@ -325,6 +1134,26 @@ impl DependencyGraph {
         &self,
         query_path: &PathBuf,
         relative_from_path: &PathBuf,
+    ) -> Result<HashMap<String, String>, DependencyGraphError> {
+        self.relative_replacements(query_path, relative_from_path, |_| true)
+    }
+
+    /// Like `get_dependencies_and_relative_paths`, but only for dependencies
+    /// referenced with one of the given kinds.
+    pub fn get_dependencies_and_relative_paths_of_kind(
+        &self,
+        query_path: &PathBuf,
+        relative_from_path: &PathBuf,
+        kinds: &[ImportKind],
+    ) -> Result<HashMap<String, String>, DependencyGraphError> {
+        self.relative_replacements(query_path, relative_from_path, |kind| kinds.contains(kind))
+    }
+
+    fn relative_replacements(
+        &self,
+        query_path: &PathBuf,
+        relative_from_path: &PathBuf,
+        include: impl Fn(&ImportKind) -> bool,
     ) -> Result<HashMap<String, String>, DependencyGraphError> {
         let current_file = self
             .get_file(relative_from_path)
@@ -334,18 +1163,22 @@ impl DependencyGraph {
             .get_dist_path()
             .ok_or_else(|| DependencyGraphError::FileNotFound(relative_from_path.clone()))?;
 
-        let dependencies = self.get_file_dependencies(query_path)?;
+        let node_idx = self
+            .path_to_index
+            .get(query_path)
+            .ok_or_else(|| DependencyGraphError::FileNotFound(query_path.clone()))?;
         let mut replacements = HashMap::new();
 
-        for (target_path, original_import) in dependencies {
-            let target_file = self
-                .get_file(&target_path)
-                .ok_or_else(|| DependencyGraphError::TargetFileNotFound(target_path.clone()))?;
-
+        for edge in self
+            .graph
+            .edges_directed(*node_idx, Direction::Outgoing)
+            .filter(|edge| include(&edge.weight().kind))
+        {
+            let target_file = &self.graph[edge.target()];
             if let Some(target_dist_path) = target_file.get_dist_path() {
                 let relative_path =
                     file_utils::compute_relative_path(&current_dist_path, &target_dist_path);
-                replacements.insert(original_import, relative_path);
+                replacements.insert(edge.weight().import_statement.clone(), relative_path);
             }
         }
 
@@ -361,6 +1194,16 @@ impl DependencyGraph {
         self.get_dependencies_and_relative_paths(file_path, file_path)
     }
 
+    /// Get the import replacements of a file for dependencies referenced with
+    /// one of the given kinds.
+    pub fn get_import_replacements_of_kind(
+        &self,
+        file_path: &PathBuf,
+        kinds: &[ImportKind],
+    ) -> Result<HashMap<String, String>, DependencyGraphError> {
+        self.get_dependencies_and_relative_paths_of_kind(file_path, file_path, kinds)
+    }
+
     /// Get all outgoing CSS file imports from a file.
     /// Returns a vector of (import_statement, css_file_path) tuples.
     pub(crate) fn get_css_imports(&self, path: &PathBuf) -> Vec<(String, PathBuf)> {
@@ -373,7 +1216,13 @@ impl DependencyGraph {
             .edges_directed(node_idx, Direction::Outgoing)
             .filter_map(|edge| {
                 let target_node = &self.graph[edge.target()];
-                if matches!(target_node.file_type, FileType::CssFile) {
+                // Classified stylesheets stay linked unless they are omitted
+                let linked =
+                    target_node.classified && target_node.target_location != TargetLocation::Omit;
+                if matches!(target_node.file_type, FileType::CssFile)
+                    && !target_node.promoted
+                    && !linked
+                {
                     Some((
                         edge.weight().import_statement.clone(),
                         target_node.path.clone(),
@@ -384,10 +1233,63 @@ impl DependencyGraph {
             })
             .collect()
     }
+
+    /// Get the file a file imports through an import statement of the given
+    /// kind.
+    pub(crate) fn get_import_target(
+        &self,
+        path: &PathBuf,
+        import_statement: &str,
+        kind: ImportKind,
+    ) -> Option<&FileNode> {
+        let node_idx = self.path_to_index.get(path)?;
+        self.graph
+            .edges_directed(*node_idx, Direction::Outgoing)
+            .find(|edge| {
+                edge.weight().kind == kind && edge.weight().import_statement == import_statement
+            })
+            .map(|edge| &self.graph[edge.target()])
+    }
+
+    /// Get the files importing a file, with the kind of each import.
+    pub(crate) fn get_importers(&self, path: &PathBuf) -> Vec<(&FileNode, ImportKind)> {
+        self.path_to_index.get(path).map_or_else(Vec::new, |&idx| {
+            self.graph
+                .edges_directed(idx, Direction::Incoming)
+                .map(|edge| (&self.graph[edge.source()], edge.weight().kind))
+                .collect()
+        })
+    }
+
+    /// Get the number of imports of a file by other files.
+    pub(crate) fn dependent_count(&self, path: &PathBuf) -> usize {
+        self.path_to_index.get(path).map_or(0, |&idx| {
+            self.graph.edges_directed(idx, Direction::Incoming).count()
+        })
+    }
+
+    /// Get the import statements through which a file links stylesheets that
+    /// were promoted to global stylesheets.
+    pub(crate) fn get_promoted_css_links(&self, path: &PathBuf) -> Vec<String> {
+        let Some(&node_idx) = self.path_to_index.get(path) else {
+            return vec![];
+        };
+
+        let mut links: Vec<String> = self
+            .graph
+            .edges_directed(node_idx, Direction::Outgoing)
+            .filter(|edge| self.graph[edge.target()].promoted)
+            .map(|edge| edge.weight().import_statement.clone())
+            .collect();
+        links.sort();
+        links.dedup();
+        links
+    }
 }
 
 /// Errors that can occur when working with the dependency graph.
 #[derive(Debug, thiserror::Error)]
+#[allow(clippy::enum_variant_names)]
 pub enum DependencyGraphError {
     /// File not found in the graph
     #[error("File not found in graph: {0}")]
@@ -398,6 +1300,23 @@ pub enum DependencyGraphError {
     /// Target file for dependency not found
     #[error("Cannot add dependency: target file '{0}' not found")]
     TargetFileNotFound(PathBuf),
+    /// No edge with the given import statement between two files
+    #[error("File '{0}' has no import '{1}' of the target")]
+    ImportNotFound(PathBuf, String),
+    /// Two merged graphs place files in different components
+    #[error(
+        "Files are claimed by two components: {}",
+        .0.iter()
+            .map(|(path, first, second)| {
+                format!("'{}' as both {first:?} and {second:?}", path.display())
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    )]
+    ConflictingTargets(Vec<(PathBuf, TargetLocation, TargetLocation)>),
+    /// The graph has a circular dependency through the file
+    #[error("Circular dependency through '{0}'")]
+    Cycle(PathBuf),
 }
 
 #[cfg(test)]
@@ -405,6 +1324,34 @@ mod tests {
     use super::*;
     use std::path::PathBuf;
 
+    impl DependencyGraph {
+        /// Get all outgoing dependencies from a file.
+        /// Returns a vector of (target_file_path, import_statement) tuples.
+        fn get_file_dependencies(
+            &self,
+            file_path: &PathBuf,
+        ) -> Result<Vec<(PathBuf, String)>, DependencyGraphError> {
+            let node_idx = self
+                .path_to_index
+                .get(file_path)
+                .ok_or_else(|| DependencyGraphError::FileNotFound(file_path.clone()))?;
+
+            let dependencies = self
+                .graph
+                .edges_directed(*node_idx, Direction::Outgoing)
+                .map(|edge| {
+                    let target_node = &self.graph[edge.target()];
+                    (
+                        target_node.path.clone(),
+                        edge.weight().import_statement.clone(),
+                    )
+                })
+                .collect();
+
+            Ok(dependencies)
+        }
+    }
+
     #[test]
     fn test_add_file_and_get_file() {
         let mut graph = DependencyGraph::new();
@@ -426,25 +1373,728 @@ mod tests {
         let b = PathBuf::from("b.js");
         graph.add_file(a.clone(), FileType::JsFile, TargetLocation::Dependency);
         graph.add_file(b.clone(), FileType::JsFile, TargetLocation::Dependency);
-        let edge = graph.add_dependency(&a, &b, "./b.js");
+        let edge = graph.add_dependency(&a, &b, "./b.js", ImportKind::StaticImport, None);
         assert!(edge.is_ok());
         assert!(!graph.has_cycles());
         // Add a cycle
-        let edge2 = graph.add_dependency(&b, &a, "./a.js");
+        let edge2 = graph.add_dependency(&b, &a, "./a.js", ImportKind::StaticImport, None);
         assert!(edge2.is_ok());
         assert!(graph.has_cycles());
     }
 
+    #[test]
+    fn test_statistics() {
+        let dir = tempfile::tempdir().unwrap();
+        let component = dir.path().join("moz-a.mjs");
+        let css = dir.path().join("moz-a.css");
+        let helper = dir.path().join("helper.mjs");
+        std::fs::write(&component, "export {};").unwrap();
+        std::fs::write(&css, ".a {}").unwrap();
+        std::fs::write(&helper, "export const a = 1;").unwrap();
+
+        let mut graph = DependencyGraph::new();
+        let moz_a = TargetLocation::Component("moz-a".to_string());
+        graph.add_file(component.clone(), FileType::JsComponent, moz_a.clone());
+        graph.add_file(css.clone(), FileType::CssFile, TargetLocation::Omit);
+        graph.add_file(helper.clone(), FileType::JsFile, TargetLocation::Dependency);
+        graph
+            .add_dependency(&component, &css, "./moz-a.css", ImportKind::HtmlLink, None)
+            .unwrap();
+        graph
+            .add_dependency(
+                &component,
+                &helper,
+                "./helper.mjs",
+                ImportKind::StaticImport,
+                None,
+            )
+            .unwrap();
+
+        let stats = graph.statistics();
+        assert_eq!(stats.node_count, 3);
+        assert_eq!(stats.edge_count, 2);
+        assert!(!stats.has_cycles);
+        assert_eq!(stats.roots, vec![component]);
+        assert_eq!(stats.by_file_type[&FileType::JsComponent], 1);
+        assert_eq!(stats.by_file_type[&FileType::CssFile], 1);
+        assert!(!stats.by_file_type.contains_key(&FileType::OpaqueFile));
+        assert_eq!(stats.by_target_location[&moz_a], 1);
+        assert_eq!(stats.bytes_by_target_location[&moz_a], 10);
+        assert_eq!(stats.bytes_by_target_location[&TargetLocation::Omit], 5);
+        assert_eq!(
+            stats.bytes_by_target_location[&TargetLocation::Dependency],
+            19
+        );
+    }
+
+    #[test]
+    fn test_compute_hashes_picks_up_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.mjs");
+        std::fs::write(&path, "export const a = 1;").unwrap();
+
+        let mut graph = DependencyGraph::new();
+        graph.add_file(path.clone(), FileType::JsFile, TargetLocation::Dependency);
+        assert_eq!(graph.get_file(&path).unwrap().content_hash, None);
+
+        graph.compute_hashes();
+        let first = graph.get_file(&path).unwrap().clone();
+        assert!(first.content_hash.is_some());
+        assert_eq!(first.size, Some(19));
+
+        std::fs::write(&path, "export const a = 22;").unwrap();
+        graph.compute_hashes();
+        let second = graph.get_file(&path).unwrap();
+        assert_ne!(second.content_hash, first.content_hash);
+        assert_eq!(second.size, Some(20));
+
+        let json = graph.to_json();
+        assert_eq!(json["files"][0]["size"], 20);
+        assert_eq!(
+            json["files"][0]["content_hash"],
+            format!("{:016x}", second.content_hash.unwrap())
+        );
+    }
+
+    #[test]
+    fn test_remove_file_keeps_other_lookups_intact() {
+        let mut graph = DependencyGraph::new();
+        let paths: Vec<PathBuf> = (0..5)
+            .map(|i| PathBuf::from(format!("file{}.mjs", i)))
+            .collect();
+        for path in &paths {
+            graph.add_file(path.clone(), FileType::JsFile, TargetLocation::Dependency);
+        }
+        for pair in paths.windows(2) {
+            graph
+                .add_dependency(
+                    &pair[0],
+                    &pair[1],
+                    &format!("./{}", pair[1].display()),
+                    ImportKind::StaticImport,
+                    None,
+                )
+                .unwrap();
+        }
+        graph
+            .add_dependency(
+                &paths[0],
+                &paths[4],
+                "./file4.mjs",
+                ImportKind::StaticImport,
+                None,
+            )
+            .unwrap();
+
+        // Remove a node from the middle, with Graph the last node would take its index
+        let removed = graph.remove_file(&paths[2]).unwrap();
+        assert_eq!(removed.path, paths[2]);
+        assert!(graph.get_file(&paths[2]).is_none());
+        assert!(graph.remove_file(&paths[2]).is_err());
+        assert_eq!(graph.file_count(), 4);
+        assert_eq!(graph.dependency_count(), 3);
+
+        for path in [&paths[0], &paths[1], &paths[3], &paths[4]] {
+            assert_eq!(&graph.get_file(path).unwrap().path, path);
+        }
+        let mut deps = graph.get_file_dependencies(&paths[0]).unwrap();
+        deps.sort();
+        assert_eq!(
+            deps,
+            vec![
+                (paths[1].clone(), "./file1.mjs".to_string()),
+                (paths[4].clone(), "./file4.mjs".to_string()),
+            ]
+        );
+        assert!(graph.get_file_dependencies(&paths[1]).unwrap().is_empty());
+        assert_eq!(
+            graph.get_file_dependencies(&paths[3]).unwrap(),
+            vec![(paths[4].clone(), "./file4.mjs".to_string())]
+        );
+
+        // New files and edges still land on the right nodes
+        let new_file = PathBuf::from("new.mjs");
+        graph.add_file(
+            new_file.clone(),
+            FileType::JsFile,
+            TargetLocation::Dependency,
+        );
+        graph
+            .add_dependency(
+                &paths[4],
+                &new_file,
+                "./new.mjs",
+                ImportKind::StaticImport,
+                None,
+            )
+            .unwrap();
+        assert_eq!(
+            graph.get_file_dependencies(&paths[4]).unwrap(),
+            vec![(new_file.clone(), "./new.mjs".to_string())]
+        );
+        assert_eq!(graph.get_file(&new_file).unwrap().path, new_file);
+    }
+
+    #[test]
+    fn test_parallel_edges_with_distinct_statements() {
+        let mut graph = DependencyGraph::new();
+        let component = PathBuf::from("moz-a/moz-a.mjs");
+        let css = PathBuf::from("moz-a/a.css");
+        graph.add_file(
+            component.clone(),
+            FileType::JsComponent,
+            TargetLocation::Component("moz-a".to_string()),
+        );
+        graph.add_file(css.clone(), FileType::CssFile, TargetLocation::CssGlobal);
+        graph
+            .add_dependency(&component, &css, "./a.css", ImportKind::StaticImport, None)
+            .unwrap();
+        graph
+            .add_dependency(
+                &component,
+                &css,
+                "chrome://global/skin/a.css",
+                ImportKind::StaticImport,
+                None,
+            )
+            .unwrap();
+
+        let replacements = graph.get_import_replacements(&component).unwrap();
+        assert_eq!(replacements.len(), 2);
+        assert_eq!(replacements["./a.css"], "../../styles/a.css");
+        assert_eq!(
+            replacements["chrome://global/skin/a.css"],
+            "../../styles/a.css"
+        );
+
+        // Only the edge with the given statement is updated
+        graph
+            .update_import_statement(&component, &css, "./a.css", "./a.css?v=2")
+            .unwrap();
+        let mut statements: Vec<String> = graph
+            .get_file_dependencies(&component)
+            .unwrap()
+            .into_iter()
+            .map(|(_, statement)| statement)
+            .collect();
+        statements.sort();
+        assert_eq!(
+            statements,
+            vec![
+                "./a.css?v=2".to_string(),
+                "chrome://global/skin/a.css".to_string()
+            ]
+        );
+        assert!(matches!(
+            graph.update_import_statement(&component, &css, "./a.css", "./b.css"),
+            Err(DependencyGraphError::ImportNotFound(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_identical_dependencies_are_added_once() {
+        let mut graph = DependencyGraph::new();
+        let component = PathBuf::from("moz-a/moz-a.mjs");
+        let icon = PathBuf::from("icons/a.svg");
+        graph.add_file(
+            component.clone(),
+            FileType::JsComponent,
+            TargetLocation::Component("moz-a".to_string()),
+        );
+        graph.add_file(icon.clone(), FileType::OpaqueFile, TargetLocation::Asset);
+
+        let url = "chrome://global/skin/icons/a.svg";
+        let first = graph
+            .add_dependency(
+                &component,
+                &icon,
+                url,
+                ImportKind::UrlLiteral,
+                Some(SourceSpan::new(10, 20)),
+            )
+            .unwrap();
+        let second = graph
+            .add_dependency(
+                &component,
+                &icon,
+                url,
+                ImportKind::UrlLiteral,
+                Some(SourceSpan::new(40, 50)),
+            )
+            .unwrap();
+        assert_eq!(first, second);
+        assert_eq!(graph.dependency_count(), 1);
+
+        // The same statement referenced in another way is a separate import
+        graph
+            .add_dependency(&component, &icon, url, ImportKind::TemplateAttribute, None)
+            .unwrap();
+        assert_eq!(graph.dependency_count(), 2);
+        graph
+            .add_dependency(&component, &icon, url, ImportKind::TemplateAttribute, None)
+            .unwrap();
+        assert_eq!(graph.dependency_count(), 2);
+    }
+
+    #[test]
+    fn test_import_replacements_of_kind() {
+        let mut graph = DependencyGraph::new();
+        let component = PathBuf::from("moz-a/moz-a.mjs");
+        let helper = PathBuf::from("shared/helper.mjs");
+        let icon = PathBuf::from("icons/close.svg");
+        graph.add_file(
+            component.clone(),
+            FileType::JsComponent,
+            TargetLocation::Component("moz-a".to_string()),
+        );
+        graph.add_file(helper.clone(), FileType::JsFile, TargetLocation::Dependency);
+        graph.add_file(icon.clone(), FileType::OpaqueFile, TargetLocation::Asset);
+        graph
+            .add_dependency(
+                &component,
+                &helper,
+                "chrome://global/content/helper.mjs",
+                ImportKind::StaticImport,
+                None,
+            )
+            .unwrap();
+        graph
+            .add_dependency(
+                &component,
+                &icon,
+                "chrome://global/skin/icons/close.svg",
+                ImportKind::TemplateAttribute,
+                None,
+            )
+            .unwrap();
+
+        let imports = graph
+            .get_import_replacements_of_kind(&component, &[ImportKind::StaticImport])
+            .unwrap();
+        assert_eq!(imports.len(), 1);
+        assert_eq!(
+            imports["chrome://global/content/helper.mjs"],
+            "../../dependencies/helper.mjs"
+        );
+
+        let urls = graph
+            .get_import_replacements_of_kind(
+                &component,
+                &[ImportKind::TemplateAttribute, ImportKind::UrlLiteral],
+            )
+            .unwrap();
+        assert_eq!(urls.len(), 1);
+        assert_eq!(
+            urls["chrome://global/skin/icons/close.svg"],
+            "../../assets/close.svg"
+        );
+        assert_eq!(graph.get_import_replacements(&component).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_component_subgraph_with_diamond_sharing() {
+        let mut graph = DependencyGraph::new();
+        let card = PathBuf::from("moz-card/moz-card.mjs");
+        let card_story = PathBuf::from("moz-card/moz-card.stories.mjs");
+        let button = PathBuf::from("moz-button/moz-button.mjs");
+        let left = PathBuf::from("shared/left.mjs");
+        let right = PathBuf::from("shared/right.mjs");
+        let lit = PathBuf::from("vendor/lit.all.mjs");
+        let button_only = PathBuf::from("shared/button-only.mjs");
+        for (path, name) in [
+            (&card, "moz-card"),
+            (&card_story, "moz-card"),
+            (&button, "moz-button"),
+        ] {
+            graph.add_file(
+                path.clone(),
+                FileType::JsComponent,
+                TargetLocation::Component(name.to_string()),
+            );
+        }
+        for path in [&left, &right, &lit, &button_only] {
+            graph.add_file(path.clone(), FileType::JsFile, TargetLocation::Dependency);
+        }
+        // moz-card -> left -> lit, moz-card -> right -> lit (diamond)
+        for (from, to) in [
+            (&card, &left),
+            (&card, &right),
+            (&left, &lit),
+            (&right, &lit),
+            (&button, &lit),
+            (&button, &button_only),
+        ] {
+            graph
+                .add_dependency(from, to, "./x.mjs", ImportKind::StaticImport, None)
+                .unwrap();
+        }
+
+        assert_eq!(
+            graph.components(),
+            vec!["moz-button".to_string(), "moz-card".to_string()]
+        );
+
+        let card_graph = graph.component_subgraph("moz-card");
+        let mut files: Vec<PathBuf> = card_graph.all_files().map(|f| f.path.clone()).collect();
+        files.sort();
+        let mut expected = vec![
+            card.clone(),
+            card_story.clone(),
+            left.clone(),
+            right.clone(),
+            lit.clone(),
+        ];
+        expected.sort();
+        assert_eq!(files, expected);
+        assert_eq!(card_graph.dependency_count(), 4);
+        assert_eq!(card_graph.components(), vec!["moz-card".to_string()]);
+        assert!(card_graph.get_file(&button_only).is_none());
+
+        // The shared dependency is part of both subgraphs
+        let button_graph = graph.component_subgraph("moz-button");
+        assert_eq!(button_graph.file_count(), 3);
+        assert!(button_graph.get_file(&lit).is_some());
+        assert_eq!(button_graph.dependency_count(), 2);
+
+        assert_eq!(graph.component_subgraph("moz-missing").file_count(), 0);
+    }
+
+    #[test]
+    fn test_transitive_dependencies_with_cycles() {
+        let mut graph = DependencyGraph::new();
+        let button = PathBuf::from("moz-button/moz-button.mjs");
+        let css = PathBuf::from("moz-button/moz-button.css");
+        let b = PathBuf::from("shared/b.mjs");
+        let a = PathBuf::from("shared/a.mjs");
+        let lit = PathBuf::from("vendor/lit.all.mjs");
+        let unrelated = PathBuf::from("shared/unrelated.mjs");
+        graph.add_file(
+            button.clone(),
+            FileType::JsComponent,
+            TargetLocation::Component("moz-button".to_string()),
+        );
+        graph.add_file(css.clone(), FileType::CssFile, TargetLocation::Omit);
+        for path in [&b, &a, &lit, &unrelated] {
+            graph.add_file(path.clone(), FileType::JsFile, TargetLocation::Dependency);
+        }
+        // moz-button -> b -> a -> b (cycle), a -> lit, a -> moz-button (cycle)
+        for (from, to, statement) in [
+            (&button, &b, "../shared/b.mjs"),
+            (&button, &css, "./moz-button.css"),
+            (&b, &a, "./a.mjs"),
+            (&a, &b, "./b.mjs"),
+            (&a, &lit, "chrome://global/content/vendor/lit.all.mjs"),
+            (&a, &button, "../moz-button/moz-button.mjs"),
+            (&unrelated, &lit, "./lit.all.mjs"),
+        ] {
+            graph
+                .add_dependency(from, to, statement, ImportKind::StaticImport, None)
+                .unwrap();
+        }
+
+        let files: Vec<&PathBuf> = graph
+            .transitive_dependencies("moz-button")
+            .into_iter()
+            .map(|f| &f.path)
+            .collect();
+        assert_eq!(files, vec![&css, &a, &b, &lit]);
+
+        let chains = graph.transitive_dependency_chains("moz-button");
+        let (node, chain) = &chains[3];
+        assert_eq!(node.path, lit);
+        assert_eq!(
+            chain,
+            &vec![
+                (button.clone(), "../shared/b.mjs".to_string()),
+                (b.clone(), "./a.mjs".to_string()),
+                (
+                    a.clone(),
+                    "chrome://global/content/vendor/lit.all.mjs".to_string()
+                ),
+            ]
+        );
+
+        assert!(graph.transitive_dependencies("moz-missing").is_empty());
+    }
+
     #[test]
     fn test_get_dist_path_omit() {
-        let node = FileNode {
-            path: PathBuf::from("foo.js"),
-            file_type: FileType::JsFile,
-            target_location: TargetLocation::Omit,
-        };
+        let node = FileNode::new(
+            PathBuf::from("foo.js"),
+            FileType::JsFile,
+            TargetLocation::Omit,
+        );
         assert_eq!(node.get_dist_path(), None);
     }
 
+    #[test]
+    fn test_find_dist_collisions_and_rename() {
+        let mut graph = DependencyGraph::new();
+        let a = PathBuf::from("a/constants.mjs");
+        let b = PathBuf::from("b/constants.mjs");
+        let user = PathBuf::from("user.mjs");
+        graph.add_file(a.clone(), FileType::JsFile, TargetLocation::Dependency);
+        graph.add_file(b.clone(), FileType::JsFile, TargetLocation::Dependency);
+        graph.add_file(user.clone(), FileType::JsFile, TargetLocation::Dependency);
+        graph
+            .add_dependency(
+                &user,
+                &b,
+                "./b/constants.mjs",
+                ImportKind::StaticImport,
+                None,
+            )
+            .unwrap();
+
+        let collisions = graph.find_dist_collisions();
+        assert_eq!(
+            collisions,
+            vec![(
+                PathBuf::from("dependencies/constants.mjs"),
+                vec![a.clone(), b.clone()]
+            )]
+        );
+
+        graph
+            .set_dist_file_name(&b, "constants-1234abcd.mjs".to_string())
+            .unwrap();
+        assert!(graph.find_dist_collisions().is_empty());
+        let replacements = graph.get_import_replacements(&user).unwrap();
+        assert_eq!(
+            replacements.get("./b/constants.mjs"),
+            Some(&"./constants-1234abcd.mjs".to_string())
+        );
+    }
+
+    #[test]
+    fn test_preserve_source_structure() {
+        let mut graph = DependencyGraph::new();
+        let root = PathBuf::from("firefox");
+        let component = root.join("toolkit/content/widgets/moz-card/moz-card.mjs");
+        let lit = root.join("toolkit/content/widgets/vendor/lit.all.mjs");
+        let helper = root.join("toolkit/content/widgets/vendor/deep/nested/helper.mjs");
+        let icon = root.join("toolkit/themes/shared/icons/close.svg");
+        let outside = PathBuf::from("elsewhere/shim.mjs");
+        graph.add_file(
+            component.clone(),
+            FileType::JsComponent,
+            TargetLocation::Component("moz-card".to_string()),
+        );
+        graph.add_file(lit.clone(), FileType::JsFile, TargetLocation::Dependency);
+        graph.add_file(helper.clone(), FileType::JsFile, TargetLocation::Dependency);
+        graph.add_file(icon.clone(), FileType::OpaqueFile, TargetLocation::Asset);
+        graph.add_file(
+            outside.clone(),
+            FileType::JsFile,
+            TargetLocation::Dependency,
+        );
+        graph
+            .add_dependency(
+                &component,
+                &lit,
+                "chrome://global/content/vendor/lit.all.mjs",
+                ImportKind::StaticImport,
+                None,
+            )
+            .unwrap();
+        graph
+            .add_dependency(
+                &component,
+                &icon,
+                "chrome://global/skin/icons/close.svg",
+                ImportKind::StaticImport,
+                None,
+            )
+            .unwrap();
+        graph
+            .add_dependency(
+                &lit,
+                &helper,
+                "./deep/nested/helper.mjs",
+                ImportKind::StaticImport,
+                None,
+            )
+            .unwrap();
+        graph
+            .add_dependency(
+                &helper,
+                &icon,
+                "chrome://global/skin/icons/close.svg",
+                ImportKind::StaticImport,
+                None,
+            )
+            .unwrap();
+        graph
+            .add_dependency(
+                &helper,
+                &outside,
+                "/elsewhere/shim.mjs",
+                ImportKind::StaticImport,
+                None,
+            )
+            .unwrap();
+
+        graph.preserve_source_structure(&root);
+
+        assert_eq!(
+            graph.get_file(&helper).unwrap().get_dist_path(),
+            Some(PathBuf::from(
+                "dependencies/toolkit/content/widgets/vendor/deep/nested/helper.mjs"
+            ))
+        );
+        assert_eq!(
+            graph.get_file(&outside).unwrap().get_dist_path(),
+            Some(PathBuf::from("dependencies/shim.mjs"))
+        );
+        // Components are not affected
+        assert_eq!(
+            graph.get_file(&component).unwrap().get_dist_path(),
+            Some(PathBuf::from("components/moz-card/moz-card.mjs"))
+        );
+
+        let component_imports = graph.get_import_replacements(&component).unwrap();
+        assert_eq!(
+            component_imports["chrome://global/content/vendor/lit.all.mjs"],
+            "../../dependencies/toolkit/content/widgets/vendor/lit.all.mjs"
+        );
+        assert_eq!(
+            component_imports["chrome://global/skin/icons/close.svg"],
+            "../../assets/toolkit/themes/shared/icons/close.svg"
+        );
+
+        let lit_imports = graph.get_import_replacements(&lit).unwrap();
+        assert_eq!(
+            lit_imports["./deep/nested/helper.mjs"],
+            "./deep/nested/helper.mjs"
+        );
+
+        let helper_imports = graph.get_import_replacements(&helper).unwrap();
+        assert_eq!(
+            helper_imports["chrome://global/skin/icons/close.svg"],
+            "../../../../../../../assets/toolkit/themes/shared/icons/close.svg"
+        );
+        assert_eq!(
+            helper_imports["/elsewhere/shim.mjs"],
+            "../../../../../../shim.mjs"
+        );
+    }
+
+    #[test]
+    fn test_place_component_local_assets() {
+        let mut graph = DependencyGraph::new();
+        let component = PathBuf::from("widgets/moz-a/moz-a.mjs");
+        let css = PathBuf::from("widgets/moz-a/moz-a.css");
+        let local_icon = PathBuf::from("widgets/moz-a/icon.svg");
+        let shared_icon = PathBuf::from("themes/icons/close.svg");
+        graph.add_file(
+            component.clone(),
+            FileType::JsComponent,
+            TargetLocation::Component("moz-a".to_string()),
+        );
+        graph.add_file(css.clone(), FileType::CssFile, TargetLocation::Omit);
+        graph.add_file(
+            local_icon.clone(),
+            FileType::OpaqueFile,
+            TargetLocation::Asset,
+        );
+        graph.add_file(
+            shared_icon.clone(),
+            FileType::OpaqueFile,
+            TargetLocation::Asset,
+        );
+        graph
+            .add_dependency(
+                &component,
+                &css,
+                "./moz-a.css",
+                ImportKind::StaticImport,
+                None,
+            )
+            .unwrap();
+        graph
+            .add_dependency(
+                &css,
+                &local_icon,
+                "./icon.svg",
+                ImportKind::StaticImport,
+                None,
+            )
+            .unwrap();
+        graph
+            .add_dependency(
+                &css,
+                &shared_icon,
+                "chrome://global/skin/icons/close.svg",
+                ImportKind::StaticImport,
+                None,
+            )
+            .unwrap();
+
+        graph.place_component_local_assets();
+
+        assert_eq!(
+            graph.get_file(&local_icon).unwrap().target_location,
+            TargetLocation::Component("moz-a".to_string())
+        );
+        assert_eq!(
+            graph.get_file(&shared_icon).unwrap().target_location,
+            TargetLocation::Asset
+        );
+        let css_replacements = graph
+            .get_dependencies_and_relative_paths(&css, &component)
+            .unwrap();
+        assert_eq!(css_replacements["./icon.svg"], "./icon.svg");
+        assert_eq!(
+            css_replacements["chrome://global/skin/icons/close.svg"],
+            "../../assets/close.svg"
+        );
+    }
+
+    #[test]
+    fn test_record_url_and_find_by_chrome_url() {
+        let mut graph = DependencyGraph::new();
+        let component = PathBuf::from("moz-a/moz-a.mjs");
+        let lit = PathBuf::from("vendor/lit.all.mjs");
+        graph.add_file(
+            component.clone(),
+            FileType::JsComponent,
+            TargetLocation::Component("moz-a".to_string()),
+        );
+        graph.add_file(lit.clone(), FileType::JsFile, TargetLocation::Dependency);
+
+        let url = "chrome://global/content/vendor/lit.all.mjs";
+        let alias = "resource://content-accessible/lit.all.mjs";
+        graph
+            .record_url(&lit, url, vec![alias.to_string()])
+            .unwrap();
+        // Importing through the alias later keeps the original URL
+        graph
+            .record_url(&lit, alias, vec![url.to_string()])
+            .unwrap();
+
+        let node = graph.get_file(&lit).unwrap();
+        assert_eq!(node.original_url.as_deref(), Some(url));
+        assert_eq!(node.url_aliases, vec![alias.to_string()]);
+        assert_eq!(graph.find_by_chrome_url(url).unwrap().path, lit);
+        assert_eq!(graph.find_by_chrome_url(alias).unwrap().path, lit);
+        assert!(
+            graph
+                .find_by_chrome_url("chrome://global/content/x.mjs")
+                .is_none()
+        );
+        assert!(graph.get_file(&component).unwrap().original_url.is_none());
+
+        let json = graph.to_json();
+        let files = json["files"].as_array().unwrap();
+        let lit_json = files
+            .iter()
+            .find(|f| f["path"] == "vendor/lit.all.mjs")
+            .unwrap();
+        assert_eq!(lit_json["original_url"], url);
+        assert_eq!(lit_json["url_aliases"][0], alias);
+        assert_eq!(lit_json["dist_path"], "dependencies/lit.all.mjs");
+    }
+
     #[test]
     fn test_get_import_replacements_empty() {
         let mut graph = DependencyGraph::new();
@@ -453,4 +2103,199 @@ mod tests {
         let replacements = graph.get_import_replacements(&path).unwrap();
         assert!(replacements.is_empty());
     }
+
+    #[test]
+    fn test_merge() {
+        let button = PathBuf::from("moz-button/moz-button.mjs");
+        let card = PathBuf::from("moz-card/moz-card.mjs");
+        let shared = PathBuf::from("shared/utils.mjs");
+        let icon = PathBuf::from("icons/close.svg");
+
+        let mut first = DependencyGraph::new();
+        first.add_file(
+            button.clone(),
+            FileType::JsComponent,
+            TargetLocation::Component("moz-button".to_string()),
+        );
+        first.add_file(shared.clone(), FileType::JsFile, TargetLocation::Omit);
+        first.add_file(icon.clone(), FileType::OpaqueFile, TargetLocation::Asset);
+        first
+            .add_dependency(
+                &button,
+                &icon,
+                "icons/close.svg",
+                ImportKind::TemplateAttribute,
+                None,
+            )
+            .unwrap();
+
+        let mut second = DependencyGraph::new();
+        second.add_file(
+            card.clone(),
+            FileType::JsComponent,
+            TargetLocation::Component("moz-card".to_string()),
+        );
+        second.add_file(shared.clone(), FileType::JsFile, TargetLocation::Dependency);
+        second.add_file(
+            button.clone(),
+            FileType::JsComponent,
+            TargetLocation::Dependency,
+        );
+        second.add_file(icon.clone(), FileType::OpaqueFile, TargetLocation::Asset);
+        second
+            .add_dependency(
+                &card,
+                &shared,
+                "../shared/utils.mjs",
+                ImportKind::StaticImport,
+                None,
+            )
+            .unwrap();
+        second
+            .add_dependency(
+                &card,
+                &button,
+                "../moz-button/moz-button.mjs",
+                ImportKind::StaticImport,
+                None,
+            )
+            .unwrap();
+        second
+            .add_dependency(
+                &button,
+                &icon,
+                "icons/close.svg",
+                ImportKind::TemplateAttribute,
+                None,
+            )
+            .unwrap();
+
+        first.merge(second).unwrap();
+        assert_eq!(first.file_count(), 4);
+        // The identical edge from both graphs is only added once
+        assert_eq!(first.dependency_count(), 3);
+        assert_eq!(
+            first.get_file(&button).unwrap().target_location,
+            TargetLocation::Component("moz-button".to_string())
+        );
+        assert_eq!(
+            first.get_file(&shared).unwrap().target_location,
+            TargetLocation::Dependency
+        );
+        assert_eq!(first.get_import_replacements(&card).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_merge_conflicting_components() {
+        let path = PathBuf::from("moz-button/moz-button.mjs");
+        let mut first = DependencyGraph::new();
+        first.add_file(
+            path.clone(),
+            FileType::JsComponent,
+            TargetLocation::Component("moz-button".to_string()),
+        );
+        let styles = PathBuf::from("moz-button/moz-button.css");
+        first.add_file(
+            styles.clone(),
+            FileType::CssFile,
+            TargetLocation::Component("moz-button".to_string()),
+        );
+        let mut second = DependencyGraph::new();
+        second.add_file(
+            path.clone(),
+            FileType::JsComponent,
+            TargetLocation::Component("button".to_string()),
+        );
+        second.add_file(
+            styles.clone(),
+            FileType::CssFile,
+            TargetLocation::Component("button".to_string()),
+        );
+        second.add_file(
+            PathBuf::from("other.mjs"),
+            FileType::JsFile,
+            TargetLocation::Dependency,
+        );
+
+        let err = first.merge(second).unwrap_err();
+        // Every conflicting file is reported, not just the first
+        let DependencyGraphError::ConflictingTargets(conflicts) = &err else {
+            panic!("{err}");
+        };
+        let mut paths: Vec<&PathBuf> = conflicts.iter().map(|(path, _, _)| path).collect();
+        paths.sort();
+        assert_eq!(paths, vec![&styles, &path]);
+        let err = err.to_string();
+        assert!(err.contains("Component(\"moz-button\")"), "{err}");
+        assert!(err.contains("Component(\"button\")"), "{err}");
+        // Nothing was merged
+        assert_eq!(first.file_count(), 2);
+    }
+
+    #[test]
+    fn test_files_by_type_and_target() {
+        let mut graph = DependencyGraph::new();
+        for (path, file_type, target_location) in [
+            (
+                "moz-a/moz-a.mjs",
+                FileType::JsComponent,
+                TargetLocation::Component("moz-a".to_string()),
+            ),
+            (
+                "moz-b/moz-b.mjs",
+                FileType::JsComponent,
+                TargetLocation::Component("moz-b".to_string()),
+            ),
+            (
+                "moz-b/icon.svg",
+                FileType::OpaqueFile,
+                TargetLocation::Component("moz-b".to_string()),
+            ),
+            ("tokens.css", FileType::CssFile, TargetLocation::CssGlobal),
+            ("utils.mjs", FileType::JsFile, TargetLocation::Dependency),
+        ] {
+            graph.add_file(PathBuf::from(path), file_type, target_location);
+        }
+
+        let sorted_paths = |files: Vec<&FileNode>| {
+            let mut paths: Vec<PathBuf> = files.into_iter().map(|f| f.path.clone()).collect();
+            paths.sort();
+            paths
+        };
+        assert_eq!(
+            sorted_paths(graph.files_by_type(FileType::JsComponent).collect()),
+            [
+                PathBuf::from("moz-a/moz-a.mjs"),
+                PathBuf::from("moz-b/moz-b.mjs")
+            ]
+        );
+        assert_eq!(graph.files_by_type(FileType::CssFile).count(), 1);
+        assert_eq!(
+            sorted_paths(graph.files_by_target(&TargetLocation::CssGlobal).collect()),
+            [PathBuf::from("tokens.css")]
+        );
+        assert_eq!(
+            sorted_paths(
+                graph
+                    .files_by_target(&TargetLocation::Component("moz-b".to_string()))
+                    .collect()
+            ),
+            [
+                PathBuf::from("moz-b/icon.svg"),
+                PathBuf::from("moz-b/moz-b.mjs")
+            ]
+        );
+        assert_eq!(graph.files_in_any_component().count(), 3);
+        assert_eq!(graph.files_by_target(&TargetLocation::Asset).count(), 0);
+
+        // Both are usable as map keys
+        let mut by_target: HashMap<TargetLocation, usize> = HashMap::new();
+        for file in graph.all_files() {
+            *by_target.entry(file.target_location.clone()).or_default() += 1;
+        }
+        assert_eq!(
+            by_target[&TargetLocation::Component("moz-b".to_string())],
+            2
+        );
+    }
 }
@@ -10,8 +10,12 @@ pub enum TransformError {
     CssParse { message: String },
     #[error("JavaScript parsing panicked")]
     JsPanicParse,
+    #[error("Failed to preprocess: {message}")]
+    Preprocess { message: String },
     #[error("Failed to transform CSS: {message}")]
     CssTransform { message: String },
+    #[error("Failed to transform JavaScript: {message}")]
+    JsTransform { message: String },
     #[error("URL '{url}' not found in replacement map")]
     UrlNotFound { url: String },
     #[error("Failed to serialize CSS: {message}")]
@@ -28,6 +32,8 @@ pub enum DependencyError {
     CssParse { message: String },
     #[error("JavaScript parsing panicked")]
     JsPanicParse,
+    #[error("Failed to preprocess: {message}")]
+    Preprocess { message: String },
     #[error("Failed to extract dependencies: {message}")]
     Extract { message: String },
 }
@@ -9,6 +9,8 @@ pub enum Error {
     GlobWalk(#[from] glob::GlobError),
     #[error("Custom error: {0}")]
     Custom(String),
+    #[error("Cancelled")]
+    Cancelled,
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -17,18 +19,265 @@ use std::{
     path::{Path, PathBuf},
 };
 
+mod cancellation;
+mod classifier;
+mod component_deps;
 mod dependencies;
 mod dependency_graph;
 mod errors;
+mod loader;
+mod pipeline;
+mod report;
+mod resolver;
+mod session;
 mod transform;
 mod utils;
 
-use dependency_graph::{DependencyGraph, FileType};
+pub use cancellation::CancellationToken;
+pub use classifier::Classifier;
+pub use component_deps::{ComponentDependencies, ImportStep, TransitiveDependency};
+pub use dependency_graph::{DependencyGraph, FileNode, FileType, GraphStatistics, TargetLocation};
+pub use pipeline::{PassInfo, PipelineExplanation};
+pub use report::{
+    BudgetBasis, BuildReport, CssWarning, DiscoveredJar, FileOutcome, FileReport, FileSizes,
+    MergedFiles, PrivilegedImport, RemovedCss, UnmappedCssUrl, UnresolvedImport,
+};
+pub use resolver::{PrefixResolver, Resolver};
+pub use session::Session;
+pub use transform::css::CssTargets;
+pub use transform::css_transform::{MozCleanup, UnknownCssUrl};
+pub use transform::js_transform::{LitCompat, PrivilegedImports};
+pub use utils::compression::Compression;
+pub use utils::jar_resolver::{IfdefUnknown, JarMapping};
+pub use utils::path_finder::{MissingFiles, ResolutionTrace};
+
+use dependencies::DependencyRef;
+use dependency_graph::ImportKind;
 use glob::glob;
+use transform::css::ImportConditions;
+use utils::external::ExternalSpecifiers;
+use utils::literals::LiteralFilter;
+use utils::preprocessor::Preprocessor;
 use utils::{file_utils, jar_resolver};
 
-use crate::{dependency_graph::TargetLocation, utils::path_finder::PathFinder};
+use crate::utils::path_finder::PathFinder;
 use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Options controlling how `transform_lib` processes files.
+#[derive(Debug, Clone)]
+pub struct TransformOptions {
+    /// Rename files that would be written to the same output path by appending
+    /// a short content hash, instead of failing with an error
+    pub rename_collisions: bool,
+    /// Reproduce the source directory structure (relative to the Firefox root)
+    /// for dependencies and assets instead of flattening them
+    pub preserve_structure: bool,
+    /// Compressed siblings (`.br`/`.gz`) to write next to text outputs
+    pub precompress: Vec<Compression>,
+    /// Minimum size in bytes for a text output to get compressed siblings
+    pub precompress_threshold: u64,
+    /// Maximum total output size in bytes, measured according to `budget_basis`
+    pub size_budget: Option<u64>,
+    /// Which size the size budget is evaluated against
+    pub budget_basis: BudgetBasis,
+    /// Merge dependencies and assets with byte-identical output into one file
+    pub dedupe: bool,
+    /// Keep assets that live in a component's folder in that component's
+    /// output folder instead of `assets/`
+    pub component_local_assets: bool,
+    /// Write the dependency graph as JSON to this path once all output paths
+    /// are final
+    pub graph_json: Option<PathBuf>,
+    /// lit version the generated imports target
+    pub lit_compat: LitCompat,
+    /// Emit stylesheets linked by components that only define `:root` custom
+    /// properties as global stylesheets instead of inlining them
+    pub promote_root_sheets: bool,
+    /// Leave global stylesheets that nothing references out of the output
+    pub prune_unused_globals: bool,
+    /// Glob patterns (relative to the Firefox root) of global stylesheets that
+    /// are consumed by the host app directly and never count as unused
+    pub keep_globals: Vec<String>,
+    /// Write a `loader.mjs` that imports components the first time their tag
+    /// appears in the document, as an alternative to importing all of them
+    pub loader: bool,
+    /// Glob patterns of import specifiers to leave untouched, e.g. for imports
+    /// a consuming bundler provides. They are not resolved or copied.
+    pub external: Vec<String>,
+    /// Accept TypeScript (`.ts`, `.mts`) component sources and imports. They
+    /// are emitted as `.mjs` modules with their types stripped.
+    pub typescript: bool,
+    /// Glob patterns of chrome:// and resource:// string literals that are
+    /// not dependencies, e.g. URLs that are only compared against
+    pub ignore_literals: Vec<String>,
+    /// Also parse every jar.mn file found below the Firefox root, in addition
+    /// to the listed ones
+    pub auto_discover_jars: bool,
+    /// How many directory levels below the Firefox root are searched for
+    /// jar.mn files
+    pub jar_discovery_depth: usize,
+    /// Glob patterns, relative to the Firefox root, of jar.mn files or
+    /// directories that discovery skips, `third_party` and `testing` by
+    /// default. Objdirs (`obj-*`) are always skipped.
+    pub jar_exclude: Vec<String>,
+    /// Directory for caches that are kept between runs: the discovered
+    /// jar.mn files and the parsed mappings of jar.mn files. Nothing is
+    /// cached if unset.
+    pub cache_dir: Option<PathBuf>,
+    /// chrome.manifest files, relative to the Firefox root, whose `content`,
+    /// `skin`, `locale`, `resource` and `override` lines are used to resolve
+    /// internal URLs, in addition to the registrations in jar.mn files
+    pub manifest_paths: Vec<String>,
+    /// Preprocessor conditions, e.g. `MOZ_DEV_EDITION`, in addition to or
+    /// overriding the defaults of a desktop build. They apply to jar.mn files
+    /// and to JS sources listed with `*` in them.
+    pub ifdef: HashMap<String, bool>,
+    /// How `#ifdef`/`#if` conditions of jar.mn files that aren't known are
+    /// evaluated. All unknown conditions are listed in one warning.
+    pub ifdef_unknown: IfdefUnknown,
+    /// Fail when jar.mn or moz.build files map the same URL to different
+    /// files, instead of warning and using the mapping parsed last
+    pub strict_mappings: bool,
+    /// Look for the files of chrome URLs that no jar.mn file maps in the
+    /// conventional source locations (`toolkit/content/`,
+    /// `toolkit/themes/shared/`, ...), with a warning naming the jar.mn file
+    /// to add. Several matching files are an error.
+    pub fallback_search: bool,
+    /// Prefixes of bare module specifiers (e.g. `toolkit-widgets`) mapped to
+    /// the directory, relative to the Firefox root, they resolve to. Relative
+    /// and chrome/resource imports take precedence.
+    pub module_roots: HashMap<String, String>,
+    /// Extensions tried, in order, when an import doesn't match a file on
+    /// disk: in place of the extension of the import, appended to imports
+    /// without one, and as `index` files of imported directories. An import
+    /// that matches several of them fails as ambiguous.
+    pub extension_fallbacks: Vec<String>,
+    /// What to do with imports that can't be resolved: fail the run, leave
+    /// them untouched, or write empty placeholders for them. They are listed
+    /// in the report either way.
+    pub missing_files: MissingFiles,
+    /// Fail when an import only matches a file when ignoring case, instead of
+    /// warning and using the file
+    pub strict_case: bool,
+    /// Resolve imports through symlinks, warning when one leads out of the
+    /// Firefox root. When false, paths are resolved lexically and a symlinked
+    /// file keeps its own path in the graph. Outputs are always regular files
+    /// with the contents of the link target.
+    pub follow_symlinks: bool,
+    /// Specifiers, or prefixes ending with `/`, replaced before resolution
+    /// with a file system path or, when they contain `://`, another
+    /// specifier
+    pub aliases: HashMap<String, String>,
+    /// What happens to `ChromeUtils.importESModule` and
+    /// `defineESModuleGetters` calls. Calls that are left untouched are
+    /// listed in the report.
+    pub privileged_imports: PrivilegedImports,
+    /// Replace `fetch()` calls of stylesheets with the transformed stylesheet
+    /// instead of rewriting their URL
+    pub inline_fetched_stylesheets: bool,
+    /// Inline the sheets global stylesheets `@import`, recursively, so each
+    /// global stylesheet is written as one self-contained file. Sheets only
+    /// global stylesheets import are not written on their own. A sheet
+    /// imported again under the same conditions is only inlined once.
+    pub flatten_imports: bool,
+    /// Browsers stylesheets must support, as a browserslist query or as
+    /// browsers mapped to their oldest version. Nesting and other modern
+    /// syntax are lowered for them. Stylesheets are kept as written if empty.
+    pub css_targets: CssTargets,
+    /// Minify written stylesheets and the stylesheets inlined into components
+    pub css_minify: bool,
+    /// Keep the comments written stylesheets start with, like their license
+    /// block. Stylesheets inlined into components name their license in the
+    /// comment that says where they're from instead.
+    pub css_license_headers: bool,
+    /// What happens to `url()` values of stylesheets that point at files
+    /// with no output. They are listed in the report unless the run fails.
+    pub unknown_css_url: UnknownCssUrl,
+    /// Remove the parts of stylesheets only Firefox understands, like
+    /// `-moz-` media features, pseudo-classes and properties. What was
+    /// removed is listed in the report.
+    pub moz_cleanup: Option<MozCleanup>,
+    /// Resolvers for specifiers mozcomp doesn't know about, like custom URL
+    /// schemes, consulted in order before the built-in rules
+    pub resolvers: Vec<Arc<dyn Resolver>>,
+    /// Add the steps that were attempted to resolve an import to the report
+    /// when it fails, see `resolve_import`
+    pub trace_resolution: bool,
+    /// Overrides the default classification of dependencies
+    pub classifier: Option<Classifier>,
+    /// Add a `data-mozcomp-src` attribute with the original URL to template
+    /// elements whose `src`/`iconsrc`/`href` gets rewritten, for debugging
+    pub trace_attributes: bool,
+    /// Stops the run with `Error::Cancelled` when cancelled
+    pub cancellation: CancellationToken,
+}
+
+impl Default for TransformOptions {
+    fn default() -> Self {
+        Self {
+            rename_collisions: false,
+            preserve_structure: false,
+            precompress: Vec::new(),
+            precompress_threshold: 1024,
+            size_budget: None,
+            budget_basis: BudgetBasis::Raw,
+            dedupe: false,
+            component_local_assets: true,
+            graph_json: None,
+            lit_compat: LitCompat::Lit3,
+            promote_root_sheets: false,
+            prune_unused_globals: false,
+            keep_globals: Vec::new(),
+            loader: false,
+            external: Vec::new(),
+            typescript: false,
+            ignore_literals: Vec::new(),
+            auto_discover_jars: false,
+            jar_discovery_depth: 8,
+            jar_exclude: vec!["third_party".to_string(), "testing".to_string()],
+            cache_dir: None,
+            manifest_paths: Vec::new(),
+            ifdef: HashMap::new(),
+            ifdef_unknown: IfdefUnknown::False,
+            strict_mappings: false,
+            fallback_search: false,
+            module_roots: HashMap::new(),
+            extension_fallbacks: vec![".mjs".to_string(), ".js".to_string(), ".css".to_string()],
+            missing_files: MissingFiles::Error,
+            strict_case: false,
+            follow_symlinks: true,
+            aliases: HashMap::new(),
+            privileged_imports: PrivilegedImports::Keep,
+            inline_fetched_stylesheets: false,
+            flatten_imports: false,
+            css_targets: CssTargets::default(),
+            css_minify: false,
+            css_license_headers: false,
+            unknown_css_url: UnknownCssUrl::Error,
+            moz_cleanup: None,
+            resolvers: Vec::new(),
+            trace_resolution: false,
+            classifier: None,
+            trace_attributes: false,
+            cancellation: CancellationToken::new(),
+        }
+    }
+}
+
+/// File in the output directory that exists while a run writes to it, and is
+/// left behind if the run fails or is cancelled.
+pub const INCOMPLETE_MARKER: &str = ".mozcomp-incomplete";
+
+/// The inputs of one config section: the JAR manifests to resolve internal
+/// URLs with, and the global stylesheets and components to start from.
+#[derive(Debug, Clone, Copy)]
+pub struct ConfigSection<'a> {
+    pub jar_paths: &'a [&'a str],
+    pub mozbuild_paths: &'a [&'a str],
+    pub global_stylesheets: &'a [&'a str],
+    pub component_paths: &'a [&'a str],
+}
 
 pub fn transform_lib(
     firefox_root: &Path,
@@ -37,72 +286,468 @@ pub fn transform_lib(
     mozbuild_paths: &[&str],
     global_stylesheets: &[&str],
     component_paths: &[&str],
-) -> Result<()> {
+    options: &TransformOptions,
+) -> Result<BuildReport> {
+    let section = ConfigSection {
+        jar_paths,
+        mozbuild_paths,
+        global_stylesheets,
+        component_paths,
+    };
+    transform_lib_multi(firefox_root, output_path, &[section], options)
+}
+
+/// Like `transform_lib`, but for several config sections. The dependency
+/// graph of each section is built with its own JAR mappings, and the graphs
+/// are merged before output paths are assigned and files are written.
+///
+/// Fails if two sections place the same file in different components.
+pub fn transform_lib_multi(
+    firefox_root: &Path,
+    output_path: &str,
+    sections: &[ConfigSection],
+    options: &TransformOptions,
+) -> Result<BuildReport> {
+    let mut session = Session::new(options.clone());
+    session.run(firefox_root, output_path, sections)?;
+    Ok(session.into_report())
+}
+
+/// Run discovery without writing anything and describe what would happen to a
+/// single file: its classification, the passes that apply to it, its import
+/// replacements and its dist path.
+///
+/// `file` is either a chrome:// / resource:// URL or a source path, relative to
+/// the current directory or the Firefox root.
+pub fn explain_file(
+    firefox_root: &Path,
+    sections: &[ConfigSection],
+    options: &TransformOptions,
+    file: &str,
+) -> Result<PipelineExplanation> {
+    let mut report = BuildReport::new();
+    let dep_graph = build_graph(firefox_root, sections, options, &mut report)?;
+
+    let node = if file.starts_with("chrome://") || file.starts_with("resource://") {
+        dep_graph.find_by_chrome_url(file)
+    } else {
+        let candidates: Vec<PathBuf> = [Path::new(file).to_path_buf(), firefox_root.join(file)]
+            .iter()
+            .filter_map(|p| p.canonicalize().ok())
+            .collect();
+        dep_graph
+            .all_files()
+            .find(|f| f.path.canonicalize().is_ok_and(|p| candidates.contains(&p)))
+    };
+    let node =
+        node.ok_or_else(|| Error::Custom(format!("{} is not part of the dependency graph", file)))?;
+
+    Ok(PipelineExplanation::new(
+        &dep_graph,
+        node,
+        firefox_root,
+        options,
+    ))
+}
+
+/// Run discovery without writing anything and list every file that ends up in
+/// `component`'s output through its imports, with the import chain that
+/// pulls it in.
+pub fn component_dependencies(
+    firefox_root: &Path,
+    sections: &[ConfigSection],
+    options: &TransformOptions,
+    component: &str,
+) -> Result<ComponentDependencies> {
+    let mut report = BuildReport::new();
+    let dep_graph = build_graph(firefox_root, sections, options, &mut report)?;
+    if !dep_graph.components().iter().any(|name| name == component) {
+        return Err(Error::Custom(format!(
+            "{} is not a component of the dependency graph",
+            component
+        )));
+    }
+    Ok(ComponentDependencies::new(
+        &dep_graph,
+        component,
+        firefox_root,
+    ))
+}
+
+/// Parse the jar.mn and moz.build files without processing any components and
+/// list their mappings whose URL starts with `prefix` and whose URL or source
+/// path contains `filter`, sorted by URL.
+pub fn jar_mappings(
+    firefox_root: &Path,
+    jar_paths: &[&str],
+    mozbuild_paths: &[&str],
+    options: &TransformOptions,
+    prefix: Option<&str>,
+    filter: Option<&str>,
+) -> Result<Vec<JarMapping>> {
+    let section = ConfigSection {
+        jar_paths,
+        mozbuild_paths,
+        global_stylesheets: &[],
+        component_paths: &[],
+    };
+    let jr = build_jar_resolver(firefox_root, &section, options)?;
+    let mappings = jr
+        .resolve_prefix(prefix.unwrap_or_default())
+        .into_iter()
+        .map(|(url, path)| JarMapping {
+            path: pipeline::display_path(&path, firefox_root),
+            origin: jr.mapping_origin(&url).unwrap_or_default().to_string(),
+            url,
+        })
+        .filter(|mapping| {
+            filter.is_none_or(|filter| {
+                mapping.url.contains(filter) || file_utils::to_slash(&mapping.path).contains(filter)
+            })
+        })
+        .collect();
+    Ok(mappings)
+}
+
+/// Resolve a single import of `file` with the JAR mappings and resolution
+/// options of a run, recording each step that was attempted.
+///
+/// `file` is relative to the current directory or the Firefox root; it
+/// doesn't have to exist for URLs and root-relative specifiers.
+pub fn resolve_import(
+    firefox_root: &Path,
+    jar_paths: &[&str],
+    mozbuild_paths: &[&str],
+    options: &TransformOptions,
+    file: &str,
+    specifier: &str,
+) -> Result<ResolutionTrace> {
+    let section = ConfigSection {
+        jar_paths,
+        mozbuild_paths,
+        global_stylesheets: &[],
+        component_paths: &[],
+    };
+    let jr = build_jar_resolver(firefox_root, &section, options)?;
+    let pf = build_path_finder(firefox_root, jr, options);
+    Ok(pf.explain(&input_path(firefox_root, file), specifier))
+}
+
+/// Look up the chrome or resource URL `file` is loaded with in the jar.mn and
+/// moz.build files, the first in sorted order if several URLs map to it.
+///
+/// `file` is relative to the current directory or the Firefox root.
+pub fn chrome_url_for_file(
+    firefox_root: &Path,
+    jar_paths: &[&str],
+    mozbuild_paths: &[&str],
+    options: &TransformOptions,
+    file: &str,
+) -> Result<Option<String>> {
+    let section = ConfigSection {
+        jar_paths,
+        mozbuild_paths,
+        global_stylesheets: &[],
+        component_paths: &[],
+    };
+    let jr = build_jar_resolver(firefox_root, &section, options)?;
+    Ok(jr
+        .chrome_url_for_path(&input_path(firefox_root, file))
+        .map(str::to_string))
+}
+
+/// A file given on the command line, relative to the current directory if it
+/// exists there and to the Firefox root otherwise.
+fn input_path(firefox_root: &Path, file: &str) -> PathBuf {
+    let file = Path::new(file);
+    if file.exists() {
+        file.to_path_buf()
+    } else {
+        firefox_root.join(file)
+    }
+}
+
+/// Discover all files, resolve their dependencies and decide their dist paths.
+fn build_graph(
+    firefox_root: &Path,
+    sections: &[ConfigSection],
+    options: &TransformOptions,
+    report: &mut BuildReport,
+) -> Result<DependencyGraph> {
+    let mut dep_graph = discover_sections(firefox_root, sections, options, report)?;
+    finalize_graph(firefox_root, &mut dep_graph, options, report)?;
+    Ok(dep_graph)
+}
+
+/// Build the dependency graph of every section with its own JAR mappings and
+/// merge them, without assigning final output paths yet.
+fn discover_sections(
+    firefox_root: &Path,
+    sections: &[ConfigSection],
+    options: &TransformOptions,
+    report: &mut BuildReport,
+) -> Result<DependencyGraph> {
+    let mut dep_graph = DependencyGraph::new();
+    for section in sections {
+        let section_graph = discover_graph(firefox_root, section, options, report)?;
+        dep_graph
+            .merge(section_graph)
+            .map_err(|e| Error::Custom(format!("Failed to merge config sections: {e}")))?;
+    }
+    Ok(dep_graph)
+}
+
+/// Parse the jar.mn, moz.build and chrome.manifest files of a section, and
+/// the discovered jar.mn files if discovery is enabled.
+fn build_jar_resolver(
+    firefox_root: &Path,
+    section: &ConfigSection,
+    options: &TransformOptions,
+) -> Result<jar_resolver::JarResolver> {
+    let discovered = if options.auto_discover_jars {
+        println!("Discovering jar.mn files...");
+        utils::jar_discovery::JarDiscovery {
+            max_depth: options.jar_discovery_depth,
+            exclude: options.jar_exclude.clone(),
+            cache_dir: options.cache_dir.clone(),
+        }
+        .discover(firefox_root)?
+    } else {
+        Vec::new()
+    };
+
     // Parse JAR mappings for chrome:// URL resolution
-    let jr = jar_resolver::JarResolver::new(firefox_root, jar_paths, mozbuild_paths, None)
-        .map_err(|e| Error::Custom(format!("Failed to parse JAR mappings: {e}")))?;
+    let jr = jar_resolver::JarResolver::new(
+        firefox_root,
+        jar_resolver::JarResolverOptions {
+            jar_paths: section.jar_paths,
+            discovered: &discovered,
+            mozbuild_paths: section.mozbuild_paths,
+            manifest_paths: &options.manifest_paths,
+            ifdef_config: Some(options.ifdef.clone()),
+            ifdef_unknown: options.ifdef_unknown,
+            cache_dir: options.cache_dir.as_deref(),
+        },
+    )
+    .map_err(|e| Error::Custom(format!("Failed to parse JAR mappings: {e}")))?;
+    let conflicts: Vec<String> = jr
+        .mapping_conflicts()
+        .iter()
+        .map(|conflict| {
+            format!(
+                "{} mapped to {} in {} and to {} in {}",
+                conflict.url,
+                conflict.first_path.display(),
+                conflict.first_origin,
+                conflict.second_path.display(),
+                conflict.second_origin
+            )
+        })
+        .collect();
+    if options.strict_mappings && !conflicts.is_empty() {
+        return Err(Error::Custom(format!(
+            "Conflicting URL mappings:\n  {}",
+            conflicts.join("\n  ")
+        )));
+    }
+    for conflict in &conflicts {
+        eprintln!("Warning: {conflict}");
+    }
 
-    let pf = utils::path_finder::PathFinder::new(jr);
+    Ok(jr)
+}
 
-    let output_dir = Path::new(output_path);
+/// Create the path finder that resolves imports with the options of a run.
+fn build_path_finder(
+    firefox_root: &Path,
+    jr: jar_resolver::JarResolver,
+    options: &TransformOptions,
+) -> PathFinder {
+    // TypeScript imports usually name the module they compile to, e.g.
+    // `./a.js` for `a.ts`
+    let mut extension_fallbacks = options.extension_fallbacks.clone();
+    if options.typescript {
+        for extension in [".ts", ".mts"] {
+            if !extension_fallbacks.iter().any(|e| e == extension) {
+                extension_fallbacks.push(extension.to_string());
+            }
+        }
+    }
+    let pf = PathFinder::new(jr, firefox_root)
+        .with_module_roots(&options.module_roots)
+        .with_extension_fallbacks(&extension_fallbacks)
+        .with_strict_case(options.strict_case)
+        .with_follow_symlinks(options.follow_symlinks)
+        .with_aliases(&options.aliases)
+        .with_resolvers(&options.resolvers)
+        .with_failure_traces(options.trace_resolution);
+    if options.fallback_search {
+        pf.with_fallback_search()
+    } else {
+        pf
+    }
+}
 
-    file_utils::ensure_directory_exists(output_dir)
-        .map_err(|e| Error::Custom(format!("Failed to ensure directory exists: {e}")))?;
-    file_utils::clear_directory(output_dir)
-        .map_err(|e| Error::Custom(format!("Failed to clear directory: {e}")))?;
+/// Build the dependency graph of a single config section, without assigning
+/// final output paths yet.
+fn discover_graph(
+    firefox_root: &Path,
+    section: &ConfigSection,
+    options: &TransformOptions,
+    report: &mut BuildReport,
+) -> Result<DependencyGraph> {
+    let jr = build_jar_resolver(firefox_root, section, options)?;
+    for jar in jr.discovered_jars() {
+        // Sections share the discovered files, report each one once
+        if !report.discovered_jars.iter().any(|j| j.path == jar.path) {
+            report.discovered_jars.push(jar.clone());
+        }
+    }
 
-    // Create output directories
-    file_utils::create_output_directories(output_dir)
-        .map_err(|e| Error::Custom(format!("Failed to create output directories: {e}")))?;
+    let pf = build_path_finder(firefox_root, jr, options);
+    let external = ExternalSpecifiers::new(&options.external)?;
+    let literals = LiteralFilter::new(&options.ignore_literals)?;
+    let preprocessor = Preprocessor::new(&options.ifdef, options.ifdef_unknown);
 
     // Initialize dependency graph
     let mut dep_graph = DependencyGraph::new();
 
     // Process components first
     println!("Processing components...");
-    process_components(firefox_root, component_paths, &mut dep_graph)?;
+    process_components(
+        firefox_root,
+        section.component_paths,
+        &mut dep_graph,
+        options.typescript,
+        &options.cancellation,
+    )?;
 
     // Process global stylesheets
     println!("Processing global stylesheets...");
-    process_global_stylesheets(firefox_root, global_stylesheets, &mut dep_graph)?;
+    process_global_stylesheets(
+        firefox_root,
+        section.global_stylesheets,
+        &mut dep_graph,
+        &options.cancellation,
+    )?;
+
+    // Components and global stylesheets are found by path, record the URLs
+    // they are known by like for imported files
+    record_entry_point_urls(&mut dep_graph, &pf)?;
 
     // Process all dependencies recursively
     println!("Processing dependencies...");
-    process_dependencies(&mut dep_graph, &pf)?;
-    dep_graph.debug_print();
+    process_dependencies(
+        &mut dep_graph,
+        &pf,
+        &external,
+        &literals,
+        &preprocessor,
+        options,
+        report,
+    )?;
+    pf.log_cache_statistics();
+    for alias in pf.aliases_hit() {
+        if !report.aliases_hit.contains(&alias) {
+            report.aliases_hit.push(alias);
+        }
+    }
+    report.aliases_hit.sort();
 
-    // Transform and write all files
-    println!("Transforming and writing files...");
-    transform_and_write_files(&mut dep_graph, &output_dir)?;
+    check_root_sheets(&mut dep_graph, options.promote_root_sheets)?;
 
-    Ok(())
+    Ok(dep_graph)
+}
+
+/// Prune unused global stylesheets and assign the final output path of every
+/// file in a (possibly merged) dependency graph.
+///
+/// Returns the outputs that were rendered to compare files, for the write
+/// phase to reuse.
+fn finalize_graph(
+    firefox_root: &Path,
+    dep_graph: &mut DependencyGraph,
+    options: &TransformOptions,
+    report: &mut BuildReport,
+) -> Result<RenderedOutputs> {
+    report.unused_globals = find_unused_globals(firefox_root, dep_graph, &options.keep_globals)?;
+    if options.prune_unused_globals {
+        for path in &report.unused_globals {
+            dep_graph
+                .remove_file(path)
+                .map_err(|e| Error::Custom(format!("Failed to prune global stylesheet: {e}")))?;
+        }
+        report.pruned_unused_globals = !report.unused_globals.is_empty();
+    }
+
+    if options.flatten_imports {
+        omit_flattened_imports(dep_graph)?;
+    }
+
+    if options.component_local_assets {
+        dep_graph.place_component_local_assets();
+    }
+
+    if options.preserve_structure {
+        dep_graph.preserve_source_structure(firefox_root);
+    }
+
+    // Files are compared by their output, so importers of different files
+    // with the same name must not render the same import first
+    let collisions = separate_dist_collisions(dep_graph)?;
+
+    let mut rendered = RenderedOutputs::new();
+    if options.dedupe {
+        println!("Deduplicating identical files...");
+        dedupe_outputs(dep_graph, report, options, &mut rendered)?;
+    }
+    // Globs can match the same sheet through different paths, never emit it twice
+    dedupe_global_stylesheets(dep_graph, report, options, &mut rendered)?;
+
+    // Make sure no two files are written to the same output path
+    name_dist_collisions(
+        dep_graph,
+        collisions,
+        options.rename_collisions,
+        &mut rendered,
+    )?;
+
+    Ok(rendered)
 }
 
 fn process_components(
     firefox_root: &Path,
     component_paths: &[&str],
     dep_graph: &mut DependencyGraph,
+    typescript: bool,
+    cancellation: &CancellationToken,
 ) -> Result<()> {
     for pattern in component_paths {
-        let full_pattern = firefox_root.join(pattern.trim_start_matches('/'));
-        let full_pattern_str = full_pattern.to_string_lossy();
-
-        let files: Vec<PathBuf> = glob(&full_pattern_str)
-            .map_err(Error::from)?
-            .filter_map(|r| r.ok())
-            .collect();
+        let files = glob_regular_files(firefox_root, pattern)?;
 
         for file_path in files {
+            cancellation.check()?;
             let file_name = file_path.file_name().and_then(|s| s.to_str()).unwrap_or("");
-            // Ignore .ts, .tsx, .css files
-            if file_name.ends_with(".ts") || file_name.ends_with(".css") {
+            // Ignore .css files, type declarations and, unless enabled,
+            // TypeScript sources
+            let is_typescript = file_name.ends_with(".ts") || file_name.ends_with(".mts");
+            if file_name.ends_with(".css")
+                || file_name.ends_with(".d.ts")
+                || file_name.ends_with(".d.mts")
+                || (is_typescript && !typescript)
+            {
                 continue;
             }
 
+            let module_name = file_name
+                .strip_suffix(".ts")
+                .or_else(|| file_name.strip_suffix(".mts"))
+                .map(|stem| format!("{stem}.mjs"))
+                .unwrap_or_else(|| file_name.to_string());
             let file_type =
-                if file_name.ends_with(".stories.mjs") || file_name.ends_with(".story.mjs") {
+                if module_name.ends_with(".stories.mjs") || module_name.ends_with(".story.mjs") {
                     FileType::JsFile
-                } else if file_name.ends_with(".mjs") {
+                } else if module_name.ends_with(".mjs") {
                     FileType::JsComponent
                 } else {
                     FileType::OpaqueFile
@@ -121,7 +766,7 @@ fn process_components(
                 })?;
 
             dep_graph.add_file(
-                file_path.clone(),
+                file_utils::make_relative_to_cwd(&file_path),
                 file_type,
                 TargetLocation::Component(component_name.to_string()),
             );
@@ -130,23 +775,42 @@ fn process_components(
     Ok(())
 }
 
+/// Set the original URL and aliases of every file in the graph that doesn't
+/// have one yet, from the URLs that map to its path.
+fn record_entry_point_urls(
+    dep_graph: &mut DependencyGraph,
+    path_finder: &PathFinder,
+) -> Result<()> {
+    let paths: Vec<PathBuf> = dep_graph
+        .all_files()
+        .filter(|f| f.original_url.is_none())
+        .map(|f| f.path.clone())
+        .collect();
+    for path in paths {
+        if let [url, aliases @ ..] = path_finder.urls_for_path(&path) {
+            dep_graph
+                .record_url(&path, url, aliases.to_vec())
+                .map_err(|e| Error::Custom(format!("Failed to record URL: {e}")))?;
+        }
+    }
+    Ok(())
+}
+
 fn process_global_stylesheets(
     firefox_root: &Path,
     stylesheet_paths: &[&str],
     dep_graph: &mut DependencyGraph,
+    cancellation: &CancellationToken,
 ) -> Result<()> {
     for pattern in stylesheet_paths {
-        let full_pattern = firefox_root.join(pattern.trim_start_matches('/'));
-        let full_pattern_str = full_pattern.to_string_lossy();
-
-        let files: Vec<PathBuf> = glob(&full_pattern_str)
-            .map_err(Error::from)?
-            .filter_map(|r| r.ok())
-            .collect();
+        let files = glob_regular_files(firefox_root, pattern)?;
 
         for file_path in files {
+            cancellation.check()?;
+            // Key the node like resolved imports, so sheets that other sheets
+            // import map to the same node
             dep_graph.add_file(
-                file_path.clone(),
+                file_utils::make_relative_to_cwd(&file_path),
                 FileType::CssFile,
                 TargetLocation::CssGlobal,
             );
@@ -155,82 +819,273 @@ fn process_global_stylesheets(
     Ok(())
 }
 
-fn process_dependencies(dep_graph: &mut DependencyGraph, path_finder: &PathFinder) -> Result<()> {
-    let mut processed: HashSet<PathBuf> = HashSet::new();
-    let mut to_process: Vec<dependency_graph::FileNode> = dep_graph.all_files().cloned().collect();
+/// Expand a glob pattern relative to the firefox root into a list of regular files.
+///
+/// Directories and other non-regular entries matched by the pattern are skipped
+/// (symlinks are followed). A pattern ending in `/` targets a directory, so the
+/// files directly inside each matched directory are returned instead.
+fn glob_regular_files(firefox_root: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
+    let targets_directory = pattern.ends_with('/');
+    let trimmed_pattern = pattern.trim_start_matches('/').trim_end_matches('/');
+    let full_pattern = firefox_root.join(trimmed_pattern);
+    let full_pattern_str = file_utils::to_slash(&full_pattern);
 
-    while let Some(file) = to_process.pop() {
-        if !processed.insert(file.path.clone()) {
-            // Already processed this file, skip to avoid cycles
-            continue;
+    let matches: Vec<PathBuf> = glob(&full_pattern_str)
+        .map_err(Error::from)?
+        .filter_map(|r| r.ok())
+        .collect();
+
+    let mut files = Vec::new();
+    for path in matches {
+        if targets_directory && path.is_dir() {
+            eprintln!(
+                "Warning: pattern '{}' matches the directory {:?}, only its direct files are included. Use an explicit glob like '{}/*' instead.",
+                pattern, path, trimmed_pattern
+            );
+            let mut entries: Vec<PathBuf> = std::fs::read_dir(&path)?
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .collect();
+            entries.sort();
+            for entry in entries {
+                if entry.is_file() {
+                    files.push(entry);
+                } else {
+                    log::debug!("Skipping non-regular file {:?} in {:?}", entry, path);
+                }
+            }
+        } else if path.is_file() {
+            files.push(path);
+        } else {
+            log::debug!(
+                "Skipping {:?} matched by pattern '{}': not a regular file",
+                path,
+                pattern
+            );
         }
+    }
+
+    Ok(files)
+}
+
+fn process_dependencies(
+    dep_graph: &mut DependencyGraph,
+    path_finder: &PathFinder,
+    external: &ExternalSpecifiers,
+    literals: &LiteralFilter,
+    preprocessor: &Preprocessor,
+    options: &TransformOptions,
+    report: &mut BuildReport,
+) -> Result<()> {
+    // Every file is queued at most once; `queued` also covers files that were
+    // already processed, which keeps cycles from being scanned again
+    let mut to_process: Vec<PathBuf> = dep_graph.all_files().map(|f| f.path.clone()).collect();
+    let mut queued: HashSet<PathBuf> = to_process.iter().cloned().collect();
+
+    while let Some(path) = to_process.pop() {
+        options.cancellation.check()?;
+
+        // Look the node up when it is scanned, since it may have been
+        // reclassified after it was queued
+        let Some((file_type, target_location)) = dep_graph
+            .get_file(&path)
+            .map(|f| (f.file_type.clone(), f.target_location.clone()))
+        else {
+            continue;
+        };
+        // Stories are the only plain JS files among the components
+        let stories = file_type == FileType::JsFile
+            && matches!(target_location, TargetLocation::Component(_));
 
-        let deps = match file.file_type {
+        let deps = match file_type {
             FileType::JsComponent | FileType::JsFile => {
-                dependencies::js::dependencies_from_file(&file.path).map_err(|e| {
+                let preprocessed = path_finder.is_preprocessed(&path);
+                if preprocessed {
+                    dep_graph
+                        .mark_preprocessed(&path)
+                        .map_err(|e| Error::Custom(format!("Failed to mark file: {e}")))?;
+                }
+                dependencies::js::dependencies_from_file(
+                    &path,
+                    literals,
+                    preprocessed.then_some(preprocessor),
+                    stories,
+                )
+                .map_err(|e| {
                     Error::Custom(format!(
                         "Failed to parse JS dependencies for {:?}: {}",
-                        file.path, e
-                    ))
-                })?
-            }
-            FileType::CssFile => {
-                dependencies::css::dependencies_from_file(&file.path).map_err(|e| {
-                    Error::Custom(format!(
-                        "Failed to parse CSS dependencies for {:?}: {}",
-                        file.path, e
+                        path, e
                     ))
                 })?
             }
+            FileType::CssFile => dependencies::css::dependencies_from_file(&path).map_err(|e| {
+                Error::Custom(format!(
+                    "Failed to parse CSS dependencies for {:?}: {}",
+                    path, e
+                ))
+            })?,
             _ => vec![],
         };
 
-        for dep in deps {
+        let deps: Vec<DependencyRef> = deps
+            .into_iter()
+            .filter(|dep| {
+                if external.is_external(&dep.specifier) {
+                    log::debug!(
+                        "Leaving external import '{}' in {:?} untouched",
+                        dep.specifier,
+                        path
+                    );
+                    return false;
+                }
+                // Only shims of privileged modules are copied, not the modules
+                if dep.kind == ImportKind::PrivilegedImport
+                    && options.privileged_imports == PrivilegedImports::Shim
+                    && !path_finder.has_alias(&dep.specifier)
+                {
+                    log::debug!(
+                        "No shim for privileged module '{}' in {:?}",
+                        dep.specifier,
+                        path
+                    );
+                    return false;
+                }
+                true
+            })
+            .collect();
+        let specifiers: Vec<&str> = deps.iter().map(|dep| dep.specifier.as_str()).collect();
+        let resolved_paths = path_finder.get_paths(&path, &specifiers);
+
+        for (
+            DependencyRef {
+                specifier: dep,
+                kind,
+                span,
+                references,
+            },
+            resolved_path,
+        ) in deps.into_iter().zip(resolved_paths)
+        {
             // Resolve the dependency path
-            let resolved_path = match path_finder.get_path(&file.path, &dep) {
+            let resolved_path = match resolved_path {
                 Ok(p) => p,
+                // URLs that don't name a file are left for the CSS transform,
+                // which keeps or strips them. Missing files are reported below.
+                Err(_)
+                    if kind == ImportKind::CssUrl
+                        && options.unknown_css_url != UnknownCssUrl::Error
+                        && !path_finder.names_file(&path, &dep) =>
+                {
+                    log::debug!(
+                        "Leaving unknown URL '{}' in {:?} to the transform",
+                        dep,
+                        path
+                    );
+                    continue;
+                }
                 Err(e) => {
+                    // Only read the source again to locate imports that failed
+                    let source = std::fs::read_to_string(&path).unwrap_or_default();
+                    let (line, column) = span.line_col(&source);
                     println!(
-                        "Failed to resolve path for dependency '{}': {:?}",
-                        &file.path.display(),
+                        "Failed to resolve '{}' imported by {}:{}:{}{}: {}",
+                        dep,
+                        path.display(),
+                        line,
+                        column,
+                        if references > 1 {
+                            format!(" (referenced {} times)", references)
+                        } else {
+                            String::new()
+                        },
                         e
                     );
+                    match options.missing_files {
+                        MissingFiles::Error => {}
+                        MissingFiles::Warn => dep_graph
+                            .record_unresolved_import(&path, &dep)
+                            .map_err(|e| Error::Custom(format!("Failed to record import: {e}")))?,
+                        MissingFiles::Stub => {
+                            let stub_path = stub_path(path_finder.firefox_root(), &path, &dep);
+                            let target_location =
+                                match stub_path.extension().and_then(|s| s.to_str()) {
+                                    Some(extension) if is_asset_extension(extension) => {
+                                        TargetLocation::Asset
+                                    }
+                                    _ => TargetLocation::Dependency,
+                                };
+                            dep_graph.add_stub(stub_path.clone(), target_location);
+                            dep_graph
+                                .add_dependency(&path, &stub_path, &dep, kind, Some(span))
+                                .map_err(|e| {
+                                    Error::Custom(format!("Failed to add dependency: {e}"))
+                                })?;
+                        }
+                    }
+                    report.unresolved_imports.push(UnresolvedImport {
+                        source: path.clone(),
+                        specifier: dep,
+                        line,
+                        column,
+                        reason: e,
+                        references,
+                    });
                     continue;
                 }
             };
 
-            // Determine file type and target location
-            let dep_file_type = match Path::new(&dep).extension().and_then(|s| s.to_str()) {
-                Some("css") => FileType::CssFile,
-                Some("js") | Some("mjs") => FileType::JsFile,
+            // Determine file type and target location from the file that was
+            // found, which may have another extension than the import
+            let extension = resolved_path.extension().and_then(|s| s.to_str());
+            let dep_file_type = match (&kind, extension) {
+                // Files referenced with `new URL()` are fetched, not imported
+                (ImportKind::ModuleUrl, _) => FileType::OpaqueFile,
+                (_, Some("js") | Some("mjs")) => FileType::JsFile,
+                (_, Some("ts") | Some("mts")) if options.typescript => FileType::JsFile,
+                // Workers without a module extension, like `.jsm` scripts,
+                // are copied as they are
+                (ImportKind::Worker, _) => FileType::OpaqueFile,
+                (_, Some("css")) => FileType::CssFile,
                 _ => FileType::OpaqueFile,
             };
 
-            let dep_target_location = match (
-                &file.file_type,
-                Path::new(&dep).extension().and_then(|s| s.to_str()),
-            ) {
+            let dep_target_location = match (&file_type, extension) {
+                _ if kind == ImportKind::ModuleUrl => TargetLocation::Asset,
+                _ if kind == ImportKind::Worker => TargetLocation::Dependency,
+                // Fetched at runtime, unlike linked stylesheets, which are
+                // inlined
+                _ if kind == ImportKind::FetchedStylesheet => TargetLocation::Dependency,
                 (FileType::JsComponent, Some("css")) => TargetLocation::Omit,
                 (FileType::JsFile, Some("css")) => TargetLocation::Omit,
-                (_, Some("png") | Some("jpg") | Some("jpeg") | Some("svg")) => {
-                    TargetLocation::Asset
-                }
+                // JSON modules are copied as they are, the import keeps its
+                // `type: "json"` attribute
+                (_, Some("json")) => TargetLocation::Dependency,
+                (_, Some(extension)) if is_asset_extension(extension) => TargetLocation::Asset,
                 _ => TargetLocation::Dependency,
             };
 
-            // Add file to dependency graph; if it is new, push to to_process
+            let classified = options.classifier.as_ref().and_then(|classifier| {
+                let importer = dep_graph.get_file(&path)?;
+                classifier.classify(importer, &dep)
+            });
+
+            // Add file to dependency graph; if it is new, queue it
             dep_graph.add_file(resolved_path.clone(), dep_file_type, dep_target_location);
             dep_graph
-                .add_dependency(&file.path, &resolved_path, &dep)
+                .add_dependency(&path, &resolved_path, &dep, kind, Some(span))
                 .map_err(|e| Error::Custom(format!("Failed to add dependency: {e}")))?;
+            if let Some((file_type, target_location)) = classified {
+                dep_graph
+                    .reclassify(&resolved_path, file_type, target_location)
+                    .map_err(|e| Error::Custom(format!("Failed to classify dependency: {e}")))?;
+            }
+            if path_finder.is_internal_url(&dep) {
+                dep_graph
+                    .record_url(&resolved_path, dep.trim(), path_finder.url_aliases(&dep))
+                    .map_err(|e| Error::Custom(format!("Failed to record URL: {e}")))?;
+            }
 
-            // Only process if not already processed and not already queued
-            if !processed.contains(&resolved_path)
-                && !to_process.iter().any(|f| f.path == resolved_path)
-            {
-                if let Some(node) = dep_graph.get_file(&resolved_path) {
-                    to_process.push(node.clone());
-                }
+            if queued.insert(resolved_path.clone()) {
+                to_process.push(resolved_path);
             }
         }
     }
@@ -238,97 +1093,4152 @@ fn process_dependencies(dep_graph: &mut DependencyGraph, path_finder: &PathFinde
     Ok(())
 }
 
-fn transform_and_write_files(dep_graph: &mut DependencyGraph, output_dir: &Path) -> Result<()> {
-    // get an iterator over all files in the dependency graph
-    let files = dep_graph
+/// Extensions of the images and fonts that go to the assets folder.
+const ASSET_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "svg", "woff", "woff2", "ttf", "otf"];
+
+/// Whether files with an extension go to the assets folder.
+fn is_asset_extension(extension: &str) -> bool {
+    ASSET_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+}
+
+/// Directory, relative to the current directory, that placeholders of
+/// unresolved chrome:// and resource:// URLs are put in. Nothing is written
+/// there.
+const STUB_DIR: &str = "mozcomp-stubs";
+
+/// Path of the placeholder for an unresolved import of `importer`: where a
+/// relative or root-relative specifier points to, or the URL's path under
+/// `STUB_DIR`.
+fn stub_path(firefox_root: &Path, importer: &Path, specifier: &str) -> PathBuf {
+    let specifier = specifier.trim();
+    let specifier = specifier.split(['?', '#']).next().unwrap_or(specifier);
+    if let Some((_, rest)) = specifier.split_once("://") {
+        return Path::new(STUB_DIR).join(rest);
+    }
+    let path = match specifier.strip_prefix('/') {
+        Some(root_relative) => firefox_root.join(root_relative),
+        None => importer.parent().unwrap_or(Path::new("")).join(specifier),
+    };
+    file_utils::normalize_path(&path)
+}
+
+/// Find stylesheets inlined into components that define `:root` custom
+/// properties. Those rules don't match anything inside a shadow root, so the
+/// properties never reach the page.
+///
+/// Sheets that only define such properties are promoted to global stylesheets
+/// when `promote` is set; otherwise, and for sheets mixing them with other
+/// rules, a warning is printed.
+fn check_root_sheets(dep_graph: &mut DependencyGraph, promote: bool) -> Result<()> {
+    let mut inlined_sheets: Vec<PathBuf> = dep_graph
         .all_files()
-        .filter(|f| f.target_location != TargetLocation::Omit);
+        .filter(|f| f.file_type == FileType::CssFile && f.target_location == TargetLocation::Omit)
+        .map(|f| f.path.clone())
+        .collect();
+    inlined_sheets.sort();
 
-    for file in files {
-        // Perform transformation and writing logic here
+    for path in inlined_sheets {
+        let scope = dependencies::css::root_scope_from_file(&path)
+            .map_err(|e| Error::Custom(format!("Failed to inspect CSS file {:?}: {}", path, e)))?;
+        match scope {
+            dependencies::css::RootScope::TokensOnly if promote => {
+                println!(
+                    "Promoting {} to a global stylesheet: it only defines :root custom properties",
+                    path.display()
+                );
+                dep_graph
+                    .promote_to_global(&path)
+                    .map_err(|e| Error::Custom(format!("Failed to promote stylesheet: {e}")))?;
+            }
+            dependencies::css::RootScope::TokensOnly => {
+                eprintln!(
+                    "Warning: {} only defines :root custom properties, which have no effect once inlined into a shadow root. Set promote_root_sheets to emit it as a global stylesheet.",
+                    path.display()
+                );
+            }
+            dependencies::css::RootScope::Mixed => {
+                eprintln!(
+                    "Warning: {} defines :root custom properties, which have no effect once inlined into a shadow root.",
+                    path.display()
+                );
+            }
+            dependencies::css::RootScope::None => {}
+        }
+    }
+    Ok(())
+}
 
-        let output_path = match file.get_dist_path() {
-            Some(path) => output_dir.join(path),
-            None => {
+/// Find global stylesheets that nothing imports and that don't define any
+/// custom property the inlined component stylesheets read. Sheets matched by
+/// the `keep` patterns are never reported.
+fn find_unused_globals(
+    firefox_root: &Path,
+    dep_graph: &DependencyGraph,
+    keep: &[String],
+) -> Result<Vec<PathBuf>> {
+    let mut kept: HashSet<PathBuf> = HashSet::new();
+    for pattern in keep {
+        for path in glob_regular_files(firefox_root, pattern)? {
+            kept.insert(path.canonicalize().unwrap_or(path));
+        }
+    }
+
+    let mut used_properties: HashSet<String> = HashSet::new();
+    for file in dep_graph.all_files() {
+        if file.file_type == FileType::CssFile && file.target_location == TargetLocation::Omit {
+            let properties =
+                dependencies::css::custom_properties_from_file(&file.path).map_err(|e| {
+                    Error::Custom(format!("Failed to inspect CSS file {:?}: {}", file.path, e))
+                })?;
+            used_properties.extend(properties.used);
+        }
+    }
+
+    let mut unused = Vec::new();
+    for file in dep_graph.files_by_target(&TargetLocation::CssGlobal) {
+        if dep_graph.dependent_count(&file.path) > 0 {
+            continue;
+        }
+        let canonical = file
+            .path
+            .canonicalize()
+            .unwrap_or_else(|_| file.path.clone());
+        if kept.contains(&canonical) {
+            continue;
+        }
+        let properties =
+            dependencies::css::custom_properties_from_file(&file.path).map_err(|e| {
+                Error::Custom(format!("Failed to inspect CSS file {:?}: {}", file.path, e))
+            })?;
+        if properties.defined.is_disjoint(&used_properties) {
+            unused.push(file.path.clone());
+        }
+    }
+    unused.sort();
+    Ok(unused)
+}
+
+/// Give every file that shares its output path with another file a
+/// provisional name derived from its source path, so no two files are
+/// compared or imported through the same path before duplicates are merged.
+/// Returns the original collisions for `name_dist_collisions`.
+fn separate_dist_collisions(
+    dep_graph: &mut DependencyGraph,
+) -> Result<Vec<(PathBuf, Vec<PathBuf>)>> {
+    let collisions = dep_graph.find_dist_collisions();
+    for (dist_path, sources) in &collisions {
+        for source in sources {
+            let file_name = hashed_file_name(dist_path, source.to_string_lossy().as_bytes());
+            dep_graph
+                .set_dist_file_name(source, file_name)
+                .map_err(|e| Error::Custom(format!("Failed to rename file: {e}")))?;
+        }
+    }
+    Ok(collisions)
+}
+
+/// Assign the final names of the files `separate_dist_collisions` renamed.
+///
+/// Of the files left at each output path after merging duplicates, the first
+/// keeps the original name. The others get a hash of their content in their
+/// name if `rename` is set, otherwise the build fails. Since import
+/// replacements are computed from the dist paths, dependents pick up the new
+/// names automatically, their outputs kept in `rendered` are dropped.
+/// Identical global stylesheets are merged before, so the error for those
+/// says how they differ.
+fn name_dist_collisions(
+    dep_graph: &mut DependencyGraph,
+    collisions: Vec<(PathBuf, Vec<PathBuf>)>,
+    rename: bool,
+    rendered: &mut RenderedOutputs,
+) -> Result<()> {
+    let collisions: Vec<(PathBuf, Vec<PathBuf>)> = collisions
+        .into_iter()
+        .map(|(dist_path, sources)| {
+            let remaining = sources
+                .into_iter()
+                .filter(|source| {
+                    dep_graph
+                        .get_file(source)
+                        .is_some_and(|file| file.canonical_dist_path.is_none())
+                })
+                .collect();
+            (dist_path, remaining)
+        })
+        .collect();
+
+    let conflicts: Vec<&(PathBuf, Vec<PathBuf>)> = collisions
+        .iter()
+        .filter(|(_, sources)| sources.len() > 1)
+        .collect();
+    if !rename && !conflicts.is_empty() {
+        let details: Vec<String> = conflicts
+            .iter()
+            .map(|(dist_path, sources)| {
+                let names: Vec<String> =
+                    sources.iter().map(|s| format!("{}", s.display())).collect();
+                let mut detail = format!("{} <- [{}]", dist_path.display(), names.join(", "));
+                let global = |source: &PathBuf| {
+                    dep_graph
+                        .get_file(source)
+                        .is_some_and(|file| file.target_location == TargetLocation::CssGlobal)
+                };
+                if sources.iter().all(global) {
+                    for other in &sources[1..] {
+                        detail.push_str(&format!(
+                            "\n    {} and {}: {}",
+                            sources[0].display(),
+                            other.display(),
+                            diff_summary(&sources[0], other)
+                        ));
+                    }
+                }
+                detail
+            })
+            .collect();
+        return Err(Error::Custom(format!(
+            "Multiple files map to the same output path (set rename_collisions to rename them):\n  {}",
+            details.join("\n  ")
+        )));
+    }
+
+    let mut taken: HashSet<PathBuf> = dep_graph
+        .all_files()
+        .filter_map(|f| f.get_dist_path())
+        .collect();
+
+    for (dist_path, sources) in collisions {
+        for (i, source) in sources.iter().enumerate() {
+            let Some(provisional) = dep_graph.get_file(source).and_then(|f| f.get_dist_path())
+            else {
                 continue;
+            };
+            if i == 0 {
+                dep_graph
+                    .reset_dist_file_name(source)
+                    .map_err(|e| Error::Custom(format!("Failed to rename file: {e}")))?;
+            } else {
+                let content = std::fs::read(source)?;
+                let mut file_name = hashed_file_name(&dist_path, &content);
+                // Identical content produces identical names, so mix in the source path
+                if taken.contains(&dist_path.with_file_name(&file_name)) {
+                    let mut salted = content.clone();
+                    salted.extend_from_slice(source.to_string_lossy().as_bytes());
+                    file_name = hashed_file_name(&dist_path, &salted);
+                }
+                println!(
+                    "Renaming {} to {} to avoid overwriting {}",
+                    source.display(),
+                    file_name,
+                    dist_path.display()
+                );
+                taken.insert(dist_path.with_file_name(&file_name));
+                dep_graph
+                    .set_dist_file_name(source, file_name)
+                    .map_err(|e| Error::Custom(format!("Failed to rename file: {e}")))?;
             }
-        };
+            forget_importers(dep_graph, rendered, source);
 
-        // Ensure the parent directory exists before writing/copying
-        if let Some(parent) = output_path.parent() {
-            std::fs::create_dir_all(parent).map_err(|e| {
-                Error::Custom(format!("Failed to create directory: {:?}: {e}", parent))
-            })?;
+            // Duplicates merged into the file point at its provisional path
+            let final_path = dep_graph
+                .get_file(source)
+                .and_then(|f| f.get_dist_path())
+                .unwrap_or_else(|| dist_path.clone());
+            let duplicates: Vec<PathBuf> = dep_graph
+                .all_files()
+                .filter(|f| f.canonical_dist_path.as_ref() == Some(&provisional))
+                .map(|f| f.path.clone())
+                .collect();
+            for duplicate in &duplicates {
+                dep_graph
+                    .mark_duplicate(duplicate, final_path.clone())
+                    .map_err(|e| Error::Custom(format!("Failed to merge duplicate file: {e}")))?;
+                forget_importers(dep_graph, rendered, duplicate);
+            }
         }
+    }
 
-        match file.file_type {
-            FileType::JsComponent | FileType::JsFile => {
-                let relative_imports = dep_graph.get_import_replacements(&file.path).unwrap();
+    Ok(())
+}
 
-                // if FileType::JsComponent, call dep_graph.get_omitted_imports(&file.path) and pass it as css_replacements, oterwise None
-                let css_replacements = if file.file_type == FileType::JsComponent
-                    || file.file_type == FileType::JsFile
-                {
-                    let omitted_imports = dep_graph.get_css_imports(&file.path);
+/// Summarize how two text files differ, by the first line that differs and
+/// the number of lines only one of them has.
+fn diff_summary(a: &Path, b: &Path) -> String {
+    let (Ok(a), Ok(b)) = (std::fs::read_to_string(a), std::fs::read_to_string(b)) else {
+        return "can't be compared".to_string();
+    };
+    let (a_lines, b_lines): (Vec<&str>, Vec<&str>) = (a.lines().collect(), b.lines().collect());
+    let Some(first) =
+        (0..a_lines.len().max(b_lines.len())).find(|&i| a_lines.get(i) != b_lines.get(i))
+    else {
+        return "same source, different output after rewriting".to_string();
+    };
+    let mut counts: HashMap<&str, isize> = HashMap::new();
+    for line in &a_lines {
+        *counts.entry(line).or_default() += 1;
+    }
+    for line in &b_lines {
+        *counts.entry(line).or_default() -= 1;
+    }
+    let only_a: isize = counts.values().filter(|&&n| n > 0).sum();
+    let only_b: isize = -counts.values().filter(|&&n| n < 0).sum::<isize>();
+    format!(
+        "differ from line {}, lines only in the first: {}, only in the second: {}",
+        first + 1,
+        only_a,
+        only_b
+    )
+}
+
+/// Build a file name with a short hash of `content` inserted before the extension,
+/// e.g. `constants.mjs` becomes `constants-1a2b3c4d.mjs`.
+fn hashed_file_name(path: &Path, content: &[u8]) -> String {
+    let hash = format!("{:016x}", file_utils::content_hash(content));
+    let short_hash = &hash[..8];
+
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    match path.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, short_hash, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, short_hash),
+    }
+}
+
+/// Content produced for a single output file.
+struct RenderedFile {
+    /// The bytes to write
+    content: Vec<u8>,
+    /// How the content was produced
+    outcome: FileOutcome,
+    /// lit-3-only exports imported by the source, see `LitCompat::Lit2`
+    lit3_only_imports: Vec<String>,
+    /// Stylesheets that were inlined into the file
+    inlined_stylesheets: Vec<PathBuf>,
+    /// Privileged modules the file still loads with `ChromeUtils`
+    privileged_imports: Vec<String>,
+    /// Parts of the stylesheets of the file that couldn't be parsed
+    css_warnings: Vec<CssWarning>,
+    /// URLs of the stylesheets of the file that point at files with no output
+    unmapped_css_urls: Vec<UnmappedCssUrl>,
+    /// Firefox-only parts of the stylesheets of the file that were removed
+    removed_css: Vec<RemovedCss>,
+}
+
+/// Outputs rendered before the write phase, by source path.
+type RenderedOutputs = HashMap<PathBuf, RenderedFile>;
+
+/// Drop the kept outputs of every file that imports `path`, directly or
+/// through other files, after the output path of `path` changed. Inlined
+/// stylesheets carry the paths of their imports into their importers.
+fn forget_importers(dep_graph: &DependencyGraph, rendered: &mut RenderedOutputs, path: &Path) {
+    let mut seen = HashSet::new();
+    let mut stack = vec![path.to_path_buf()];
+    while let Some(path) = stack.pop() {
+        for (importer, _) in dep_graph.get_importers(&path) {
+            if seen.insert(importer.path.clone()) {
+                rendered.remove(&importer.path);
+                stack.push(importer.path.clone());
+            }
+        }
+    }
+}
+
+/// Import kinds that JS transformers rewrite outside of import declarations.
+const JS_URL_KINDS: &[ImportKind] = &[
+    ImportKind::HtmlLink,
+    ImportKind::TemplateAttribute,
+    ImportKind::UrlLiteral,
+    ImportKind::ModuleUrl,
+    ImportKind::Worker,
+    ImportKind::FetchedStylesheet,
+];
+
+/// Transform a file (or read it, for files that are copied as is) and return
+/// the content that should be written to its dist path.
+fn render_file(
+    dep_graph: &DependencyGraph,
+    file: &dependency_graph::FileNode,
+    options: &TransformOptions,
+) -> Result<RenderedFile> {
+    if file.stub {
+        return Ok(RenderedFile {
+            content: Vec::new(),
+            outcome: FileOutcome::Generated,
+            lit3_only_imports: Vec::new(),
+            inlined_stylesheets: Vec::new(),
+            privileged_imports: Vec::new(),
+            css_warnings: Vec::new(),
+            unmapped_css_urls: Vec::new(),
+            removed_css: Vec::new(),
+        });
+    }
+    let external =
+        ExternalSpecifiers::new(&options.external)?.with_specifiers(&file.unresolved_imports);
+    let css_print = transform::css::CssPrintOptions {
+        minify: options.css_minify,
+        targets: transform::css::browser_targets(&options.css_targets)
+            .map_err(|e| Error::Custom(e.to_string()))?,
+        license_headers: options.css_license_headers,
+    };
+    match file.file_type {
+        FileType::JsComponent | FileType::JsFile => {
+            let import_replacements = dep_graph
+                .get_import_replacements_of_kind(
+                    &file.path,
+                    &[
+                        ImportKind::StaticImport,
+                        ImportKind::DynamicImport,
+                        ImportKind::PrivilegedImport,
+                    ],
+                )
+                .unwrap();
+            let url_replacements = dep_graph
+                .get_import_replacements_of_kind(&file.path, JS_URL_KINDS)
+                .unwrap();
+
+            // if FileType::JsComponent, call dep_graph.get_omitted_imports(&file.path) and pass it as css_replacements, oterwise None
+            let mut css_warnings = Vec::new();
+            let mut unmapped_css_urls = Vec::new();
+            let mut removed_css = Vec::new();
+            let mut css_licenses = HashMap::new();
+            let omitted_imports = dep_graph.get_css_imports(&file.path);
+            let css_replacements =
+                if file.file_type == FileType::JsComponent || file.file_type == FileType::JsFile {
                     // omitted imports is a Vec<(String, PathBuf)> of css files. We load the files, trnsform them like any other css file,
                     // and then return a HashMap<String, String> where the key is the original path and the value is the transformed CSS code.
                     let mut css_replacements = HashMap::new();
-                    for (original_path, css_path) in omitted_imports {
-                        let r_i = dep_graph
-                            .get_dependencies_and_relative_paths(&css_path, &file.path)
+                    for (original_path, css_path) in &omitted_imports {
+                        let css_urls = dep_graph
+                            .get_dependencies_and_relative_paths_of_kind(
+                                css_path,
+                                &file.path,
+                                &[ImportKind::CssUrl],
+                            )
+                            .unwrap();
+                        let css_imports = dep_graph
+                            .get_dependencies_and_relative_paths_of_kind(
+                                css_path,
+                                &file.path,
+                                &[ImportKind::CssImport],
+                            )
                             .unwrap();
-                        let css_code = transform::css::transform_from_file(&css_path, &r_i)
-                            .map_err(|e| {
-                                Error::Custom(format!(
-                                    "Failed to transform CSS file: {:?}: {}",
-                                    css_path, e
-                                ))
-                            })?;
-                        css_replacements.insert(original_path, css_code);
+                        let css_external = match dep_graph.get_file(css_path) {
+                            Some(css_file) => external
+                                .clone()
+                                .with_specifiers(&css_file.unresolved_imports),
+                            None => external.clone(),
+                        };
+                        let transformed_css = transform::css::transform_from_file(
+                            css_path,
+                            &css_urls,
+                            &css_imports,
+                            &css_external,
+                            options.unknown_css_url,
+                            options.moz_cleanup.as_ref(),
+                            // The header is reduced to the license, see below
+                            transform::css::CssPrintOptions {
+                                license_headers: false,
+                                ..css_print
+                            },
+                        )
+                        .map_err(|e| {
+                            Error::Custom(format!(
+                                "Failed to transform CSS file: {:?}: {}",
+                                css_path, e
+                            ))
+                        })?;
+                        css_warnings.extend(stylesheet_warnings(css_path, &transformed_css));
+                        unmapped_css_urls
+                            .extend(unmapped_urls(css_path, &transformed_css.unknown_urls));
+                        removed_css.extend(removed_parts(css_path, &transformed_css.removed));
+                        if let Some(license) = transformed_css
+                            .header
+                            .as_deref()
+                            .filter(|_| options.css_license_headers)
+                            .and_then(transform::css::header_license)
+                        {
+                            css_licenses.insert(original_path.clone(), license);
+                        }
+                        css_replacements.insert(original_path.clone(), transformed_css.code);
                     }
                     Some(css_replacements)
                 } else {
                     None
                 };
 
-                let transformed_code = transform::js::transform_from_file(
-                    &file.path,
-                    &relative_imports,
-                    css_replacements.as_ref(),
-                )
-                .map_err(|e| {
-                    Error::Custom(format!(
-                        "Failed to transform JS file: {:?}: {}",
-                        file.path, e
-                    ))
-                })?;
-                std::fs::write(&output_path, transformed_code).map_err(|e| {
-                    Error::Custom(format!("Failed to write JS file: {:?}: {e}", file.path))
-                })?;
-            }
-            FileType::CssFile => {
-                let relative_imports = dep_graph.get_import_replacements(&file.path).unwrap();
-                let transformed_code =
-                    transform::css::transform_from_file(&file.path, &relative_imports).map_err(
-                        |e| {
-                            Error::Custom(format!(
-                                "Failed to transform CSS file: {:?}: {e}",
-                                file.path
-                            ))
-                        },
-                    )?;
-                std::fs::write(&output_path, transformed_code).map_err(|e| {
-                    Error::Custom(format!("Failed to write CSS file: {:?}: {e}", file.path))
-                })?;
-            }
-            _ => {
-                // other files are copied as is
-                std::fs::copy(&file.path, &output_path).map_err(|e| {
-                    Error::Custom(format!("Failed to copy file: {:?}: {e}", file.path))
-                })?;
+            let promoted_links = dep_graph.get_promoted_css_links(&file.path);
+            let preprocessor = file
+                .preprocessed
+                .then(|| Preprocessor::new(&options.ifdef, options.ifdef_unknown));
+
+            let stylesheets =
+                css_replacements
+                    .as_ref()
+                    .map(|code| transform::js::InlineStylesheets {
+                        code,
+                        licenses: &css_licenses,
+                        promoted_links: &promoted_links,
+                    });
+            let transformed = transform::js::transform_from_file(
+                &file.path,
+                &import_replacements,
+                &url_replacements,
+                stylesheets.as_ref(),
+                &external,
+                transform::js::JsTransformOptions {
+                    lit_compat: options.lit_compat,
+                    privileged_imports: options.privileged_imports,
+                    inline_fetched_stylesheets: options.inline_fetched_stylesheets,
+                    trace_attributes: options.trace_attributes,
+                },
+                preprocessor.as_ref(),
+            )
+            .map_err(|e| {
+                Error::Custom(format!(
+                    "Failed to transform JS file: {:?}: {}",
+                    file.path, e
+                ))
+            })?;
+            let outcome = if transformed.passthrough {
+                FileOutcome::Passthrough
+            } else {
+                FileOutcome::Transformed
+            };
+            let inlined_stylesheets = omitted_imports
+                .into_iter()
+                .filter(|(href, _)| transformed.inlined_stylesheets.contains(href))
+                .map(|(_, css_path)| css_path)
+                .collect();
+            Ok(RenderedFile {
+                content: transformed.code.into_bytes(),
+                outcome,
+                lit3_only_imports: transformed.lit3_only_imports,
+                inlined_stylesheets,
+                privileged_imports: transformed.unhandled_privileged_imports,
+                css_warnings,
+                unmapped_css_urls,
+                removed_css,
+            })
+        }
+        FileType::CssFile if flattens_imports(file, options) => {
+            let mut flattener = Flattener {
+                dep_graph,
+                entry: &file.path,
+                external: ExternalSpecifiers::new(&options.external)?,
+                unknown_css_url: options.unknown_css_url,
+                moz_cleanup: options.moz_cleanup.as_ref(),
+                stack: Vec::new(),
+                conditions: Vec::new(),
+                inlined: Vec::new(),
+                inlined_under: HashSet::new(),
+                kept_imports: Vec::new(),
+                warnings: Vec::new(),
+                unmapped_css_urls: Vec::new(),
+                removed_css: Vec::new(),
+                header: None,
+            };
+            let (layer_statements, rules) = flattener.inline(&file.path)?;
+            let mut css = layer_statements;
+            for rule in &flattener.kept_imports {
+                css.push_str(rule);
+                css.push('\n');
             }
+            css.push_str(&rules);
+            let code = transform::css::print_from_string(&css, css_print).map_err(|e| {
+                Error::Custom(format!("Failed to flatten CSS file: {:?}: {e}", file.path))
+            })?;
+            let code = css_print.with_header(code, flattener.header.as_deref());
+            Ok(RenderedFile {
+                content: code.into_bytes(),
+                outcome: FileOutcome::Transformed,
+                lit3_only_imports: Vec::new(),
+                inlined_stylesheets: flattener.inlined,
+                privileged_imports: Vec::new(),
+                css_warnings: flattener.warnings,
+                unmapped_css_urls: flattener.unmapped_css_urls,
+                removed_css: flattener.removed_css,
+            })
         }
+        FileType::CssFile => {
+            let css_urls = dep_graph
+                .get_import_replacements_of_kind(&file.path, &[ImportKind::CssUrl])
+                .unwrap();
+            let css_imports = dep_graph
+                .get_import_replacements_of_kind(&file.path, &[ImportKind::CssImport])
+                .unwrap();
+            let transformed = transform::css::transform_from_file(
+                &file.path,
+                &css_urls,
+                &css_imports,
+                &external,
+                options.unknown_css_url,
+                options.moz_cleanup.as_ref(),
+                css_print,
+            )
+            .map_err(|e| {
+                Error::Custom(format!(
+                    "Failed to transform CSS file: {:?}: {e}",
+                    file.path
+                ))
+            })?;
+            let outcome = if transformed.verbatim {
+                FileOutcome::Copied
+            } else {
+                FileOutcome::Transformed
+            };
+            Ok(RenderedFile {
+                css_warnings: stylesheet_warnings(&file.path, &transformed),
+                unmapped_css_urls: unmapped_urls(&file.path, &transformed.unknown_urls),
+                removed_css: removed_parts(&file.path, &transformed.removed),
+                content: transformed.code.into_bytes(),
+                outcome,
+                lit3_only_imports: Vec::new(),
+                inlined_stylesheets: Vec::new(),
+                privileged_imports: Vec::new(),
+            })
+        }
+        _ => {
+            // other files are copied as is
+            let content = std::fs::read(&file.path)
+                .map_err(|e| Error::Custom(format!("Failed to read file: {:?}: {e}", file.path)))?;
+            Ok(RenderedFile {
+                content,
+                outcome: FileOutcome::Copied,
+                lit3_only_imports: Vec::new(),
+                inlined_stylesheets: Vec::new(),
+                privileged_imports: Vec::new(),
+                css_warnings: Vec::new(),
+                unmapped_css_urls: Vec::new(),
+                removed_css: Vec::new(),
+            })
+        }
+    }
+}
+
+/// Whether the imports of a stylesheet are inlined into it, see
+/// `TransformOptions::flatten_imports`.
+pub(crate) fn flattens_imports(
+    file: &dependency_graph::FileNode,
+    options: &TransformOptions,
+) -> bool {
+    options.flatten_imports
+        && file.file_type == FileType::CssFile
+        && file.target_location == TargetLocation::CssGlobal
+}
+
+/// Inlines the sheets a global stylesheet imports, see
+/// `TransformOptions::flatten_imports`.
+struct Flattener<'a> {
+    dep_graph: &'a DependencyGraph,
+    /// The global stylesheet, which URLs are made relative to
+    entry: &'a PathBuf,
+    external: ExternalSpecifiers,
+    unknown_css_url: UnknownCssUrl,
+    moz_cleanup: Option<&'a MozCleanup>,
+    /// The sheets being inlined, starting at the entry, to find cycles
+    stack: Vec<PathBuf>,
+    /// The conditions of the imports being inlined, starting at the entry
+    conditions: Vec<ImportConditions>,
+    /// Every sheet inlined so far
+    inlined: Vec<PathBuf>,
+    /// The sheets inlined so far with the conditions they were inlined
+    /// under
+    inlined_under: HashSet<(PathBuf, Vec<ImportConditions>)>,
+    /// Imports of sheets that aren't part of the graph, which are kept and
+    /// moved to the top of the output
+    kept_imports: Vec<String>,
+    warnings: Vec<CssWarning>,
+    unmapped_css_urls: Vec<UnmappedCssUrl>,
+    removed_css: Vec<RemovedCss>,
+    /// The comments the entry starts with, like its license block
+    header: Option<String>,
+}
+
+impl Flattener<'_> {
+    /// Get a sheet with its imports inlined, as its leading `@layer`
+    /// statements and its other rules.
+    fn inline(&mut self, path: &PathBuf) -> Result<(String, String)> {
+        let error = |e: &dyn std::fmt::Display| {
+            Error::Custom(format!("Failed to flatten CSS file: {:?}: {e}", path))
+        };
+        let unresolved = self
+            .dep_graph
+            .get_file(path)
+            .map(|file| file.unresolved_imports.as_slice())
+            .unwrap_or_default();
+        let external = self.external.clone().with_specifiers(unresolved);
+        let css_urls = self
+            .dep_graph
+            .get_dependencies_and_relative_paths_of_kind(path, self.entry, &[ImportKind::CssUrl])
+            .map_err(|e| error(&e))?;
+        let split = transform::css::split_imports_from_file(
+            path,
+            &css_urls,
+            &external,
+            self.unknown_css_url,
+            self.moz_cleanup,
+        )
+        .map_err(|e| error(&e))?;
+        self.unmapped_css_urls
+            .extend(unmapped_urls(path, &split.unknown_urls));
+        self.removed_css.extend(removed_parts(path, &split.removed));
+        if path == self.entry {
+            self.header = split.header.clone();
+        }
+        self.warnings
+            .extend(split.warnings.into_iter().map(|message| CssWarning {
+                source: path.clone(),
+                message,
+                verbatim: false,
+            }));
+
+        self.stack.push(path.clone());
+        let mut rules = String::new();
+        for import in &split.imports {
+            let Some(target) = self
+                .dep_graph
+                .get_import_target(path, &import.specifier, ImportKind::CssImport)
+                .filter(|target| target.file_type == FileType::CssFile && !target.stub)
+            else {
+                self.kept_imports.push(import.rule.clone());
+                continue;
+            };
+            if let Some(start) = self.stack.iter().position(|p| p == &target.path) {
+                let cycle: Vec<String> = self.stack[start..]
+                    .iter()
+                    .chain([&target.path])
+                    .map(|p| p.display().to_string())
+                    .collect();
+                return Err(Error::Custom(format!(
+                    "Stylesheets import each other and can't be flattened: {}",
+                    cycle.join(" -> ")
+                )));
+            }
+            self.conditions.push(import.conditions.clone());
+            if !self
+                .inlined_under
+                .insert((target.path.clone(), self.conditions.clone()))
+            {
+                log::debug!(
+                    "{:?} is already inlined into {:?} with the same conditions, skipping its import in {:?}",
+                    target.path,
+                    self.entry,
+                    path
+                );
+                self.conditions.pop();
+                continue;
+            }
+            if !self.inlined.contains(&target.path) {
+                self.inlined.push(target.path.clone());
+            }
+            let inlined = self.inline(&target.path);
+            self.conditions.pop();
+            let (layer_statements, content) = inlined?;
+            rules.push_str(&import.wrap(&(layer_statements + &content)));
+            rules.push('\n');
+        }
+        self.stack.pop();
+        rules.push_str(&split.body);
+        Ok((split.layer_statements, rules))
+    }
+}
+
+/// Leave the stylesheets out of the output that are only imported by global
+/// stylesheets, directly or through other such sheets, since flattening
+/// inlines them.
+fn omit_flattened_imports(dep_graph: &mut DependencyGraph) -> Result<()> {
+    let mut flattened: HashSet<PathBuf> = dep_graph
+        .files_by_target(&TargetLocation::CssGlobal)
+        .map(|f| f.path.clone())
+        .collect();
+    let mut omitted = Vec::new();
+    loop {
+        let found: Vec<PathBuf> = dep_graph
+            .files_by_target(&TargetLocation::Dependency)
+            .filter(|f| f.file_type == FileType::CssFile && !flattened.contains(&f.path))
+            .filter(|f| {
+                let importers = dep_graph.get_importers(&f.path);
+                !importers.is_empty()
+                    && importers.iter().all(|(importer, kind)| {
+                        *kind == ImportKind::CssImport && flattened.contains(&importer.path)
+                    })
+            })
+            .map(|f| f.path.clone())
+            .collect();
+        if found.is_empty() {
+            break;
+        }
+        flattened.extend(found.iter().cloned());
+        omitted.extend(found);
+    }
+    for path in omitted {
+        dep_graph
+            .reclassify(&path, FileType::CssFile, TargetLocation::Omit)
+            .map_err(|e| Error::Custom(format!("Failed to omit flattened stylesheet: {e}")))?;
+    }
+    Ok(())
+}
+
+/// The warnings of a transformed stylesheet, for the report.
+fn stylesheet_warnings(
+    source: &Path,
+    transformed: &transform::css::CssTransformOutput,
+) -> Vec<CssWarning> {
+    transformed
+        .warnings
+        .iter()
+        .map(|message| CssWarning {
+            source: source.to_path_buf(),
+            message: message.clone(),
+            verbatim: transformed.verbatim,
+        })
+        .collect()
+}
+
+/// The URLs of a transformed stylesheet without an output file, for the
+/// report.
+fn unmapped_urls(
+    source: &Path,
+    unknown_urls: &[transform::css_transform::UnknownUrl],
+) -> Vec<UnmappedCssUrl> {
+    unknown_urls
+        .iter()
+        .map(|unknown| UnmappedCssUrl {
+            source: source.to_path_buf(),
+            url: unknown.url.clone(),
+            stripped: unknown.stripped,
+        })
+        .collect()
+}
+
+fn removed_parts(source: &Path, removed: &[String]) -> Vec<RemovedCss> {
+    removed
+        .iter()
+        .map(|description| RemovedCss {
+            source: source.to_path_buf(),
+            description: description.clone(),
+        })
+        .collect()
+}
+
+/// Merge dependencies and assets whose transformed output is byte-identical.
+///
+/// The content is compared after transformation, since the same source can
+/// produce different output depending on where it is placed (and vice versa).
+/// Each group of identical files keeps the one with the smallest dist path as
+/// the canonical copy; the others are pointed at its dist path, so dependents
+/// import the canonical copy and the duplicates are not written.
+fn dedupe_outputs(
+    dep_graph: &mut DependencyGraph,
+    report: &mut BuildReport,
+    options: &TransformOptions,
+    rendered: &mut RenderedOutputs,
+) -> Result<()> {
+    merge_identical_outputs(
+        dep_graph,
+        report,
+        options,
+        rendered,
+        |file| {
+            matches!(
+                file.target_location,
+                TargetLocation::Dependency | TargetLocation::Asset
+            )
+        },
+        |dist_path| dist_path.clone(),
+    )
+}
+
+/// Merge global stylesheets whose transformed output is byte-identical, e.g.
+/// when two globs match the same sheet through different paths.
+///
+/// The sheet with the shortest dist path wins, ties are broken
+/// lexicographically. References to the dropped sheets resolve to the winner.
+fn dedupe_global_stylesheets(
+    dep_graph: &mut DependencyGraph,
+    report: &mut BuildReport,
+    options: &TransformOptions,
+    rendered: &mut RenderedOutputs,
+) -> Result<()> {
+    merge_identical_outputs(
+        dep_graph,
+        report,
+        options,
+        rendered,
+        |file| file.target_location == TargetLocation::CssGlobal,
+        |dist_path| (dist_path.as_os_str().len(), dist_path.clone()),
+    )
+}
+
+/// Group the files matched by `include` by their rendered content and merge
+/// each group into the file whose dist path sorts first by `rank`. The
+/// outputs are kept in `rendered`.
+fn merge_identical_outputs<K: Ord>(
+    dep_graph: &mut DependencyGraph,
+    report: &mut BuildReport,
+    options: &TransformOptions,
+    rendered: &mut RenderedOutputs,
+    include: impl Fn(&dependency_graph::FileNode) -> bool,
+    rank: impl Fn(&PathBuf) -> K,
+) -> Result<()> {
+    let mut files = Vec::new();
+    for file in dep_graph.all_files() {
+        if !include(file) || file.canonical_dist_path.is_some() {
+            continue;
+        }
+        let Some(dist_path) = file.get_dist_path() else {
+            continue;
+        };
+        if !rendered.contains_key(&file.path) {
+            rendered.insert(file.path.clone(), render_file(dep_graph, file, options)?);
+        }
+        files.push((dist_path, file.path.clone()));
+    }
+    let files = files
+        .into_iter()
+        .map(|(dist_path, source)| {
+            let content = rendered[&source].content.as_slice();
+            ((dist_path, source), content)
+        })
+        .collect();
+
+    let groups = group_identical(files, file_utils::content_hash);
+
+    for mut group in groups {
+        if group.len() < 2 {
+            continue;
+        }
+        group.sort_by_key(|(dist_path, source)| (rank(dist_path), source.clone()));
+        let (canonical_dist, canonical_source) = group.remove(0);
+        let duplicates: Vec<PathBuf> = group.into_iter().map(|(_, source)| source).collect();
+        for duplicate in &duplicates {
+            dep_graph
+                .mark_duplicate(duplicate, canonical_dist.clone())
+                .map_err(|e| Error::Custom(format!("Failed to merge duplicate file: {e}")))?;
+            // Importers of the duplicate were rendered with its own path
+            forget_importers(dep_graph, rendered, duplicate);
+        }
+        report.record_merged(canonical_source, duplicates);
     }
 
     Ok(())
 }
+
+/// Group items by equal content, in the order of their hashes. The hash only
+/// narrows down the candidates, items are grouped only if their bytes are equal.
+fn group_identical<T>(items: Vec<(T, &[u8])>, hash: impl Fn(&[u8]) -> u64) -> Vec<Vec<T>> {
+    use std::collections::BTreeMap;
+
+    let mut by_hash: BTreeMap<u64, Vec<(T, &[u8])>> = BTreeMap::new();
+    for (item, content) in items {
+        by_hash
+            .entry(hash(content))
+            .or_default()
+            .push((item, content));
+    }
+
+    let mut groups = Vec::new();
+    for (_, candidates) in by_hash {
+        let mut same_hash: Vec<(&[u8], Vec<T>)> = Vec::new();
+        for (item, content) in candidates {
+            match same_hash.iter_mut().find(|(c, _)| *c == content) {
+                Some((_, group)) => group.push(item),
+                None => same_hash.push((content, vec![item])),
+            }
+        }
+        groups.extend(same_hash.into_iter().map(|(_, group)| group));
+    }
+    groups
+}
+
+/// Write the output of every file, reusing the outputs `finalize_graph`
+/// already rendered.
+fn transform_and_write_files(
+    dep_graph: &mut DependencyGraph,
+    mut rendered_outputs: RenderedOutputs,
+    output_dir: &Path,
+    report: &mut BuildReport,
+    options: &TransformOptions,
+) -> Result<()> {
+    // Write the files a file imports before the file itself
+    let files: Vec<&dependency_graph::FileNode> = match dep_graph.topological_sort() {
+        Ok(mut files) => {
+            files.reverse();
+            files
+        }
+        Err(e) => {
+            eprintln!("Warning: {e}, transforming files in graph order");
+            dep_graph.all_files().collect()
+        }
+    };
+    let mut inlined_stylesheets = HashSet::new();
+
+    // skip duplicates that were merged into another file
+    let files = files
+        .into_iter()
+        .filter(|f| f.target_location != TargetLocation::Omit && f.canonical_dist_path.is_none());
+
+    for file in files {
+        options.cancellation.check()?;
+
+        let dist_path = match file.get_dist_path() {
+            Some(path) => path,
+            None => {
+                continue;
+            }
+        };
+        let output_path = output_dir.join(&dist_path);
+
+        // Ensure the parent directory exists before writing/copying
+        if let Some(parent) = output_path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| {
+                Error::Custom(format!("Failed to create directory: {:?}: {e}", parent))
+            })?;
+        }
+
+        let rendered = match rendered_outputs.remove(&file.path) {
+            Some(rendered) => rendered,
+            None => render_file(dep_graph, file, options)?,
+        };
+        if !rendered.lit3_only_imports.is_empty() {
+            eprintln!(
+                "Warning: {:?} imports lit-3-only exports that won't work with lit 2: {}",
+                file.path,
+                rendered.lit3_only_imports.join(", ")
+            );
+        }
+        // Its URLs point nowhere in the output
+        for warning in rendered.css_warnings.iter().filter(|w| w.verbatim) {
+            eprintln!(
+                "Warning: {} can't be parsed and is copied as it is, its URLs are not rewritten: {}",
+                warning.source.display(),
+                warning.message
+            );
+        }
+        // Outputs are always regular files with the contents of the source,
+        // even if it was reached through a symlink. Replace links left in the
+        // output directory rather than writing through them.
+        if output_path.is_symlink() {
+            std::fs::remove_file(&output_path).map_err(|e| {
+                Error::Custom(format!("Failed to remove symlink: {:?}: {e}", output_path))
+            })?;
+        }
+        std::fs::write(&output_path, &rendered.content)
+            .map_err(|e| Error::Custom(format!("Failed to write file: {:?}: {e}", file.path)))?;
+        let entry = report.record_file(
+            file.path.clone(),
+            dist_path,
+            rendered.outcome,
+            rendered.content.len() as u64,
+        );
+        entry.source_hash = file.content_hash;
+        entry.source_size = file.size;
+        entry.preprocessed = file.preprocessed;
+        inlined_stylesheets.extend(rendered.inlined_stylesheets);
+        for specifier in rendered.privileged_imports {
+            report.privileged_imports.push(PrivilegedImport {
+                source: file.path.clone(),
+                specifier,
+            });
+        }
+        for unmapped in &rendered.unmapped_css_urls {
+            eprintln!(
+                "Warning: {} references {}, which has no output file. {}",
+                unmapped.source.display(),
+                unmapped.url,
+                if unmapped.stripped {
+                    "The declaration was removed."
+                } else {
+                    "The URL was kept."
+                }
+            );
+        }
+        report.css_warnings.extend(rendered.css_warnings);
+        report.unmapped_css_urls.extend(rendered.unmapped_css_urls);
+        report.removed_css.extend(rendered.removed_css);
+    }
+
+    // A stylesheet is omitted because a component links it, but if the link
+    // in the template doesn't match the import, nothing inlines it and the
+    // component renders unstyled
+    let mut orphaned: Vec<PathBuf> = dep_graph
+        .files_by_target(&TargetLocation::Omit)
+        .filter(|f| {
+            f.file_type == FileType::CssFile
+                && !f.promoted
+                && !inlined_stylesheets.contains(&f.path)
+        })
+        .map(|f| f.path.clone())
+        .collect();
+    orphaned.sort();
+    if !orphaned.is_empty() {
+        eprintln!("Warning: stylesheets were omitted but never inlined into a component:");
+        for path in &orphaned {
+            eprintln!("  {}", path.display());
+        }
+    }
+    report.orphaned_stylesheets = orphaned;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Write a fixture tree of `(relative path, content)` entries below `root`.
+    fn write_tree(root: &Path, files: &[(&str, &str)]) {
+        for (path, content) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+    }
+
+    /// Run `transform_lib` on a fixture tree with the given component globs.
+    fn run_fixture(
+        root: &Path,
+        component_paths: &[&str],
+        options: &TransformOptions,
+    ) -> (tempfile::TempDir, BuildReport) {
+        let output = tempfile::tempdir().unwrap();
+        let report = transform_lib(
+            root,
+            output.path().to_str().unwrap(),
+            &[],
+            &[],
+            &[],
+            component_paths,
+            options,
+        )
+        .unwrap();
+        (output, report)
+    }
+
+    const COMPONENT_WITH_LOCAL_ICON: &[(&str, &str)] = &[
+        (
+            "widgets/moz-a/moz-a.mjs",
+            r#"import { html, MozLitElement } from "../vendor/lit.all.mjs";
+
+export default class MozA extends MozLitElement {
+  render() {
+    return html`<link rel="stylesheet" href="./moz-a.css" /><span></span>`;
+  }
+}
+"#,
+        ),
+        (
+            "widgets/moz-a/moz-a.css",
+            ".icon { background-image: url(\"./icon.svg\"); }\n",
+        ),
+        ("widgets/moz-a/icon.svg", "<svg/>"),
+        (
+            "widgets/vendor/lit.all.mjs",
+            "export const html = 1, css = 2, MozLitElement = class {};\n",
+        ),
+    ];
+
+    #[test]
+    fn test_explain_file_snapshot() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(root.path(), COMPONENT_WITH_LOCAL_ICON);
+
+        let explanation = explain_file(
+            root.path(),
+            &[ConfigSection {
+                jar_paths: &[],
+                mozbuild_paths: &[],
+                global_stylesheets: &[],
+                component_paths: &["widgets/moz-a/*.mjs"],
+            }],
+            &TransformOptions {
+                dedupe: true,
+                ..Default::default()
+            },
+            "widgets/moz-a/moz-a.mjs",
+        )
+        .unwrap();
+        assert_eq!(
+            explanation.to_string(),
+            r#"File: widgets/moz-a/moz-a.mjs
+File type: JsComponent
+Target location: Component("moz-a")
+Component: moz-a
+Graph passes:
+  [ ] promote-root-sheets (only applies to stylesheets inlined into components)
+  [ ] prune-unused-globals (only applies to global stylesheets)
+  [ ] component-local-assets (only applies to assets)
+  [ ] preserve-structure (only applies to dependencies and assets)
+  [ ] dedupe (only applies to dependencies and assets)
+  [ ] dedupe-global-stylesheets (only applies to global stylesheets)
+  [ ] rename-collisions (no dist path collision, default `rename_collisions` = false)
+Transform passes:
+  [ ] preprocess (not listed with `*` in a jar.mn file)
+  [ ] strip-types (not a TypeScript source)
+  [x] css-inline (imports 1 stylesheet(s))
+  [x] import-css (adds `css` to the lit.all.mjs import when stylesheets were inlined)
+  [x] url-rewrite (always runs on JS files)
+  [ ] external (default `external` = [])
+  [ ] privileged-imports (default `privileged_imports` = Keep)
+  [ ] fetched-stylesheet (default `inline_fetched_stylesheets` = false)
+  [x] module-url (always runs on JS files)
+  [x] icon-template-import (always runs on JS files, default `trace_attributes` = false)
+  [ ] lit-compat (default `lit_compat` = Lit3)
+  [x] import-attributes (runs on JS files that changed, keeps the import attributes of re-exports)
+  [ ] moz-cleanup (on inlined stylesheets, default `moz_cleanup` is unset)
+  [x] unknown-css-url (on inlined stylesheets, default `unknown_css_url` = Error)
+  [ ] css-targets (on inlined stylesheets, default `css_targets` is empty)
+  [ ] css-minify (on inlined stylesheets, default `css_minify` = false)
+  [ ] css-license-headers (on inlined stylesheets, default `css_license_headers` = false)
+Replacements:
+  ../vendor/lit.all.mjs -> ../../dependencies/lit.all.mjs
+Dist path: components/moz-a/moz-a.mjs
+"#
+        );
+
+        let dependency = explain_file(
+            root.path(),
+            &[ConfigSection {
+                jar_paths: &[],
+                mozbuild_paths: &[],
+                global_stylesheets: &[],
+                component_paths: &["widgets/moz-a/*.mjs"],
+            }],
+            &TransformOptions {
+                dedupe: true,
+                ..Default::default()
+            },
+            "widgets/vendor/lit.all.mjs",
+        )
+        .unwrap();
+        assert!(
+            dependency
+                .graph_passes
+                .iter()
+                .any(|p| p.name == "dedupe" && p.enabled && p.reason.starts_with("config option"))
+        );
+        assert_eq!(
+            dependency.dist_path,
+            Some(PathBuf::from("dependencies/lit.all.mjs"))
+        );
+    }
+
+    #[test]
+    fn test_component_local_assets_stay_in_component_folder() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(root.path(), COMPONENT_WITH_LOCAL_ICON);
+
+        let (output, _) = run_fixture(
+            root.path(),
+            &["widgets/moz-a/*.mjs"],
+            &TransformOptions::default(),
+        );
+        assert!(output.path().join("components/moz-a/icon.svg").exists());
+        assert!(!output.path().join("assets/icon.svg").exists());
+        let component =
+            fs::read_to_string(output.path().join("components/moz-a/moz-a.mjs")).unwrap();
+        assert!(component.contains("url(\"./icon.svg\")"));
+    }
+
+    #[test]
+    fn test_component_local_assets_disabled() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(root.path(), COMPONENT_WITH_LOCAL_ICON);
+
+        let options = TransformOptions {
+            component_local_assets: false,
+            ..Default::default()
+        };
+        let (output, _) = run_fixture(root.path(), &["widgets/moz-a/*.mjs"], &options);
+        assert!(output.path().join("assets/icon.svg").exists());
+        assert!(!output.path().join("components/moz-a/icon.svg").exists());
+        let component =
+            fs::read_to_string(output.path().join("components/moz-a/moz-a.mjs")).unwrap();
+        assert!(component.contains("url(\"../../assets/icon.svg\")"));
+    }
+
+    /// Read every file below `dir`, keyed by the path relative to it.
+    fn read_output(dir: &Path) -> std::collections::BTreeMap<PathBuf, Vec<u8>> {
+        let mut files = std::collections::BTreeMap::new();
+        let mut stack = vec![dir.to_path_buf()];
+        while let Some(current) = stack.pop() {
+            for entry in fs::read_dir(&current).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    stack.push(path);
+                } else {
+                    let relative = path.strip_prefix(dir).unwrap().to_path_buf();
+                    files.insert(relative, fs::read(&path).unwrap());
+                }
+            }
+        }
+        files
+    }
+
+    #[test]
+    fn test_sequential_sessions_are_independent() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(root.path(), COMPONENT_WITH_LOCAL_ICON);
+        let output = tempfile::tempdir().unwrap();
+        let output_path = output.path().to_str().unwrap();
+        let sections = [ConfigSection {
+            jar_paths: &[],
+            mozbuild_paths: &[],
+            global_stylesheets: &[],
+            component_paths: &["widgets/moz-a/*.mjs"],
+        }];
+
+        let mut runs = Vec::new();
+        for component_local_assets in [true, true, false] {
+            let mut session = Session::new(TransformOptions {
+                component_local_assets,
+                ..Default::default()
+            });
+            // The output directory is reused by every session
+            session.run(root.path(), output_path, &sections).unwrap();
+            runs.push((read_output(output.path()), session.into_report()));
+        }
+
+        let (first_files, first_report) = &runs[0];
+        let (second_files, second_report) = &runs[1];
+        assert_eq!(first_files, second_files);
+        assert_eq!(first_report.files, second_report.files);
+        assert_eq!(first_report.graph, second_report.graph);
+
+        // Only the icon and the component referencing it change
+        let (third_files, _) = &runs[2];
+        let changed: Vec<&Path> = first_files
+            .keys()
+            .chain(third_files.keys())
+            .filter(|path| first_files.get(*path) != third_files.get(*path))
+            .map(PathBuf::as_path)
+            .collect::<std::collections::BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        assert_eq!(
+            changed,
+            [
+                Path::new("assets/icon.svg"),
+                Path::new("components/moz-a/icon.svg"),
+                Path::new("components/moz-a/moz-a.mjs"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_glob_regular_files_skips_directories() {
+        let root = tempfile::tempdir().unwrap();
+        let widgets = root.path().join("widgets");
+        fs::create_dir_all(widgets.join("moz-card/nested")).unwrap();
+        fs::write(widgets.join("moz-card/moz-card.mjs"), "").unwrap();
+        fs::write(widgets.join("moz-card/nested/inner.mjs"), "").unwrap();
+
+        let files = glob_regular_files(root.path(), "widgets/moz-card/*").unwrap();
+        assert_eq!(files, vec![widgets.join("moz-card/moz-card.mjs")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_glob_regular_files_follows_symlinked_files() {
+        let root = tempfile::tempdir().unwrap();
+        let widgets = root.path().join("widgets");
+        fs::create_dir_all(widgets.join("moz-card")).unwrap();
+        fs::create_dir_all(root.path().join("elsewhere")).unwrap();
+        fs::write(root.path().join("elsewhere/real.mjs"), "").unwrap();
+        std::os::unix::fs::symlink(
+            root.path().join("elsewhere/real.mjs"),
+            widgets.join("moz-card/linked.mjs"),
+        )
+        .unwrap();
+        std::os::unix::fs::symlink(root.path().join("elsewhere"), widgets.join("moz-card/dir"))
+            .unwrap();
+
+        let files = glob_regular_files(root.path(), "widgets/moz-card/*").unwrap();
+        assert_eq!(files, vec![widgets.join("moz-card/linked.mjs")]);
+    }
+
+    fn resolve_dist_collisions(dep_graph: &mut DependencyGraph, rename: bool) -> Result<()> {
+        let collisions = separate_dist_collisions(dep_graph)?;
+        name_dist_collisions(dep_graph, collisions, rename, &mut RenderedOutputs::new())
+    }
+
+    #[test]
+    fn test_group_identical_compares_bytes() {
+        let items = vec![
+            ("a", b"x".as_slice()),
+            ("b", b"y".as_slice()),
+            ("c", b"x".as_slice()),
+            ("d", b"z".as_slice()),
+        ];
+        // Every item hashes the same, only the bytes tell them apart
+        let groups = group_identical(items, |_| 0);
+        assert_eq!(groups, vec![vec!["a", "c"], vec!["b"], vec!["d"]]);
+    }
+
+    #[test]
+    fn test_resolve_dist_collisions_errors_without_rename() {
+        let root = tempfile::tempdir().unwrap();
+        let a = root.path().join("a/constants.mjs");
+        let b = root.path().join("b/constants.mjs");
+        let mut graph = DependencyGraph::new();
+        graph.add_file(a, FileType::JsFile, TargetLocation::Dependency);
+        graph.add_file(b, FileType::JsFile, TargetLocation::Dependency);
+
+        let err = resolve_dist_collisions(&mut graph, false).unwrap_err();
+        assert!(format!("{err}").contains("dependencies/constants.mjs"));
+    }
+
+    #[test]
+    fn test_resolve_dist_collisions_renames_with_content_hash() {
+        let root = tempfile::tempdir().unwrap();
+        let a = root.path().join("a/constants.mjs");
+        let b = root.path().join("b/constants.mjs");
+        let user = root.path().join("user.mjs");
+        fs::create_dir_all(a.parent().unwrap()).unwrap();
+        fs::create_dir_all(b.parent().unwrap()).unwrap();
+        fs::write(&a, "export const A = 1;").unwrap();
+        fs::write(&b, "export const B = 2;").unwrap();
+
+        let mut graph = DependencyGraph::new();
+        graph.add_file(a.clone(), FileType::JsFile, TargetLocation::Dependency);
+        graph.add_file(b.clone(), FileType::JsFile, TargetLocation::Dependency);
+        graph.add_file(user.clone(), FileType::JsFile, TargetLocation::Dependency);
+        graph
+            .add_dependency(
+                &user,
+                &a,
+                "./a/constants.mjs",
+                ImportKind::StaticImport,
+                None,
+            )
+            .unwrap();
+        graph
+            .add_dependency(
+                &user,
+                &b,
+                "./b/constants.mjs",
+                ImportKind::StaticImport,
+                None,
+            )
+            .unwrap();
+
+        resolve_dist_collisions(&mut graph, true).unwrap();
+        assert!(graph.find_dist_collisions().is_empty());
+
+        let renamed = hashed_file_name(&b, b"export const B = 2;");
+        let replacements = graph.get_import_replacements(&user).unwrap();
+        assert_eq!(replacements["./a/constants.mjs"], "./constants.mjs");
+        assert_eq!(replacements["./b/constants.mjs"], format!("./{renamed}"));
+    }
+
+    #[test]
+    fn test_resolve_dist_collisions_identical_content() {
+        let root = tempfile::tempdir().unwrap();
+        let paths: Vec<PathBuf> = ["a", "b", "c"]
+            .iter()
+            .map(|dir| root.path().join(dir).join("icon.svg"))
+            .collect();
+        let mut graph = DependencyGraph::new();
+        for path in &paths {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "<svg/>").unwrap();
+            graph.add_file(path.clone(), FileType::OpaqueFile, TargetLocation::Asset);
+        }
+
+        resolve_dist_collisions(&mut graph, true).unwrap();
+        assert!(graph.find_dist_collisions().is_empty());
+    }
+
+    #[test]
+    fn test_dedupe_outputs_compares_final_paths() {
+        let root = tempfile::tempdir().unwrap();
+        let import = "import { X } from \"./x.mjs\";\nexport { X };\n";
+        write_tree(
+            root.path(),
+            &[
+                ("a/x.mjs", "export const X = 1;\n"),
+                ("b/x.mjs", "export const X = 2;\n"),
+                ("a/use-a.mjs", import),
+                ("b/use-b.mjs", import),
+            ],
+        );
+        let path = |p: &str| root.path().join(p);
+
+        let mut graph = DependencyGraph::new();
+        for p in ["a/x.mjs", "b/x.mjs", "a/use-a.mjs", "b/use-b.mjs"] {
+            graph.add_file(path(p), FileType::JsFile, TargetLocation::Dependency);
+        }
+        for (user, dep) in [("a/use-a.mjs", "a/x.mjs"), ("b/use-b.mjs", "b/x.mjs")] {
+            graph
+                .add_dependency(
+                    &path(user),
+                    &path(dep),
+                    "./x.mjs",
+                    ImportKind::StaticImport,
+                    None,
+                )
+                .unwrap();
+        }
+
+        let options = TransformOptions {
+            dedupe: true,
+            rename_collisions: true,
+            ..Default::default()
+        };
+        let mut report = BuildReport::new();
+        let collisions = separate_dist_collisions(&mut graph).unwrap();
+        dedupe_outputs(
+            &mut graph,
+            &mut report,
+            &options,
+            &mut RenderedOutputs::new(),
+        )
+        .unwrap();
+        name_dist_collisions(&mut graph, collisions, true, &mut RenderedOutputs::new()).unwrap();
+
+        // Both importers render `./x.mjs` before the collision is resolved
+        assert!(report.merged.is_empty(), "{:?}", report.merged);
+        let renamed = hashed_file_name(&path("b/x.mjs"), b"export const X = 2;\n");
+        let a = graph.get_import_replacements(&path("a/use-a.mjs")).unwrap();
+        let b = graph.get_import_replacements(&path("b/use-b.mjs")).unwrap();
+        assert_eq!(a["./x.mjs"], "./x.mjs");
+        assert_eq!(b["./x.mjs"], format!("./{renamed}"));
+    }
+
+    const COMPONENT_WITH_TOKEN_SHEETS: &[(&str, &str)] = &[
+        (
+            "widgets/moz-a/moz-a.mjs",
+            r#"import { html, MozLitElement } from "../vendor/lit.all.mjs";
+
+export default class MozA extends MozLitElement {
+  render() {
+    return html`<link rel="stylesheet" href="./tokens.css" />${this.label}<link rel="stylesheet" href="./moz-a.css" /><span></span>`;
+  }
+}
+"#,
+        ),
+        ("widgets/moz-a/tokens.css", ":root { --moz-a-size: 4px; }\n"),
+        (
+            "widgets/moz-a/moz-a.css",
+            ":root { --moz-a-gap: 2px; }\n.a { padding: var(--moz-a-gap); }\n",
+        ),
+        (
+            "widgets/vendor/lit.all.mjs",
+            "export const html = 1, css = 2, MozLitElement = class {};\n",
+        ),
+    ];
+
+    #[test]
+    fn test_promote_root_sheets() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(root.path(), COMPONENT_WITH_TOKEN_SHEETS);
+
+        let (output, _) = run_fixture(
+            root.path(),
+            &["widgets/moz-a/*.mjs"],
+            &TransformOptions {
+                promote_root_sheets: true,
+                ..Default::default()
+            },
+        );
+
+        // The tokens-only sheet is emitted as a global stylesheet
+        let tokens = fs::read_to_string(output.path().join("styles/tokens.css")).unwrap();
+        assert!(tokens.contains("--moz-a-size"), "{}", tokens);
+
+        // and its link is replaced by a comment instead of being inlined,
+        // while the mixed sheet is inlined as usual
+        let component =
+            fs::read_to_string(output.path().join("components/moz-a/moz-a.mjs")).unwrap();
+        assert!(
+            component.contains("<!-- ./tokens.css was promoted to a global stylesheet -->"),
+            "{}",
+            component
+        );
+        assert!(!component.contains("--moz-a-size"), "{}", component);
+        assert!(
+            component.contains("/* From ./moz-a.css */"),
+            "{}",
+            component
+        );
+        assert!(!output.path().join("styles/moz-a.css").exists());
+    }
+
+    #[test]
+    fn test_root_sheets_are_inlined_by_default() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(root.path(), COMPONENT_WITH_TOKEN_SHEETS);
+
+        let (output, _) = run_fixture(
+            root.path(),
+            &["widgets/moz-a/*.mjs"],
+            &TransformOptions::default(),
+        );
+
+        assert!(!output.path().join("styles/tokens.css").exists());
+        let component =
+            fs::read_to_string(output.path().join("components/moz-a/moz-a.mjs")).unwrap();
+        assert!(
+            component.contains("/* From ./tokens.css */"),
+            "{}",
+            component
+        );
+        assert!(
+            component.contains("/* From ./moz-a.css */"),
+            "{}",
+            component
+        );
+    }
+
+    #[test]
+    fn test_process_dependencies_scans_large_graph() {
+        const FILES: usize = 5000;
+        let root = tempfile::tempdir().unwrap();
+        let lib = root.path().join("lib");
+        fs::create_dir_all(&lib).unwrap();
+
+        // A binary tree of modules, where every seventh module also imports
+        // its parent to add cycles and already visited targets
+        let mut expected_edges = 1;
+        for i in 0..FILES {
+            let mut imports: Vec<usize> = [2 * i + 1, 2 * i + 2]
+                .into_iter()
+                .filter(|&child| child < FILES)
+                .collect();
+            if i > 0 && i % 7 == 0 {
+                imports.push((i - 1) / 2);
+            }
+            expected_edges += imports.len();
+            let code: String = imports
+                .iter()
+                .map(|j| format!("import \"./f{}.mjs\";\n", j))
+                .collect();
+            fs::write(lib.join(format!("f{}.mjs", i)), code).unwrap();
+        }
+        write_tree(
+            root.path(),
+            &[("widgets/moz-a/moz-a.mjs", "import \"../../lib/f0.mjs\";\n")],
+        );
+
+        let mut report = BuildReport::new();
+        let graph = build_graph(
+            root.path(),
+            &[ConfigSection {
+                jar_paths: &[],
+                mozbuild_paths: &[],
+                global_stylesheets: &[],
+                component_paths: &["widgets/moz-a/*.mjs"],
+            }],
+            &TransformOptions::default(),
+            &mut report,
+        )
+        .unwrap();
+
+        assert_eq!(graph.file_count(), FILES + 1);
+        assert_eq!(graph.dependency_count(), expected_edges);
+        assert!(graph.has_cycles());
+        assert!(report.unresolved_imports.is_empty());
+    }
+
+    #[test]
+    fn test_unresolved_imports_point_at_the_import_line() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[(
+                "widgets/moz-a/moz-a.mjs",
+                "// A component\n\nimport \"./missing.mjs\";\nexport * from \"./missing.mjs\";\n",
+            )],
+        );
+
+        let mut report = BuildReport::new();
+        build_graph(
+            root.path(),
+            &[ConfigSection {
+                jar_paths: &[],
+                mozbuild_paths: &[],
+                global_stylesheets: &[],
+                component_paths: &["widgets/moz-a/*.mjs"],
+            }],
+            &TransformOptions::default(),
+            &mut report,
+        )
+        .unwrap();
+
+        assert_eq!(report.unresolved_imports.len(), 1);
+        let unresolved = &report.unresolved_imports[0];
+        assert_eq!(unresolved.specifier, "./missing.mjs");
+        assert_eq!((unresolved.line, unresolved.column), (3, 8));
+        assert_eq!(unresolved.references, 2);
+        assert!(unresolved.source.ends_with("widgets/moz-a/moz-a.mjs"));
+    }
+
+    #[test]
+    fn test_symlinked_assets_are_written_as_files() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "widgets/moz-a/moz-a.mjs",
+                    r#"import { html, MozLitElement } from "../vendor/lit.all.mjs";
+
+export default class MozA extends MozLitElement {
+  render() {
+    return html`<link rel="stylesheet" href="./moz-a.css" />`;
+  }
+}
+"#,
+                ),
+                (
+                    "widgets/moz-a/moz-a.css",
+                    ".a { background: url(\"./icon.svg\"); }\n",
+                ),
+                ("shared/icon.svg", "<svg/>"),
+                (
+                    "widgets/vendor/lit.all.mjs",
+                    "export const html = 1, css = 2, MozLitElement = class {};\n",
+                ),
+            ],
+        );
+        std::os::unix::fs::symlink(
+            root.path().join("shared/icon.svg"),
+            root.path().join("widgets/moz-a/icon.svg"),
+        )
+        .unwrap();
+
+        let (output, _) = run_fixture(
+            root.path(),
+            &["widgets/moz-a/*.mjs"],
+            &TransformOptions {
+                follow_symlinks: false,
+                ..Default::default()
+            },
+        );
+        let icon = output.path().join("components/moz-a/icon.svg");
+        assert!(!icon.is_symlink());
+        assert_eq!(fs::read_to_string(icon).unwrap(), "<svg/>");
+    }
+
+    #[test]
+    fn test_missing_files_modes() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "widgets/moz-a/moz-a.mjs",
+                    r#"import { html, MozLitElement } from "../vendor/lit.all.mjs";
+import { strings } from "./generated.mjs";
+
+export default class MozA extends MozLitElement {
+  render() {
+    return html`<link rel="stylesheet" href="./moz-a.css" />${strings}`;
+  }
+}
+"#,
+                ),
+                (
+                    "widgets/moz-a/moz-a.css",
+                    ".a { background: url(\"./missing.svg\"); }\n",
+                ),
+                (
+                    "widgets/vendor/lit.all.mjs",
+                    "export const html = 1, css = 2, MozLitElement = class {};\n",
+                ),
+            ],
+        );
+        let component_paths = ["widgets/moz-a/*.mjs"];
+
+        let output = tempfile::tempdir().unwrap();
+        let error = transform_lib(
+            root.path(),
+            output.path().to_str().unwrap(),
+            &[],
+            &[],
+            &[],
+            &component_paths,
+            &TransformOptions::default(),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("./generated.mjs"), "{}", error);
+        assert!(error.contains("./missing.svg"), "{}", error);
+
+        let (output, report) = run_fixture(
+            root.path(),
+            &component_paths,
+            &TransformOptions {
+                missing_files: MissingFiles::Warn,
+                ..Default::default()
+            },
+        );
+        assert_eq!(report.unresolved_imports.len(), 2);
+        let component =
+            fs::read_to_string(output.path().join("components/moz-a/moz-a.mjs")).unwrap();
+        assert!(
+            component.contains("from \"./generated.mjs\""),
+            "{}",
+            component
+        );
+        assert!(
+            component.contains("url(\"./missing.svg\")"),
+            "{}",
+            component
+        );
+
+        let (output, report) = run_fixture(
+            root.path(),
+            &component_paths,
+            &TransformOptions {
+                missing_files: MissingFiles::Stub,
+                ..Default::default()
+            },
+        );
+        assert_eq!(report.unresolved_imports.len(), 2);
+        let component =
+            fs::read_to_string(output.path().join("components/moz-a/moz-a.mjs")).unwrap();
+        assert!(
+            component.contains("from \"../../dependencies/generated.mjs\""),
+            "{}",
+            component
+        );
+        assert_eq!(
+            fs::read(output.path().join("dependencies/generated.mjs")).unwrap(),
+            b""
+        );
+        // Only used by the component, so placed next to it
+        assert!(
+            component.contains("url(\"./missing.svg\")"),
+            "{}",
+            component
+        );
+        assert!(output.path().join("components/moz-a/missing.svg").is_file());
+    }
+
+    const COMPONENT_WITH_CLASSIFIED_DEPENDENCIES: &[(&str, &str)] = &[
+        (
+            "widgets/moz-a/moz-a.mjs",
+            r#"import { html, MozLitElement } from "../vendor/lit.all.mjs";
+
+export default class MozA extends MozLitElement {
+  render() {
+    return html`<link rel="stylesheet" href="./moz-a.css" />${this.label}<link rel="stylesheet" href="./linked.css" />${this.label}<link rel="stylesheet" href="../../styles/tokens.css" />${this.label}<link rel="stylesheet" href="../../styles/shared.css" /><moz-chart src="../data/data.json"></moz-chart>`;
+  }
+}
+"#,
+        ),
+        ("widgets/moz-a/moz-a.css", ".a { color: red; }\n"),
+        ("widgets/moz-a/linked.css", ".linked { color: blue; }\n"),
+        ("widgets/data/data.json", "{}\n"),
+        ("styles/tokens.css", ".tokens { color: green; }\n"),
+        ("styles/shared.css", ".shared { color: gray; }\n"),
+        (
+            "widgets/vendor/lit.all.mjs",
+            "export const html = 1, css = 2, MozLitElement = class {};\n",
+        ),
+    ];
+
+    fn classified_dependencies_options() -> TransformOptions {
+        TransformOptions {
+            classifier: Some(Classifier::new(|importer, specifier| {
+                assert_eq!(importer.file_type, FileType::JsComponent);
+                match specifier {
+                    "./linked.css" => Some((FileType::CssFile, TargetLocation::Dependency)),
+                    "../data/data.json" => Some((FileType::OpaqueFile, TargetLocation::Asset)),
+                    "../../styles/shared.css" => Some((FileType::CssFile, TargetLocation::Omit)),
+                    _ => None,
+                }
+            })),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_module_urls_are_assets() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "widgets/moz-a/moz-a.mjs",
+                    r#"import { html, MozLitElement } from "../vendor/lit.all.mjs";
+
+const warning = new URL("../shared/warning.mjs", import.meta.url);
+
+export default class MozA extends MozLitElement {
+  render() {
+    return html`<img src=${warning.href} />`;
+  }
+}
+"#,
+                ),
+                ("widgets/shared/warning.mjs", "not parsed as a module ("),
+                (
+                    "widgets/vendor/lit.all.mjs",
+                    "export const html = 1, css = 2, MozLitElement = class {};\n",
+                ),
+            ],
+        );
+
+        let (output, _) = run_fixture(
+            root.path(),
+            &["widgets/moz-a/moz-a.mjs"],
+            &TransformOptions::default(),
+        );
+        let component =
+            fs::read_to_string(output.path().join("components/moz-a/moz-a.mjs")).unwrap();
+        assert!(
+            component.contains("new URL(\"../../assets/warning.mjs\", import.meta.url)"),
+            "{}",
+            component
+        );
+        assert_eq!(
+            fs::read_to_string(output.path().join("assets/warning.mjs")).unwrap(),
+            "not parsed as a module ("
+        );
+    }
+
+    #[test]
+    fn test_privileged_imports_with_shims() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "widgets/moz-a/moz-a.mjs",
+                    r#"import { html, MozLitElement } from "../vendor/lit.all.mjs";
+
+const { XPCOMUtils } = ChromeUtils.importESModule("resource://gre/modules/XPCOMUtils.sys.mjs");
+const lazy = {};
+ChromeUtils.defineESModuleGetters(lazy, {
+  PlacesUtils: "resource://gre/modules/PlacesUtils.sys.mjs",
+});
+
+export default class MozA extends MozLitElement {
+  render() {
+    return html`${XPCOMUtils}`;
+  }
+}
+"#,
+                ),
+                ("shims/XPCOMUtils.mjs", "export const XPCOMUtils = {};\n"),
+                (
+                    "widgets/vendor/lit.all.mjs",
+                    "export const html = 1, css = 2, MozLitElement = class {};\n",
+                ),
+            ],
+        );
+        let options = TransformOptions {
+            privileged_imports: PrivilegedImports::Shim,
+            aliases: HashMap::from([(
+                "resource://gre/modules/XPCOMUtils.sys.mjs".to_string(),
+                root.path()
+                    .join("shims/XPCOMUtils.mjs")
+                    .to_string_lossy()
+                    .into_owned(),
+            )]),
+            ..Default::default()
+        };
+
+        let (output, report) = run_fixture(root.path(), &["widgets/moz-a/moz-a.mjs"], &options);
+        let component =
+            fs::read_to_string(output.path().join("components/moz-a/moz-a.mjs")).unwrap();
+        assert!(
+            component.contains("await import(\"../../dependencies/XPCOMUtils.mjs\")"),
+            "{}",
+            component
+        );
+        assert!(output.path().join("dependencies/XPCOMUtils.mjs").is_file());
+        // The module without a shim is neither copied nor rewritten
+        assert!(
+            component.contains("ChromeUtils.defineESModuleGetters"),
+            "{}",
+            component
+        );
+        assert!(report.unresolved_imports.is_empty());
+        assert_eq!(report.privileged_imports.len(), 1);
+        assert_eq!(
+            report.privileged_imports[0].specifier,
+            "resource://gre/modules/PlacesUtils.sys.mjs"
+        );
+    }
+
+    #[test]
+    fn test_worker_scripts_are_followed() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "widgets/moz-a/moz-a.mjs",
+                    r#"import { html, MozLitElement } from "../vendor/lit.all.mjs";
+
+const worker = new Worker("../workers/worker.mjs", { type: "module" });
+const legacy = new Worker("../workers/legacy.jsm");
+const shared = new SharedWorker("../workers/shared-worker");
+
+export default class MozA extends MozLitElement {
+  render() {
+    return html`${worker}`;
+  }
+}
+"#,
+                ),
+                (
+                    "widgets/workers/worker.mjs",
+                    "import { helper } from \"../shared/helper.mjs\";\nhelper();\n",
+                ),
+                ("widgets/shared/helper.mjs", "export function helper() {}\n"),
+                ("widgets/workers/legacy.jsm", "importScripts(\"a.js\");\n"),
+                ("widgets/workers/shared-worker", "onconnect = () => {};\n"),
+                (
+                    "widgets/vendor/lit.all.mjs",
+                    "export const html = 1, css = 2, MozLitElement = class {};\n",
+                ),
+            ],
+        );
+
+        let (output, _) = run_fixture(
+            root.path(),
+            &["widgets/moz-a/moz-a.mjs"],
+            &TransformOptions::default(),
+        );
+        let component =
+            fs::read_to_string(output.path().join("components/moz-a/moz-a.mjs")).unwrap();
+        assert!(
+            component.contains(
+                "new Worker(new URL(\"../../dependencies/worker.mjs\", import.meta.url).href"
+            ),
+            "{}",
+            component
+        );
+        let worker = fs::read_to_string(output.path().join("dependencies/worker.mjs")).unwrap();
+        assert!(worker.contains("from \"./helper.mjs\""), "{}", worker);
+        assert!(output.path().join("dependencies/helper.mjs").is_file());
+        // Workers without a module extension aren't scanned for imports
+        assert_eq!(
+            fs::read_to_string(output.path().join("dependencies/legacy.jsm")).unwrap(),
+            "importScripts(\"a.js\");\n"
+        );
+        assert!(output.path().join("dependencies/shared-worker").is_file());
+    }
+
+    #[test]
+    fn test_fetched_stylesheets() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "widgets/moz-a/moz-a.mjs",
+                    r#"import { html, MozLitElement } from "../vendor/lit.all.mjs";
+
+const sheet = new CSSStyleSheet();
+sheet.replaceSync(await (await fetch("../shared/shared.css")).text());
+
+export default class MozA extends MozLitElement {
+  connectedCallback() {
+    super.connectedCallback();
+    this.shadowRoot.adoptedStyleSheets = [sheet];
+  }
+  render() {
+    return html`<div></div>`;
+  }
+}
+"#,
+                ),
+                (
+                    "widgets/shared/shared.css",
+                    ".a { background: url(\"./icon.svg\"); }\n",
+                ),
+                ("widgets/shared/icon.svg", "<svg></svg>"),
+                (
+                    "widgets/vendor/lit.all.mjs",
+                    "export const html = 1, css = 2, MozLitElement = class {};\n",
+                ),
+            ],
+        );
+
+        let (output, _) = run_fixture(
+            root.path(),
+            &["widgets/moz-a/moz-a.mjs"],
+            &TransformOptions::default(),
+        );
+        let component =
+            fs::read_to_string(output.path().join("components/moz-a/moz-a.mjs")).unwrap();
+        assert!(
+            component.contains(
+                "fetch(new URL(\"../../dependencies/shared.css\", import.meta.url).href)"
+            ),
+            "{}",
+            component
+        );
+        assert!(output.path().join("dependencies/shared.css").is_file());
+        assert!(output.path().join("assets/icon.svg").is_file());
+
+        let options = TransformOptions {
+            inline_fetched_stylesheets: true,
+            ..TransformOptions::default()
+        };
+        let (output, _) = run_fixture(root.path(), &["widgets/moz-a/moz-a.mjs"], &options);
+        let component =
+            fs::read_to_string(output.path().join("components/moz-a/moz-a.mjs")).unwrap();
+        assert!(
+            component.contains("Promise.resolve(new Response(") && !component.contains("fetch("),
+            "{}",
+            component
+        );
+        assert!(component.contains("../../assets/icon.svg"), "{}", component);
+    }
+
+    /// Resolves `brand://` to the files of one brand, like a fork would
+    struct BrandResolver(PathBuf);
+
+    impl Resolver for BrandResolver {
+        fn resolve(
+            &self,
+            _current_file: &Path,
+            specifier: &str,
+        ) -> Option<std::result::Result<PathBuf, String>> {
+            let rest = specifier.strip_prefix("brand://")?;
+            Some(Ok(self.0.join("branding/nightly").join(rest)))
+        }
+    }
+
+    #[test]
+    fn test_custom_resolvers() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "widgets/moz-a/moz-a.mjs",
+                    r#"import { html, MozLitElement } from "../vendor/lit.all.mjs";
+import { brandName } from "brand://strings.mjs";
+
+export default class MozA extends MozLitElement {
+  render() {
+    return html`${brandName}`;
+  }
+}
+"#,
+                ),
+                (
+                    "branding/nightly/strings.mjs",
+                    "export const brandName = \"Nightly\";\n",
+                ),
+                (
+                    "widgets/vendor/lit.all.mjs",
+                    "export const html = 1, css = 2, MozLitElement = class {};\n",
+                ),
+            ],
+        );
+        let options = TransformOptions {
+            resolvers: vec![Arc::new(BrandResolver(root.path().to_path_buf()))],
+            ..Default::default()
+        };
+
+        let mut report = BuildReport::new();
+        let graph = build_graph(
+            root.path(),
+            &[ConfigSection {
+                jar_paths: &[],
+                mozbuild_paths: &[],
+                global_stylesheets: &[],
+                component_paths: &["widgets/moz-a/moz-a.mjs"],
+            }],
+            &options,
+            &mut report,
+        )
+        .unwrap();
+        let strings = graph
+            .all_files()
+            .find(|f| f.path.ends_with("branding/nightly/strings.mjs"))
+            .unwrap();
+        assert_eq!(strings.target_location, TargetLocation::Dependency);
+
+        let (output, _) = run_fixture(root.path(), &["widgets/moz-a/moz-a.mjs"], &options);
+        let component =
+            fs::read_to_string(output.path().join("components/moz-a/moz-a.mjs")).unwrap();
+        assert!(
+            component.contains("from \"../../dependencies/strings.mjs\""),
+            "{}",
+            component
+        );
+        assert!(output.path().join("dependencies/strings.mjs").is_file());
+    }
+
+    #[test]
+    fn test_classifier_precedence() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(root.path(), COMPONENT_WITH_CLASSIFIED_DEPENDENCIES);
+
+        let mut report = BuildReport::new();
+        let graph = build_graph(
+            root.path(),
+            &[ConfigSection {
+                jar_paths: &[],
+                mozbuild_paths: &[],
+                global_stylesheets: &["styles/*.css"],
+                component_paths: &["widgets/moz-a/moz-a.mjs"],
+            }],
+            &classified_dependencies_options(),
+            &mut report,
+        )
+        .unwrap();
+        let target = |name: &str| {
+            graph
+                .all_files()
+                .find(|f| f.path.ends_with(name))
+                .unwrap()
+                .target_location
+                .clone()
+        };
+
+        // The classifier wins over the existing node and the default rules
+        assert_eq!(target("shared.css"), TargetLocation::Omit);
+        assert_eq!(target("linked.css"), TargetLocation::Dependency);
+        assert_eq!(target("data.json"), TargetLocation::Asset);
+        // Without a classification, the existing node wins over the default
+        assert_eq!(target("tokens.css"), TargetLocation::CssGlobal);
+        // and the default rules apply to new files
+        assert_eq!(target("moz-a.css"), TargetLocation::Omit);
+    }
+
+    #[test]
+    fn test_classified_stylesheets_stay_linked() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(root.path(), COMPONENT_WITH_CLASSIFIED_DEPENDENCIES);
+
+        let output = tempfile::tempdir().unwrap();
+        transform_lib(
+            root.path(),
+            output.path().to_str().unwrap(),
+            &[],
+            &[],
+            &["styles/*.css"],
+            &["widgets/moz-a/moz-a.mjs"],
+            &classified_dependencies_options(),
+        )
+        .unwrap();
+
+        let component =
+            fs::read_to_string(output.path().join("components/moz-a/moz-a.mjs")).unwrap();
+        assert!(component.contains(".a {"), "{}", component);
+        assert!(component.contains(".shared {"), "{}", component);
+        assert!(!component.contains(".linked"), "{}", component);
+        assert!(
+            component.contains(
+                "<link rel=\"stylesheet\" href=\"${new URL(\"../../dependencies/linked.css\", import.meta.url).href}\" />"
+            ),
+            "{}",
+            component
+        );
+        assert!(output.path().join("dependencies/linked.css").exists());
+        assert!(output.path().join("assets/data.json").exists());
+        assert!(!output.path().join("styles/shared.css").exists());
+        assert!(output.path().join("styles/tokens.css").exists());
+    }
+
+    #[test]
+    fn test_loader_maps_every_component_tag() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "widgets/moz-a/moz-a.mjs",
+                    "class MozA extends HTMLElement {}\ncustomElements.define(\"moz-a\", MozA);\n",
+                ),
+                (
+                    "widgets/moz-b/moz-b.mjs",
+                    "class MozB extends HTMLElement {}\nclass MozBItem extends HTMLElement {}\ncustomElements.define(\"moz-b\", MozB);\ncustomElements.define(\"moz-b-item\", MozBItem);\n",
+                ),
+            ],
+        );
+
+        let (output, report) = run_fixture(
+            root.path(),
+            &["widgets/moz-*/*.mjs"],
+            &TransformOptions {
+                loader: true,
+                ..Default::default()
+            },
+        );
+
+        let code = fs::read_to_string(output.path().join("loader.mjs")).unwrap();
+        assert!(
+            code.contains(
+                r#"const components = {
+  "moz-a": "./components/moz-a/moz-a.mjs",
+  "moz-b": "./components/moz-b/moz-b.mjs",
+  "moz-b-item": "./components/moz-b/moz-b.mjs",
+};"#
+            ),
+            "{}",
+            code
+        );
+        assert!(code.contains("new MutationObserver("));
+        assert_eq!(report.count(FileOutcome::Generated), 1);
+    }
+
+    #[test]
+    fn test_transform_lib_multi_merges_sections() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "widgets/moz-a/moz-a.mjs",
+                    "import { shared } from \"../shared/utils.mjs\";\nexport default shared;\n",
+                ),
+                (
+                    "browser/moz-b/moz-b.mjs",
+                    "import { shared } from \"../../widgets/shared/utils.mjs\";\nexport default shared;\n",
+                ),
+                ("widgets/shared/utils.mjs", "export const shared = 1;\n"),
+            ],
+        );
+
+        let output = tempfile::tempdir().unwrap();
+        let sections = [
+            ConfigSection {
+                jar_paths: &[],
+                mozbuild_paths: &[],
+                global_stylesheets: &[],
+                component_paths: &["widgets/moz-a/*.mjs"],
+            },
+            ConfigSection {
+                jar_paths: &[],
+                mozbuild_paths: &[],
+                global_stylesheets: &[],
+                component_paths: &["browser/moz-b/*.mjs"],
+            },
+        ];
+        let report = transform_lib_multi(
+            root.path(),
+            output.path().to_str().unwrap(),
+            &sections,
+            &TransformOptions::default(),
+        )
+        .unwrap();
+
+        assert!(output.path().join("components/moz-a/moz-a.mjs").exists());
+        assert!(output.path().join("components/moz-b/moz-b.mjs").exists());
+        // The dependency both sections share is written once
+        let shared: Vec<_> = report
+            .files
+            .iter()
+            .filter(|f| f.source.ends_with("utils.mjs"))
+            .collect();
+        assert_eq!(shared.len(), 1);
+        assert_eq!(shared[0].dist, PathBuf::from("dependencies/utils.mjs"));
+        let moz_b = fs::read_to_string(output.path().join("components/moz-b/moz-b.mjs")).unwrap();
+        assert!(moz_b.contains("../../dependencies/utils.mjs"), "{}", moz_b);
+    }
+
+    #[test]
+    fn test_component_dependencies_see_every_section() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                ("widgets/moz-a/moz-a.mjs", "export default 1;\n"),
+                (
+                    "browser/moz-b/moz-b.mjs",
+                    "import { shared } from \"../../widgets/shared/utils.mjs\";\nexport default shared;\n",
+                ),
+                ("widgets/shared/utils.mjs", "export const shared = 1;\n"),
+            ],
+        );
+
+        let sections = [
+            ConfigSection {
+                jar_paths: &[],
+                mozbuild_paths: &[],
+                global_stylesheets: &[],
+                component_paths: &["widgets/moz-a/*.mjs"],
+            },
+            ConfigSection {
+                jar_paths: &[],
+                mozbuild_paths: &[],
+                global_stylesheets: &[],
+                component_paths: &["browser/moz-b/*.mjs"],
+            },
+        ];
+        let dependencies = component_dependencies(
+            root.path(),
+            &sections,
+            &TransformOptions::default(),
+            "moz-b",
+        )
+        .unwrap();
+        assert!(
+            dependencies
+                .dependencies
+                .iter()
+                .any(|dependency| dependency.source.ends_with("utils.mjs")),
+            "{:?}",
+            dependencies
+        );
+    }
+
+    #[test]
+    fn test_entry_points_get_their_chrome_url() {
+        let root = tempfile::tempdir().unwrap();
+        let mut files = COMPONENT_WITH_LOCAL_ICON.to_vec();
+        files.push((
+            "widgets/jar.mn",
+            "toolkit.jar:\n% content global %content/global/\n  content/global/elements/moz-a.mjs (moz-a/moz-a.mjs)\n  content/global/vendor/lit.all.mjs (vendor/lit.all.mjs)\n",
+        ));
+        write_tree(root.path(), &files);
+
+        let explanation = explain_file(
+            root.path(),
+            &[ConfigSection {
+                jar_paths: &["widgets/jar.mn"],
+                mozbuild_paths: &[],
+                global_stylesheets: &[],
+                component_paths: &["widgets/moz-a/*.mjs"],
+            }],
+            &TransformOptions::default(),
+            "widgets/moz-a/moz-a.mjs",
+        )
+        .unwrap();
+        assert_eq!(
+            explanation.original_url.as_deref(),
+            Some("chrome://global/content/elements/moz-a.mjs")
+        );
+    }
+
+    #[test]
+    fn test_template_scripts_and_sources() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "widgets/moz-a/moz-a.stories.mjs",
+                    r#"import { html } from "../vendor/lit.all.mjs";
+
+export default { title: "UI Widgets/A" };
+
+export const Default = () => html`
+  <script type="module" src="../shared/setup.mjs"></script>
+  <video controls>
+    <source src="../shared/clip.webm" type="video/webm" />
+  </video>
+`;
+
+export const Plain = () => {
+  const container = document.createElement("div");
+  container.innerHTML = `<script type="module" src="../shared/setup.mjs"></script>`;
+  return container;
+};
+"#,
+                ),
+                ("widgets/shared/setup.mjs", "import \"./helper.mjs\";\n"),
+                ("widgets/shared/helper.mjs", "export {};\n"),
+                ("widgets/shared/clip.webm", "webm"),
+                ("widgets/vendor/lit.all.mjs", "export const html = 1;\n"),
+            ],
+        );
+
+        let (output, _) = run_fixture(
+            root.path(),
+            &["widgets/moz-a/*.mjs"],
+            &TransformOptions::default(),
+        );
+        let story =
+            fs::read_to_string(output.path().join("components/moz-a/moz-a.stories.mjs")).unwrap();
+        let script_src =
+            "src=\"${new URL(\"../../dependencies/setup.mjs\", import.meta.url).href}\"";
+        assert_eq!(story.matches(script_src).count(), 2, "{}", story);
+        assert!(
+            story.contains(
+                "<source src=\"${new URL(\"../../dependencies/clip.webm\", import.meta.url).href}\""
+            ),
+            "{}",
+            story
+        );
+        assert!(!story.contains("../shared/"), "{}", story);
+        // The module script is walked like an import
+        assert!(output.path().join("dependencies/setup.mjs").is_file());
+        assert!(output.path().join("dependencies/helper.mjs").is_file());
+        assert!(output.path().join("dependencies/clip.webm").is_file());
+    }
+
+    #[test]
+    fn test_story_fixtures() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "widgets/moz-a/moz-a.stories.mjs",
+                    r#"import { html } from "../vendor/lit.all.mjs";
+
+export default {
+  title: "UI Widgets/A",
+  argTypes: { iconSrc: { options: ["./fixtures/edit.svg"] } },
+};
+
+export const Default = ({ iconSrc }) => html`<moz-a .iconSrc=${iconSrc}></moz-a>`;
+Default.args = { iconSrc: "./fixtures/avatar.png", label: "./not-an-asset" };
+"#,
+                ),
+                (
+                    "widgets/moz-a/moz-a.mjs",
+                    "const fallback = { icon: \"./fixtures/avatar.png\" };\n",
+                ),
+                ("widgets/moz-a/fixtures/avatar.png", "png"),
+                ("widgets/moz-a/fixtures/edit.svg", "<svg/>"),
+                ("widgets/vendor/lit.all.mjs", "export const html = 1;\n"),
+            ],
+        );
+
+        let (output, _) = run_fixture(
+            root.path(),
+            &["widgets/moz-a/*.mjs"],
+            &TransformOptions::default(),
+        );
+        let story =
+            fs::read_to_string(output.path().join("components/moz-a/moz-a.stories.mjs")).unwrap();
+        for expected in [
+            r#"iconSrc: new URL("./avatar.png", import.meta.url).href"#,
+            r#"options: [new URL("./edit.svg", import.meta.url).href]"#,
+            r#"label: "./not-an-asset""#,
+        ] {
+            assert!(story.contains(expected), "{}", story);
+        }
+        // Fixtures of the component are copied next to it
+        assert!(output.path().join("components/moz-a/avatar.png").is_file());
+        assert!(output.path().join("components/moz-a/edit.svg").is_file());
+        // Components only follow their imports and URLs
+        let component =
+            fs::read_to_string(output.path().join("components/moz-a/moz-a.mjs")).unwrap();
+        assert!(
+            component.contains("\"./fixtures/avatar.png\""),
+            "{}",
+            component
+        );
+    }
+
+    #[test]
+    fn test_flatten_imports() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "themes/global.css",
+                    "@layer base, widgets;\n@import url(\"./reset.css\") layer(base);\n@import \"./reset.css\" layer(base);\n@import \"./widgets.css\" screen and (min-width: 600px);\n@import \"./shared.css\" supports(display: grid);\n@import \"https://example.com/remote.css\";\n.g { background: url(\"./icons/g.svg\"); }\n",
+                ),
+                ("themes/reset.css", "body { margin: 0; }\n"),
+                (
+                    "themes/widgets.css",
+                    "@import \"./shared.css\" layer(widgets);\n.w { background: url(\"icons/w.svg\"); }\n",
+                ),
+                (
+                    "themes/shared.css",
+                    ".s { mask: url(\"./icons/s.svg\"); }\n",
+                ),
+                ("themes/icons/g.svg", "<svg id='g'/>"),
+                ("themes/icons/w.svg", "<svg id='w'/>"),
+                ("themes/icons/s.svg", "<svg id='s'/>"),
+            ],
+        );
+
+        let output = tempfile::tempdir().unwrap();
+        let report = transform_lib(
+            root.path(),
+            output.path().to_str().unwrap(),
+            &[],
+            &[],
+            &["themes/global.css"],
+            &[],
+            &TransformOptions {
+                flatten_imports: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let global = fs::read_to_string(output.path().join("styles/global.css")).unwrap();
+        assert!(global.starts_with("@layer base, widgets;\n"), "{global}");
+        assert!(!global.contains("reset.css"), "{global}");
+        assert!(
+            global.contains(r#"@import "https://example.com/remote.css";"#),
+            "{global}"
+        );
+        for expected in [
+            "@layer base {\n  body {",
+            "@media screen and (width >= 600px) {\n  @layer widgets {\n    .s {",
+            r#"mask: url("../assets/s.svg")"#,
+            r#"background: url("../assets/w.svg")"#,
+            r#"background: url("../assets/g.svg")"#,
+        ] {
+            assert!(global.contains(expected), "{expected}: {global}");
+        }
+        // Imports with the same conditions are only inlined once
+        assert_eq!(global.matches("body {").count(), 1, "{global}");
+        // shared.css is imported with different conditions by global.css
+        // and widgets.css, so it's inlined for both
+        assert_eq!(global.matches(".s {").count(), 2, "{global}");
+        assert!(
+            global.contains("@supports (display: grid) {\n  .s {"),
+            "{global}"
+        );
+
+        for asset in ["g.svg", "w.svg", "s.svg"] {
+            assert!(output.path().join("assets").join(asset).exists());
+        }
+        // The imported sheets are not written on their own
+        let dependencies: Vec<_> = glob::glob(&format!(
+            "{}/dependencies/**/*.css",
+            output.path().display()
+        ))
+        .unwrap()
+        .collect();
+        assert!(dependencies.is_empty(), "{dependencies:?}");
+        assert!(report.orphaned_stylesheets.is_empty());
+    }
+
+    #[test]
+    fn test_flatten_imports_cycle() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                ("themes/global.css", "@import \"./a.css\";\n"),
+                ("themes/a.css", "@import \"./b.css\";\n.a { color: red; }\n"),
+                ("themes/b.css", "@import \"./a.css\";\n.b { color: red; }\n"),
+            ],
+        );
+
+        let output = tempfile::tempdir().unwrap();
+        let error = transform_lib(
+            root.path(),
+            output.path().to_str().unwrap(),
+            &[],
+            &[],
+            &["themes/global.css"],
+            &[],
+            &TransformOptions {
+                flatten_imports: true,
+                ..Default::default()
+            },
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("import each other"), "{error}");
+        assert!(error.contains("a.css -> "), "{error}");
+        assert!(error.contains("b.css -> "), "{error}");
+    }
+
+    #[test]
+    fn test_css_minify() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "widgets/moz-a/moz-a.mjs",
+                    r#"import { html, MozLitElement } from "../vendor/lit.all.mjs";
+
+export default class MozA extends MozLitElement {
+  render() {
+    return html`<link rel="stylesheet" href="./moz-a.css" /><span></span>`;
+  }
+}
+"#,
+                ),
+                (
+                    "widgets/moz-a/moz-a.css",
+                    ":host {\n  background: url(\"../icons/a.svg\") no-repeat;\n}\n",
+                ),
+                ("widgets/icons/a.svg", "<svg/>"),
+                (
+                    "widgets/vendor/lit.all.mjs",
+                    "export const html = 1, css = 2, MozLitElement = class {};\n",
+                ),
+                (
+                    "styles/global.css",
+                    "body {\n  background: url(\"../widgets/icons/a.svg\");\n}\n",
+                ),
+            ],
+        );
+
+        let output = tempfile::tempdir().unwrap();
+        transform_lib(
+            root.path(),
+            output.path().to_str().unwrap(),
+            &[],
+            &[],
+            &["styles/global.css"],
+            &["widgets/moz-a/*"],
+            &TransformOptions {
+                css_minify: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let global = fs::read_to_string(output.path().join("styles/global.css")).unwrap();
+        assert_eq!(global, "body{background:url(../assets/a.svg)}");
+        let component =
+            fs::read_to_string(output.path().join("components/moz-a/moz-a.mjs")).unwrap();
+        assert!(
+            component.contains(":host{background:url(../../assets/a.svg) no-repeat}"),
+            "{component}"
+        );
+    }
+
+    #[test]
+    fn test_unknown_css_urls() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[(
+                "styles/global.css",
+                ".a {\n  list-style-image: url(\"moz-icon://stock/a?size=16\");\n  color: red;\n}\n.b {\n  background: url(\"page-icon:https://example.com/\");\n}\n",
+            )],
+        );
+
+        let run = |unknown_css_url| {
+            let output = tempfile::tempdir().unwrap();
+            let report = transform_lib(
+                root.path(),
+                output.path().to_str().unwrap(),
+                &[],
+                &[],
+                &["styles/global.css"],
+                &[],
+                &TransformOptions {
+                    unknown_css_url,
+                    ..Default::default()
+                },
+            );
+            let global = fs::read_to_string(output.path().join("styles/global.css"));
+            (report, global)
+        };
+
+        let (report, _) = run(UnknownCssUrl::Error);
+        let error = report.unwrap_err().to_string();
+        assert!(error.contains("moz-icon://stock/a"), "{error}");
+
+        let (report, global) = run(UnknownCssUrl::Keep);
+        let report = report.unwrap();
+        let global = global.unwrap();
+        assert!(
+            global.contains(r#"url("moz-icon://stock/a?size=16")"#),
+            "{global}"
+        );
+        assert!(
+            global.contains(r#"url("page-icon:https://example.com/")"#),
+            "{global}"
+        );
+        assert_eq!(report.unmapped_css_urls.len(), 2);
+        assert!(
+            report.unmapped_css_urls[0]
+                .source
+                .ends_with("styles/global.css")
+        );
+        assert!(!report.unmapped_css_urls[0].stripped);
+
+        let (report, global) = run(UnknownCssUrl::Strip);
+        let report = report.unwrap();
+        let global = global.unwrap();
+        assert!(!global.contains("moz-icon"), "{global}");
+        assert!(!global.contains("page-icon"), "{global}");
+        assert!(global.contains("color: red"), "{global}");
+        assert!(report.unmapped_css_urls.iter().all(|u| u.stripped));
+    }
+
+    #[test]
+    fn test_missing_css_files_are_not_unknown_urls() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[(
+                "styles/global.css",
+                ".a {\n  list-style-image: url(\"moz-icon://stock/a?size=16\");\n}\n.b {\n  background: url(\"./icon.svg\");\n}\n",
+            )],
+        );
+
+        let mut report = BuildReport::new();
+        build_graph(
+            root.path(),
+            &[ConfigSection {
+                jar_paths: &[],
+                mozbuild_paths: &[],
+                global_stylesheets: &["styles/global.css"],
+                component_paths: &[],
+            }],
+            &TransformOptions {
+                unknown_css_url: UnknownCssUrl::Keep,
+                ..Default::default()
+            },
+            &mut report,
+        )
+        .unwrap();
+
+        let specifiers: Vec<&str> = report
+            .unresolved_imports
+            .iter()
+            .map(|unresolved| unresolved.specifier.as_str())
+            .collect();
+        assert_eq!(specifiers, ["./icon.svg"]);
+    }
+
+    #[test]
+    fn test_css_license_headers() {
+        const MPL: &str = "/* This Source Code Form is subject to the terms of the Mozilla Public\n * License, v. 2.0. If a copy of the MPL was not distributed with this\n * file, You can obtain one at http://mozilla.org/MPL/2.0/. */";
+        let root = tempfile::tempdir().unwrap();
+        let global = format!("{MPL}\n\n@import \"./shared.css\";\n.g {{ color: red; }}\n");
+        let shared = format!("{MPL}\n.s {{ color: blue; }}\n");
+        let component_sheet = format!("{MPL}\n.a {{ color: green; }}\n");
+        write_tree(
+            root.path(),
+            &[
+                ("themes/global.css", global.as_str()),
+                ("themes/shared.css", shared.as_str()),
+                ("widgets/moz-a/moz-a.css", component_sheet.as_str()),
+                (
+                    "widgets/moz-a/moz-a.mjs",
+                    r#"import { html, MozLitElement } from "../vendor/lit.all.mjs";
+
+export default class MozA extends MozLitElement {
+  render() {
+    return html`<link rel="stylesheet" href="./moz-a.css" /><span></span>`;
+  }
+}
+"#,
+                ),
+                (
+                    "widgets/vendor/lit.all.mjs",
+                    "export const html = 1, css = 2, MozLitElement = class {};\n",
+                ),
+            ],
+        );
+
+        let run = |css_license_headers| {
+            let output = tempfile::tempdir().unwrap();
+            transform_lib(
+                root.path(),
+                output.path().to_str().unwrap(),
+                &[],
+                &[],
+                &["themes/global.css"],
+                &["widgets/moz-a/*.mjs"],
+                &TransformOptions {
+                    css_license_headers,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            let read = |path: &str| fs::read_to_string(output.path().join(path)).unwrap();
+            (
+                read("styles/global.css"),
+                read("dependencies/shared.css"),
+                read("components/moz-a/moz-a.mjs"),
+            )
+        };
+
+        let (global, shared, component) = run(true);
+        assert!(global.starts_with(MPL), "{global}");
+        assert!(shared.starts_with(MPL), "{shared}");
+        assert!(
+            component.contains("/* From ./moz-a.css, MPL-2.0 */"),
+            "{component}"
+        );
+        assert!(!component.contains("Source Code Form"), "{component}");
+
+        let (global, shared, component) = run(false);
+        assert!(!global.contains("Source Code Form"), "{global}");
+        assert!(!shared.contains("Source Code Form"), "{shared}");
+        assert!(component.contains("/* From ./moz-a.css */"), "{component}");
+    }
+
+    #[test]
+    fn test_moz_cleanup() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[(
+                "styles/global.css",
+                "@media (-moz-platform: macos) {\n  .a {\n    color: red;\n  }\n}\n@layer base {\n  .b:-moz-window-inactive {\n    color: gray;\n  }\n  .c {\n    -moz-default-appearance: button;\n    margin: 0;\n  }\n}\n",
+            )],
+        );
+        let output = tempfile::tempdir().unwrap();
+        let report = transform_lib(
+            root.path(),
+            output.path().to_str().unwrap(),
+            &[],
+            &[],
+            &["styles/global.css"],
+            &[],
+            &TransformOptions {
+                moz_cleanup: Some(MozCleanup::default()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let global = fs::read_to_string(output.path().join("styles/global.css")).unwrap();
+        assert!(!global.contains("-moz-"), "{global}");
+        assert!(global.contains("@layer base"), "{global}");
+        assert!(global.contains("margin: 0"), "{global}");
+        let removed: Vec<_> = report
+            .removed_css
+            .iter()
+            .map(|removed| removed.description.as_str())
+            .collect();
+        assert_eq!(
+            removed,
+            [
+                "@media (-moz-platform: macos)",
+                "selector .b:-moz-window-inactive",
+                "declaration -moz-default-appearance: button",
+            ]
+        );
+        assert!(report.removed_css[0].source.ends_with("styles/global.css"));
+    }
+
+    #[test]
+    fn test_conditional_css_imports() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "widgets/moz-a/moz-a.mjs",
+                    r#"import { html, MozLitElement } from "../vendor/lit.all.mjs";
+
+export default class MozA extends MozLitElement {
+  render() {
+    return html`<link rel="stylesheet" href="./moz-a.css" /><span></span>`;
+  }
+}
+"#,
+                ),
+                (
+                    "widgets/moz-a/moz-a.css",
+                    "@import url(\"../tokens/tokens.css\") layer(tokens);\n@import \"../tokens/wide.css\" screen and (min-width: 600px);\n",
+                ),
+                ("widgets/tokens/tokens.css", ":root { --size: 1px; }\n"),
+                ("widgets/tokens/wide.css", ".wide { margin: 0; }\n"),
+                (
+                    "widgets/vendor/lit.all.mjs",
+                    "export const html = 1, css = 2, MozLitElement = class {};\n",
+                ),
+                (
+                    "styles/global.css",
+                    "@import url(\"./layers.css\") layer(base) supports(display: grid);\n",
+                ),
+                ("styles/layers.css", "body { margin: 0; }\n"),
+            ],
+        );
+
+        let output = tempfile::tempdir().unwrap();
+        transform_lib(
+            root.path(),
+            output.path().to_str().unwrap(),
+            &[],
+            &[],
+            &["styles/global.css"],
+            &["widgets/moz-a/*"],
+            &TransformOptions::default(),
+        )
+        .unwrap();
+        let component =
+            fs::read_to_string(output.path().join("components/moz-a/moz-a.mjs")).unwrap();
+        for expected in [
+            r#"@import "../../dependencies/tokens.css" layer(tokens);"#,
+            r#"@import "../../dependencies/wide.css" screen and (width >= 600px);"#,
+        ] {
+            assert!(component.contains(expected), "{}", component);
+        }
+        let global = fs::read_to_string(output.path().join("styles/global.css")).unwrap();
+        assert!(
+            global.contains(
+                r#"@import "../dependencies/layers.css" layer(base) supports(display: grid);"#
+            ),
+            "{}",
+            global
+        );
+        assert!(output.path().join("dependencies/layers.css").is_file());
+    }
+
+    #[test]
+    fn test_typescript_sources() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "widgets/moz-a/moz-a.ts",
+                    r#"import { html, MozLitElement } from "../vendor/lit.all.mjs";
+import type { Size } from "./types.ts";
+import { type Label, label } from "../shared/helper.js";
+
+export default class MozA extends MozLitElement {
+  size: Size = "small";
+  render(): unknown {
+    const text: Label = label;
+    return html`<span>${text}</span>`;
+  }
+}
+"#,
+                ),
+                ("widgets/moz-a/types.ts", "export type Size = \"small\";\n"),
+                (
+                    "widgets/shared/helper.ts",
+                    "export type Label = string;\nexport const label: Label = \"a\";\n",
+                ),
+                (
+                    "widgets/vendor/lit.all.mjs",
+                    "export const html = 1, MozLitElement = class {};\n",
+                ),
+            ],
+        );
+
+        // TypeScript sources are ignored unless enabled
+        let (output, _) = run_fixture(
+            root.path(),
+            &["widgets/moz-a/*"],
+            &TransformOptions::default(),
+        );
+        assert!(!output.path().join("components/moz-a").exists());
+
+        let options = TransformOptions {
+            typescript: true,
+            ..TransformOptions::default()
+        };
+        let (output, report) = run_fixture(root.path(), &["widgets/moz-a/moz-a.ts"], &options);
+        assert!(report.unresolved_imports.is_empty());
+        let component =
+            fs::read_to_string(output.path().join("components/moz-a/moz-a.mjs")).unwrap();
+        assert!(
+            component.contains("import { label } from \"../../dependencies/helper.mjs\";"),
+            "{}",
+            component
+        );
+        assert!(
+            !component.contains("types.ts") && !component.contains(": Label"),
+            "{}",
+            component
+        );
+        assert_eq!(
+            fs::read_to_string(output.path().join("dependencies/helper.mjs")).unwrap(),
+            "export const label = \"a\";\n"
+        );
+        assert!(!output.path().join("dependencies/types.mjs").exists());
+    }
+
+    #[test]
+    fn test_json_module_imports() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "widgets/moz-a/moz-a.mjs",
+                    r#"import { html, MozLitElement } from "../vendor/lit.all.mjs";
+import tokens from "../tokens/tokens.json" with { type: "json" };
+export { default as tokens } from "../tokens/tokens.json" with { type: "json" };
+
+export default class MozA extends MozLitElement {
+  render() {
+    return html`<span>${tokens.size}</span>`;
+  }
+}
+"#,
+                ),
+                ("widgets/tokens/tokens.json", "{ \"size\": 4 }\n"),
+                (
+                    "widgets/vendor/lit.all.mjs",
+                    "export const html = 1, MozLitElement = class {};\n",
+                ),
+            ],
+        );
+
+        let (output, report) = run_fixture(
+            root.path(),
+            &["widgets/moz-a/moz-a.mjs"],
+            &TransformOptions::default(),
+        );
+        let component =
+            fs::read_to_string(output.path().join("components/moz-a/moz-a.mjs")).unwrap();
+        assert!(
+            component.contains(
+                "import tokens from \"../../dependencies/tokens.json\" with { type: \"json\" };"
+            ),
+            "{}",
+            component
+        );
+        assert!(
+            component.contains(
+                "from \"../../dependencies/tokens.json\" with { type: \"json\" };\nexport {"
+            ),
+            "{}",
+            component
+        );
+        assert_eq!(
+            fs::read_to_string(output.path().join("dependencies/tokens.json")).unwrap(),
+            "{ \"size\": 4 }\n"
+        );
+        let json = report
+            .files
+            .iter()
+            .find(|f| f.source.ends_with("tokens.json"))
+            .unwrap();
+        assert_eq!(json.outcome, FileOutcome::Copied);
+    }
+
+    #[test]
+    fn test_preprocessed_sources() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "widgets/moz-a/moz-a.mjs",
+                    r#"import { html, MozLitElement } from "../vendor/lit.all.mjs";
+#ifdef MOZ_A_NIGHTLY
+import { label } from "./nightly.mjs";
+#else
+import { label } from "./release.mjs";
+#endif
+
+export default class MozA extends MozLitElement {
+  render() {
+    return html`<span>${label}</span>`;
+  }
+}
+"#,
+                ),
+                ("widgets/moz-a/nightly.mjs", "export const label = 1;\n"),
+                ("widgets/moz-a/release.mjs", "export const label = 2;\n"),
+                (
+                    "widgets/vendor/lit.all.mjs",
+                    "export const html = 1, MozLitElement = class {};\n",
+                ),
+                (
+                    "widgets/jar.mn",
+                    "toolkit.jar:\n% content global %content/global/\n* content/global/elements/moz-a.mjs (moz-a/moz-a.mjs)\n",
+                ),
+            ],
+        );
+
+        let options = TransformOptions {
+            ifdef: HashMap::from([("MOZ_A_NIGHTLY".to_string(), true)]),
+            ..TransformOptions::default()
+        };
+        let output = tempfile::tempdir().unwrap();
+        let report = transform_lib(
+            root.path(),
+            output.path().to_str().unwrap(),
+            &["widgets/jar.mn"],
+            &[],
+            &[],
+            &["widgets/moz-a/moz-a.mjs"],
+            &options,
+        )
+        .unwrap();
+        let component =
+            fs::read_to_string(output.path().join("components/moz-a/moz-a.mjs")).unwrap();
+        assert!(
+            component.contains("../../dependencies/nightly.mjs"),
+            "{}",
+            component
+        );
+        assert!(!component.contains("#ifdef"), "{}", component);
+        assert!(output.path().join("dependencies/nightly.mjs").is_file());
+        assert!(!output.path().join("dependencies/release.mjs").exists());
+        let preprocessed: Vec<_> = report.files.iter().filter(|f| f.preprocessed).collect();
+        assert_eq!(preprocessed.len(), 1);
+        assert!(preprocessed[0].source.ends_with("moz-a/moz-a.mjs"));
+    }
+
+    #[test]
+    fn test_image_set_urls() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "themes/global.css",
+                    ".a { background-image: image-set(url(\"chrome://global/skin/icons/a.png\") 1x, url(\"chrome://global/skin/icons/a@2x.png\") 2x); }\n",
+                ),
+                ("themes/icons/a.png", "1x"),
+                ("themes/icons/a@2x.png", "2x"),
+                (
+                    "themes/jar.mn",
+                    "toolkit.jar:\n% skin global classic/1.0 %skin/classic/global/\n  skin/classic/global/icons/a.png (icons/a.png)\n  skin/classic/global/icons/a@2x.png (icons/a@2x.png)\n",
+                ),
+            ],
+        );
+
+        let output = tempfile::tempdir().unwrap();
+        transform_lib(
+            root.path(),
+            output.path().to_str().unwrap(),
+            &["themes/jar.mn"],
+            &[],
+            &["themes/global.css"],
+            &[],
+            &TransformOptions::default(),
+        )
+        .unwrap();
+        let global = fs::read_to_string(output.path().join("styles/global.css")).unwrap();
+        assert!(!global.contains("chrome://"), "{}", global);
+        for expected in [r#""../assets/a.png" 1x"#, r#""../assets/a@2x.png" 2x"#] {
+            assert!(global.contains(expected), "{}", global);
+        }
+        assert!(output.path().join("assets/a.png").is_file());
+        assert!(output.path().join("assets/a@2x.png").is_file());
+    }
+
+    #[test]
+    fn test_jar_mappings_under_a_prefix() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                ("themes/global.css", ".a { color: red; }\n"),
+                ("themes/icons/b.png", "b"),
+                ("themes/icons/a.png", "a"),
+                (
+                    "themes/jar.mn",
+                    "toolkit.jar:\n% skin global classic/1.0 %skin/classic/global/\n  skin/classic/global/global.css (global.css)\n  skin/classic/global/icons/b.png (icons/b.png)\n  skin/classic/global/icons/a.png (icons/a.png)\n",
+                ),
+            ],
+        );
+
+        let mappings = |prefix, filter| {
+            jar_mappings(
+                root.path(),
+                &["themes/jar.mn"],
+                &[],
+                &TransformOptions::default(),
+                prefix,
+                filter,
+            )
+            .unwrap()
+            .into_iter()
+            .map(|mapping| mapping.url)
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            mappings(Some("chrome://global/skin/icons/"), None),
+            [
+                "chrome://global/skin/icons/a.png",
+                "chrome://global/skin/icons/b.png"
+            ]
+        );
+        assert_eq!(
+            mappings(Some("chrome://global/skin/icons/"), Some("b.png")),
+            ["chrome://global/skin/icons/b.png"]
+        );
+        assert_eq!(mappings(None, None).len(), 3);
+    }
+
+    #[test]
+    fn test_chrome_url_for_file() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                ("themes/icons/a.png", "a"),
+                ("themes/icons/b.png", "b"),
+                (
+                    "themes/jar.mn",
+                    "toolkit.jar:\n% skin global classic/1.0 %skin/classic/global/\n  skin/classic/global/icons/a.png (icons/a.png)\n",
+                ),
+            ],
+        );
+
+        let url = |file| {
+            chrome_url_for_file(
+                root.path(),
+                &["themes/jar.mn"],
+                &[],
+                &TransformOptions::default(),
+                file,
+            )
+            .unwrap()
+        };
+        assert_eq!(
+            url("themes/icons/a.png").as_deref(),
+            Some("chrome://global/skin/icons/a.png")
+        );
+        let absolute = root.path().join("themes/icons/a.png");
+        assert_eq!(
+            url(absolute.to_str().unwrap()).as_deref(),
+            Some("chrome://global/skin/icons/a.png")
+        );
+        assert_eq!(url("themes/icons/b.png"), None);
+    }
+
+    #[test]
+    fn test_font_face_sources() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "themes/fonts.css",
+                    r#"@font-face {
+  font-family: Inter;
+  src: url("chrome://global/skin/fonts/inter.woff2") format("woff2") tech(variations),
+    url("chrome://global/skin/fonts/inter.woff") format("woff"), local("Inter");
+}
+"#,
+                ),
+                ("themes/fonts/inter.woff2", "woff2"),
+                ("themes/fonts/inter.woff", "woff"),
+                (
+                    "themes/jar.mn",
+                    "toolkit.jar:\n% skin global classic/1.0 %skin/classic/global/\n  skin/classic/global/fonts/inter.woff2 (fonts/inter.woff2)\n  skin/classic/global/fonts/inter.woff (fonts/inter.woff)\n",
+                ),
+            ],
+        );
+
+        let output = tempfile::tempdir().unwrap();
+        transform_lib(
+            root.path(),
+            output.path().to_str().unwrap(),
+            &["themes/jar.mn"],
+            &[],
+            &["themes/fonts.css"],
+            &[],
+            &TransformOptions::default(),
+        )
+        .unwrap();
+        let fonts = fs::read_to_string(output.path().join("styles/fonts.css")).unwrap();
+        assert!(
+            fonts.contains(
+                r#"src: url("../assets/inter.woff2") format("woff2") tech(variations), url("../assets/inter.woff") format("woff"), local(Inter);"#
+            ),
+            "{}",
+            fonts
+        );
+        assert!(output.path().join("assets/inter.woff2").is_file());
+        assert!(output.path().join("assets/inter.woff").is_file());
+    }
+
+    #[test]
+    fn test_css_parse_errors_are_reported() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "themes/global.css",
+                    r#".a:-moz-window-inactive { -moz-default-appearance: none; }
+@media (-moz-platform: windows) { .b { background: url("./icons/b.svg"); } }
+.c { color: red; } }
+"#,
+                ),
+                ("themes/icons/b.svg", "<svg/>"),
+            ],
+        );
+
+        let output = tempfile::tempdir().unwrap();
+        let report = transform_lib(
+            root.path(),
+            output.path().to_str().unwrap(),
+            &[],
+            &[],
+            &["themes/global.css"],
+            &[],
+            &TransformOptions::default(),
+        )
+        .unwrap();
+        let global = fs::read_to_string(output.path().join("styles/global.css")).unwrap();
+        for expected in [
+            ".a:-moz-window-inactive",
+            "-moz-default-appearance: none;",
+            "@media (-moz-platform: windows)",
+            r#"url("../assets/b.svg")"#,
+            "color: red;",
+        ] {
+            assert!(global.contains(expected), "{}", global);
+        }
+        assert_eq!(report.css_warnings.len(), 1);
+        let warning = &report.css_warnings[0];
+        assert!(warning.source.ends_with("themes/global.css"));
+        assert_eq!(warning.message, "4:1: Unexpected end of input");
+        assert!(!warning.verbatim);
+    }
+
+    #[test]
+    fn test_custom_property_urls() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "widgets/moz-a/moz-a.mjs",
+                    r#"import { css, html, MozLitElement } from "../vendor/lit.all.mjs";
+export default class MozA extends MozLitElement {
+  static styles = css`:host { --icon-x: url("chrome://global/skin/icons/fallback.svg"); }`;
+  render() {
+    return html`<span style="--icon: url('chrome://global/skin/icons/info.svg')"></span>`;
+  }
+}
+"#,
+                ),
+                (
+                    "widgets/vendor/lit.all.mjs",
+                    "export const css = 1, html = 1, MozLitElement = class {};\n",
+                ),
+                (
+                    "themes/tokens.css",
+                    ":root {\n  --icon-info: url(\"chrome://global/skin/icons/info.svg\");\n  --icon-a: var(--icon-b, var(--icon-c, url(\"chrome://global/skin/icons/fallback.svg\")));\n}\n",
+                ),
+                ("themes/icons/info.svg", "<svg/>"),
+                ("themes/icons/fallback.svg", "<svg id='f'/>"),
+                (
+                    "themes/jar.mn",
+                    "toolkit.jar:\n% skin global classic/1.0 %skin/classic/global/\n  skin/classic/global/icons/info.svg (icons/info.svg)\n  skin/classic/global/icons/fallback.svg (icons/fallback.svg)\n",
+                ),
+            ],
+        );
+        let output = tempfile::tempdir().unwrap();
+        transform_lib(
+            root.path(),
+            output.path().to_str().unwrap(),
+            &["themes/jar.mn"],
+            &[],
+            &["themes/tokens.css"],
+            &["widgets/moz-a/*.mjs"],
+            &TransformOptions::default(),
+        )
+        .unwrap();
+
+        assert!(output.path().join("assets/info.svg").exists());
+        assert!(output.path().join("assets/fallback.svg").exists());
+        let tokens = fs::read_to_string(output.path().join("styles/tokens.css")).unwrap();
+        assert!(
+            tokens.contains(r#"--icon-info: url("../assets/info.svg");"#),
+            "{tokens}"
+        );
+        assert!(
+            tokens.contains(r#"url("../assets/fallback.svg")"#),
+            "{tokens}"
+        );
+        let component =
+            fs::read_to_string(output.path().join("components/moz-a/moz-a.mjs")).unwrap();
+        assert!(
+            component.contains(r#"--icon-x: url("../../assets/fallback.svg");"#),
+            "{component}"
+        );
+        assert!(
+            component.contains(r#"new URL("../../assets/info.svg", import.meta.url)"#),
+            "{component}"
+        );
+    }
+
+    #[test]
+    fn test_auto_discovered_jars_resolve_chrome_urls() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "widgets/moz-a/moz-a.mjs",
+                    "import { certs } from \"chrome://pippki/content/certManager.mjs\";\nimport { helper } from \"chrome://global/content/helper.mjs\";\nexport default [certs, helper];\n",
+                ),
+                (
+                    "security/manager/pki/resources/jar.mn",
+                    "toolkit.jar:\n% content pippki %content/pippki/\n  content/pippki/certManager.mjs (content/certManager.mjs)\n",
+                ),
+                (
+                    "security/manager/pki/resources/content/certManager.mjs",
+                    "export const certs = 1;\n",
+                ),
+                // Discovered, but the explicit jar.mn maps the same URL elsewhere
+                (
+                    "toolkit/stale/jar.mn",
+                    "toolkit.jar:\n% content global %content/global/\n  content/global/helper.mjs (stale-helper.mjs)\n",
+                ),
+                (
+                    "toolkit/stale/stale-helper.mjs",
+                    "export const helper = 0;\n",
+                ),
+                (
+                    "toolkit/content/jar.mn",
+                    "toolkit.jar:\n% content global %content/global/\n  content/global/helper.mjs (helper.mjs)\n",
+                ),
+                ("toolkit/content/helper.mjs", "export const helper = 1;\n"),
+            ],
+        );
+
+        let output = tempfile::tempdir().unwrap();
+        let report = transform_lib(
+            root.path(),
+            output.path().to_str().unwrap(),
+            &["toolkit/content/jar.mn"],
+            &[],
+            &[],
+            &["widgets/moz-a/*.mjs"],
+            &TransformOptions {
+                auto_discover_jars: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let component =
+            fs::read_to_string(output.path().join("components/moz-a/moz-a.mjs")).unwrap();
+        assert!(
+            component.contains("\"../../dependencies/certManager.mjs\""),
+            "{}",
+            component
+        );
+        assert!(
+            component.contains("\"../../dependencies/helper.mjs\""),
+            "{}",
+            component
+        );
+        assert_eq!(
+            fs::read_to_string(output.path().join("dependencies/helper.mjs")).unwrap(),
+            "export const helper = 1;\n"
+        );
+        assert!(report.unresolved_imports.is_empty());
+
+        // The explicit jar.mn is not reported as discovered
+        let discovered: Vec<&Path> = report
+            .discovered_jars
+            .iter()
+            .map(|jar| jar.path.as_path())
+            .collect();
+        assert_eq!(
+            discovered,
+            [
+                Path::new("security/manager/pki/resources/jar.mn"),
+                Path::new("toolkit/stale/jar.mn")
+            ]
+        );
+        assert!(report.discovered_jars.iter().all(|jar| jar.mappings == 1));
+    }
+
+    #[test]
+    fn test_strict_mappings_fail_on_conflicts() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "widgets/moz-a/moz-a.mjs",
+                    "import { helper } from \"chrome://global/content/helper.mjs\";\nexport default helper;\n",
+                ),
+                (
+                    "toolkit/content/jar.mn",
+                    "toolkit.jar:\n% content global %content/global/\n  content/global/helper.mjs (helper.mjs)\n",
+                ),
+                ("toolkit/content/helper.mjs", "export const helper = 1;\n"),
+                (
+                    "browser/content/jar.mn",
+                    "toolkit.jar:\n% content global %content/global/\n  content/global/helper.mjs (helper.mjs)\n",
+                ),
+                ("browser/content/helper.mjs", "export const helper = 2;\n"),
+            ],
+        );
+        let jar_paths = ["toolkit/content/jar.mn", "browser/content/jar.mn"];
+
+        let output = tempfile::tempdir().unwrap();
+        transform_lib(
+            root.path(),
+            output.path().to_str().unwrap(),
+            &jar_paths,
+            &[],
+            &[],
+            &["widgets/moz-a/*.mjs"],
+            &TransformOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(output.path().join("dependencies/helper.mjs")).unwrap(),
+            "export const helper = 2;\n"
+        );
+
+        let error = transform_lib(
+            root.path(),
+            output.path().to_str().unwrap(),
+            &jar_paths,
+            &[],
+            &[],
+            &["widgets/moz-a/*.mjs"],
+            &TransformOptions {
+                strict_mappings: true,
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("chrome://global/content/helper.mjs"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_external_specifiers_are_left_untouched() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "widgets/moz-a/moz-a.mjs",
+                    r#"import { html, MozLitElement } from "../vendor/lit.all.mjs";
+import { shim } from "chrome://global/content/fluent/shim.mjs";
+
+export default class MozA extends MozLitElement {
+  render() {
+    return html`<link rel="stylesheet" href="./moz-a.css" /><span></span>`;
+  }
+}
+"#,
+                ),
+                (
+                    "widgets/moz-a/moz-a.css",
+                    ".a { background: url(\"chrome://branding/content/logo.svg\"); }\n",
+                ),
+                (
+                    "widgets/vendor/lit.all.mjs",
+                    "export const html = 1, css = 2, MozLitElement = class {};\n",
+                ),
+            ],
+        );
+        let options = TransformOptions {
+            external: vec![
+                "chrome://global/content/fluent/*".to_string(),
+                "chrome://branding/*".to_string(),
+            ],
+            ..Default::default()
+        };
+
+        let mut report = BuildReport::new();
+        let graph = build_graph(
+            root.path(),
+            &[ConfigSection {
+                jar_paths: &[],
+                mozbuild_paths: &[],
+                global_stylesheets: &[],
+                component_paths: &["widgets/moz-a/*.mjs"],
+            }],
+            &options,
+            &mut report,
+        )
+        .unwrap();
+        // Externals get no node and aren't reported as unresolved
+        assert_eq!(graph.file_count(), 3);
+        assert!(report.unresolved_imports.is_empty());
+
+        let (output, _) = run_fixture(root.path(), &["widgets/moz-a/*.mjs"], &options);
+        let component =
+            fs::read_to_string(output.path().join("components/moz-a/moz-a.mjs")).unwrap();
+        assert!(
+            component.contains("from \"chrome://global/content/fluent/shim.mjs\""),
+            "{}",
+            component
+        );
+        assert!(
+            component.contains("url(\"chrome://branding/content/logo.svg\")"),
+            "{}",
+            component
+        );
+    }
+
+    #[test]
+    fn test_cancelled_run_leaves_incomplete_marker() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(root.path(), COMPONENT_WITH_LOCAL_ICON);
+
+        // A finished run removes the marker
+        let (output, _) = run_fixture(
+            root.path(),
+            &["widgets/moz-a/*.mjs"],
+            &TransformOptions::default(),
+        );
+        assert!(!output.path().join(INCOMPLETE_MARKER).exists());
+
+        let run = |options: &TransformOptions| {
+            transform_lib(
+                root.path(),
+                output.path().to_str().unwrap(),
+                &[],
+                &[],
+                &[],
+                &["widgets/moz-a/*.mjs"],
+                options,
+            )
+        };
+
+        // A run cancelled before it starts keeps the previous output
+        let options = TransformOptions::default();
+        options.cancellation.cancel();
+        assert!(matches!(run(&options), Err(Error::Cancelled)));
+        assert!(output.path().join("components/moz-a/moz-a.mjs").exists());
+        assert!(!output.path().join(INCOMPLETE_MARKER).exists());
+
+        // Cancel once the run resolves its first import
+        struct CancelOnResolve(CancellationToken);
+        impl Resolver for CancelOnResolve {
+            fn resolve(&self, _: &Path, _: &str) -> Option<std::result::Result<PathBuf, String>> {
+                self.0.cancel();
+                None
+            }
+        }
+        let cancellation = CancellationToken::new();
+        let options = TransformOptions {
+            resolvers: vec![Arc::new(CancelOnResolve(cancellation.clone()))],
+            cancellation,
+            ..Default::default()
+        };
+        assert!(matches!(run(&options), Err(Error::Cancelled)));
+        assert!(output.path().join(INCOMPLETE_MARKER).exists());
+    }
+
+    #[test]
+    fn test_unused_global_stylesheets() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "widgets/moz-a/moz-a.mjs",
+                    r#"import { html, MozLitElement } from "../vendor/lit.all.mjs";
+
+export default class MozA extends MozLitElement {
+  render() {
+    return html`<link rel="stylesheet" href="./moz-a.css" /><span></span>`;
+  }
+}
+"#,
+                ),
+                (
+                    "widgets/moz-a/moz-a.css",
+                    ".a { color: var(--color-accent); }\n",
+                ),
+                (
+                    "widgets/vendor/lit.all.mjs",
+                    "export const html = 1, css = 2, MozLitElement = class {};\n",
+                ),
+                ("styles/tokens.css", ":root { --color-accent: blue; }\n"),
+                ("styles/common.css", "@import url(\"./imported.css\");\n"),
+                ("styles/imported.css", "body { margin: 0; }\n"),
+                ("styles/stale.css", ".old { color: red; }\n"),
+            ],
+        );
+
+        let run = |options: &TransformOptions| {
+            let output = tempfile::tempdir().unwrap();
+            let report = transform_lib(
+                root.path(),
+                output.path().to_str().unwrap(),
+                &[],
+                &[],
+                &["styles/*.css"],
+                &["widgets/moz-a/*.mjs"],
+                options,
+            )
+            .unwrap();
+            (output, report)
+        };
+        let names = |report: &BuildReport| -> Vec<String> {
+            report
+                .unused_globals
+                .iter()
+                .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+
+        // Imported sheets and sheets defining properties components read are used
+        let (output, report) = run(&TransformOptions {
+            keep_globals: vec!["styles/common.css".to_string()],
+            ..Default::default()
+        });
+        assert_eq!(names(&report), vec!["stale.css"]);
+        assert!(!report.pruned_unused_globals);
+        assert!(output.path().join("styles/stale.css").exists());
+
+        let (output, report) = run(&TransformOptions {
+            prune_unused_globals: true,
+            ..Default::default()
+        });
+        assert_eq!(names(&report), vec!["common.css", "stale.css"]);
+        assert!(report.pruned_unused_globals);
+        assert!(!output.path().join("styles/stale.css").exists());
+        assert!(!output.path().join("styles/common.css").exists());
+        assert!(output.path().join("styles/tokens.css").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_identical_global_stylesheets_are_merged() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                ("shared/tokens.css", ":root { --color: red; }\n"),
+                (
+                    "shared/all.css",
+                    "@import url(\"../linked/tokens-copy.css\");\n",
+                ),
+            ],
+        );
+        fs::create_dir_all(root.path().join("linked")).unwrap();
+        std::os::unix::fs::symlink(
+            root.path().join("shared/tokens.css"),
+            root.path().join("linked/tokens-copy.css"),
+        )
+        .unwrap();
+
+        let output = tempfile::tempdir().unwrap();
+        let report = transform_lib(
+            root.path(),
+            output.path().to_str().unwrap(),
+            &[],
+            &[],
+            &["shared/*.css", "linked/*.css"],
+            &[],
+            &TransformOptions::default(),
+        )
+        .unwrap();
+
+        // The shorter name wins and the other sheet is not written
+        assert!(output.path().join("styles/tokens.css").exists());
+        assert!(!output.path().join("styles/tokens-copy.css").exists());
+        assert_eq!(report.merged.len(), 1);
+        assert!(report.merged[0].canonical.ends_with("shared/tokens.css"));
+        assert!(report.merged[0].duplicates[0].ends_with("linked/tokens-copy.css"));
+
+        // References to the dropped sheet point at the canonical one
+        let all = fs::read_to_string(output.path().join("styles/all.css")).unwrap();
+        assert!(all.contains("tokens.css"), "{}", all);
+        assert!(!all.contains("tokens-copy"), "{}", all);
+    }
+
+    #[test]
+    fn test_identical_global_stylesheets_with_the_same_name_are_merged() {
+        let root = tempfile::tempdir().unwrap();
+        let tokens = ":root {\n  --color: red;\n}\n";
+        write_tree(
+            root.path(),
+            &[
+                ("toolkit/themes/tokens-shared.css", tokens),
+                ("browser/themes/tokens-shared.css", tokens),
+                (
+                    "browser/themes/all.css",
+                    "@import url(\"./tokens-shared.css\");\n",
+                ),
+            ],
+        );
+
+        let output = tempfile::tempdir().unwrap();
+        let report = transform_lib(
+            root.path(),
+            output.path().to_str().unwrap(),
+            &[],
+            &[],
+            &["toolkit/themes/*.css", "browser/themes/*.css"],
+            &[],
+            &TransformOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(report.merged.len(), 1);
+        assert_eq!(report.merged[0].duplicates.len(), 1);
+        let written = fs::read_to_string(output.path().join("styles/tokens-shared.css")).unwrap();
+        assert!(written.contains("--color: red"), "{written}");
+        let all = fs::read_to_string(output.path().join("styles/all.css")).unwrap();
+        assert!(all.contains("@import \"./tokens-shared.css\";"), "{all}");
+    }
+
+    #[test]
+    fn test_conflicting_global_stylesheets_fail() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "toolkit/themes/tokens-shared.css",
+                    ":root {\n  --color: red;\n  --size: 4px;\n}\n",
+                ),
+                (
+                    "browser/themes/tokens-shared.css",
+                    ":root {\n  --color: blue;\n  --size: 4px;\n  --gap: 2px;\n}\n",
+                ),
+            ],
+        );
+
+        let output = tempfile::tempdir().unwrap();
+        let error = transform_lib(
+            root.path(),
+            output.path().to_str().unwrap(),
+            &[],
+            &[],
+            &["toolkit/themes/*.css", "browser/themes/*.css"],
+            &[],
+            &TransformOptions::default(),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("styles/tokens-shared.css <- ["), "{error}");
+        assert!(
+            error.contains("differ from line 2, lines only in the first: 2, only in the second: 1"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn test_orphaned_stylesheets_are_reported() {
+        let root = tempfile::tempdir().unwrap();
+        let mut files = COMPONENT_WITH_LOCAL_ICON.to_vec();
+        files.extend([
+            (
+                "widgets/moz-b/moz-b.mjs",
+                r#"import { html, MozLitElement } from "../vendor/lit.all.mjs";
+
+const template = html`<link rel="stylesheet" href="./moz-b.css" /><span></span>`;
+
+export default class MozB extends MozLitElement {
+  render() {
+    return template;
+  }
+}
+"#,
+            ),
+            ("widgets/moz-b/moz-b.css", ".b { color: red; }\n"),
+        ]);
+        write_tree(root.path(), &files);
+
+        let (_output, report) = run_fixture(
+            root.path(),
+            &["widgets/moz-a/*.mjs", "widgets/moz-b/*.mjs"],
+            &TransformOptions::default(),
+        );
+        let orphaned: Vec<String> = report
+            .orphaned_stylesheets
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(orphaned, vec!["moz-b.css"]);
+    }
+
+    #[test]
+    fn test_imported_stylesheets_are_transformed_first() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                ("styles/a.css", "@import url(\"./m.css\");\n"),
+                ("styles/m.css", "@import url(\"./z.css\");\n"),
+                ("styles/z.css", "body { margin: 0; }\n"),
+            ],
+        );
+
+        let output = tempfile::tempdir().unwrap();
+        let report = transform_lib(
+            root.path(),
+            output.path().to_str().unwrap(),
+            &[],
+            &[],
+            &["styles/*.css"],
+            &[],
+            &TransformOptions::default(),
+        )
+        .unwrap();
+        let order: Vec<String> = report
+            .files
+            .iter()
+            .map(|f| f.dist.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(order, vec!["z.css", "m.css", "a.css"]);
+    }
+
+    #[test]
+    fn test_dedupe_outputs_merges_identical_dependencies() {
+        let root = tempfile::tempdir().unwrap();
+        let component = root.path().join("moz-a/moz-a.mjs");
+        let copy_a = root.path().join("a/helper.mjs");
+        let copy_b = root.path().join("b/shared-helper.mjs");
+        let other = root.path().join("c/other.mjs");
+        for (path, content) in [
+            (&component, "export {};"),
+            (&copy_a, "export const help = () => 1;\n"),
+            (&copy_b, "export const help = () => 1;\n"),
+            (&other, "export const other = 2;\n"),
+        ] {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+
+        let mut graph = DependencyGraph::new();
+        graph.add_file(
+            component.clone(),
+            FileType::JsComponent,
+            TargetLocation::Component("moz-a".to_string()),
+        );
+        for path in [&copy_a, &copy_b, &other] {
+            graph.add_file(path.clone(), FileType::JsFile, TargetLocation::Dependency);
+        }
+        graph
+            .add_dependency(
+                &component,
+                &copy_a,
+                "chrome://a/helper.mjs",
+                ImportKind::StaticImport,
+                None,
+            )
+            .unwrap();
+        graph
+            .add_dependency(
+                &component,
+                &copy_b,
+                "chrome://b/shared-helper.mjs",
+                ImportKind::StaticImport,
+                None,
+            )
+            .unwrap();
+        graph
+            .add_dependency(
+                &component,
+                &other,
+                "chrome://c/other.mjs",
+                ImportKind::StaticImport,
+                None,
+            )
+            .unwrap();
+
+        let mut report = BuildReport::new();
+        let mut rendered = RenderedOutputs::new();
+        dedupe_outputs(
+            &mut graph,
+            &mut report,
+            &TransformOptions::default(),
+            &mut rendered,
+        )
+        .unwrap();
+
+        let replacements = graph.get_import_replacements(&component).unwrap();
+        assert_eq!(
+            replacements["chrome://a/helper.mjs"],
+            "../../dependencies/helper.mjs"
+        );
+        assert_eq!(
+            replacements["chrome://b/shared-helper.mjs"],
+            "../../dependencies/helper.mjs"
+        );
+        assert_eq!(
+            replacements["chrome://c/other.mjs"],
+            "../../dependencies/other.mjs"
+        );
+        assert_eq!(report.merged.len(), 1);
+        assert_eq!(report.merged[0].canonical, copy_a);
+        assert_eq!(report.merged[0].duplicates, vec![copy_b.clone()]);
+        assert!(graph.find_dist_collisions().is_empty());
+
+        let output = tempfile::tempdir().unwrap();
+        transform_and_write_files(
+            &mut graph,
+            rendered,
+            output.path(),
+            &mut report,
+            &TransformOptions::default(),
+        )
+        .unwrap();
+        assert!(output.path().join("dependencies/helper.mjs").exists());
+        assert!(
+            !output
+                .path()
+                .join("dependencies/shared-helper.mjs")
+                .exists()
+        );
+    }
+
+    #[test]
+    fn test_reused_outputs_import_merged_and_renamed_files() {
+        let root = tempfile::tempdir().unwrap();
+        write_tree(
+            root.path(),
+            &[
+                (
+                    "widgets/moz-a/moz-a.mjs",
+                    r#"import { help } from "../../a/helper.mjs";
+import { X } from "../../a/x.mjs";
+import * as uses from "../../b/uses.mjs";
+export { help, X, uses };
+"#,
+                ),
+                ("a/helper.mjs", "export const help = () => 1;\n"),
+                ("b/shared-helper.mjs", "export const help = () => 1;\n"),
+                ("a/x.mjs", "export const X = 1;\n"),
+                ("b/x.mjs", "export const X = 2;\n"),
+                (
+                    "b/uses.mjs",
+                    r#"import { help } from "./shared-helper.mjs";
+import { X } from "./x.mjs";
+export { help, X };
+"#,
+                ),
+            ],
+        );
+
+        let (output, report) = run_fixture(
+            root.path(),
+            &["widgets/moz-a/*.mjs"],
+            &TransformOptions {
+                dedupe: true,
+                rename_collisions: true,
+                ..Default::default()
+            },
+        );
+
+        // uses.mjs is rendered to compare it before its imports are merged
+        // and renamed, the written file must import their final paths
+        let dist_of = |source: &str| {
+            let entry = report
+                .files
+                .iter()
+                .find(|f| f.source.ends_with(source))
+                .unwrap();
+            entry
+                .dist
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .into_owned()
+        };
+        let uses = fs::read_to_string(output.path().join("dependencies/uses.mjs")).unwrap();
+        assert!(uses.contains("\"./helper.mjs\""), "{uses}");
+        assert!(!uses.contains("shared-helper"), "{uses}");
+        let x = dist_of("b/x.mjs");
+        assert_ne!(x, "x.mjs");
+        assert!(uses.contains(&format!("\"./{x}\"")), "{uses}");
+    }
+
+    #[test]
+    fn test_glob_regular_files_expands_trailing_slash_one_level() {
+        let root = tempfile::tempdir().unwrap();
+        let card = root.path().join("widgets/moz-card");
+        fs::create_dir_all(card.join("nested")).unwrap();
+        fs::write(card.join("moz-card.mjs"), "").unwrap();
+        fs::write(card.join("moz-card.css"), "").unwrap();
+        fs::write(card.join("nested/inner.mjs"), "").unwrap();
+
+        let files = glob_regular_files(root.path(), "widgets/moz-card/").unwrap();
+        assert_eq!(
+            files,
+            vec![card.join("moz-card.css"), card.join("moz-card.mjs")]
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::dependency_graph::{DependencyGraph, FileType};
+use crate::utils::file_utils;
+use crate::{Error, Result};
+
+/// Output path of the generated loader, relative to the output directory.
+pub const LOADER_PATH: &str = "loader.mjs";
+
+/// Lazy loader logic, appended to the generated tag map.
+const LOADER_TEMPLATE: &str = r#"const pending = new Map(Object.entries(components));
+
+/**
+ * Import the component defining `tag`, if it isn't loaded yet.
+ *
+ * @param {string} tag
+ * @returns {Promise<CustomElementConstructor>}
+ */
+export function loadComponent(tag) {
+  const path = pending.get(tag);
+  if (path) {
+    pending.delete(tag);
+    import(path);
+  }
+  return customElements.whenDefined(tag);
+}
+
+function loadUsedComponents(root) {
+  for (const tag of [...pending.keys()]) {
+    if (root.localName === tag || root.querySelector?.(tag)) {
+      loadComponent(tag);
+    }
+  }
+}
+
+loadUsedComponents(document.documentElement);
+new MutationObserver(records => {
+  for (const record of records) {
+    for (const node of record.addedNodes) {
+      if (node.nodeType === Node.ELEMENT_NODE) {
+        loadUsedComponents(node);
+      }
+    }
+  }
+}).observe(document.documentElement, { childList: true, subtree: true });
+"#;
+
+/// Map the custom element tags defined by components to the path of their
+/// module, relative to the loader.
+///
+/// If several components define the same tag, the first by source path wins.
+pub fn component_tags(dep_graph: &DependencyGraph) -> Result<BTreeMap<String, String>> {
+    let mut components: Vec<(PathBuf, PathBuf)> = dep_graph
+        .files_by_type(FileType::JsComponent)
+        .filter_map(|f| f.get_dist_path().map(|dist| (f.path.clone(), dist)))
+        .collect();
+    components.sort();
+
+    let mut tags: BTreeMap<String, (PathBuf, String)> = BTreeMap::new();
+    for (source, dist_path) in components {
+        let defined =
+            crate::dependencies::js::custom_element_tags_from_file(&source).map_err(|e| {
+                Error::Custom(format!(
+                    "Failed to find custom elements in {:?}: {}",
+                    source, e
+                ))
+            })?;
+        for tag in defined {
+            if let Some((first, _)) = tags.get(&tag) {
+                eprintln!(
+                    "Warning: {} defines <{}>, which {} already defines. The loader imports {}.",
+                    source.display(),
+                    tag,
+                    first.display(),
+                    first.display()
+                );
+                continue;
+            }
+            let relative = file_utils::compute_relative_path(Path::new(LOADER_PATH), &dist_path);
+            tags.insert(tag, (source.clone(), relative));
+        }
+    }
+
+    Ok(tags
+        .into_iter()
+        .map(|(tag, (_, path))| (tag, path))
+        .collect())
+}
+
+/// Generate the loader module for a tag to module path map.
+pub fn loader_module(components: &BTreeMap<String, String>) -> String {
+    let mut code = String::from(
+        "// Generated by mozcomp. Imports components the first time their tag appears in the document.\n\nconst components = {\n",
+    );
+    for (tag, path) in components {
+        // JSON strings are valid JS string literals
+        code.push_str(&format!(
+            "  {}: {},\n",
+            serde_json::Value::from(tag.as_str()),
+            serde_json::Value::from(path.as_str())
+        ));
+    }
+    code.push_str("};\n\n");
+    code.push_str(LOADER_TEMPLATE);
+    code
+}
@@ -2,11 +2,14 @@ mod config;
 
 use std::fs;
 
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use thiserror::Error;
 
 use config::Config;
-use mozcomp::transform_lib;
+use mozcomp::{
+    ConfigSection, PrefixResolver, Resolver, TransformOptions, chrome_url_for_file,
+    component_dependencies, explain_file, jar_mappings, resolve_import, transform_lib_multi,
+};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -21,6 +24,65 @@ struct Args {
     /// Path to the configuration file
     #[arg(default_value = "mozcomp.toml")]
     config: String,
+
+    /// Print the classification, passes, replacements and dist path of a
+    /// single file (source path or chrome:// URL) instead of building
+    #[arg(long, value_name = "FILE")]
+    print_effective_pipeline: Option<String>,
+
+    /// Ignore the cache directory of the config, neither reading nor writing
+    /// cached jar.mn files
+    #[arg(long)]
+    no_cache: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List every file that ends up in a component's output through its
+    /// imports, with its dist path and the import chain that pulls it in
+    Deps {
+        /// Name of the component, e.g. moz-button
+        component: String,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+    },
+    /// List the URL mappings of the jar.mn and moz.build files, with the
+    /// file each one comes from
+    Jars {
+        /// Only list mappings whose URL or source path contains this text
+        filter: Option<String>,
+
+        /// Only list mappings whose URL starts with this prefix, e.g.
+        /// chrome://global/skin/icons/
+        #[arg(long)]
+        prefix: Option<String>,
+    },
+    /// Print the chrome or resource URL a file is loaded with, from the
+    /// jar.mn and moz.build files
+    Url {
+        /// The file, relative to the current directory or the Firefox root
+        file: String,
+    },
+    /// Resolve an import of a file and print each step that was attempted
+    Resolve {
+        /// The importing file, relative to the current directory or the
+        /// Firefox root
+        file: String,
+
+        /// The import specifier, e.g. chrome://global/content/vendor/lit.all.mjs
+        specifier: String,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Format {
+    Text,
+    Json,
 }
 
 #[derive(Error, Debug)]
@@ -34,37 +96,183 @@ pub enum MainError {
 }
 
 fn main() -> Result<(), MainError> {
+    env_logger::init();
     let args = Args::parse();
 
     // Read and parse the config file
     let config_str = fs::read_to_string(&args.config)?;
     let config: Config = toml::from_str(&config_str)?;
 
-    // Call the transform_lib function with the parsed configuration
-    transform_lib(
-        std::path::Path::new(&args.firefox_root),
-        &args.output,
-        &config
-            .jar_paths
-            .iter()
-            .map(String::as_str)
-            .collect::<Vec<_>>(),
-        &config
-            .mozbuild_paths
-            .iter()
-            .map(String::as_str)
-            .collect::<Vec<_>>(),
-        &config
-            .globals_stylesheets
-            .iter()
-            .map(String::as_str)
-            .collect::<Vec<_>>(),
-        &config
-            .component_paths
+    let jar_paths: Vec<&str> = config.jar_paths.iter().map(String::as_str).collect();
+    let mozbuild_paths: Vec<&str> = config.mozbuild_paths.iter().map(String::as_str).collect();
+    let globals_stylesheets: Vec<&str> = config
+        .globals_stylesheets
+        .iter()
+        .map(String::as_str)
+        .collect();
+    let component_paths: Vec<&str> = config.component_paths.iter().map(String::as_str).collect();
+    // Longest prefixes first, so `brand://icons/` wins over `brand://`
+    let mut custom_schemes: Vec<(String, String)> = config.custom_schemes.into_iter().collect();
+    custom_schemes.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then_with(|| a.0.cmp(&b.0)));
+    let resolvers: Vec<std::sync::Arc<dyn Resolver>> = custom_schemes
+        .into_iter()
+        .map(|(prefix, dir)| {
+            std::sync::Arc::new(PrefixResolver::new(
+                prefix,
+                std::path::Path::new(&args.firefox_root).join(dir),
+            )) as std::sync::Arc<dyn Resolver>
+        })
+        .collect();
+    let options = TransformOptions {
+        rename_collisions: config.rename_collisions,
+        preserve_structure: config.preserve_structure,
+        precompress: config.precompress,
+        precompress_threshold: config.precompress_threshold,
+        size_budget: config.size_budget,
+        budget_basis: config.budget_basis,
+        dedupe: config.dedupe,
+        component_local_assets: config.component_local_assets,
+        graph_json: config.graph_json.map(std::path::PathBuf::from),
+        lit_compat: config.lit_compat,
+        promote_root_sheets: config.promote_root_sheets,
+        prune_unused_globals: config.prune_unused_globals,
+        keep_globals: config.keep_globals,
+        loader: config.loader,
+        external: config.external,
+        typescript: config.typescript,
+        ignore_literals: config.ignore_literals,
+        auto_discover_jars: config.auto_discover_jars,
+        jar_discovery_depth: config.jar_discovery_depth,
+        jar_exclude: config.jar_exclude,
+        cache_dir: config
+            .cache_dir
+            .filter(|_| !args.no_cache)
+            .map(std::path::PathBuf::from),
+        trace_attributes: config.trace_attributes,
+        manifest_paths: config.manifest_paths,
+        ifdef: config.ifdef,
+        ifdef_unknown: config.ifdef_unknown,
+        strict_mappings: config.strict_mappings,
+        fallback_search: config.fallback_search,
+        module_roots: config.module_roots,
+        extension_fallbacks: config.extension_fallbacks,
+        missing_files: config.missing_files,
+        strict_case: config.strict_case,
+        follow_symlinks: config.follow_symlinks,
+        aliases: config.aliases,
+        privileged_imports: config.privileged_imports,
+        inline_fetched_stylesheets: config.inline_fetched_stylesheets,
+        flatten_imports: config.flatten_imports,
+        css_targets: config.css_targets,
+        css_minify: config.css_minify,
+        css_license_headers: config.css_license_headers,
+        unknown_css_url: config.unknown_css_url,
+        moz_cleanup: config.moz_cleanup,
+        resolvers,
+        trace_resolution: config.trace_resolution,
+        ..Default::default()
+    };
+    let firefox_root = std::path::Path::new(&args.firefox_root);
+
+    // The subcommands see the components of every section like a full build
+    let extra_sections: Vec<[Vec<&str>; 4]> = config
+        .sections
+        .iter()
+        .map(|section| {
+            [
+                &section.jar_paths,
+                &section.mozbuild_paths,
+                &section.globals_stylesheets,
+                &section.component_paths,
+            ]
+            .map(|paths| paths.iter().map(String::as_str).collect())
+        })
+        .collect();
+    let sections: Vec<ConfigSection> = std::iter::once(ConfigSection {
+        jar_paths: &jar_paths,
+        mozbuild_paths: &mozbuild_paths,
+        global_stylesheets: &globals_stylesheets,
+        component_paths: &component_paths,
+    })
+    .chain(
+        extra_sections
             .iter()
-            .map(String::as_str)
-            .collect::<Vec<_>>(),
+            .map(|[jars, mozbuilds, globals, components]| ConfigSection {
+                jar_paths: jars,
+                mozbuild_paths: mozbuilds,
+                global_stylesheets: globals,
+                component_paths: components,
+            }),
     )
-    .map_err(|e| MainError::TransformError(format!("{}", e)))?;
+    .collect();
+
+    if let Some(file) = &args.print_effective_pipeline {
+        let explanation = explain_file(firefox_root, &sections, &options, file)
+            .map_err(|e| MainError::TransformError(format!("{}", e)))?;
+        print!("{}", explanation);
+        return Ok(());
+    }
+
+    if let Some(Command::Jars { filter, prefix }) = &args.command {
+        let mappings = jar_mappings(
+            firefox_root,
+            &jar_paths,
+            &mozbuild_paths,
+            &options,
+            prefix.as_deref(),
+            filter.as_deref(),
+        )
+        .map_err(|e| MainError::TransformError(format!("{}", e)))?;
+        for mapping in mappings {
+            println!(
+                "{} -> {} ({})",
+                mapping.url,
+                mapping.path.display(),
+                mapping.origin
+            );
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Url { file }) = &args.command {
+        let url = chrome_url_for_file(firefox_root, &jar_paths, &mozbuild_paths, &options, file)
+            .map_err(|e| MainError::TransformError(format!("{}", e)))?
+            .ok_or_else(|| MainError::TransformError(format!("No URL maps to {}", file)))?;
+        println!("{}", url);
+        return Ok(());
+    }
+
+    if let Some(Command::Resolve { file, specifier }) = &args.command {
+        let trace = resolve_import(
+            firefox_root,
+            &jar_paths,
+            &mozbuild_paths,
+            &options,
+            file,
+            specifier,
+        )
+        .map_err(|e| MainError::TransformError(format!("{}", e)))?;
+        print!("{}", trace);
+        return Ok(());
+    }
+
+    if let Some(Command::Deps { component, format }) = &args.command {
+        let dependencies = component_dependencies(firefox_root, &sections, &options, component)
+            .map_err(|e| MainError::TransformError(format!("{}", e)))?;
+        match format {
+            Format::Text => print!("{}", dependencies),
+            Format::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(&dependencies.to_json())
+                    .map_err(|e| MainError::TransformError(format!("{}", e)))?
+            ),
+        }
+        return Ok(());
+    }
+
+    // Call the transform_lib_multi function with the parsed configuration
+    let report = transform_lib_multi(firefox_root, &args.output, &sections, &options)
+        .map_err(|e| MainError::TransformError(format!("{}", e)))?;
+    report.print_summary();
     Ok(())
 }
@@ -0,0 +1,661 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::dependency_graph::{DependencyGraph, FileNode, FileType, TargetLocation};
+use crate::transform::css::browser_targets;
+use crate::transform::js::is_typescript;
+use crate::utils::file_utils;
+use crate::{LitCompat, PrivilegedImports, TransformOptions, flattens_imports};
+
+/// A single pass that can run on a file, and whether it applies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassInfo {
+    /// Name of the pass
+    pub name: &'static str,
+    /// Whether the pass will run on the file
+    pub enabled: bool,
+    /// Why the pass is enabled or disabled
+    pub reason: String,
+}
+
+/// Everything mozcomp will do to a single file, as returned by `explain_file`.
+#[derive(Debug, Clone)]
+pub struct PipelineExplanation {
+    /// Source path, relative to the Firefox root when the file lives inside it
+    pub source: PathBuf,
+    /// Type of the file
+    pub file_type: String,
+    /// Where the file is placed in the output
+    pub target_location: String,
+    /// Component the file belongs to, if any
+    pub component: Option<String>,
+    /// chrome:// or resource:// URL the file was imported through
+    pub original_url: Option<String>,
+    /// Other URLs that map to the same file
+    pub url_aliases: Vec<String>,
+    /// Graph passes that decide the output location
+    pub graph_passes: Vec<PassInfo>,
+    /// Transformers that run on the file content
+    pub transform_passes: Vec<PassInfo>,
+    /// Import specifier replacements applied to the file, sorted by specifier
+    pub replacements: Vec<(String, String)>,
+    /// Output path relative to the output directory, None if omitted
+    pub dist_path: Option<PathBuf>,
+}
+
+impl PipelineExplanation {
+    /// Describe the pipeline for a node of a fully discovered graph.
+    pub(crate) fn new(
+        dep_graph: &DependencyGraph,
+        file: &FileNode,
+        firefox_root: &Path,
+        options: &TransformOptions,
+    ) -> Self {
+        let component = match &file.target_location {
+            TargetLocation::Component(name) => Some(name.clone()),
+            _ => None,
+        };
+
+        let mut replacements: Vec<(String, String)> = if file.get_dist_path().is_some() {
+            dep_graph
+                .get_import_replacements(&file.path)
+                .unwrap_or_default()
+                .into_iter()
+                .collect()
+        } else {
+            Vec::new()
+        };
+        replacements.sort();
+
+        Self {
+            source: display_path(&file.path, firefox_root),
+            file_type: format!("{:?}", file.file_type),
+            target_location: format!("{:?}", file.target_location),
+            component,
+            original_url: file.original_url.clone(),
+            url_aliases: file.url_aliases.clone(),
+            graph_passes: graph_passes(file, options),
+            transform_passes: transform_passes(dep_graph, file, options),
+            replacements,
+            dist_path: file.get_dist_path(),
+        }
+    }
+}
+
+/// Make a path relative to the Firefox root for display, if it lives inside it.
+pub(crate) fn display_path(path: &Path, firefox_root: &Path) -> PathBuf {
+    let path = file_utils::make_relative_to_cwd(&path.to_path_buf());
+    let root = file_utils::make_relative_to_cwd(&firefox_root.to_path_buf());
+    match pathdiff::diff_paths(&path, &root) {
+        Some(relative)
+            if !relative
+                .components()
+                .any(|c| matches!(c, std::path::Component::ParentDir)) =>
+        {
+            relative
+        }
+        _ => path,
+    }
+}
+
+/// Describe where an option value comes from.
+fn option_reason<T: PartialEq + fmt::Debug>(name: &str, value: &T, default: &T) -> String {
+    if value == default {
+        format!("default `{}` = {:?}", name, value)
+    } else {
+        format!("config option `{}` = {:?}", name, value)
+    }
+}
+
+/// The passes of `finalize_graph` and discovery that decide where a file is
+/// written, in the order they run.
+fn graph_passes(file: &FileNode, options: &TransformOptions) -> Vec<PassInfo> {
+    let defaults = TransformOptions::default();
+    let is_dependency_or_asset = matches!(
+        file.target_location,
+        TargetLocation::Dependency | TargetLocation::Asset
+    );
+    let is_global = file.target_location == TargetLocation::CssGlobal;
+    let mut passes = Vec::new();
+
+    let inlined_sheet = file.promoted
+        || (file.file_type == FileType::CssFile && file.target_location == TargetLocation::Omit);
+    passes.push(PassInfo {
+        name: "promote-root-sheets",
+        enabled: file.promoted,
+        reason: if file.promoted {
+            format!(
+                "only defines :root custom properties, {}",
+                option_reason(
+                    "promote_root_sheets",
+                    &options.promote_root_sheets,
+                    &defaults.promote_root_sheets
+                )
+            )
+        } else if inlined_sheet && !options.promote_root_sheets {
+            option_reason(
+                "promote_root_sheets",
+                &options.promote_root_sheets,
+                &defaults.promote_root_sheets,
+            )
+        } else if inlined_sheet {
+            "doesn't only define :root custom properties".to_string()
+        } else {
+            "only applies to stylesheets inlined into components".to_string()
+        },
+    });
+
+    passes.push(PassInfo {
+        name: "prune-unused-globals",
+        enabled: false,
+        reason: if !is_global {
+            "only applies to global stylesheets".to_string()
+        } else if options.prune_unused_globals {
+            format!(
+                "referenced or kept, {}",
+                option_reason(
+                    "prune_unused_globals",
+                    &options.prune_unused_globals,
+                    &defaults.prune_unused_globals
+                )
+            )
+        } else {
+            option_reason(
+                "prune_unused_globals",
+                &options.prune_unused_globals,
+                &defaults.prune_unused_globals,
+            )
+        },
+    });
+
+    let applies_to_assets = matches!(
+        file.target_location,
+        TargetLocation::Asset | TargetLocation::Component(_)
+    ) && file.file_type == FileType::OpaqueFile;
+    passes.push(PassInfo {
+        name: "component-local-assets",
+        enabled: options.component_local_assets && applies_to_assets,
+        reason: if applies_to_assets {
+            option_reason(
+                "component_local_assets",
+                &options.component_local_assets,
+                &defaults.component_local_assets,
+            )
+        } else {
+            "only applies to assets".to_string()
+        },
+    });
+
+    for (name, option, value, default) in [
+        (
+            "preserve-structure",
+            "preserve_structure",
+            options.preserve_structure,
+            defaults.preserve_structure,
+        ),
+        ("dedupe", "dedupe", options.dedupe, defaults.dedupe),
+    ] {
+        passes.push(PassInfo {
+            name,
+            enabled: value && is_dependency_or_asset,
+            reason: if is_dependency_or_asset {
+                option_reason(option, &value, &default)
+            } else {
+                "only applies to dependencies and assets".to_string()
+            },
+        });
+    }
+
+    passes.push(PassInfo {
+        name: "dedupe-global-stylesheets",
+        enabled: is_global,
+        reason: if is_global {
+            "always runs on global stylesheets".to_string()
+        } else {
+            "only applies to global stylesheets".to_string()
+        },
+    });
+
+    let renamed = file.dist_file_name.is_some();
+    passes.push(PassInfo {
+        name: "rename-collisions",
+        enabled: renamed,
+        reason: format!(
+            "{}, {}",
+            if renamed {
+                "dist path collided with another file"
+            } else {
+                "no dist path collision"
+            },
+            option_reason(
+                "rename_collisions",
+                &options.rename_collisions,
+                &defaults.rename_collisions
+            )
+        ),
+    });
+
+    passes
+}
+
+/// The transformers `render_file` runs on the content of a file, in the
+/// order they run.
+fn transform_passes(
+    dep_graph: &DependencyGraph,
+    file: &FileNode,
+    options: &TransformOptions,
+) -> Vec<PassInfo> {
+    let defaults = TransformOptions::default();
+    let pass = |name, enabled, reason: &str| PassInfo {
+        name,
+        enabled,
+        reason: reason.to_string(),
+    };
+
+    if file.get_dist_path().is_none() {
+        return vec![pass(
+            "write",
+            false,
+            "omitted from output, inlined into the files importing it",
+        )];
+    }
+
+    match file.file_type {
+        FileType::JsComponent | FileType::JsFile => {
+            let css_imports = dep_graph
+                .get_css_imports(&file.path)
+                .into_iter()
+                .map(|(statement, _)| statement)
+                .collect::<std::collections::HashSet<_>>()
+                .len();
+            let inline_reason = if css_imports > 0 {
+                format!("imports {} stylesheet(s)", css_imports)
+            } else {
+                "no stylesheet imports".to_string()
+            };
+            let typescript = is_typescript(&file.path);
+            let mut passes = vec![
+                pass(
+                    "preprocess",
+                    file.preprocessed,
+                    if file.preprocessed {
+                        "listed with `*` in a jar.mn file"
+                    } else {
+                        "not listed with `*` in a jar.mn file"
+                    },
+                ),
+                pass(
+                    "strip-types",
+                    typescript,
+                    if typescript {
+                        "TypeScript source"
+                    } else {
+                        "not a TypeScript source"
+                    },
+                ),
+                PassInfo {
+                    name: "css-inline",
+                    enabled: css_imports > 0,
+                    reason: inline_reason,
+                },
+                pass(
+                    "import-css",
+                    css_imports > 0,
+                    match options.lit_compat {
+                        LitCompat::Lit3 => {
+                            "adds `css` to the lit.all.mjs import when stylesheets were inlined"
+                        }
+                        LitCompat::Lit2 => {
+                            "adds `import { css } from \"lit\"` when stylesheets were inlined (lit_compat = 2)"
+                        }
+                    },
+                ),
+                pass("url-rewrite", true, "always runs on JS files"),
+                external_pass(options),
+                PassInfo {
+                    name: "privileged-imports",
+                    enabled: options.privileged_imports != PrivilegedImports::Keep,
+                    reason: option_reason(
+                        "privileged_imports",
+                        &options.privileged_imports,
+                        &defaults.privileged_imports,
+                    ),
+                },
+                PassInfo {
+                    name: "fetched-stylesheet",
+                    enabled: options.inline_fetched_stylesheets,
+                    reason: option_reason(
+                        "inline_fetched_stylesheets",
+                        &options.inline_fetched_stylesheets,
+                        &defaults.inline_fetched_stylesheets,
+                    ),
+                },
+                pass("module-url", true, "always runs on JS files"),
+                PassInfo {
+                    name: "icon-template-import",
+                    enabled: true,
+                    reason: format!(
+                        "always runs on JS files, {}",
+                        option_reason(
+                            "trace_attributes",
+                            &options.trace_attributes,
+                            &defaults.trace_attributes
+                        )
+                    ),
+                },
+                PassInfo {
+                    name: "lit-compat",
+                    enabled: options.lit_compat == LitCompat::Lit2,
+                    reason: option_reason("lit_compat", &options.lit_compat, &defaults.lit_compat),
+                },
+                pass(
+                    "import-attributes",
+                    true,
+                    "runs on JS files that changed, keeps the import attributes of re-exports",
+                ),
+            ];
+            // The inlined stylesheets are transformed like written ones
+            if css_imports > 0 {
+                passes.extend(stylesheet_passes(options).into_iter().map(|pass| PassInfo {
+                    reason: format!("on inlined stylesheets, {}", pass.reason),
+                    ..pass
+                }));
+            }
+            passes
+        }
+        FileType::CssFile => {
+            let flattens = flattens_imports(file, options);
+            let mut passes = vec![
+                PassInfo {
+                    name: "flatten-imports",
+                    enabled: flattens,
+                    reason: if file.target_location == TargetLocation::CssGlobal {
+                        option_reason(
+                            "flatten_imports",
+                            &options.flatten_imports,
+                            &defaults.flatten_imports,
+                        )
+                    } else {
+                        "only applies to global stylesheets".to_string()
+                    },
+                },
+                pass("css-url-replace", true, "always runs on CSS files"),
+                pass(
+                    "css-import-replace",
+                    !flattens,
+                    if flattens {
+                        "the imported sheets are inlined instead"
+                    } else {
+                        "runs on CSS files that aren't flattened"
+                    },
+                ),
+                external_pass(options),
+            ];
+            passes.extend(stylesheet_passes(options));
+            passes
+        }
+        FileType::OpaqueFile => vec![pass("copy", true, "opaque files are copied as-is")],
+    }
+}
+
+/// Imports matching `external` are left untouched by the passes rewriting
+/// imports and URLs.
+fn external_pass(options: &TransformOptions) -> PassInfo {
+    PassInfo {
+        name: "external",
+        enabled: !options.external.is_empty(),
+        reason: option_reason("external", &options.external, &Vec::new()),
+    }
+}
+
+/// The passes of written and inlined stylesheets that only depend on options.
+fn stylesheet_passes(options: &TransformOptions) -> Vec<PassInfo> {
+    let defaults = TransformOptions::default();
+    let targets = browser_targets(&options.css_targets).is_ok_and(|t| t.is_some());
+    vec![
+        PassInfo {
+            name: "moz-cleanup",
+            enabled: options.moz_cleanup.is_some(),
+            reason: if options.moz_cleanup.is_some() {
+                "config option `moz_cleanup` is set".to_string()
+            } else {
+                "default `moz_cleanup` is unset".to_string()
+            },
+        },
+        PassInfo {
+            name: "unknown-css-url",
+            enabled: true,
+            reason: option_reason(
+                "unknown_css_url",
+                &options.unknown_css_url,
+                &defaults.unknown_css_url,
+            ),
+        },
+        PassInfo {
+            name: "css-targets",
+            enabled: targets,
+            reason: if targets {
+                "config option `css_targets` is set".to_string()
+            } else {
+                "default `css_targets` is empty".to_string()
+            },
+        },
+        PassInfo {
+            name: "css-minify",
+            enabled: options.css_minify,
+            reason: option_reason("css_minify", &options.css_minify, &defaults.css_minify),
+        },
+        PassInfo {
+            name: "css-license-headers",
+            enabled: options.css_license_headers,
+            reason: option_reason(
+                "css_license_headers",
+                &options.css_license_headers,
+                &defaults.css_license_headers,
+            ),
+        },
+    ]
+}
+
+impl fmt::Display for PipelineExplanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "File: {}", self.source.display())?;
+        if let Some(url) = &self.original_url {
+            writeln!(f, "URL: {}", url)?;
+        }
+        for alias in &self.url_aliases {
+            writeln!(f, "Alias: {}", alias)?;
+        }
+        writeln!(f, "File type: {}", self.file_type)?;
+        writeln!(f, "Target location: {}", self.target_location)?;
+        if let Some(component) = &self.component {
+            writeln!(f, "Component: {}", component)?;
+        }
+
+        writeln!(f, "Graph passes:")?;
+        for pass in &self.graph_passes {
+            write_pass(f, pass)?;
+        }
+        writeln!(f, "Transform passes:")?;
+        for pass in &self.transform_passes {
+            write_pass(f, pass)?;
+        }
+
+        writeln!(f, "Replacements:")?;
+        if self.replacements.is_empty() {
+            writeln!(f, "  (none)")?;
+        }
+        for (from, to) in &self.replacements {
+            writeln!(f, "  {} -> {}", from, to)?;
+        }
+
+        match &self.dist_path {
+            Some(dist_path) => writeln!(f, "Dist path: {}", dist_path.display()),
+            None => writeln!(f, "Dist path: (omitted)"),
+        }
+    }
+}
+
+fn write_pass(f: &mut fmt::Formatter<'_>, pass: &PassInfo) -> fmt::Result {
+    writeln!(
+        f,
+        "  [{}] {} ({})",
+        if pass.enabled { "x" } else { " " },
+        pass.name,
+        pass.reason
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MozCleanup;
+    use crate::dependency_graph::ImportKind;
+    use crate::transform::css::CssTargets;
+    use regex::Regex;
+    use std::collections::HashSet;
+
+    /// The passes the steps of `finalize_graph` and the transformers are
+    /// listed as
+    const PASSES: &[(&str, &str)] = &[
+        ("find_unused_globals", "prune-unused-globals"),
+        ("remove_file", "prune-unused-globals"),
+        ("omit_flattened_imports", "flatten-imports"),
+        ("place_component_local_assets", "component-local-assets"),
+        ("preserve_source_structure", "preserve-structure"),
+        ("dedupe_outputs", "dedupe"),
+        ("dedupe_global_stylesheets", "dedupe-global-stylesheets"),
+        ("separate_dist_collisions", "rename-collisions"),
+        ("name_dist_collisions", "rename-collisions"),
+        ("Transformer", "strip-types"),
+        ("CssInlineTransformer", "css-inline"),
+        ("ImportCssTransformer", "import-css"),
+        ("UrlTransformer", "url-rewrite"),
+        ("PrivilegedImportTransformer", "privileged-imports"),
+        ("FetchedStylesheetTransformer", "fetched-stylesheet"),
+        ("ModuleUrlTransformer", "module-url"),
+        ("IconTemplateImportTransformer", "icon-template-import"),
+        ("ImportAttributesTransformer", "import-attributes"),
+        ("UrlReplacer", "css-url-replace"),
+        ("ImportReplacer", "css-import-replace"),
+        ("MozCleaner", "moz-cleanup"),
+    ];
+
+    /// Calls in `finalize_graph` that aren't passes
+    const NOT_PASSES: &[&str] = &["format", "map_err", "is_empty", "println", "new", "Ok"];
+
+    /// Options that configure a pass listed under another option
+    const CONFIGURES_OTHER_PASSES: &[&str] = &["keep_globals", "ifdef", "ifdef_unknown"];
+
+    fn function_body<'a>(source: &'a str, name: &str) -> &'a str {
+        let signature = source.find(&format!("fn {name}(")).unwrap();
+        let start = signature + source[signature..].find(" {\n").unwrap();
+        let end = start + source[start..].find("\n}\n").unwrap();
+        &source[start..end]
+    }
+
+    /// Every pass and option a file can go through, from a JS component
+    /// that inlines a stylesheet, a global stylesheet and an asset.
+    fn explained(options: &TransformOptions) -> (HashSet<&'static str>, String) {
+        let root = Path::new("/firefox");
+        let component = root.join("moz-a/moz-a.mjs");
+        let sheet = root.join("moz-a/moz-a.css");
+        let global = root.join("themes/global.css");
+        let asset = root.join("icons/icon.svg");
+        let mut graph = DependencyGraph::new();
+        graph.add_file(
+            component.clone(),
+            FileType::JsComponent,
+            TargetLocation::Component("moz-a".to_string()),
+        );
+        graph.add_file(sheet.clone(), FileType::CssFile, TargetLocation::Omit);
+        graph.add_file(global.clone(), FileType::CssFile, TargetLocation::CssGlobal);
+        graph.add_file(asset.clone(), FileType::OpaqueFile, TargetLocation::Asset);
+        graph
+            .add_dependency(
+                &component,
+                &sheet,
+                "./moz-a.css",
+                ImportKind::HtmlLink,
+                None,
+            )
+            .unwrap();
+
+        let mut names = HashSet::new();
+        let mut reasons = String::new();
+        for path in [&component, &global, &asset] {
+            let file = graph.get_file(path).unwrap();
+            let explanation = PipelineExplanation::new(&graph, file, root, options);
+            for pass in explanation
+                .graph_passes
+                .iter()
+                .chain(&explanation.transform_passes)
+            {
+                names.insert(pass.name);
+                reasons.push_str(&pass.reason);
+                reasons.push('\n');
+            }
+        }
+        (names, reasons)
+    }
+
+    #[test]
+    fn test_every_pass_is_explained() {
+        let options = TransformOptions {
+            flatten_imports: true,
+            moz_cleanup: Some(MozCleanup::default()),
+            css_targets: CssTargets::Query("firefox >= 115".to_string()),
+            external: vec!["lit".to_string()],
+            ..Default::default()
+        };
+        let (names, reasons) = explained(&options);
+
+        let lib = include_str!("lib.rs");
+        let finalize = function_body(lib, "finalize_graph");
+        let render = function_body(lib, "render_file");
+        let js = include_str!("transform/js.rs");
+        let css = include_str!("transform/css.rs");
+
+        let call = Regex::new(r"\b([a-z_]+)\(").unwrap();
+        let constructor = Regex::new(r"\b([A-Z][A-Za-z]*)::new\(").unwrap();
+        let mut steps: Vec<&str> = call
+            .captures_iter(finalize)
+            .map(|c| c.get(1).unwrap().as_str())
+            .filter(|name| !NOT_PASSES.contains(name))
+            .collect();
+        for source in [function_body(js, "transform_from_string"), css] {
+            steps.extend(
+                constructor
+                    .captures_iter(source)
+                    .map(|c| c.get(1).unwrap().as_str())
+                    .filter(|name| {
+                        name.ends_with("Transformer")
+                            || name.ends_with("Replacer")
+                            || name.ends_with("Cleaner")
+                    }),
+            );
+        }
+        for step in steps {
+            let pass = PASSES
+                .iter()
+                .find(|(name, _)| *name == step)
+                .map(|(_, pass)| *pass)
+                .unwrap_or_else(|| panic!("`{step}` is not listed as a pass"));
+            assert!(names.contains(pass), "{pass} is not explained");
+        }
+
+        let option = Regex::new(r"\boptions\.([a-z_]+)").unwrap();
+        for source in [finalize, render, function_body(lib, "flattens_imports")] {
+            for name in option
+                .captures_iter(source)
+                .map(|c| c.get(1).unwrap().as_str())
+            {
+                assert!(
+                    CONFIGURES_OTHER_PASSES.contains(&name)
+                        || reasons.contains(&format!("`{name}`")),
+                    "option `{name}` is not explained: {reasons}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,411 @@
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::dependency_graph::GraphStatistics;
+
+/// Describes how a file ended up in the output directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileOutcome {
+    /// The file was parsed, transformed and re-emitted
+    Transformed,
+    /// The file went through the transform pipeline, but no transformer
+    /// changed it, so the original source bytes were written
+    Passthrough,
+    /// The file was copied without processing
+    Copied,
+    /// The file has no source and was generated by mozcomp
+    Generated,
+}
+
+/// Which size of the output files budget checks are evaluated against.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BudgetBasis {
+    /// Uncompressed size
+    #[default]
+    Raw,
+    /// Gzip-compressed size
+    Gzip,
+    /// Brotli-compressed size
+    Brotli,
+}
+
+/// Sizes of a written file in bytes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileSizes {
+    /// Size of the file as written
+    pub raw: u64,
+    /// Gzip-compressed size, if computed
+    pub gzip: Option<u64>,
+    /// Brotli-compressed size, if computed
+    pub brotli: Option<u64>,
+}
+
+impl FileSizes {
+    /// Get the size for the given basis, falling back to the raw size if the
+    /// compressed size was not computed (e.g. for binary files).
+    pub fn for_basis(&self, basis: BudgetBasis) -> u64 {
+        match basis {
+            BudgetBasis::Raw => self.raw,
+            BudgetBasis::Gzip => self.gzip.unwrap_or(self.raw),
+            BudgetBasis::Brotli => self.brotli.unwrap_or(self.raw),
+        }
+    }
+}
+
+/// A single written file in the build report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileReport {
+    /// Path to the source file
+    pub source: PathBuf,
+    /// Path of the written file, relative to the output directory
+    pub dist: PathBuf,
+    /// How the file was produced
+    pub outcome: FileOutcome,
+    /// Sizes of the written file
+    pub sizes: FileSizes,
+    /// Hash of the source content, if the file has a source
+    pub source_hash: Option<u64>,
+    /// Size of the source in bytes, if the file has a source
+    pub source_size: Option<u64>,
+    /// Whether the source went through the preprocessor, so the output
+    /// differs from the file in the tree
+    pub preprocessed: bool,
+}
+
+/// Files with identical output that were merged into a single copy.
+#[derive(Debug, Clone)]
+pub struct MergedFiles {
+    /// Source path of the file whose output was kept
+    pub canonical: PathBuf,
+    /// Source paths of the files that now point at the canonical copy
+    pub duplicates: Vec<PathBuf>,
+}
+
+/// A `ChromeUtils` call loading a privileged module that was left in the
+/// output, see `PrivilegedImports`.
+#[derive(Debug, Clone)]
+pub struct PrivilegedImport {
+    /// Source path of the file with the call
+    pub source: PathBuf,
+    /// The module specifier as written
+    pub specifier: String,
+}
+
+/// An import that could not be resolved to a file.
+#[derive(Debug, Clone)]
+pub struct UnresolvedImport {
+    /// Source path of the importing file
+    pub source: PathBuf,
+    /// The import specifier as written
+    pub specifier: String,
+    /// 1-based line of the import in the importing file
+    pub line: usize,
+    /// 1-based column of the import in the importing file
+    pub column: usize,
+    /// Why resolution failed
+    pub reason: String,
+    /// How often the importing file references the specifier
+    pub references: usize,
+}
+
+/// A part of a stylesheet that couldn't be parsed. The rule or declaration
+/// is missing from the output, or the whole stylesheet was copied as it is.
+#[derive(Debug, Clone)]
+pub struct CssWarning {
+    /// Source path of the stylesheet
+    pub source: PathBuf,
+    /// What couldn't be parsed, as `line:column: message`
+    pub message: String,
+    /// Whether the stylesheet couldn't be parsed at all and was copied
+    /// without rewriting its URLs
+    pub verbatim: bool,
+}
+
+/// A `url()` of a stylesheet that points at a file with no output, kept or
+/// removed according to `TransformOptions::unknown_css_url`.
+#[derive(Debug, Clone)]
+pub struct UnmappedCssUrl {
+    /// Source path of the stylesheet
+    pub source: PathBuf,
+    pub url: String,
+    /// Whether the declaration with the URL was removed
+    pub stripped: bool,
+}
+
+/// A Firefox-only part of a stylesheet removed by
+/// `TransformOptions::moz_cleanup`.
+#[derive(Debug, Clone)]
+pub struct RemovedCss {
+    /// Source path of the stylesheet
+    pub source: PathBuf,
+    /// What was removed, e.g. `@media (-moz-platform: macos)` or
+    /// `declaration -moz-user-focus: normal`
+    pub description: String,
+}
+
+/// Summary of a build, returned by `transform_lib`.
+/// A jar.mn file found by `auto_discover_jars`, with how much it contributed
+/// and cost, to decide which ones to exclude.
+#[derive(Debug, Clone)]
+pub struct DiscoveredJar {
+    /// Path of the jar.mn file, relative to the Firefox root
+    pub path: PathBuf,
+    /// Number of URL mappings the file added
+    pub mappings: usize,
+    /// Time spent reading and parsing the file
+    pub parse_time: Duration,
+    /// Why the file couldn't be parsed, if it couldn't
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct BuildReport {
+    /// All files written to the output directory
+    pub files: Vec<FileReport>,
+    /// Groups of identical files merged by the dedupe pass
+    pub merged: Vec<MergedFiles>,
+    /// Global stylesheets that nothing references
+    pub unused_globals: Vec<PathBuf>,
+    /// Whether the unused global stylesheets were left out of the output
+    pub pruned_unused_globals: bool,
+    /// Stylesheets that were omitted to be inlined into a component, but that
+    /// no template linked with a matching href
+    pub orphaned_stylesheets: Vec<PathBuf>,
+    /// Imports that could not be resolved, left untouched or stubbed
+    /// depending on `missing_files`
+    pub unresolved_imports: Vec<UnresolvedImport>,
+    /// Privileged modules that are still loaded with `ChromeUtils` calls,
+    /// which don't work on the web
+    pub privileged_imports: Vec<PrivilegedImport>,
+    /// Rules and declarations of stylesheets that couldn't be parsed
+    pub css_warnings: Vec<CssWarning>,
+    /// URLs of stylesheets that point at files with no output
+    pub unmapped_css_urls: Vec<UnmappedCssUrl>,
+    /// Firefox-only parts of stylesheets that were removed
+    pub removed_css: Vec<RemovedCss>,
+    /// Statistics of the dependency graph the output was built from
+    pub graph: GraphStatistics,
+    /// jar.mn files found by `auto_discover_jars`
+    pub discovered_jars: Vec<DiscoveredJar>,
+    /// Configured aliases that imports were resolved through, sorted
+    pub aliases_hit: Vec<String>,
+}
+
+impl BuildReport {
+    /// Create a new empty build report.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a file of `size` bytes that was written to the output directory.
+    /// Returns the new entry, so source metadata can be filled in.
+    pub fn record_file(
+        &mut self,
+        source: PathBuf,
+        dist: PathBuf,
+        outcome: FileOutcome,
+        size: u64,
+    ) -> &mut FileReport {
+        self.files.push(FileReport {
+            source,
+            dist,
+            outcome,
+            sizes: FileSizes {
+                raw: size,
+                ..Default::default()
+            },
+            source_hash: None,
+            source_size: None,
+            preprocessed: false,
+        });
+        self.files.last_mut().unwrap()
+    }
+
+    /// Record files that were merged into a canonical copy.
+    pub fn record_merged(&mut self, canonical: PathBuf, duplicates: Vec<PathBuf>) {
+        self.merged.push(MergedFiles {
+            canonical,
+            duplicates,
+        });
+    }
+
+    /// Get the number of written files with the given outcome.
+    pub fn count(&self, outcome: FileOutcome) -> usize {
+        self.files.iter().filter(|f| f.outcome == outcome).count()
+    }
+
+    /// Get the total size of all written files for the given basis.
+    pub fn total_size(&self, basis: BudgetBasis) -> u64 {
+        self.files.iter().map(|f| f.sizes.for_basis(basis)).sum()
+    }
+
+    /// Check the total output size against a budget in bytes.
+    /// Returns an error message describing the overrun if the budget is exceeded.
+    pub fn check_budget(&self, budget: u64, basis: BudgetBasis) -> Result<(), String> {
+        let total = self.total_size(basis);
+        if total > budget {
+            return Err(format!(
+                "Output size {} bytes ({:?}) exceeds the budget of {} bytes",
+                total, basis, budget
+            ));
+        }
+        Ok(())
+    }
+
+    /// Print a short summary of the build to stdout.
+    pub fn print_summary(&self) {
+        println!("=== Build Report ===");
+        println!(
+            "Dependency graph: {} files, {} imports, {} roots{}",
+            self.graph.node_count,
+            self.graph.edge_count,
+            self.graph.roots.len(),
+            if self.graph.has_cycles {
+                ", has cycles"
+            } else {
+                ""
+            }
+        );
+        println!(
+            "Files written: {} (transformed: {}, passthrough: {}, copied: {}, generated: {})",
+            self.files.len(),
+            self.count(FileOutcome::Transformed),
+            self.count(FileOutcome::Passthrough),
+            self.count(FileOutcome::Copied),
+            self.count(FileOutcome::Generated)
+        );
+        // Compressed sizes are only measured for precompression or a
+        // compressed budget basis
+        let mut sizes = vec![format!("{} bytes raw", self.total_size(BudgetBasis::Raw))];
+        if self.files.iter().any(|f| f.sizes.gzip.is_some()) {
+            sizes.push(format!("{} bytes gzip", self.total_size(BudgetBasis::Gzip)));
+        }
+        if self.files.iter().any(|f| f.sizes.brotli.is_some()) {
+            sizes.push(format!(
+                "{} bytes brotli",
+                self.total_size(BudgetBasis::Brotli)
+            ));
+        }
+        println!("Total size: {}", sizes.join(", "));
+        if !self.discovered_jars.is_empty() {
+            let failed = self
+                .discovered_jars
+                .iter()
+                .filter(|jar| jar.error.is_some())
+                .count();
+            let unused = self
+                .discovered_jars
+                .iter()
+                .filter(|jar| jar.error.is_none() && jar.mappings == 0)
+                .count();
+            let parse_time: Duration = self.discovered_jars.iter().map(|jar| jar.parse_time).sum();
+            println!(
+                "Discovered jar.mn files: {} ({} without mappings, {} failed to parse), parsed in {:.2?}",
+                self.discovered_jars.len(),
+                unused,
+                failed,
+                parse_time
+            );
+            if let Some(slowest) = self.discovered_jars.iter().max_by_key(|jar| jar.parse_time) {
+                println!(
+                    "  slowest: {} ({:.2?})",
+                    slowest.path.display(),
+                    slowest.parse_time
+                );
+            }
+        }
+        if !self.merged.is_empty() {
+            println!("Merged identical files:");
+            for merged in &self.merged {
+                println!("  {}", merged.canonical.display());
+                for duplicate in &merged.duplicates {
+                    println!("    <- {}", duplicate.display());
+                }
+            }
+        }
+        if self.files.iter().any(|f| f.preprocessed) {
+            println!("Preprocessed sources, whose output differs from the tree:");
+            for file in self.files.iter().filter(|f| f.preprocessed) {
+                println!("  {}", file.source.display());
+            }
+        }
+        if !self.aliases_hit.is_empty() {
+            println!("Aliases used: {}", self.aliases_hit.join(", "));
+        }
+        if !self.unresolved_imports.is_empty() {
+            println!("Unresolved imports:");
+            for import in &self.unresolved_imports {
+                println!(
+                    "  {}:{}:{}: {} ({}){}",
+                    import.source.display(),
+                    import.line,
+                    import.column,
+                    import.specifier,
+                    import.reason,
+                    if import.references > 1 {
+                        format!(", referenced {} times", import.references)
+                    } else {
+                        String::new()
+                    }
+                );
+            }
+        }
+        if !self.css_warnings.is_empty() {
+            println!("Stylesheet parts that couldn't be parsed:");
+            for warning in &self.css_warnings {
+                println!(
+                    "  {}:{}{}",
+                    warning.source.display(),
+                    warning.message,
+                    if warning.verbatim {
+                        " (copied without rewriting URLs)"
+                    } else {
+                        " (dropped)"
+                    }
+                );
+            }
+        }
+        if !self.unmapped_css_urls.is_empty() {
+            println!("Stylesheet URLs without an output file:");
+            for unmapped in &self.unmapped_css_urls {
+                println!(
+                    "  {}: {}{}",
+                    unmapped.source.display(),
+                    unmapped.url,
+                    if unmapped.stripped {
+                        " (declaration removed)"
+                    } else {
+                        " (kept)"
+                    }
+                );
+            }
+        }
+        if !self.removed_css.is_empty() {
+            println!("Firefox-only stylesheet parts removed:");
+            for removed in &self.removed_css {
+                println!("  {}: {}", removed.source.display(), removed.description);
+            }
+        }
+        if !self.privileged_imports.is_empty() {
+            println!("Privileged imports left untouched:");
+            for import in &self.privileged_imports {
+                println!("  {}: {}", import.source.display(), import.specifier);
+            }
+        }
+        if !self.unused_globals.is_empty() {
+            if self.pruned_unused_globals {
+                println!("Pruned unused global stylesheets:");
+            } else {
+                println!(
+                    "Warning: global stylesheets nothing references (set prune_unused_globals to drop them, or list them in keep_globals):"
+                );
+            }
+            for source in &self.unused_globals {
+                println!("  {}", source.display());
+            }
+        }
+        println!("=== End Report ===");
+    }
+}
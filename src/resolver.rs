@@ -0,0 +1,75 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Resolves specifiers mozcomp doesn't know about, like a custom `brand://`
+/// scheme, to files on disk.
+///
+/// Resolvers are consulted in order after aliases are applied and before the
+/// built-in handling of chrome/resource URLs and relative paths. Returning
+/// `None` leaves the specifier to the next resolver and the built-in rules,
+/// an error fails the import with the given reason. The returned path goes
+/// through the same extension fallbacks and existence checks as built-in
+/// results.
+pub trait Resolver: Send + Sync {
+    fn resolve(&self, current_file: &Path, specifier: &str) -> Option<Result<PathBuf, String>>;
+}
+
+impl fmt::Debug for dyn Resolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Resolver(..)")
+    }
+}
+
+/// Resolves specifiers starting with a prefix, e.g. `brand://`, to the rest
+/// of the specifier below a directory.
+#[derive(Debug, Clone)]
+pub struct PrefixResolver {
+    prefix: String,
+    dir: PathBuf,
+}
+
+impl PrefixResolver {
+    pub fn new(prefix: impl Into<String>, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            dir: dir.into(),
+        }
+    }
+}
+
+impl Resolver for PrefixResolver {
+    fn resolve(&self, _current_file: &Path, specifier: &str) -> Option<Result<PathBuf, String>> {
+        let rest = specifier.strip_prefix(self.prefix.as_str())?;
+        if rest.split('/').any(|component| component == "..") {
+            return Some(Err(format!(
+                "'{}' leaves {}",
+                specifier,
+                self.dir.display()
+            )));
+        }
+        Some(Ok(self.dir.join(rest)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefix_resolver() {
+        let resolver = PrefixResolver::new("brand://", "browser/branding/nightly");
+        let file = Path::new("widgets/moz-a/moz-a.mjs");
+        assert_eq!(
+            resolver.resolve(file, "brand://icons/logo.svg"),
+            Some(Ok(PathBuf::from("browser/branding/nightly/icons/logo.svg")))
+        );
+        assert_eq!(
+            resolver.resolve(file, "chrome://global/content/a.mjs"),
+            None
+        );
+        assert!(matches!(
+            resolver.resolve(file, "brand://../secret.mjs"),
+            Some(Err(_))
+        ));
+    }
+}
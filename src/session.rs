@@ -0,0 +1,153 @@
+use std::path::{Path, PathBuf};
+
+use crate::dependency_graph::DependencyGraph;
+use crate::report::{BuildReport, FileOutcome};
+use crate::utils::{self, file_utils};
+use crate::{
+    ConfigSection, Error, INCOMPLETE_MARKER, MissingFiles, Result, TransformOptions,
+    discover_sections, finalize_graph, loader, transform_and_write_files,
+};
+
+/// One run of the pipeline and all of its state.
+///
+/// The session owns the dependency graph and the build report; JAR resolvers
+/// and import matchers are built from the options at the start of every run.
+/// Nothing is kept in statics, so sessions with the same options and inputs
+/// produce the same output, also when several run one after the other in the
+/// same process. Paths are made relative to the current directory at the
+/// time of the run.
+pub struct Session {
+    options: TransformOptions,
+    graph: DependencyGraph,
+    report: BuildReport,
+}
+
+impl Session {
+    pub fn new(options: TransformOptions) -> Self {
+        Self {
+            options,
+            graph: DependencyGraph::new(),
+            report: BuildReport::new(),
+        }
+    }
+
+    /// Build the dependency graph of all `sections` and write the output.
+    /// The output directory is cleared first, and the graph and report of an
+    /// earlier run of this session are discarded.
+    pub fn run(
+        &mut self,
+        firefox_root: &Path,
+        output_path: &str,
+        sections: &[ConfigSection],
+    ) -> Result<&BuildReport> {
+        let Session {
+            options,
+            graph: dep_graph,
+            report,
+        } = self;
+        *dep_graph = DependencyGraph::new();
+        *report = BuildReport::new();
+        let output_dir = Path::new(output_path);
+
+        // Leave the output of the previous run alone if cancelled already
+        options.cancellation.check()?;
+        file_utils::ensure_directory_exists(output_dir)
+            .map_err(|e| Error::Custom(format!("Failed to ensure directory exists: {e}")))?;
+        file_utils::clear_directory(output_dir)
+            .map_err(|e| Error::Custom(format!("Failed to clear directory: {e}")))?;
+
+        // Create output directories
+        file_utils::create_output_directories(output_dir)
+            .map_err(|e| Error::Custom(format!("Failed to create output directories: {e}")))?;
+
+        // Marks the output as incomplete until the run finishes successfully, so
+        // consumers can tell a cancelled or failed run from a finished one
+        let incomplete_marker = output_dir.join(INCOMPLETE_MARKER);
+        std::fs::write(&incomplete_marker, "")?;
+
+        *dep_graph = discover_sections(firefox_root, sections, options, report)?;
+        if options.missing_files == MissingFiles::Error && !report.unresolved_imports.is_empty() {
+            let imports: Vec<String> = report
+                .unresolved_imports
+                .iter()
+                .map(|import| {
+                    format!(
+                        "{}:{}:{}: {} ({})",
+                        import.source.display(),
+                        import.line,
+                        import.column,
+                        import.specifier,
+                        import.reason
+                    )
+                })
+                .collect();
+            return Err(Error::Custom(format!(
+                "Unresolved imports (set missing_files to \"warn\" or \"stub\" to continue without them):\n  {}",
+                imports.join("\n  ")
+            )));
+        }
+        let rendered = finalize_graph(firefox_root, dep_graph, options, report)?;
+        dep_graph.compute_hashes();
+        dep_graph.debug_print();
+        report.graph = dep_graph.statistics();
+
+        if let Some(graph_json) = &options.graph_json {
+            let json = serde_json::to_string_pretty(&dep_graph.to_json())
+                .map_err(|e| Error::Custom(format!("Failed to serialize dependency graph: {e}")))?;
+            std::fs::write(graph_json, json)?;
+        }
+
+        // Transform and write all files
+        println!("Transforming and writing files...");
+        transform_and_write_files(dep_graph, rendered, output_dir, report, options)?;
+
+        if options.loader {
+            let code = loader::loader_module(&loader::component_tags(dep_graph)?);
+            std::fs::write(output_dir.join(loader::LOADER_PATH), &code)?;
+            report.record_file(
+                PathBuf::from(loader::LOADER_PATH),
+                PathBuf::from(loader::LOADER_PATH),
+                FileOutcome::Generated,
+                code.len() as u64,
+            );
+        }
+
+        // Compress once all written content is final
+        let compression_settings = utils::compression::CompressionSettings {
+            precompress: options.precompress.clone(),
+            threshold: options.precompress_threshold,
+            budget_basis: options.budget_basis,
+            cancellation: options.cancellation.clone(),
+        };
+        utils::compression::compress_outputs(output_dir, &mut report.files, &compression_settings)?;
+
+        if let Some(budget) = options.size_budget {
+            report
+                .check_budget(budget, options.budget_basis)
+                .map_err(Error::Custom)?;
+        }
+
+        std::fs::remove_file(&incomplete_marker)?;
+        Ok(report)
+    }
+
+    /// The options the session runs with.
+    pub fn options(&self) -> &TransformOptions {
+        &self.options
+    }
+
+    /// The dependency graph of the last run.
+    pub fn graph(&self) -> &DependencyGraph {
+        &self.graph
+    }
+
+    /// The build report of the last run.
+    pub fn report(&self) -> &BuildReport {
+        &self.report
+    }
+
+    /// Take the build report of the last run.
+    pub fn into_report(self) -> BuildReport {
+        self.report
+    }
+}
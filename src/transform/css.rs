@@ -1,51 +1,646 @@
 use lightningcss::{
     printer::PrinterOptions,
-    stylesheet::{ParserOptions, StyleSheet},
+    rules::{CssRule, CssRuleList, import::ImportRule},
+    stylesheet::StyleSheet,
+    targets::{Browsers, Targets},
+    traits::ToCss,
+    visitor::{Visit, VisitTypes, Visitor},
 };
+use serde::Deserialize;
 use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 
 use crate::{
+    dependencies::css::parse_stylesheet,
     errors::{TransformError, TransformResult},
-    transform::css_transform::{ImportReplacer, UrlReplacer},
+    transform::css_transform::{
+        ImportReplacer, MozCleaner, MozCleanup, UnknownCssUrl, UnknownUrl, UrlReplacer,
+    },
+    utils::external::ExternalSpecifiers,
 };
 
+/// Output of a CSS transformation.
+#[derive(Debug)]
+pub struct CssTransformOutput {
+    /// The code to write to the output file
+    pub code: String,
+    /// The rules and declarations that couldn't be parsed and were dropped,
+    /// as `line:column: message`
+    pub warnings: Vec<String>,
+    /// True if the stylesheet couldn't be parsed at all, in which case `code`
+    /// holds the source without any URL rewritten
+    pub verbatim: bool,
+    /// The URLs without a replacement that were kept or removed
+    pub unknown_urls: Vec<UnknownUrl>,
+    /// The Firefox-only rules, selectors and declarations that were removed,
+    /// see `MozCleanup`
+    pub removed: Vec<String>,
+    /// The comments the source starts with, like its license block
+    pub header: Option<String>,
+}
+
+/// Browsers stylesheets are lowered for.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum CssTargets {
+    /// A browserslist query, e.g. `defaults` or `firefox >= 115`
+    Query(String),
+    /// Browsers, e.g. `firefox`, mapped to the oldest version to support,
+    /// e.g. `115` or `16.4`
+    Versions(HashMap<String, String>),
+}
+
+impl Default for CssTargets {
+    fn default() -> Self {
+        Self::Versions(HashMap::new())
+    }
+}
+
+/// How transformed stylesheets are printed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CssPrintOptions {
+    /// Leave out whitespace and comments
+    pub minify: bool,
+    /// Browsers to lower nesting and other modern syntax for, see
+    /// `browser_targets`. Stylesheets are kept as written if `None`.
+    pub targets: Option<Browsers>,
+    /// Keep the comments the source starts with, like its license block,
+    /// which are dropped with the other comments otherwise
+    pub license_headers: bool,
+}
+
+impl CssPrintOptions {
+    fn printer(&self) -> PrinterOptions<'static> {
+        PrinterOptions {
+            minify: self.minify,
+            targets: Targets::from(self.targets),
+            ..Default::default()
+        }
+    }
+
+    /// Put `header` before printed `code` if license headers are kept.
+    pub fn with_header(&self, code: String, header: Option<&str>) -> String {
+        match header {
+            Some(header) if self.license_headers => format!("{header}\n{code}"),
+            _ => code,
+        }
+    }
+}
+
+/// Transform a stylesheet.
+///
+/// `url_replacements` are applied to `url()` values, `import_replacements` to
+/// `@import` rules. `external` specifiers are left untouched, other URLs
+/// without a replacement are handled according to `unknown_urls`. Nesting, layers
+/// and other modern syntax are kept as written unless `print` has targets, in
+/// which case they are lowered for those browsers. With `moz_cleanup`, the
+/// Firefox-only parts are removed first. Stylesheets that can't be parsed at
+/// all are returned as they are.
 pub fn transform_from_file(
     source_path: &PathBuf,
     url_replacements: &HashMap<String, String>,
-) -> TransformResult<String> {
+    import_replacements: &HashMap<String, String>,
+    external: &ExternalSpecifiers,
+    unknown_urls: UnknownCssUrl,
+    moz_cleanup: Option<&MozCleanup>,
+    print: CssPrintOptions,
+) -> TransformResult<CssTransformOutput> {
     let css_content = fs::read_to_string(source_path)?;
-    transform_from_string(&css_content, url_replacements)
+    match transform_from_string(
+        &css_content,
+        url_replacements,
+        import_replacements,
+        external,
+        unknown_urls,
+        moz_cleanup,
+        print,
+    ) {
+        Err(TransformError::CssParse { message }) => Ok(CssTransformOutput {
+            header: leading_comments(&css_content).map(str::to_string),
+            code: css_content,
+            warnings: vec![message],
+            verbatim: true,
+            unknown_urls: Vec::new(),
+            removed: Vec::new(),
+        }),
+        result => result,
+    }
 }
 
 pub fn transform_from_string(
     css_content: &str,
     url_replacements: &HashMap<String, String>,
-) -> TransformResult<String> {
-    // Parse the CSS using StyleSheet::parse
-
-    let mut stylesheet = StyleSheet::parse(
-        &css_content,
-        ParserOptions {
-            ..Default::default()
-        },
-    )
-    .map_err(|e| TransformError::CssParse {
-        message: format!("{:?}", e),
-    })?;
+    import_replacements: &HashMap<String, String>,
+    external: &ExternalSpecifiers,
+    unknown_urls: UnknownCssUrl,
+    moz_cleanup: Option<&MozCleanup>,
+    print: CssPrintOptions,
+) -> TransformResult<CssTransformOutput> {
+    // Rules and declarations that can't be parsed are dropped with a warning
+    let (mut stylesheet, warnings) =
+        parse_stylesheet(css_content).map_err(|message| TransformError::CssParse { message })?;
+    reject_unknown_at_rules(&mut stylesheet)?;
+    let removed = clean(&mut stylesheet, moz_cleanup)?;
 
     // Use UrlReplacer to mutate the stylesheet in place
-    UrlReplacer::new(url_replacements).build(&mut stylesheet)?;
-    ImportReplacer::new(url_replacements).build(&mut stylesheet)?;
+    let unknown_urls = UrlReplacer::new(url_replacements, external)
+        .unknown_urls(unknown_urls)
+        .build(&mut stylesheet)?;
+    ImportReplacer::new(import_replacements, external).build(&mut stylesheet)?;
 
     // Serialize the transformed stylesheet back to CSS
-    let result =
-        stylesheet
-            .to_css(PrinterOptions::default())
-            .map_err(|e| TransformError::CssSerialize {
-                message: format!("{:?}", e),
+    let result = stylesheet
+        .to_css(print.printer())
+        .map_err(|e| TransformError::CssSerialize {
+            message: format!("{:?}", e),
+        })?;
+    let header = leading_comments(css_content).map(str::to_string);
+
+    Ok(CssTransformOutput {
+        code: print.with_header(result.code, header.as_deref()),
+        warnings,
+        verbatim: false,
+        unknown_urls,
+        removed,
+        header,
+    })
+}
+
+/// Print a stylesheet whose URLs are already rewritten.
+pub fn print_from_string(css_content: &str, print: CssPrintOptions) -> TransformResult<String> {
+    let (stylesheet, _) =
+        parse_stylesheet(css_content).map_err(|message| TransformError::CssParse { message })?;
+    stylesheet
+        .to_css(print.printer())
+        .map(|result| result.code)
+        .map_err(|e| TransformError::CssSerialize {
+            message: format!("{:?}", e),
+        })
+}
+
+/// A stylesheet split at its `@import` rules, so the imported sheets can be
+/// inlined in their place.
+#[derive(Debug)]
+pub struct SplitStylesheet {
+    /// The `@layer` statements before the first `@import`, which set the
+    /// order of the layers the imported sheets are put in
+    pub layer_statements: String,
+    /// The `@import` rules, in order
+    pub imports: Vec<CssImport>,
+    /// The rules after the `@import` rules
+    pub body: String,
+    /// The rules and declarations that couldn't be parsed and were dropped
+    pub warnings: Vec<String>,
+    /// The URLs without a replacement that were kept or removed
+    pub unknown_urls: Vec<UnknownUrl>,
+    /// The Firefox-only rules, selectors and declarations that were removed
+    pub removed: Vec<String>,
+    /// The comments the source starts with, like its license block
+    pub header: Option<String>,
+}
+
+/// An `@import` rule of a `SplitStylesheet`.
+#[derive(Debug)]
+pub struct CssImport {
+    /// The URL, without query or fragment
+    pub specifier: String,
+    /// The rule as written, for imports that are kept
+    pub rule: String,
+    /// The conditions the imported sheet applies under
+    pub conditions: ImportConditions,
+}
+
+/// The `layer`, `supports()` and media conditions of an `@import` rule.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ImportConditions {
+    /// `Some(None)` for an anonymous `layer`
+    layer: Option<Option<String>>,
+    supports: Option<String>,
+    media: Option<String>,
+}
+
+impl CssImport {
+    fn new(rule: &ImportRule) -> TransformResult<Self> {
+        let url = rule.url.to_string();
+        Ok(Self {
+            specifier: url.split(['?', '#']).next().unwrap_or(&url).to_string(),
+            rule: print(rule)?,
+            conditions: ImportConditions {
+                layer: match &rule.layer {
+                    Some(Some(name)) => Some(Some(print(name)?)),
+                    Some(None) => Some(None),
+                    None => None,
+                },
+                supports: rule.supports.as_ref().map(print).transpose()?,
+                media: (!rule.media.media_queries.is_empty())
+                    .then(|| print(&rule.media))
+                    .transpose()?,
+            },
+        })
+    }
+
+    /// Wrap the content of the imported sheet in blocks with the conditions
+    /// of the import, so it applies like the imported sheet did.
+    pub fn wrap(&self, content: &str) -> String {
+        let mut css = content.to_string();
+        if let Some(layer) = &self.conditions.layer {
+            css = match layer {
+                Some(name) => format!("@layer {name} {{\n{css}\n}}"),
+                None => format!("@layer {{\n{css}\n}}"),
+            };
+        }
+        if let Some(supports) = &self.conditions.supports {
+            css = format!("@supports {supports} {{\n{css}\n}}");
+        }
+        if let Some(media) = &self.conditions.media {
+            css = format!("@media {media} {{\n{css}\n}}");
+        }
+        css
+    }
+}
+
+/// Split a stylesheet at its `@import` rules, with `url_replacements` applied
+/// to its `url()` values and `moz_cleanup` like in `transform_from_file`.
+pub fn split_imports_from_file(
+    source_path: &PathBuf,
+    url_replacements: &HashMap<String, String>,
+    external: &ExternalSpecifiers,
+    unknown_urls: UnknownCssUrl,
+    moz_cleanup: Option<&MozCleanup>,
+) -> TransformResult<SplitStylesheet> {
+    let css_content = fs::read_to_string(source_path)?;
+    let (mut stylesheet, warnings) =
+        parse_stylesheet(&css_content).map_err(|message| TransformError::CssParse { message })?;
+    reject_unknown_at_rules(&mut stylesheet)?;
+    let removed = clean(&mut stylesheet, moz_cleanup)?;
+    let unknown_urls = UrlReplacer::new(url_replacements, external)
+        .unknown_urls(unknown_urls)
+        .build(&mut stylesheet)?;
+
+    let mut layer_statements = Vec::new();
+    let mut imports = Vec::new();
+    let mut body = Vec::new();
+    for rule in std::mem::take(&mut stylesheet.rules.0) {
+        match rule {
+            CssRule::LayerStatement(_) if imports.is_empty() && body.is_empty() => {
+                layer_statements.push(rule)
+            }
+            CssRule::Import(import) => imports.push(CssImport::new(&import)?),
+            rule => body.push(rule),
+        }
+    }
+
+    Ok(SplitStylesheet {
+        layer_statements: print_rules(&mut stylesheet, layer_statements)?,
+        imports,
+        body: print_rules(&mut stylesheet, body)?,
+        warnings,
+        unknown_urls,
+        removed,
+        header: leading_comments(&css_content).map(str::to_string),
+    })
+}
+
+/// The comments a stylesheet starts with, like its license block, up to the
+/// first rule.
+pub fn leading_comments(css: &str) -> Option<&str> {
+    let start = css.len() - css.trim_start().len();
+    let mut end = start;
+    while css[end..].trim_start().starts_with("/*") {
+        let comment = css.len() - css[end..].trim_start().len();
+        end = comment + css[comment..].find("*/")? + 2;
+    }
+    (end > start).then(|| &css[start..end])
+}
+
+/// The license of a stylesheet header: its SPDX identifier, or `MPL-2.0`
+/// for the Mozilla Public License block.
+pub fn header_license(header: &str) -> Option<String> {
+    if let Some((_, rest)) = header.split_once("SPDX-License-Identifier:") {
+        return rest
+            .split_whitespace()
+            .next()
+            .map(|id| id.trim_end_matches("*/").to_string())
+            .filter(|id| !id.is_empty());
+    }
+    (header.contains("Mozilla Public") && header.contains("2.0")).then(|| "MPL-2.0".to_string())
+}
+
+/// Fail with a parse error on at-rules lightningcss doesn't know, like
+/// `@document`: their blocks are kept as tokens and printed with spaces in the
+/// wrong places, so `.d` comes out as `. d`.
+fn reject_unknown_at_rules(stylesheet: &mut StyleSheet) -> TransformResult<()> {
+    let mut finder = UnknownAtRuleFinder { name: None };
+    stylesheet.visit(&mut finder)?;
+    match finder.name {
+        Some(name) => Err(TransformError::CssParse {
+            message: format!("@{name} rules aren't supported"),
+        }),
+        None => Ok(()),
+    }
+}
+
+struct UnknownAtRuleFinder {
+    name: Option<String>,
+}
+
+impl<'i> Visitor<'i> for UnknownAtRuleFinder {
+    type Error = TransformError;
+
+    fn visit_rule(&mut self, rule: &mut CssRule<'i>) -> Result<(), Self::Error> {
+        if let CssRule::Unknown(unknown) = rule {
+            self.name.get_or_insert_with(|| unknown.name.to_string());
+            return Ok(());
+        }
+        rule.visit_children(self)
+    }
+
+    fn visit_types(&self) -> VisitTypes {
+        lightningcss::visit_types!(RULES)
+    }
+}
+
+fn clean(
+    stylesheet: &mut StyleSheet,
+    moz_cleanup: Option<&MozCleanup>,
+) -> TransformResult<Vec<String>> {
+    match moz_cleanup {
+        Some(cleanup) => MozCleaner::new(cleanup).build(stylesheet),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn print<T: ToCss>(value: &T) -> TransformResult<String> {
+    value
+        .to_css_string(PrinterOptions::default())
+        .map_err(|e| TransformError::CssSerialize {
+            message: format!("{:?}", e),
+        })
+}
+
+fn print_rules<'i>(
+    stylesheet: &mut StyleSheet<'i, '_>,
+    rules: Vec<CssRule<'i>>,
+) -> TransformResult<String> {
+    stylesheet.rules = CssRuleList(rules);
+    stylesheet
+        .to_css(PrinterOptions::default())
+        .map(|result| result.code)
+        .map_err(|e| TransformError::CssSerialize {
+            message: format!("{:?}", e),
+        })
+}
+
+/// Resolve browser targets to the oldest version of each browser to support.
+/// Returns `None` if there are no targets.
+pub fn browser_targets(targets: &CssTargets) -> TransformResult<Option<Browsers>> {
+    match targets {
+        CssTargets::Query(query) if query.trim().is_empty() => Ok(None),
+        CssTargets::Query(query) => browsers_from_query(query).map(Some),
+        CssTargets::Versions(versions) if versions.is_empty() => Ok(None),
+        CssTargets::Versions(versions) => browsers_from_versions(versions).map(Some),
+    }
+}
+
+/// Resolve a browserslist query. Browsers lightningcss has no data for, like
+/// Opera Mini, are ignored.
+fn browsers_from_query(query: &str) -> TransformResult<Browsers> {
+    let distribs =
+        browserslist::resolve(&[query], &browserslist::Opts::default()).map_err(|e| {
+            TransformError::CssTransform {
+                message: format!("Invalid browser targets '{query}': {e}"),
+            }
+        })?;
+    let mut browsers = Browsers::default();
+    for distrib in distribs {
+        let name = match distrib.name() {
+            "and_chr" => "chrome",
+            "and_ff" => "firefox",
+            "ie_mob" => "ie",
+            "op_mob" => "opera",
+            name => name,
+        };
+        // Ranges like `16.4-16.5` start at their first version
+        let version = distrib.version().split('-').next().unwrap_or_default();
+        let (Some(slot), Some(version)) =
+            (browser_slot(&mut browsers, name), parse_version(version))
+        else {
+            continue;
+        };
+        *slot = Some(slot.map_or(version, |oldest| oldest.min(version)));
+    }
+    Ok(browsers)
+}
+
+/// Parse browser names, e.g. `firefox` or `ios_saf`, mapped to versions like
+/// `115` or `16.4`.
+fn browsers_from_versions(versions: &HashMap<String, String>) -> TransformResult<Browsers> {
+    let mut browsers = Browsers::default();
+    for (browser, version) in versions {
+        let slot =
+            browser_slot(&mut browsers, browser).ok_or_else(|| TransformError::CssTransform {
+                message: format!("Unknown browser target '{browser}'"),
             })?;
+        *slot = Some(
+            parse_version(version).ok_or_else(|| TransformError::CssTransform {
+                message: format!("Invalid version '{version}' of browser target '{browser}'"),
+            })?,
+        );
+    }
+    Ok(browsers)
+}
+
+fn browser_slot<'a>(browsers: &'a mut Browsers, name: &str) -> Option<&'a mut Option<u32>> {
+    Some(match name {
+        "android" => &mut browsers.android,
+        "chrome" => &mut browsers.chrome,
+        "edge" => &mut browsers.edge,
+        "firefox" => &mut browsers.firefox,
+        "ie" => &mut browsers.ie,
+        "ios_saf" => &mut browsers.ios_saf,
+        "opera" => &mut browsers.opera,
+        "safari" => &mut browsers.safari,
+        "samsung" => &mut browsers.samsung,
+        _ => return None,
+    })
+}
+
+/// Encode `major[.minor[.patch]]` the way lightningcss expects it.
+fn parse_version(version: &str) -> Option<u32> {
+    let mut parts = version.trim().split('.');
+    let mut encoded = 0;
+    for shift in [16, 8, 0] {
+        let part = match parts.next() {
+            Some(part) => part.parse::<u8>().ok()? as u32,
+            None => 0,
+        };
+        encoded |= part << shift;
+    }
+    parts.next().is_none().then_some(encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODERN: &str = "@layer tokens, components;\n\n.a {\n  color: red;\n\n  & .b {\n    color: green;\n  }\n}\n\n@layer components {\n  .c {\n    color: red;\n  }\n}\n\n@layer tokens {\n  :root {\n    --size: 4px;\n  }\n}\n";
+
+    fn transform(css: &str, print: CssPrintOptions) -> String {
+        transform_from_string(
+            css,
+            &HashMap::from([(
+                "chrome://global/skin/icons/info.svg".to_string(),
+                "../assets/info.svg".to_string(),
+            )]),
+            &HashMap::new(),
+            &ExternalSpecifiers::default(),
+            UnknownCssUrl::Error,
+            None,
+            print,
+        )
+        .unwrap()
+        .code
+    }
+
+    fn versions(versions: &[(&str, &str)]) -> CssTargets {
+        CssTargets::Versions(
+            versions
+                .iter()
+                .map(|(browser, version)| (browser.to_string(), version.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_nesting_and_layers_are_kept() {
+        assert_eq!(transform(MODERN, CssPrintOptions::default()), MODERN);
+    }
+
+    #[test]
+    fn test_targets_lower_nesting() {
+        let targets = browser_targets(&versions(&[("firefox", "115")])).unwrap();
+        let css = transform(
+            MODERN,
+            CssPrintOptions {
+                targets,
+                ..Default::default()
+            },
+        );
+        assert!(css.starts_with("@layer tokens, components;"), "{css}");
+        assert!(css.contains(".a .b {"), "{css}");
+        assert!(!css.contains("& .b"), "{css}");
+    }
+
+    #[test]
+    fn test_minify() {
+        let source = "/* Icons */\n.a {\n  background: url(\"chrome://global/skin/icons/info.svg\") no-repeat;\n  color: red;\n}\n";
+        let css = transform(
+            source,
+            CssPrintOptions {
+                minify: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            css,
+            ".a{background:url(../assets/info.svg) no-repeat;color:red}"
+        );
+    }
+
+    #[test]
+    fn test_browser_targets() {
+        let browsers = browser_targets(&versions(&[("firefox", "128"), ("safari", "16.4")]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(browsers.firefox, Some(128 << 16));
+        assert_eq!(browsers.safari, Some(16 << 16 | 4 << 8));
+        assert!(browser_targets(&versions(&[])).unwrap().is_none());
+        assert!(browser_targets(&versions(&[("netscape", "4")])).is_err());
+        assert!(browser_targets(&versions(&[("firefox", "latest")])).is_err());
+    }
+
+    #[test]
+    fn test_browser_target_queries() {
+        let browsers = browser_targets(&CssTargets::Query("firefox >= 115".to_string()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(browsers.firefox, Some(115 << 16));
+        assert_eq!(browsers.chrome, None);
+        let defaults = browser_targets(&CssTargets::Query("defaults".to_string()))
+            .unwrap()
+            .unwrap();
+        assert!(defaults.firefox.is_some() && defaults.safari.is_some());
+        assert!(
+            browser_targets(&CssTargets::Query(String::new()))
+                .unwrap()
+                .is_none()
+        );
+        assert!(browser_targets(&CssTargets::Query("netscape >= 4".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_license_headers() {
+        const MPL: &str = "/* This Source Code Form is subject to the terms of the Mozilla Public\n * License, v. 2.0. If a copy of the MPL was not distributed with this\n * file, You can obtain one at http://mozilla.org/MPL/2.0/. */";
+        let source =
+            format!("\n{MPL}\n\n/* Buttons */\n.a {{ color: red; }}\n/* Not a header */\n");
+        assert_eq!(
+            leading_comments(&source),
+            Some(format!("{MPL}\n\n/* Buttons */").as_str())
+        );
+        assert_eq!(leading_comments(".a { color: red; }"), None);
+        assert_eq!(leading_comments("/* unterminated"), None);
+        assert_eq!(header_license(MPL).as_deref(), Some("MPL-2.0"));
+        assert_eq!(
+            header_license("/* SPDX-License-Identifier: Apache-2.0 */").as_deref(),
+            Some("Apache-2.0")
+        );
+        assert_eq!(header_license("/* Buttons */"), None);
+
+        let print = CssPrintOptions {
+            license_headers: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            transform(&source, print),
+            format!("{MPL}\n\n/* Buttons */\n.a {{\n  color: red;\n}}\n")
+        );
+        assert_eq!(
+            transform(&source, CssPrintOptions::default()),
+            ".a {\n  color: red;\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_unknown_at_rules_are_copied_verbatim() {
+        let dir = tempfile::tempdir().unwrap();
+        let source_path = dir.path().join("document.css");
+        let css = ".a { color: red; }\n@supports (display: grid) {\n  @document domain(example.com) { .d { color: red; } }\n}\n";
+        fs::write(&source_path, css).unwrap();
+
+        let output = transform_from_file(
+            &source_path,
+            &HashMap::new(),
+            &HashMap::new(),
+            &ExternalSpecifiers::default(),
+            UnknownCssUrl::Error,
+            None,
+            CssPrintOptions::default(),
+        )
+        .unwrap();
+        assert!(output.verbatim);
+        assert_eq!(output.code, css);
+        assert_eq!(output.warnings, vec!["@document rules aren't supported"]);
 
-    Ok(result.code)
+        let error = split_imports_from_file(
+            &source_path,
+            &HashMap::new(),
+            &ExternalSpecifiers::default(),
+            UnknownCssUrl::Error,
+            None,
+        )
+        .err()
+        .unwrap();
+        assert!(error.to_string().contains("@document"), "{}", error);
+    }
 }
@@ -3,19 +3,28 @@ use lightningcss::visitor::{Visit, VisitTypes, Visitor};
 use std::collections::HashMap;
 
 use crate::errors::TransformError;
+use crate::utils::external::ExternalSpecifiers;
 
 pub struct ImportReplacer<'a> {
     url_replacements: &'a HashMap<String, String>,
+    external: &'a ExternalSpecifiers,
 }
 
 impl<'a> ImportReplacer<'a> {
-    pub fn new(url_replacements: &'a HashMap<String, String>) -> Self {
-        Self { url_replacements }
+    pub fn new(
+        url_replacements: &'a HashMap<String, String>,
+        external: &'a ExternalSpecifiers,
+    ) -> Self {
+        Self {
+            url_replacements,
+            external,
+        }
     }
 
     pub fn build(&self, stylesheet: &mut StyleSheet) -> Result<(), TransformError> {
         let mut visitor = ImportReplacerVisitor {
             url_replacements: self.url_replacements,
+            external: self.external,
         };
         stylesheet
             .visit(&mut visitor)
@@ -27,6 +36,7 @@ impl<'a> ImportReplacer<'a> {
 
 struct ImportReplacerVisitor<'a> {
     url_replacements: &'a HashMap<String, String>,
+    external: &'a ExternalSpecifiers,
 }
 
 impl<'a, 'i> Visitor<'i> for ImportReplacerVisitor<'a> {
@@ -38,12 +48,23 @@ impl<'a, 'i> Visitor<'i> for ImportReplacerVisitor<'a> {
     ) -> std::result::Result<(), Self::Error> {
         if let lightningcss::rules::CssRule::Import(import_rule) = rule {
             let url_str = import_rule.url.to_string();
-            if let Some(replacement) = self.url_replacements.get(&url_str) {
-                import_rule.url = replacement.clone().into();
-            } else if !url_str.starts_with("data:")
-                && !url_str.starts_with("http://")
-                && !url_str.starts_with("https://")
-                && !url_str.starts_with("//")
+
+            // Dependencies are collected without the query string or
+            // fragment, look up the base and keep the suffix
+            let (base, suffix) = match url_str.find(['?', '#']) {
+                Some(idx) => (&url_str[..idx], &url_str[idx..]),
+                None => (url_str.as_str(), ""),
+            };
+
+            if self.external.is_external(base) {
+                return Ok(());
+            }
+            if let Some(replacement) = self.url_replacements.get(base) {
+                import_rule.url = format!("{}{}", replacement, suffix).into();
+            } else if !base.starts_with("data:")
+                && !base.starts_with("http://")
+                && !base.starts_with("https://")
+                && !base.starts_with("//")
             {
                 return Err(TransformError::UrlNotFound { url: url_str });
             }
@@ -55,3 +76,61 @@ impl<'a, 'i> Visitor<'i> for ImportReplacerVisitor<'a> {
         lightningcss::visit_types!(URLS | RULES)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lightningcss::stylesheet::{ParserOptions, PrinterOptions};
+
+    #[test]
+    fn test_imports_with_query_strings_are_replaced() {
+        let mut stylesheet = StyleSheet::parse(
+            "@import url(\"../shared.css?cache=bust\");\n.a { color: red; }",
+            ParserOptions::default(),
+        )
+        .unwrap();
+        let replacements =
+            HashMap::from([("../shared.css".to_string(), "./shared.css".to_string())]);
+        let external = ExternalSpecifiers::default();
+        ImportReplacer::new(&replacements, &external)
+            .build(&mut stylesheet)
+            .unwrap();
+        let css = stylesheet.to_css(PrinterOptions::default()).unwrap().code;
+        assert!(
+            css.contains("@import \"./shared.css?cache=bust\""),
+            "{}",
+            css
+        );
+    }
+
+    #[test]
+    fn test_import_conditions_are_kept() {
+        let mut stylesheet = StyleSheet::parse(
+            r#"@import url("a.css") layer(tokens);
+@import "b.css" screen and (min-width: 600px);
+@import "c.css" layer supports(display: grid) print;
+@import "d.css?v=1" layer(tokens.base) (prefers-color-scheme: dark);
+"#,
+            ParserOptions::default(),
+        )
+        .unwrap();
+        let replacements: HashMap<String, String> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|name| (format!("{name}.css"), format!("./out/{name}.css")))
+            .collect();
+        let external = ExternalSpecifiers::default();
+        ImportReplacer::new(&replacements, &external)
+            .build(&mut stylesheet)
+            .unwrap();
+        let css = stylesheet.to_css(PrinterOptions::default()).unwrap().code;
+        for expected in [
+            r#"@import "./out/a.css" layer(tokens);"#,
+            // Media features are printed in the equivalent range syntax
+            r#"@import "./out/b.css" screen and (width >= 600px);"#,
+            r#"@import "./out/c.css" layer supports(display: grid) print;"#,
+            r#"@import "./out/d.css?v=1" layer(tokens.base) (prefers-color-scheme: dark);"#,
+        ] {
+            assert!(css.contains(expected), "{}", css);
+        }
+    }
+}
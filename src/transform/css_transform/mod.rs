@@ -1,5 +1,9 @@
 mod import_replacer;
+mod moz_cleanup;
 mod url_replacer;
 
 pub(crate) use import_replacer::ImportReplacer;
+pub(crate) use moz_cleanup::MozCleaner;
+pub use moz_cleanup::MozCleanup;
 pub(crate) use url_replacer::UrlReplacer;
+pub use url_replacer::{UnknownCssUrl, UnknownUrl};
@@ -0,0 +1,330 @@
+use lightningcss::declaration::DeclarationBlock;
+use lightningcss::media_query::{
+    MediaCondition, MediaList, MediaQuery, MediaType, Operator, Qualifier,
+};
+use lightningcss::properties::Property;
+use lightningcss::rules::{CssRule, CssRuleList, style::StyleRule};
+use lightningcss::stylesheet::{PrinterOptions, StyleSheet};
+use lightningcss::traits::ToCss;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::errors::TransformError;
+
+/// Removes the parts of stylesheets that only Firefox's own UI understands:
+/// rules guarded by `-moz-` media features, selectors with `-moz-`
+/// pseudo-classes and elements, `-moz-` declarations and `@-moz-document`
+/// rules.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct MozCleanup {
+    /// `-moz-` media features, like `-moz-platform: macos` or
+    /// `-moz-overlay-scrollbars`, mapped to whether they match. Rules whose
+    /// media query only matches with the features that don't match are
+    /// removed, rules whose media query always matches are kept without it.
+    /// Features not listed don't match.
+    pub media_features: HashMap<String, bool>,
+    /// `-moz-` properties that are kept, like `-moz-osx-font-smoothing`
+    pub keep_properties: Vec<String>,
+}
+
+pub struct MozCleaner<'a> {
+    cleanup: &'a MozCleanup,
+}
+
+impl<'a> MozCleaner<'a> {
+    pub fn new(cleanup: &'a MozCleanup) -> Self {
+        Self { cleanup }
+    }
+
+    /// Remove the Firefox-only parts of a stylesheet, returning what was
+    /// removed, e.g. `@media (-moz-platform: macos)`.
+    pub fn build(&self, stylesheet: &mut StyleSheet) -> Result<Vec<String>, TransformError> {
+        let mut removed = Vec::new();
+        self.clean_rules(&mut stylesheet.rules, &mut removed)?;
+        Ok(removed)
+    }
+
+    fn clean_rules(
+        &self,
+        rules: &mut CssRuleList<'_>,
+        removed: &mut Vec<String>,
+    ) -> Result<(), TransformError> {
+        let mut kept = Vec::with_capacity(rules.0.len());
+        for rule in std::mem::take(&mut rules.0) {
+            match rule {
+                CssRule::Media(mut media) => {
+                    let query = print(&media.query)?;
+                    match self.evaluate_list(&media.query) {
+                        Some(false) => removed.push(format!("@media {query}")),
+                        Some(true) => {
+                            removed.push(format!("@media {query} (its rules are kept)"));
+                            self.clean_rules(&mut media.rules, removed)?;
+                            kept.append(&mut media.rules.0);
+                        }
+                        None => {
+                            self.clean_rules(&mut media.rules, removed)?;
+                            kept.push(CssRule::Media(media));
+                        }
+                    }
+                }
+                CssRule::MozDocument(_) => removed.push("@-moz-document".to_string()),
+                CssRule::Style(mut style) => {
+                    if self.clean_style(&mut style, removed)? {
+                        kept.push(CssRule::Style(style));
+                    }
+                }
+                CssRule::Nesting(mut nesting) => {
+                    if self.clean_style(&mut nesting.style, removed)? {
+                        kept.push(CssRule::Nesting(nesting));
+                    }
+                }
+                CssRule::NestedDeclarations(mut nested) => {
+                    self.clean_declarations(&mut nested.declarations, removed)?;
+                    kept.push(CssRule::NestedDeclarations(nested));
+                }
+                CssRule::Supports(mut supports) => {
+                    self.clean_rules(&mut supports.rules, removed)?;
+                    kept.push(CssRule::Supports(supports));
+                }
+                CssRule::LayerBlock(mut layer) => {
+                    self.clean_rules(&mut layer.rules, removed)?;
+                    kept.push(CssRule::LayerBlock(layer));
+                }
+                CssRule::Container(mut container) => {
+                    self.clean_rules(&mut container.rules, removed)?;
+                    kept.push(CssRule::Container(container));
+                }
+                CssRule::Scope(mut scope) => {
+                    self.clean_rules(&mut scope.rules, removed)?;
+                    kept.push(CssRule::Scope(scope));
+                }
+                CssRule::StartingStyle(mut starting_style) => {
+                    self.clean_rules(&mut starting_style.rules, removed)?;
+                    kept.push(CssRule::StartingStyle(starting_style));
+                }
+                rule => kept.push(rule),
+            }
+        }
+        rules.0 = kept;
+        Ok(())
+    }
+
+    /// Clean a style rule, returning false if none of its selectors is left.
+    fn clean_style(
+        &self,
+        style: &mut StyleRule<'_>,
+        removed: &mut Vec<String>,
+    ) -> Result<bool, TransformError> {
+        let mut selectors = Vec::with_capacity(style.selectors.0.len());
+        for selector in std::mem::take(&mut style.selectors.0) {
+            let text = print(&selector)?;
+            if text.contains(":-moz-") {
+                removed.push(format!("selector {text}"));
+            } else {
+                selectors.push(selector);
+            }
+        }
+        if selectors.is_empty() {
+            return Ok(false);
+        }
+        style.selectors.0 = selectors.into_iter().collect();
+        self.clean_declarations(&mut style.declarations, removed)?;
+        self.clean_rules(&mut style.rules, removed)?;
+        Ok(true)
+    }
+
+    fn clean_declarations(
+        &self,
+        decls: &mut DeclarationBlock<'_>,
+        removed: &mut Vec<String>,
+    ) -> Result<(), TransformError> {
+        for (declarations, important) in [
+            (&mut decls.declarations, false),
+            (&mut decls.important_declarations, true),
+        ] {
+            let mut kept: Vec<Property<'_>> = Vec::with_capacity(declarations.len());
+            for property in std::mem::take(declarations) {
+                let name = print(&property.property_id())?;
+                if name.starts_with("-moz-") && !self.cleanup.keep_properties.contains(&name) {
+                    removed.push(format!(
+                        "declaration {}",
+                        property
+                            .to_css_string(important, PrinterOptions::default())
+                            .map_err(serialize_error)?
+                    ));
+                } else {
+                    kept.push(property);
+                }
+            }
+            *declarations = kept;
+        }
+        Ok(())
+    }
+
+    /// Whether a media query list always (`Some(true)`) or never
+    /// (`Some(false)`) matches because of its `-moz-` features, or `None` if
+    /// that depends on other features.
+    fn evaluate_list(&self, list: &MediaList<'_>) -> Option<bool> {
+        any(list.media_queries.iter().map(|q| self.evaluate_query(q)))
+    }
+
+    fn evaluate_query(&self, query: &MediaQuery<'_>) -> Option<bool> {
+        let matches = self.evaluate_condition(query.condition.as_ref()?)?;
+        let all = query.media_type == MediaType::All;
+        match (query.qualifier, matches) {
+            (Some(Qualifier::Not), matches) if all => Some(!matches),
+            (Some(Qualifier::Not), _) => None,
+            (_, true) if !all => None,
+            (_, matches) => Some(matches),
+        }
+    }
+
+    fn evaluate_condition(&self, condition: &MediaCondition<'_>) -> Option<bool> {
+        match condition {
+            MediaCondition::Feature(feature) => {
+                let feature = normalize(&print(feature).ok()?);
+                if !feature.starts_with("-moz-") {
+                    return None;
+                }
+                Some(
+                    self.cleanup
+                        .media_features
+                        .iter()
+                        .any(|(name, matches)| *matches && normalize(name) == feature),
+                )
+            }
+            MediaCondition::Not(condition) => self.evaluate_condition(condition).map(|m| !m),
+            MediaCondition::Operation {
+                operator: Operator::And,
+                conditions,
+            } => any(conditions
+                .iter()
+                .map(|c| self.evaluate_condition(c).map(|m| !m)))
+            .map(|m| !m),
+            MediaCondition::Operation {
+                operator: Operator::Or,
+                conditions,
+            } => any(conditions.iter().map(|c| self.evaluate_condition(c))),
+        }
+    }
+}
+
+/// Three-valued `or`: true if any value is true, false if all are false.
+fn any(values: impl Iterator<Item = Option<bool>>) -> Option<bool> {
+    let mut result = Some(false);
+    for value in values {
+        match value {
+            Some(true) => return Some(true),
+            Some(false) => {}
+            None => result = None,
+        }
+    }
+    result
+}
+
+/// A media feature without parentheses and whitespace, e.g.
+/// `-moz-platform:macos`.
+fn normalize(feature: &str) -> String {
+    feature
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '(' && *c != ')')
+        .collect()
+}
+
+fn print<T: ToCss>(value: &T) -> Result<String, TransformError> {
+    value
+        .to_css_string(PrinterOptions::default())
+        .map_err(serialize_error)
+}
+
+fn serialize_error(error: lightningcss::error::PrinterError) -> TransformError {
+    TransformError::CssSerialize {
+        message: format!("{:?}", error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lightningcss::stylesheet::ParserOptions;
+
+    fn clean(source: &str, cleanup: &MozCleanup) -> (String, Vec<String>) {
+        let mut stylesheet = StyleSheet::parse(source, ParserOptions::default()).unwrap();
+        let removed = MozCleaner::new(cleanup).build(&mut stylesheet).unwrap();
+        let css = stylesheet.to_css(PrinterOptions::default()).unwrap().code;
+        (css, removed)
+    }
+
+    #[test]
+    fn test_media_features() {
+        let source = r#"@media (-moz-platform: macos) { .mac { color: red; } }
+@media (-moz-platform: linux) { .linux { color: red; } }
+@media not (-moz-platform: windows) { .not-windows { color: red; } }
+@media (-moz-platform: linux) and (width >= 600px) { .linux-wide { color: red; } }
+@media (-moz-platform: macos) and (width >= 600px) { .mac-wide { color: red; } }
+@layer base { @media (-moz-platform: windows) { .windows { color: red; } } }
+@media (width >= 600px) { .wide { color: red; } }"#;
+        let cleanup = MozCleanup {
+            media_features: HashMap::from([("-moz-platform:  macos".to_string(), true)]),
+            ..Default::default()
+        };
+        let (css, removed) = clean(source, &cleanup);
+        for kept in [
+            ".mac {",
+            ".not-windows {",
+            ".mac-wide {",
+            ".wide {",
+            "@layer base",
+        ] {
+            assert!(css.contains(kept), "{kept}: {css}");
+        }
+        for dropped in [".linux", ".windows", "-moz-platform: linux"] {
+            assert!(!css.contains(dropped), "{dropped}: {css}");
+        }
+        // Unwrapped, since it always matches
+        assert!(!css.contains("@media (-moz-platform: macos) {"), "{css}");
+        assert_eq!(
+            removed,
+            [
+                "@media (-moz-platform: macos) (its rules are kept)",
+                "@media (-moz-platform: linux)",
+                "@media not (-moz-platform: windows) (its rules are kept)",
+                "@media (-moz-platform: linux) and (width >= 600px)",
+                "@media (-moz-platform: windows)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_selectors_and_declarations() {
+        let source = r#".a:-moz-window-inactive, .b { color: red; -moz-default-appearance: button; }
+.c:-moz-window-inactive { color: gray; }
+.d {
+  -moz-osx-font-smoothing: grayscale;
+  -moz-user-focus: normal !important;
+  &:-moz-window-inactive { color: gray; }
+  & .e { -moz-box-flex: 1; margin: 0; }
+}"#;
+        let cleanup = MozCleanup {
+            keep_properties: vec!["-moz-osx-font-smoothing".to_string()],
+            ..Default::default()
+        };
+        let (css, removed) = clean(source, &cleanup);
+        assert!(css.contains(".b {\n  color: red;\n}"), "{css}");
+        assert!(css.contains("-moz-osx-font-smoothing: grayscale"), "{css}");
+        assert!(css.contains("& .e {\n    margin: 0;\n  }"), "{css}");
+        assert!(!css.contains(".c"), "{css}");
+        assert_eq!(
+            removed,
+            [
+                "selector .a:-moz-window-inactive",
+                "declaration -moz-default-appearance: button",
+                "selector .c:-moz-window-inactive",
+                "declaration -moz-user-focus: normal !important",
+                "selector &:-moz-window-inactive",
+                "declaration -moz-box-flex: 1",
+            ]
+        );
+    }
+}
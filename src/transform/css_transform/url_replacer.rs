@@ -1,33 +1,87 @@
+use lightningcss::declaration::DeclarationBlock;
+use lightningcss::properties::Property;
 use lightningcss::stylesheet::StyleSheet;
-use lightningcss::values::url::Url;
+use lightningcss::values::{image::Image, url::Url};
 use lightningcss::visitor::{Visit, VisitTypes, Visitor};
+use serde::Deserialize;
 use std::collections::HashMap;
 
+use crate::dependencies::css::{is_passthrough_url, visit_image_set_options};
 use crate::errors::TransformError;
+use crate::utils::external::ExternalSpecifiers;
+
+/// What happens to `url()` values of stylesheets that have no output file,
+/// like files left out on purpose or `moz-icon://` resources Firefox
+/// generates at runtime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnknownCssUrl {
+    /// Fail the run
+    #[default]
+    Error,
+    /// Leave the URL as it is, with a warning
+    Keep,
+    /// Remove the declaration with the URL, with a warning. URLs outside of
+    /// declarations, like `@font-face` sources, are kept.
+    Strip,
+}
+
+/// A `url()` value with no replacement that was kept or removed, see
+/// `UnknownCssUrl`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownUrl {
+    pub url: String,
+    /// Whether the declaration with the URL was removed
+    pub stripped: bool,
+}
 
 pub struct UrlReplacer<'a> {
     url_replacements: &'a HashMap<String, String>,
+    external: &'a ExternalSpecifiers,
+    unknown_urls: UnknownCssUrl,
 }
 
 impl<'a> UrlReplacer<'a> {
-    pub fn new(url_replacements: &'a HashMap<String, String>) -> Self {
-        Self { url_replacements }
+    pub fn new(
+        url_replacements: &'a HashMap<String, String>,
+        external: &'a ExternalSpecifiers,
+    ) -> Self {
+        Self {
+            url_replacements,
+            external,
+            unknown_urls: UnknownCssUrl::Error,
+        }
     }
 
-    pub fn build(&self, stylesheet: &mut StyleSheet) -> Result<(), TransformError> {
+    /// Set what happens to URLs without a replacement, instead of failing.
+    pub fn unknown_urls(mut self, unknown_urls: UnknownCssUrl) -> Self {
+        self.unknown_urls = unknown_urls;
+        self
+    }
+
+    /// Replace the URLs of a stylesheet, returning the URLs without a
+    /// replacement that were kept or removed.
+    pub fn build(&self, stylesheet: &mut StyleSheet) -> Result<Vec<UnknownUrl>, TransformError> {
         let mut visitor = UrlReplacerVisitor {
             url_replacements: self.url_replacements,
+            external: self.external,
+            unknown_urls: self.unknown_urls,
+            unknown: Vec::new(),
         };
         stylesheet
             .visit(&mut visitor)
             .map_err(|e| TransformError::CssTransform {
                 message: format!("{:?}", e),
-            })
+            })?;
+        Ok(visitor.unknown)
     }
 }
 
 struct UrlReplacerVisitor<'a> {
     url_replacements: &'a HashMap<String, String>,
+    external: &'a ExternalSpecifiers,
+    unknown_urls: UnknownCssUrl,
+    unknown: Vec<UnknownUrl>,
 }
 
 impl<'a, 'i> Visitor<'i> for UrlReplacerVisitor<'a> {
@@ -37,26 +91,297 @@ impl<'a, 'i> Visitor<'i> for UrlReplacerVisitor<'a> {
         let url_str = url.url.to_string();
 
         // Split at the first '?' or '#' to get the base part for replacement
-        let (base, suffix) = match url_str.find(|c| c == '?' || c == '#') {
+        let (base, suffix) = match url_str.find(['?', '#']) {
             Some(idx) => (&url_str[..idx], &url_str[idx..]),
             None => (url_str.as_str(), ""),
         };
 
-        if let Some(replacement) = self.url_replacements.get(base) {
-            // Reconstruct the url with the replacement and the original suffix
-            let new_url = format!("{}{}", replacement, suffix);
-            url.url = new_url.into();
-        } else if !base.starts_with("data:")
-            && !base.starts_with("http://")
-            && !base.starts_with("https://")
-            && !base.starts_with("//")
-        {
-            return Err(TransformError::UrlNotFound { url: url_str });
+        if is_passthrough_url(base) || self.external.is_external(base) {
+            return Ok(());
+        }
+        let Some(replacement) = self.url_replacements.get(base) else {
+            if self.unknown_urls == UnknownCssUrl::Error {
+                return Err(TransformError::UrlNotFound { url: url_str });
+            }
+            self.unknown.push(UnknownUrl {
+                url: url_str,
+                stripped: false,
+            });
+            return Ok(());
+        };
+        // Reconstruct the url with the replacement and the original suffix
+        url.url = format!("{}{}", replacement, suffix).into();
+        Ok(())
+    }
+
+    fn visit_declaration_block(
+        &mut self,
+        decls: &mut DeclarationBlock<'i>,
+    ) -> std::result::Result<(), Self::Error> {
+        if self.unknown_urls != UnknownCssUrl::Strip {
+            return decls.visit_children(self);
+        }
+        for declarations in [&mut decls.declarations, &mut decls.important_declarations] {
+            let mut kept: Vec<Property<'i>> = Vec::with_capacity(declarations.len());
+            for mut property in std::mem::take(declarations) {
+                let known = self.unknown.len();
+                self.visit_property(&mut property)?;
+                if self.unknown.len() == known {
+                    kept.push(property);
+                } else {
+                    for unknown in &mut self.unknown[known..] {
+                        unknown.stripped = true;
+                    }
+                }
+            }
+            *declarations = kept;
         }
         Ok(())
     }
 
+    fn visit_image(&mut self, image: &mut Image<'i>) -> std::result::Result<(), Self::Error> {
+        visit_image_set_options(image, self)
+    }
+
     fn visit_types(&self) -> VisitTypes {
-        lightningcss::visit_types!(URLS | RULES)
+        lightningcss::visit_types!(URLS | IMAGES | RULES | PROPERTIES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lightningcss::stylesheet::{ParserOptions, PrinterOptions};
+
+    #[test]
+    fn test_replacements_keep_query_and_fragment() {
+        let mut stylesheet = StyleSheet::parse(
+            ".a { background: url(\"chrome://global/skin/icons/arrow-down.svg#flip\"); }\n\
+             .b { background: url(\"chrome://global/skin/icons/arrow-down.svg?size=16\"); }",
+            ParserOptions::default(),
+        )
+        .unwrap();
+        let replacements = HashMap::from([(
+            "chrome://global/skin/icons/arrow-down.svg".to_string(),
+            "../assets/arrow-down.svg".to_string(),
+        )]);
+        let external = ExternalSpecifiers::default();
+        UrlReplacer::new(&replacements, &external)
+            .build(&mut stylesheet)
+            .unwrap();
+        let css = stylesheet.to_css(PrinterOptions::default()).unwrap().code;
+        assert!(
+            css.contains("url(\"../assets/arrow-down.svg#flip\")"),
+            "{}",
+            css
+        );
+        assert!(
+            css.contains("url(\"../assets/arrow-down.svg?size=16\")"),
+            "{}",
+            css
+        );
+    }
+
+    #[test]
+    fn test_image_set_urls_are_replaced() {
+        let mut stylesheet = StyleSheet::parse(
+            r#".a { background-image: image-set(url("chrome://global/skin/a.png") 1x, url("chrome://global/skin/a@2x.png") 2x); }
+.b { background-image: -webkit-image-set(url("chrome://global/skin/a.png") 1x, "chrome://global/skin/a@2x.png" 2x); }"#,
+            ParserOptions::default(),
+        )
+        .unwrap();
+        let replacements = HashMap::from([
+            (
+                "chrome://global/skin/a.png".to_string(),
+                "../assets/a.png".to_string(),
+            ),
+            (
+                "chrome://global/skin/a@2x.png".to_string(),
+                "../assets/a@2x.png".to_string(),
+            ),
+        ]);
+        let external = ExternalSpecifiers::default();
+        UrlReplacer::new(&replacements, &external)
+            .build(&mut stylesheet)
+            .unwrap();
+        let css = stylesheet.to_css(PrinterOptions::default()).unwrap().code;
+        assert!(!css.contains("chrome://"), "{}", css);
+        assert!(
+            css.contains(r#"image-set(url("../assets/a.png") 1x, url("../assets/a@2x.png") 2x)"#),
+            "{}",
+            css
+        );
+        assert!(css.contains("-webkit-image-set("), "{}", css);
+    }
+
+    #[test]
+    fn test_cursor_and_mask_urls_are_replaced() {
+        let mut stylesheet = StyleSheet::parse(
+            r#".a { cursor: url("chrome://global/skin/cursors/grab.png") 4 4, pointer; }
+.b { -webkit-mask-image: url("chrome://global/skin/icons/a.svg"); mask-image: url("chrome://global/skin/icons/a.svg"); }
+.c { -webkit-mask: url("chrome://global/skin/icons/a.svg") no-repeat center; }"#,
+            ParserOptions::default(),
+        )
+        .unwrap();
+        let replacements = HashMap::from([
+            (
+                "chrome://global/skin/cursors/grab.png".to_string(),
+                "../assets/grab.png".to_string(),
+            ),
+            (
+                "chrome://global/skin/icons/a.svg".to_string(),
+                "../assets/a.svg".to_string(),
+            ),
+        ]);
+        let external = ExternalSpecifiers::default();
+        UrlReplacer::new(&replacements, &external)
+            .build(&mut stylesheet)
+            .unwrap();
+        let css = stylesheet.to_css(PrinterOptions::default()).unwrap().code;
+        assert!(!css.contains("chrome://"), "{}", css);
+        assert!(
+            css.contains(r#"cursor: url("../assets/grab.png") 4 4, pointer;"#),
+            "{}",
+            css
+        );
+        assert!(
+            css.contains(r#"-webkit-mask-image: url("../assets/a.svg");"#),
+            "{}",
+            css
+        );
+        assert!(
+            css.contains(r#"-webkit-mask: url("../assets/a.svg") center no-repeat;"#),
+            "{}",
+            css
+        );
+    }
+
+    #[test]
+    fn test_fragment_only_urls_are_kept() {
+        let source = r##".a { fill: url(#gradient); clip-path: url("#clip"); }
+.b { background: url(""), url(about:blank); }"##;
+        let mut stylesheet = StyleSheet::parse(source, ParserOptions::default()).unwrap();
+        let replacements = HashMap::new();
+        let external = ExternalSpecifiers::default();
+        UrlReplacer::new(&replacements, &external)
+            .build(&mut stylesheet)
+            .unwrap();
+        let css = stylesheet.to_css(PrinterOptions::default()).unwrap().code;
+        assert!(css.contains("fill: url(\"#gradient\");"), "{}", css);
+        assert!(css.contains("clip-path: url(\"#clip\");"), "{}", css);
+        assert!(css.contains("url(\"about:blank\")"), "{}", css);
+    }
+
+    #[test]
+    fn test_nested_at_rule_urls_are_replaced() {
+        let mut stylesheet = StyleSheet::parse(
+            r#"@namespace url(http://www.w3.org/1999/xhtml);
+@supports (-moz-appearance: none) {
+  @supports (display: grid) {
+    .a { background: url("chrome://global/skin/a.png"); }
+  }
+}
+@-moz-document url-prefix() {
+  .c { background: url(./c.png); }
+}
+@container (width > 400px) {
+  @layer widgets {
+    @supports (display: grid) { .d { mask-image: url(./c.png); } }
+  }
+}"#,
+            ParserOptions::default(),
+        )
+        .unwrap();
+        let replacements = HashMap::from([
+            (
+                "chrome://global/skin/a.png".to_string(),
+                "../assets/a.png".to_string(),
+            ),
+            ("./c.png".to_string(), "../assets/c.png".to_string()),
+        ]);
+        let external = ExternalSpecifiers::default();
+        UrlReplacer::new(&replacements, &external)
+            .build(&mut stylesheet)
+            .unwrap();
+        let css = stylesheet.to_css(PrinterOptions::default()).unwrap().code;
+        assert!(
+            css.contains(r#"@namespace "http://www.w3.org/1999/xhtml";"#),
+            "{}",
+            css
+        );
+        assert!(css.contains(r#"url("../assets/a.png")"#), "{}", css);
+        assert_eq!(
+            css.matches(r#"url("../assets/c.png")"#).count(),
+            2,
+            "{}",
+            css
+        );
+        assert!(!css.contains("chrome://"), "{}", css);
+    }
+
+    #[test]
+    fn test_unknown_urls_fail() {
+        let mut stylesheet = StyleSheet::parse(
+            ".a { background: url(./a.png#frag); }",
+            ParserOptions::default(),
+        )
+        .unwrap();
+        let replacements = HashMap::new();
+        let external = ExternalSpecifiers::default();
+        let error = UrlReplacer::new(&replacements, &external)
+            .build(&mut stylesheet)
+            .unwrap_err();
+        assert!(error.to_string().contains("./a.png#frag"), "{}", error);
+    }
+
+    #[test]
+    fn test_unknown_urls_are_kept_or_stripped() {
+        let source = r#".a { list-style-image: url("moz-icon://stock/a?size=16"); color: red; }
+.b { background: url("chrome://global/skin/a.png") !important; }
+@font-face { font-family: A; src: url("moz-icon://stock/a"); }"#;
+        let replacements = HashMap::new();
+        let external = ExternalSpecifiers::default();
+
+        let mut stylesheet = StyleSheet::parse(source, ParserOptions::default()).unwrap();
+        let unknown = UrlReplacer::new(&replacements, &external)
+            .unknown_urls(UnknownCssUrl::Keep)
+            .build(&mut stylesheet)
+            .unwrap();
+        assert_eq!(unknown.len(), 3);
+        assert!(unknown.iter().all(|u| !u.stripped));
+        let css = stylesheet.to_css(PrinterOptions::default()).unwrap().code;
+        assert!(
+            css.contains(r#"url("moz-icon://stock/a?size=16")"#),
+            "{}",
+            css
+        );
+
+        let mut stylesheet = StyleSheet::parse(source, ParserOptions::default()).unwrap();
+        let unknown = UrlReplacer::new(&replacements, &external)
+            .unknown_urls(UnknownCssUrl::Strip)
+            .build(&mut stylesheet)
+            .unwrap();
+        assert_eq!(
+            unknown,
+            [
+                UnknownUrl {
+                    url: "moz-icon://stock/a?size=16".to_string(),
+                    stripped: true
+                },
+                UnknownUrl {
+                    url: "chrome://global/skin/a.png".to_string(),
+                    stripped: true
+                },
+                UnknownUrl {
+                    url: "moz-icon://stock/a".to_string(),
+                    stripped: false
+                },
+            ]
+        );
+        let css = stylesheet.to_css(PrinterOptions::default()).unwrap().code;
+        assert!(!css.contains("list-style-image"), "{}", css);
+        assert!(!css.contains("chrome://"), "{}", css);
+        assert!(css.contains("color: red"), "{}", css);
+        assert!(css.contains(r#"src: url("moz-icon://stock/a")"#), "{}", css);
     }
 }
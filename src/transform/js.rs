@@ -1,37 +1,132 @@
 use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use oxc::allocator::Allocator;
 use oxc::parser::{Parser, ParserReturn};
 use oxc::semantic::{SemanticBuilder, SemanticBuilderReturn};
 use oxc::span::SourceType;
+use oxc::transformer::{Transformer, TransformerReturn};
 use oxc_codegen::Codegen;
 use oxc_traverse::ReusableTraverseCtx;
 
 use crate::errors::{TransformError, TransformResult};
 use crate::transform::js_transform::{
-    CssInlineTransformer, IconTemplateImportTransformer, ImportCssTransformer, UrlTransformer,
+    CssInlineTransformer, FetchedStylesheetTransformer, IconTemplateImportTransformer,
+    ImportAttributesTransformer, ImportCssTransformer, LitCompat, ModuleUrlTransformer,
+    PrivilegedImportTransformer, PrivilegedImports, UrlTransformer, lit_imports, lit3_only_imports,
+    validate_lit2_imports,
 };
+use crate::utils::external::ExternalSpecifiers;
+use crate::utils::preprocessor::Preprocessor;
 
+/// Output of a JS transformation.
+#[derive(Debug)]
+pub struct JsTransformOutput {
+    /// The code to write to the output file
+    pub code: String,
+    /// True if no transformer changed the module, in which case `code` holds
+    /// the original source bytes instead of the codegen result
+    pub passthrough: bool,
+    /// lit-3-only exports the source imports, collected in lit 2 mode so
+    /// consumers know what won't work for them
+    pub lit3_only_imports: Vec<String>,
+    /// Hrefs of the stylesheet links that were inlined from `css_replacements`
+    pub inlined_stylesheets: Vec<String>,
+    /// Modules of `ChromeUtils` calls that were left untouched, see
+    /// `PrivilegedImports`
+    pub unhandled_privileged_imports: Vec<String>,
+}
+
+/// How JS modules are transformed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsTransformOptions {
+    /// Which lit version generated imports target
+    pub lit_compat: LitCompat,
+    /// What happens to privileged `ChromeUtils` module loads, which are
+    /// replaced using the import replacements
+    pub privileged_imports: PrivilegedImports,
+    /// Resolve `fetch()` calls of inlined stylesheets to the stylesheet
+    /// without a request
+    pub inline_fetched_stylesheets: bool,
+    /// Give rewritten template attributes a `data-mozcomp-src` sibling with
+    /// the original URL
+    pub trace_attributes: bool,
+}
+
+/// Stylesheets to inline into a JS module.
+#[derive(Debug, Clone, Copy)]
+pub struct InlineStylesheets<'a> {
+    /// Transformed stylesheets by the href they are linked or imported with
+    pub code: &'a HashMap<String, String>,
+    /// Licenses of the stylesheets, named next to where they're from
+    pub licenses: &'a HashMap<String, String>,
+    /// Hrefs of the links that are removed instead of inlined
+    pub promoted_links: &'a [String],
+}
+
+/// Transform a JS module.
+///
+/// `import_replacements` are applied to static import declarations,
+/// `url_replacements` to URLs in templates and string literals. Links to and
+/// imports of `stylesheets` are inlined, and `external` import specifiers are
+/// left untouched. Sources that go through the build's preprocessor are
+/// preprocessed with `preprocessor` first. TypeScript sources (`.ts`, `.mts`)
+/// are emitted as plain modules without their types.
 pub fn transform_from_file(
     source_path: &PathBuf,
+    import_replacements: &HashMap<String, String>,
     url_replacements: &HashMap<String, String>,
-    css_replacements: Option<&HashMap<String, String>>,
-) -> TransformResult<String> {
-    let source_code = fs::read_to_string(source_path)?;
-    transform_from_string(&source_code, url_replacements, css_replacements)
+    stylesheets: Option<&InlineStylesheets>,
+    external: &ExternalSpecifiers,
+    options: JsTransformOptions,
+    preprocessor: Option<&Preprocessor>,
+) -> TransformResult<JsTransformOutput> {
+    let mut source_code = fs::read_to_string(source_path)?;
+    if let Some(preprocessor) = preprocessor {
+        source_code = preprocessor
+            .process(&source_code, source_path)
+            .map_err(|e| TransformError::Preprocess {
+                message: e.to_string(),
+            })?;
+    }
+    let source_type = if is_typescript(source_path) {
+        SourceType::ts()
+    } else {
+        SourceType::mjs()
+    };
+    transform_from_string(
+        &source_code,
+        source_type,
+        import_replacements,
+        url_replacements,
+        stylesheets,
+        external,
+        options,
+    )
 }
 
+/// Whether a source is TypeScript, whose types are stripped.
+pub fn is_typescript(source_path: &Path) -> bool {
+    matches!(
+        source_path.extension().and_then(|e| e.to_str()),
+        Some("ts" | "mts")
+    )
+}
+
+/// Transform the source of a JS module like `transform_from_file`.
 pub fn transform_from_string(
     source_code: &str,
+    source_type: SourceType,
+    import_replacements: &HashMap<String, String>,
     url_replacements: &HashMap<String, String>,
-    css_replacements: Option<&HashMap<String, String>>,
-) -> TransformResult<String> {
+    stylesheets: Option<&InlineStylesheets>,
+    external: &ExternalSpecifiers,
+    options: JsTransformOptions,
+) -> TransformResult<JsTransformOutput> {
     // Prepare allocator and parser
     let allocator = Allocator::default();
-    let source_type = SourceType::default().with_module(true);
-    let parser = Parser::new(&allocator, &source_code, source_type);
+    let parser = Parser::new(&allocator, source_code, source_type);
     let ParserReturn {
         mut program,
         errors: _parser_errors,
@@ -59,26 +154,788 @@ pub fn transform_from_string(
             message: format!("Semantic errors: {}", error_messages.join(", ")),
         });
     }
-    let scoping = semantic.into_scoping();
+    let mut scoping = semantic.into_scoping();
+
+    // Strip the types of TypeScript sources, the output is a plain module
+    let is_typescript = source_type.is_typescript();
+    if is_typescript {
+        let TransformerReturn {
+            errors,
+            scoping: stripped,
+            ..
+        } = Transformer::new(
+            &allocator,
+            Path::new(""),
+            &oxc::transformer::TransformOptions::default(),
+        )
+        .build_with_scoping(scoping, &mut program);
+        if !errors.is_empty() {
+            let error_messages: Vec<String> = errors.iter().map(|e| format!("{:?}", e)).collect();
+            return Err(TransformError::JsTransform {
+                message: format!("Failed to strip types: {}", error_messages.join(", ")),
+            });
+        }
+        scoping = stripped;
+    }
 
     let mut ctx = ReusableTraverseCtx::new((), scoping, &allocator);
 
+    let upstream_lit_imports = lit_imports(&program);
+    let lit_compat = options.lit_compat;
+    let lit3_only_imports = match lit_compat {
+        LitCompat::Lit2 => lit3_only_imports(&upstream_lit_imports),
+        LitCompat::Lit3 => Vec::new(),
+    };
+
     // Traverse the AST to transform URLs
-    if let Some(css_replacements) = css_replacements {
-        let made_replacements =
-            CssInlineTransformer::new(css_replacements).build(&mut program, &mut ctx);
-        if made_replacements {
-            ImportCssTransformer::new().build(&mut program, &mut ctx);
+    let mut changed = is_typescript;
+    let mut inlined_stylesheets = Vec::new();
+    if let Some(stylesheets) = stylesheets {
+        let mut inliner = CssInlineTransformer::new(stylesheets.code, stylesheets.promoted_links)
+            .licenses(stylesheets.licenses);
+        if inliner.build(&mut program, &mut ctx) {
+            ImportCssTransformer::new(lit_compat).build(&mut program, &mut ctx);
+            changed = true;
         }
+        changed |= inliner.removed_promoted_links();
+        inlined_stylesheets = inliner.inlined_hrefs().to_vec();
+    }
+    changed |= UrlTransformer::new(import_replacements, external).build(&mut program, &mut ctx);
+    // After `UrlTransformer`, which would otherwise see the generated imports
+    let mut privileged =
+        PrivilegedImportTransformer::new(import_replacements, options.privileged_imports);
+    changed |= privileged.build(&mut program, &mut ctx);
+    // Before `ModuleUrlTransformer` rewrites the URLs of fetches
+    if options.inline_fetched_stylesheets
+        && let Some(stylesheets) = stylesheets
+    {
+        changed |=
+            FetchedStylesheetTransformer::new(stylesheets.code).build(&mut program, &mut ctx);
+    }
+    changed |= ModuleUrlTransformer::new(url_replacements).build(&mut program, &mut ctx);
+    changed |= IconTemplateImportTransformer::new(url_replacements, options.trace_attributes)
+        .build(&mut program, &mut ctx);
+
+    if lit_compat == LitCompat::Lit2 {
+        validate_lit2_imports(&program, &upstream_lit_imports)
+            .map_err(|message| TransformError::JsTransform { message })?;
+    }
+
+    // Keep the original bytes when nothing changed, so untouched modules
+    // don't pick up codegen formatting churn
+    let output = if changed {
+        // Codegen would drop the import attributes of re-exports
+        ImportAttributesTransformer::new().build(&mut program, &mut ctx);
+
+        // Codegen back to JavaScript string
+        let codegen = Codegen::new();
+        let output = codegen.build(&program);
+
+        // replace tabs with 2 spaces
+        output.code.replace("\t", "  ")
+    } else {
+        source_code.to_string()
+    };
+
+    validate_output(&output, SourceType::default().with_module(true))?;
+
+    Ok(JsTransformOutput {
+        code: output,
+        passthrough: !changed,
+        lit3_only_imports,
+        inlined_stylesheets,
+        unhandled_privileged_imports: privileged.unhandled().to_vec(),
+    })
+}
+
+/// Smoke check that the emitted code still parses as a module.
+fn validate_output(code: &str, source_type: SourceType) -> TransformResult<()> {
+    let allocator = Allocator::default();
+    let ParserReturn {
+        errors, panicked, ..
+    } = Parser::new(&allocator, code, source_type).parse();
+
+    if panicked {
+        return Err(TransformError::JsPanicParse);
+    }
+    if !errors.is_empty() {
+        let error_messages: Vec<String> = errors.iter().map(|e| format!("{:?}", e)).collect();
+        return Err(TransformError::JsParse {
+            message: format!("Output failed to re-parse: {}", error_messages.join(", ")),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_untouched_module_is_passed_through_byte_identical() {
+        let source = "import {  helper } from './helper.mjs'\n\nexport const x = helper( 'a' );\n";
+        let mut replacements = HashMap::new();
+        replacements.insert("./helper.mjs".to_string(), "./helper.mjs".to_string());
+
+        let output = transform_from_string(
+            source,
+            SourceType::mjs(),
+            &replacements,
+            &replacements,
+            None,
+            &ExternalSpecifiers::default(),
+            JsTransformOptions::default(),
+        )
+        .unwrap();
+        assert!(output.passthrough);
+        assert_eq!(output.code, source);
+    }
+
+    #[test]
+    fn test_touched_module_is_transformed() {
+        let source = "import {  helper } from 'chrome://global/content/helper.mjs'\n\nexport const x = helper( 'a' );\n";
+        let mut replacements = HashMap::new();
+        replacements.insert(
+            "chrome://global/content/helper.mjs".to_string(),
+            "../../dependencies/helper.mjs".to_string(),
+        );
+
+        let output = transform_from_string(
+            source,
+            SourceType::mjs(),
+            &replacements,
+            &replacements,
+            None,
+            &ExternalSpecifiers::default(),
+            JsTransformOptions::default(),
+        )
+        .unwrap();
+        assert!(!output.passthrough);
+        assert!(output.code.contains("\"../../dependencies/helper.mjs\""));
+        assert!(!output.code.contains("chrome://"));
+    }
+
+    #[test]
+    fn test_re_exports_are_rewritten() {
+        let source = r#"export { default as MozButton } from "./moz-button.mjs";
+export * from "chrome://global/content/elements/named-deck.js";
+"#;
+        let mut replacements = HashMap::new();
+        replacements.insert(
+            "./moz-button.mjs".to_string(),
+            "../moz-button/moz-button.mjs".to_string(),
+        );
+        replacements.insert(
+            "chrome://global/content/elements/named-deck.js".to_string(),
+            "../../dependencies/named-deck.js".to_string(),
+        );
+
+        let output = transform_from_string(
+            source,
+            SourceType::mjs(),
+            &replacements,
+            &HashMap::new(),
+            None,
+            &ExternalSpecifiers::default(),
+            JsTransformOptions::default(),
+        )
+        .unwrap();
+        assert!(
+            output
+                .code
+                .contains("from \"../moz-button/moz-button.mjs\""),
+            "{}",
+            output.code
+        );
+        assert!(
+            output
+                .code
+                .contains("export * from \"../../dependencies/named-deck.js\""),
+            "{}",
+            output.code
+        );
+    }
+
+    const PRIVILEGED_MODULE: &str = r#"const { XPCOMUtils } = ChromeUtils.importESModule("resource://gre/modules/XPCOMUtils.sys.mjs");
+const lazy = {};
+ChromeUtils.defineESModuleGetters(lazy, {
+  PlacesUtils: "resource://gre/modules/PlacesUtils.sys.mjs",
+});
+function later() {
+  return ChromeUtils.importESModule("resource://gre/modules/Later.sys.mjs");
+}
+"#;
+
+    fn transform_privileged_module(mode: PrivilegedImports) -> JsTransformOutput {
+        let replacements = HashMap::from([
+            (
+                "resource://gre/modules/XPCOMUtils.sys.mjs".to_string(),
+                "../../dependencies/XPCOMUtils.sys.mjs".to_string(),
+            ),
+            (
+                "resource://gre/modules/PlacesUtils.sys.mjs".to_string(),
+                "../../dependencies/PlacesUtils.sys.mjs".to_string(),
+            ),
+            (
+                "resource://gre/modules/Later.sys.mjs".to_string(),
+                "../../dependencies/Later.sys.mjs".to_string(),
+            ),
+        ]);
+        transform_from_string(
+            PRIVILEGED_MODULE,
+            SourceType::mjs(),
+            &replacements,
+            &HashMap::new(),
+            None,
+            &ExternalSpecifiers::default(),
+            JsTransformOptions {
+                privileged_imports: mode,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_privileged_imports_become_dynamic_imports() {
+        let output = transform_privileged_module(PrivilegedImports::Import);
+        assert!(
+            output.code.contains(
+                "const { XPCOMUtils } = await import(\"../../dependencies/XPCOMUtils.sys.mjs\")"
+            ),
+            "{}",
+            output.code
+        );
+        assert!(
+            output.code.contains("Object.assign(lazy"),
+            "{}",
+            output.code
+        );
+        assert!(
+            output.code.contains(
+                "PlacesUtils: (await import(\"../../dependencies/PlacesUtils.sys.mjs\")).PlacesUtils"
+            ),
+            "{}",
+            output.code
+        );
+        // Inside functions, the call can't become a top-level await
+        assert!(
+            output
+                .code
+                .contains("ChromeUtils.importESModule(\"resource://gre/modules/Later.sys.mjs\")"),
+            "{}",
+            output.code
+        );
+        assert_eq!(
+            output.unhandled_privileged_imports,
+            ["resource://gre/modules/Later.sys.mjs"]
+        );
+    }
+
+    #[test]
+    fn test_privileged_imports_are_kept() {
+        let output = transform_privileged_module(PrivilegedImports::Keep);
+        assert!(output.passthrough);
+        assert_eq!(output.unhandled_privileged_imports.len(), 3);
     }
-    UrlTransformer::new(url_replacements).build(&mut program, &mut ctx);
-    IconTemplateImportTransformer::new(url_replacements).build(&mut program, &mut ctx);
-    // Codegen back to JavaScript string
-    let codegen = Codegen::new();
-    let output = codegen.build(&program);
 
-    // replace tabs with 2 spaces
-    let output = output.code.replace("\t", "  ");
+    #[test]
+    fn test_dynamic_imports_are_rewritten() {
+        let source = "const panel = await import('./lazy-panel.mjs');\nimport(`./${name}.mjs`);\n";
+        let mut replacements = HashMap::new();
+        replacements.insert(
+            "./lazy-panel.mjs".to_string(),
+            "../../dependencies/lazy-panel.mjs".to_string(),
+        );
 
-    Ok(output)
+        let output = transform_from_string(
+            source,
+            SourceType::mjs(),
+            &replacements,
+            &HashMap::new(),
+            None,
+            &ExternalSpecifiers::default(),
+            JsTransformOptions::default(),
+        )
+        .unwrap();
+        assert!(
+            output
+                .code
+                .contains("import(\"../../dependencies/lazy-panel.mjs\")"),
+            "{}",
+            output.code
+        );
+        assert!(
+            output.code.contains("import(`./${name}.mjs`)"),
+            "{}",
+            output.code
+        );
+    }
+
+    const LIT_COMPONENT: &str = r#"import { html, MozLitElement } from "./lit.all.mjs";
+
+export default class MozA extends MozLitElement {
+  render() {
+    return html`<link rel="stylesheet" href="./moz-a.css" /><span></span>`;
+  }
+}
+"#;
+
+    fn transform_lit_component(source: &str, lit_compat: LitCompat) -> JsTransformOutput {
+        let mut replacements = HashMap::new();
+        replacements.insert(
+            "./lit.all.mjs".to_string(),
+            "../../dependencies/lit.all.mjs".to_string(),
+        );
+        let mut css_replacements = HashMap::new();
+        css_replacements.insert("./moz-a.css".to_string(), ".a { color: red; }".to_string());
+        transform_from_string(
+            source,
+            SourceType::mjs(),
+            &replacements,
+            &replacements,
+            Some(&InlineStylesheets {
+                code: &css_replacements,
+                licenses: &HashMap::new(),
+                promoted_links: &[],
+            }),
+            &ExternalSpecifiers::default(),
+            JsTransformOptions {
+                lit_compat,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_lit3_adds_css_to_vendored_lit_import() {
+        let output = transform_lit_component(LIT_COMPONENT, LitCompat::Lit3);
+        assert!(output.code.contains(
+            "import { html, MozLitElement, css } from \"../../dependencies/lit.all.mjs\";"
+        ));
+        assert!(!output.code.contains("from \"lit\""));
+        assert!(
+            output
+                .code
+                .contains("static styles = [...MozLitElement.styles ?? [], css`")
+        );
+    }
+
+    #[test]
+    fn test_inlines_links_from_templates_returned_in_an_array() {
+        let source = r#"import { html, MozLitElement } from "./lit.all.mjs";
+
+export default class MozA extends MozLitElement {
+  render() {
+    return [html`<link rel="stylesheet" href="./moz-a.css" />`, html`<div></div>`];
+  }
+}
+"#;
+        let output = transform_lit_component(source, LitCompat::Lit3);
+        assert!(!output.code.contains("<link"), "{}", output.code);
+        assert!(
+            output
+                .code
+                .contains("static styles = [...MozLitElement.styles ?? [], css`"),
+            "{}",
+            output.code
+        );
+        assert!(output.code.contains("return [html``, html`<div></div>`];"));
+    }
+
+    #[test]
+    fn test_inlined_css_is_escaped() {
+        let mut replacements = HashMap::new();
+        replacements.insert(
+            "./lit.all.mjs".to_string(),
+            "../../dependencies/lit.all.mjs".to_string(),
+        );
+        let css = r#"/* `code` */
+.a::before { content: "\2022"; }
+.b::after { content: "${name}"; }"#;
+        let css_replacements = HashMap::from([("./moz-a.css".to_string(), css.to_string())]);
+        let output = transform_from_string(
+            LIT_COMPONENT,
+            SourceType::mjs(),
+            &replacements,
+            &replacements,
+            Some(&InlineStylesheets {
+                code: &css_replacements,
+                licenses: &HashMap::new(),
+                promoted_links: &[],
+            }),
+            &ExternalSpecifiers::default(),
+            JsTransformOptions::default(),
+        )
+        .unwrap();
+        assert!(output.code.contains(r"/* \`code\` */"), "{}", output.code);
+        assert!(
+            output.code.contains(r#"content: "\\2022";"#),
+            "{}",
+            output.code
+        );
+        assert!(
+            output.code.contains(r#"content: "\${name}";"#),
+            "{}",
+            output.code
+        );
+    }
+
+    #[test]
+    fn test_lit2_imports_css_from_bare_lit() {
+        let output = transform_lit_component(LIT_COMPONENT, LitCompat::Lit2);
+        assert!(output.code.starts_with("import { css } from \"lit\";"));
+        assert!(
+            output.code.contains(
+                "import { html, MozLitElement } from \"../../dependencies/lit.all.mjs\";"
+            )
+        );
+        // The styles array shape is the same as with lit 3
+        assert!(
+            output
+                .code
+                .contains("static styles = [...MozLitElement.styles ?? [], css`")
+        );
+        assert!(output.lit3_only_imports.is_empty());
+    }
+
+    #[test]
+    fn test_lit2_reports_lit3_only_imports() {
+        let source =
+            LIT_COMPONENT.replace("{ html, MozLitElement }", "{ html, mathml, MozLitElement }");
+        let output = transform_lit_component(&source, LitCompat::Lit2);
+        assert_eq!(output.lit3_only_imports, vec!["mathml".to_string()]);
+
+        let output = transform_lit_component(&source, LitCompat::Lit3);
+        assert!(output.lit3_only_imports.is_empty());
+    }
+
+    const TEMPLATE_WITH_ICONS: &str = r#"const ICONS = { close: "chrome://global/skin/icons/close.svg" };
+
+export const render = label => html`<img src="chrome://global/skin/icons/close.svg" /><moz-button src="chrome://global/skin/icons/info.svg" label=${label} iconsrc="chrome://global/skin/icons/close.svg"></moz-button>`;
+"#;
+
+    fn transform_icons(trace_attributes: bool) -> String {
+        let mut replacements = HashMap::new();
+        replacements.insert(
+            "chrome://global/skin/icons/close.svg".to_string(),
+            "../../assets/close.svg".to_string(),
+        );
+        replacements.insert(
+            "chrome://global/skin/icons/info.svg".to_string(),
+            "../../assets/info.svg".to_string(),
+        );
+        transform_from_string(
+            TEMPLATE_WITH_ICONS,
+            SourceType::mjs(),
+            &HashMap::new(),
+            &replacements,
+            None,
+            &ExternalSpecifiers::default(),
+            JsTransformOptions {
+                trace_attributes,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .code
+    }
+
+    #[test]
+    fn test_rewritten_attributes_keep_query_and_fragment() {
+        let source = r#"export const render = () => html`<img src="chrome://global/skin/icons/arrow-down.svg#flip" /><moz-button iconsrc="chrome://global/skin/icons/arrow-down.svg?size=16"></moz-button>`;
+"#;
+        let mut replacements = HashMap::new();
+        replacements.insert(
+            "chrome://global/skin/icons/arrow-down.svg".to_string(),
+            "../../assets/arrow-down.svg".to_string(),
+        );
+        let code = transform_from_string(
+            source,
+            SourceType::mjs(),
+            &HashMap::new(),
+            &replacements,
+            None,
+            &ExternalSpecifiers::default(),
+            JsTransformOptions::default(),
+        )
+        .unwrap()
+        .code;
+        assert!(
+            code.contains(
+                "src=\"${new URL(\"../../assets/arrow-down.svg#flip\", import.meta.url).href}\""
+            ),
+            "{}",
+            code
+        );
+        assert!(
+            code.contains("iconsrc=\"${new URL(\"../../assets/arrow-down.svg?size=16\", import.meta.url).href}\""),
+            "{}",
+            code
+        );
+    }
+
+    #[test]
+    fn test_rewrites_srcset_urls() {
+        let source = r#"export const render = () => html`<img srcset="chrome://global/skin/icons/a-1x.png 1x, https://example.com/a.png 2x, chrome://global/skin/icons/a-3x.png 3x" />`;
+export const markup = svg`<image srcset="chrome://global/skin/icons/a-1x.png 1x" />`;
+"#;
+        let mut replacements = HashMap::new();
+        for name in ["a-1x.png", "a-3x.png"] {
+            replacements.insert(
+                format!("chrome://global/skin/icons/{}", name),
+                format!("../../assets/{}", name),
+            );
+        }
+        let code = transform_from_string(
+            source,
+            SourceType::mjs(),
+            &HashMap::new(),
+            &replacements,
+            None,
+            &ExternalSpecifiers::default(),
+            JsTransformOptions::default(),
+        )
+        .unwrap()
+        .code;
+        assert!(
+            code.contains(
+                "srcset=\"${new URL(\"../../assets/a-1x.png\", import.meta.url).href} 1x, https://example.com/a.png 2x, ${new URL(\"../../assets/a-3x.png\", import.meta.url).href} 3x\""
+            ),
+            "{}",
+            code
+        );
+        // Interpolations would be passed to the svg tag, so the path is
+        // inlined
+        assert!(
+            code.contains("svg`<image srcset=\"../../assets/a-1x.png 1x\" />`"),
+            "{}",
+            code
+        );
+    }
+
+    #[test]
+    fn test_rewrites_urls_in_style_attributes() {
+        let source = r#"export const render = () => html`<div style="background-image: url('chrome://global/skin/icons/info.svg')"></div><span style='mask: url(chrome://global/skin/icons/info.svg) no-repeat'></span>`;
+"#;
+        let mut replacements = HashMap::new();
+        replacements.insert(
+            "chrome://global/skin/icons/info.svg".to_string(),
+            "../../assets/info.svg".to_string(),
+        );
+        let code = transform_from_string(
+            source,
+            SourceType::mjs(),
+            &HashMap::new(),
+            &replacements,
+            None,
+            &ExternalSpecifiers::default(),
+            JsTransformOptions::default(),
+        )
+        .unwrap()
+        .code;
+        assert!(
+            code.contains(
+                "style=\"background-image: url('${new URL(\"../../assets/info.svg\", import.meta.url).href}')\""
+            ),
+            "{}",
+            code
+        );
+        assert!(
+            code.contains(
+                "style='mask: url(${new URL(\"../../assets/info.svg\", import.meta.url).href}) no-repeat'"
+            ),
+            "{}",
+            code
+        );
+    }
+
+    #[test]
+    fn test_import_attributes_are_kept() {
+        let source = r#"import tokens from "./tokens.json" with { type: "json" };
+export { default as colors, "x-y" as z } from "./tokens.json" with { type: "json" };
+export * from "./more.json" with { type: "json" };
+const lazy = await import("./tokens.json", { with: { type: "json" } });
+"#;
+        let mut replacements = HashMap::new();
+        replacements.insert(
+            "./tokens.json".to_string(),
+            "../../dependencies/tokens.json".to_string(),
+        );
+
+        let output = transform_from_string(
+            source,
+            SourceType::mjs(),
+            &replacements,
+            &replacements,
+            None,
+            &ExternalSpecifiers::default(),
+            JsTransformOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            output.code,
+            r#"import tokens from "../../dependencies/tokens.json" with { type: "json" };
+import { default as _reexport, "x-y" as _reexport2 } from "../../dependencies/tokens.json" with { type: "json" };
+export { _reexport as colors, _reexport2 as z };
+export * from "./more.json" with { type: "json" };
+const lazy = await import("../../dependencies/tokens.json", { with: { type: "json" } });
+"#
+        );
+    }
+
+    #[test]
+    fn test_rewrites_urls_in_css_templates() {
+        let source = r#"class MozA extends MozLitElement {
+  static styles = css`
+    :host { --icon-info: url("chrome://global/skin/icons/info.svg"); }
+    .a { background-image: var(--icon, url(chrome://global/skin/icons/info.svg?size=16)); }
+    ${other}
+    .b { mask: url("./unknown.svg"); }
+  `;
+}
+"#;
+        let mut replacements = HashMap::new();
+        replacements.insert(
+            "chrome://global/skin/icons/info.svg".to_string(),
+            "../../assets/info.svg".to_string(),
+        );
+
+        let output = transform_from_string(
+            source,
+            SourceType::mjs(),
+            &HashMap::new(),
+            &replacements,
+            None,
+            &ExternalSpecifiers::default(),
+            JsTransformOptions::default(),
+        )
+        .unwrap();
+        for expected in [
+            r#"--icon-info: url("../../assets/info.svg");"#,
+            "var(--icon, url(../../assets/info.svg?size=16))",
+            "${other}",
+            r#"mask: url("./unknown.svg");"#,
+        ] {
+            assert!(output.code.contains(expected), "{}", output.code);
+        }
+        assert!(!output.code.contains("new URL"), "{}", output.code);
+    }
+
+    #[test]
+    fn test_rewrites_assigned_urls() {
+        let source = r#"img.src = "chrome://global/skin/icons/loading.svg";
+this.iconSrc = this.open ? "chrome://global/skin/icons/arrow-up.svg" : "chrome://global/skin/icons/arrow-down.svg";
+const fallback = this.icon || "chrome://global/skin/icons/info.svg";
+const label = "chrome://global/locale/unknown.ftl";
+"#;
+        let mut replacements = HashMap::new();
+        for icon in ["loading", "arrow-up", "arrow-down", "info"] {
+            replacements.insert(
+                format!("chrome://global/skin/icons/{}.svg", icon),
+                format!("../../assets/{}.svg", icon),
+            );
+        }
+
+        let output = transform_from_string(
+            source,
+            SourceType::mjs(),
+            &HashMap::new(),
+            &replacements,
+            None,
+            &ExternalSpecifiers::default(),
+            JsTransformOptions::default(),
+        )
+        .unwrap();
+        for expected in [
+            r#"img.src = new URL("../../assets/loading.svg", import.meta.url).href;"#,
+            r#"this.iconSrc = this.open ? new URL("../../assets/arrow-up.svg", import.meta.url).href : new URL("../../assets/arrow-down.svg", import.meta.url).href;"#,
+            r#"const fallback = this.icon || new URL("../../assets/info.svg", import.meta.url).href;"#,
+            // Strings without a replacement are kept
+            r#"const label = "chrome://global/locale/unknown.ftl";"#,
+        ] {
+            assert!(output.code.contains(expected), "{}", output.code);
+        }
+    }
+
+    #[test]
+    fn test_rewrites_urls_in_other_templates() {
+        let source = r#"el.innerHTML = `<script type="module" src="./setup.mjs"></script><img src="./a.png" class="${cls}">`;
+const markup = svg`<image href="./a.png" />`;
+"#;
+        let mut replacements = HashMap::new();
+        replacements.insert(
+            "./setup.mjs".to_string(),
+            "../../dependencies/setup.mjs".to_string(),
+        );
+        replacements.insert("./a.png".to_string(), "../../assets/a.png".to_string());
+
+        let output = transform_from_string(
+            source,
+            SourceType::mjs(),
+            &HashMap::new(),
+            &replacements,
+            None,
+            &ExternalSpecifiers::default(),
+            JsTransformOptions::default(),
+        )
+        .unwrap();
+        assert!(
+            output.code.contains(
+                r#"src="${new URL("../../dependencies/setup.mjs", import.meta.url).href}""#
+            ),
+            "{}",
+            output.code
+        );
+        assert!(
+            output
+                .code
+                .contains(r#"<img src="${new URL("../../assets/a.png", import.meta.url).href}" class="${cls}">"#),
+            "{}",
+            output.code
+        );
+        // Expressions would be passed to the tag function
+        assert!(
+            output
+                .code
+                .contains(r#"svg`<image href="../../assets/a.png" />`"#),
+            "{}",
+            output.code
+        );
+    }
+
+    #[test]
+    fn test_trace_attributes_mark_rewritten_elements_once() {
+        let code = transform_icons(true);
+        assert_eq!(code.matches("data-mozcomp-src=").count(), 2, "{}", code);
+        assert!(
+            code.contains(
+                "<img data-mozcomp-src=\"chrome://global/skin/icons/close.svg\" src=\"${new URL(\"../../assets/close.svg\""
+            ),
+            "{}",
+            code
+        );
+        assert!(
+            code.contains(
+                "<moz-button data-mozcomp-src=\"chrome://global/skin/icons/info.svg\" src=\"${"
+            ),
+            "{}",
+            code
+        );
+        assert!(
+            code.contains("label=${label} iconsrc=\"${new URL("),
+            "{}",
+            code
+        );
+        // The icon map has no element to annotate
+        assert!(code.contains("close: new URL("), "{}", code);
+    }
+
+    #[test]
+    fn test_trace_attributes_are_off_by_default() {
+        let code = transform_icons(false);
+        assert!(!code.contains("data-mozcomp-src"), "{}", code);
+        assert!(code.contains("<img src=\"${new URL("), "{}", code);
+    }
 }
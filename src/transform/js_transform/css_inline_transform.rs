@@ -6,18 +6,41 @@ use std::collections::HashMap;
 
 pub struct CssInlineTransformer<'a> {
     css_replacements: &'a HashMap<String, String>,
+    /// Links to stylesheets that were promoted to global stylesheets, removed
+    /// from templates instead of inlined
+    promoted_links: &'a [String],
+    /// Licenses of the stylesheets, by href, named in the comment that says
+    /// where an inlined stylesheet is from
+    licenses: Option<&'a HashMap<String, String>>,
     made_replacements: bool,
+    removed_promoted_links: bool,
     referenced_hrefs: Vec<String>,
+    /// Hrefs of all stylesheets that were inlined into a styles property
+    inlined_hrefs: Vec<String>,
 }
 
 impl<'a> CssInlineTransformer<'a> {
-    pub fn new(css_replacements: &'a HashMap<String, String>) -> Self {
+    pub fn new(
+        css_replacements: &'a HashMap<String, String>,
+        promoted_links: &'a [String],
+    ) -> Self {
         Self {
             css_replacements,
+            promoted_links,
+            licenses: None,
             made_replacements: false,
+            removed_promoted_links: false,
             referenced_hrefs: Vec::new(),
+            inlined_hrefs: Vec::new(),
         }
     }
+
+    /// Name the licenses of inlined stylesheets, e.g.
+    /// `/* From ./moz-a.css, MPL-2.0 */`.
+    pub fn licenses(mut self, licenses: &'a HashMap<String, String>) -> Self {
+        self.licenses = Some(licenses);
+        self
+    }
     pub fn build(
         &mut self,
         program: &mut ast::Program<'a>,
@@ -27,6 +50,34 @@ impl<'a> CssInlineTransformer<'a> {
         self.made_replacements
     }
 
+    /// Whether any link to a promoted stylesheet was removed.
+    pub fn removed_promoted_links(&self) -> bool {
+        self.removed_promoted_links
+    }
+
+    /// Hrefs of the stylesheet links that were inlined, in the order they were
+    /// first inlined.
+    pub fn inlined_hrefs(&self) -> &[String] {
+        &self.inlined_hrefs
+    }
+
+    /// Replace the link tags of promoted stylesheets with a comment.
+    fn replace_promoted_link_tags(&self, template_str: &str) -> String {
+        let mut result = template_str.to_string();
+        for href in self.promoted_links {
+            let link_regex = Regex::new(&format!(
+                r#"<link[^>]*href\s*=\s*["']{}["'][^>]*/?>"#,
+                regex::escape(href)
+            ))
+            .unwrap();
+            let comment = format!("<!-- {} was promoted to a global stylesheet -->", href);
+            result = link_regex
+                .replace_all(&result, regex::NoExpand(&comment))
+                .to_string();
+        }
+        result
+    }
+
     fn extract_href_from_link_tag(&self, template_str: &str) -> Option<String> {
         let link_regex = Regex::new(r#"<link[^>]*href\s*=\s*["']([^"']+)["'][^>]*/?>"#).unwrap();
         if let Some(caps) = link_regex.captures(template_str) {
@@ -46,16 +97,13 @@ impl<'a> CssInlineTransformer<'a> {
 impl<'a> Traverse<'a, ()> for CssInlineTransformer<'a> {
     fn enter_class(&mut self, class: &mut ast::Class<'a>, ctx: &mut TraverseCtx<'a, ()>) {
         // get super_class name if it exists
-        let super_class_name_string = if let Some(super_class) = &class.super_class {
-            if let ast::Expression::Identifier(ident) = &super_class {
+        let super_class_name_string =
+            if let Some(ast::Expression::Identifier(ident)) = &class.super_class {
                 Some(ident.name.to_string())
             } else {
                 None
-            }
-        } else {
-            None
-        };
-        let super_class_name = super_class_name_string.as_ref().map(|s| s.as_str());
+            };
+        let super_class_name = super_class_name_string.as_deref();
 
         let mut new_properties: Vec<ClassElement<'a>> = Vec::new();
 
@@ -170,6 +218,29 @@ impl<'a> CssInlineTransformer<'a> {
                     }
                 }
             }
+            Expression::ArrayExpression(array) => {
+                // render() can return several templates as an array
+                for element in &mut array.elements {
+                    if let ast::ArrayExpressionElement::SpreadElement(spread) = element {
+                        self.process_expression(
+                            &mut spread.argument,
+                            ctx,
+                            new_properties,
+                            super_class_name,
+                        );
+                    } else if let Some(expr) = element.as_expression_mut() {
+                        self.process_expression(expr, ctx, new_properties, super_class_name);
+                    }
+                }
+            }
+            Expression::ParenthesizedExpression(paren) => {
+                self.process_expression(
+                    &mut paren.expression,
+                    ctx,
+                    new_properties,
+                    super_class_name,
+                );
+            }
             // Add more expression types as needed
             _ => {}
         }
@@ -181,14 +252,24 @@ impl<'a> CssInlineTransformer<'a> {
         ctx: &mut TraverseCtx<'a, ()>,
     ) -> bool {
         let mut found_replacement = false;
+        // Check for stylesheet link tags
+        let link_tag_regex =
+            Regex::new(r#"<link[\s\S]*?rel\s*=\s*[\"']stylesheet[\"'][\s\S]*/?>"#).unwrap();
 
         for quasi in &mut template.quasis {
             let Some(cooked) = &quasi.value.cooked else {
                 continue;
             };
-            // Check for stylesheet link tags
-            let link_tag_regex =
-                Regex::new(r#"<link[\s\S]*?rel\s*=\s*[\"']stylesheet[\"'][\s\S]*/?>"#).unwrap();
+            let without_promoted = self.replace_promoted_link_tags(cooked);
+            if without_promoted != cooked.as_str() {
+                quasi.value.cooked =
+                    Some(ctx.ast.atom_from_strs_array([without_promoted.as_str()]));
+                quasi.value.raw = ctx.ast.atom_from_strs_array([without_promoted.as_str()]);
+                self.removed_promoted_links = true;
+            }
+            let Some(cooked) = &quasi.value.cooked else {
+                continue;
+            };
             if !link_tag_regex.is_match(cooked) {
                 continue;
             }
@@ -225,10 +306,10 @@ impl<'a> CssInlineTransformer<'a> {
     ) {
         // Only add styles property once per class
         if new_properties.iter().any(|prop| {
-            if let ClassElement::PropertyDefinition(prop_def) = prop {
-                if let PropertyKey::Identifier(ident) = &prop_def.key {
-                    return ident.name == "styles";
-                }
+            if let ClassElement::PropertyDefinition(prop_def) = prop
+                && let PropertyKey::Identifier(ident) = &prop_def.key
+            {
+                return ident.name == "styles";
             }
             false
         }) {
@@ -239,19 +320,30 @@ impl<'a> CssInlineTransformer<'a> {
         let mut combined_css = String::new();
         for href in &self.referenced_hrefs {
             if let Some(css) = self.css_replacements.get(href) {
-                combined_css.push_str(&format!("/* From {} */\n", href));
+                match self.licenses.and_then(|licenses| licenses.get(href)) {
+                    Some(license) => {
+                        combined_css.push_str(&format!("/* From {}, {} */\n", href, license))
+                    }
+                    None => combined_css.push_str(&format!("/* From {} */\n", href)),
+                }
                 combined_css.push_str(css);
                 combined_css.push('\n');
+                if !self.inlined_hrefs.contains(href) {
+                    self.inlined_hrefs.push(href.clone());
+                }
             }
         }
 
         if !combined_css.is_empty() {
-            // Build the tagged template expression for the new styles
+            // Build the tagged template expression for the new styles. The raw
+            // text is what gets printed, so it needs the characters that end
+            // the template or start a substitution escaped.
+            let raw = escape_template_text(&combined_css);
             let template_element = ctx.ast.template_element(
                 SPAN,
                 ast::TemplateElementValue {
                     cooked: Some(ctx.ast.atom_from_strs_array([combined_css.as_str()])),
-                    raw: ctx.ast.atom_from_strs_array([combined_css.as_str()]),
+                    raw: ctx.ast.atom_from_strs_array([raw.as_str()]),
                 },
                 true,
             );
@@ -330,3 +422,11 @@ impl<'a> CssInlineTransformer<'a> {
         }
     }
 }
+
+/// Escape backslashes, backticks and `${` so text can be the raw text of a
+/// template literal.
+fn escape_template_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('`', "\\`")
+        .replace("${", "\\${")
+}
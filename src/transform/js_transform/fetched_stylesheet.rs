@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+
+use oxc::ast::ast::{Argument, Expression};
+use oxc::span::SPAN;
+use oxc_traverse::{ReusableTraverseCtx, Traverse, TraverseCtx};
+
+use crate::dependencies::js::stylesheet_fetch;
+
+/// Replaces `fetch("...css")` with `Promise.resolve(new Response("..."))`
+/// holding the transformed stylesheet, so code reading the response, like
+/// `sheet.replaceSync(await (await fetch(url)).text())`, keeps working without
+/// a request.
+pub struct FetchedStylesheetTransformer<'a> {
+    /// Transformed stylesheets by the URL they are fetched from
+    stylesheets: &'a HashMap<String, String>,
+    made_replacements: bool,
+}
+
+impl<'a> FetchedStylesheetTransformer<'a> {
+    pub fn new(stylesheets: &'a HashMap<String, String>) -> Self {
+        Self {
+            stylesheets,
+            made_replacements: false,
+        }
+    }
+
+    /// Inlines fetched stylesheets and returns true if any of them changed.
+    pub fn build(
+        &mut self,
+        program: &mut oxc::ast::ast::Program<'a>,
+        ctx: &mut ReusableTraverseCtx<'a, ()>,
+    ) -> bool {
+        oxc_traverse::traverse_mut_with_ctx(self, program, ctx);
+        self.made_replacements
+    }
+}
+
+impl<'a> Traverse<'a, ()> for FetchedStylesheetTransformer<'a> {
+    fn enter_expression(&mut self, expr: &mut Expression<'a>, ctx: &mut TraverseCtx<'a, ()>) {
+        let Expression::CallExpression(call) = expr else {
+            return;
+        };
+        let Some(css) =
+            stylesheet_fetch(call).and_then(|literal| self.stylesheets.get(literal.value.as_str()))
+        else {
+            return;
+        };
+
+        // new Response("<css>")
+        let css = ctx.ast.atom_from_strs_array([css.as_str()]);
+        let body = ctx.ast.string_literal(SPAN, css, None);
+        let mut response_arguments = ctx.ast.vec_with_capacity(1);
+        response_arguments.push(Argument::StringLiteral(ctx.ast.alloc(body)));
+        let response_ident = ctx.ast.identifier_reference(SPAN, "Response");
+        let response = ctx.ast.expression_new(
+            SPAN,
+            Expression::Identifier(ctx.ast.alloc(response_ident)),
+            None::<oxc::allocator::Box<'a, oxc::ast::ast::TSTypeParameterInstantiation<'a>>>,
+            response_arguments,
+        );
+
+        // Promise.resolve(...)
+        let promise_ident = ctx.ast.identifier_reference(SPAN, "Promise");
+        let resolve = ctx.ast.static_member_expression(
+            SPAN,
+            Expression::Identifier(ctx.ast.alloc(promise_ident)),
+            ctx.ast.identifier_name(SPAN, "resolve"),
+            false,
+        );
+        let mut arguments = ctx.ast.vec_with_capacity(1);
+        arguments.push(Argument::from(response));
+        *expr = ctx.ast.expression_call(
+            SPAN,
+            Expression::StaticMemberExpression(ctx.ast.alloc(resolve)),
+            None::<oxc::allocator::Box<'a, oxc::ast::ast::TSTypeParameterInstantiation<'a>>>,
+            arguments,
+            false,
+        );
+        self.made_replacements = true;
+    }
+}
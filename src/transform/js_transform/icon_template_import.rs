@@ -1,21 +1,42 @@
 use oxc::ast::ast::{
-    ArrayExpression, Expression, ObjectProperty, PropertyKey, TaggedTemplateExpression,
-    TemplateLiteral,
+    ArrayExpression, AssignmentExpression, Expression, ObjectProperty, PropertyKey,
+    TaggedTemplateExpression, TemplateElement, TemplateLiteral, VariableDeclarator,
 };
 use oxc::span::SPAN;
 use oxc_traverse::{ReusableTraverseCtx, Traverse, TraverseCtx};
 use regex::Regex;
 use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::dependencies::js::{attribute_regex, srcset_urls, style_urls};
+
+/// Attribute added next to a rewritten template attribute when tracing, holding
+/// the original URL.
+const TRACE_ATTRIBUTE: &str = "data-mozcomp-src";
+
+/// An attribute in a template element with URLs to replace, like `srcset`.
+struct UrlAttribute {
+    /// Where the attribute starts in the element
+    start: usize,
+    /// The value of the attribute
+    original: String,
+    /// The replacements of the URLs, with their ranges in the element
+    replacements: Vec<(Range<usize>, String)>,
+}
 
 pub struct IconTemplateImportTransformer<'a> {
     path_replacements: &'a HashMap<String, String>,
+    /// Add a `data-mozcomp-src` attribute with the original URL to every
+    /// element whose attribute gets rewritten
+    trace_attributes: bool,
     made_replacements: bool,
 }
 
 impl<'a> IconTemplateImportTransformer<'a> {
-    pub fn new(path_replacements: &'a HashMap<String, String>) -> Self {
+    pub fn new(path_replacements: &'a HashMap<String, String>, trace_attributes: bool) -> Self {
         Self {
             path_replacements,
+            trace_attributes,
             made_replacements: false,
         }
     }
@@ -40,11 +61,54 @@ impl<'a> Traverse<'a, ()> for IconTemplateImportTransformer<'a> {
         let Expression::Identifier(ident) = &tagged.tag else {
             return;
         };
+        if ident.name == "css" {
+            self.process_css_urls(&mut tagged.quasi, ctx);
+            return;
+        }
         if ident.name != "html" {
+            // Expressions would be passed to the tag function, so other
+            // templates only get the rewritten paths
+            for attribute in ["src", "iconsrc", "href"] {
+                self.process_attribute_urls(&mut tagged.quasi, attribute, whole_value, false, ctx);
+            }
+            self.process_attribute_urls(&mut tagged.quasi, "srcset", srcset_urls, false, ctx);
+            self.process_attribute_urls(&mut tagged.quasi, "style", style_urls, false, ctx);
             return;
         }
 
         self.process_html_template(&mut tagged.quasi, ctx);
+        self.process_attribute_urls(&mut tagged.quasi, "srcset", srcset_urls, true, ctx);
+        self.process_attribute_urls(&mut tagged.quasi, "style", style_urls, true, ctx);
+    }
+
+    fn enter_expression(&mut self, expr: &mut Expression<'a>, ctx: &mut TraverseCtx<'a, ()>) {
+        // Untagged templates, e.g. assigned to `innerHTML`, join their
+        // expressions into a string like `html` templates
+        let Expression::TemplateLiteral(template) = expr else {
+            return;
+        };
+        self.process_html_template(template, ctx);
+        self.process_attribute_urls(template, "srcset", srcset_urls, true, ctx);
+        self.process_attribute_urls(template, "style", style_urls, true, ctx);
+    }
+
+    fn enter_assignment_expression(
+        &mut self,
+        assignment: &mut AssignmentExpression<'a>,
+        ctx: &mut TraverseCtx<'a, ()>,
+    ) {
+        // Icons set imperatively, like `img.src = "chrome://..."`
+        self.replace_url_literals(&mut assignment.right, ctx);
+    }
+
+    fn enter_variable_declarator(
+        &mut self,
+        declarator: &mut VariableDeclarator<'a>,
+        ctx: &mut TraverseCtx<'a, ()>,
+    ) {
+        if let Some(init) = &mut declarator.init {
+            self.replace_url_literals(init, ctx);
+        }
     }
 
     fn enter_array_expression(
@@ -59,7 +123,7 @@ impl<'a> Traverse<'a, ()> for IconTemplateImportTransformer<'a> {
 
                 if let Some(replacement_path) = self.path_replacements.get(string_value) {
                     // Replace the string literal with new URL(replacement_path, import.meta.url).href
-                    let url_expression = self.create_url_expression(replacement_path, ctx);
+                    let url_expression = create_url_expression(replacement_path, ctx);
 
                     // Replace the array element with the new expression
                     *element = oxc::ast::ast::ArrayExpressionElement::from(url_expression);
@@ -80,7 +144,7 @@ impl<'a> Traverse<'a, ()> for IconTemplateImportTransformer<'a> {
 
             if let Some(replacement_path) = self.path_replacements.get(string_value) {
                 // Replace the string literal with new URL(replacement_path, import.meta.url).href
-                let url_expression = self.create_url_expression(replacement_path, ctx);
+                let url_expression = create_url_expression(replacement_path, ctx);
 
                 // Replace the property value with the new expression
                 property.value = url_expression;
@@ -94,7 +158,7 @@ impl<'a> Traverse<'a, ()> for IconTemplateImportTransformer<'a> {
 
             if let Some(replacement_path) = self.path_replacements.get(string_value) {
                 // Replace the string literal key with new URL(replacement_path, import.meta.url).href
-                let url_expression = self.create_url_expression(replacement_path, ctx);
+                let url_expression = create_url_expression(replacement_path, ctx);
 
                 // Create a computed property key from the expression
                 property.key = PropertyKey::from(url_expression);
@@ -106,13 +170,51 @@ impl<'a> Traverse<'a, ()> for IconTemplateImportTransformer<'a> {
 }
 
 impl<'a> IconTemplateImportTransformer<'a> {
+    /// Replace string literals with a replacement by their URL expression,
+    /// including both branches of conditionals like `a ? "x.svg" : "y.svg"`
+    /// and the operands of fallbacks like `a || "x.svg"`.
+    fn replace_url_literals(&mut self, expr: &mut Expression<'a>, ctx: &mut TraverseCtx<'a, ()>) {
+        match expr {
+            Expression::StringLiteral(string_literal) => {
+                if let Some(replacement_path) =
+                    self.path_replacements.get(string_literal.value.as_str())
+                {
+                    *expr = create_url_expression(replacement_path, ctx);
+                    self.made_replacements = true;
+                }
+            }
+            Expression::ConditionalExpression(conditional) => {
+                self.replace_url_literals(&mut conditional.consequent, ctx);
+                self.replace_url_literals(&mut conditional.alternate, ctx);
+            }
+            Expression::LogicalExpression(logical) => {
+                self.replace_url_literals(&mut logical.left, ctx);
+                self.replace_url_literals(&mut logical.right, ctx);
+            }
+            Expression::ParenthesizedExpression(parenthesized) => {
+                self.replace_url_literals(&mut parenthesized.expression, ctx);
+            }
+            _ => {}
+        }
+    }
+
+    /// The replacement of an attribute value, keeping its query string or
+    /// fragment, e.g. `icon.svg#flip`.
+    fn replacement_for(&self, value: &str) -> Option<String> {
+        let (base, suffix) = value.split_at(value.find(['?', '#']).unwrap_or(value.len()));
+        self.path_replacements
+            .get(base)
+            .map(|replacement| format!("{}{}", replacement, suffix))
+    }
+
     fn process_html_template(
         &mut self,
         template: &mut TemplateLiteral<'a>,
         ctx: &mut TraverseCtx<'a, ()>,
     ) {
-        // Pattern to match src="chrome://..." or iconsrc="chrome://..." attributes
-        let src_regex = Regex::new(r#"(src|iconsrc)\s*=\s*["']([^"']+)["']"#).unwrap();
+        // Pattern to match src="chrome://...", iconsrc="chrome://..." or
+        // href="chrome://..." attributes
+        let src_regex = Regex::new(r#"(src|iconsrc|href)\s*=\s*["']([^"']+)["']"#).unwrap();
 
         // To support multiple replacements, iterate until no more matches are found
         let mut idx = 0;
@@ -123,31 +225,41 @@ impl<'a> IconTemplateImportTransformer<'a> {
                 continue;
             };
 
-            // Find all matches in this quasi
-            let mut cooked_str = cooked.as_ref();
+            // Find the first match with a replacement in this quasi
+            let cooked_str = cooked.as_ref();
+            let mut search_start = 0;
             let mut found = false;
-            while let Some(caps) = src_regex.captures(cooked_str) {
+            while let Some(caps) = src_regex.captures_at(cooked_str, search_start) {
                 let full_match = caps.get(0).unwrap();
+                let attribute = caps.get(1).unwrap().as_str();
                 let src_value = caps.get(2).unwrap().as_str();
-                if let Some(replacement_path) = self.path_replacements.get(src_value) {
+                if let Some(replacement_path) = self.replacement_for(src_value) {
                     let before_src = cooked_str[..full_match.start()].to_string();
                     let after_src = cooked_str[full_match.end()..].to_string();
+                    let attribute = if self.trace_attributes
+                        && !element_is_traced(template, idx, &before_src)
+                    {
+                        format!("{}=\"{}\" {}", TRACE_ATTRIBUTE, src_value, attribute)
+                    } else {
+                        attribute.to_string()
+                    };
                     self.replace_src_with_url_expression(
                         template,
                         idx,
-                        &before_src,
+                        &format!("{}{}", before_src, attribute),
                         &after_src,
-                        replacement_path,
+                        &replacement_path,
                         ctx,
                     );
                     self.made_replacements = true;
-                    // After insertion, the current quasi is split, so move to the next quasi after the inserted one
-                    idx += 2;
+                    // After insertion, the rest of the current quasi is in the
+                    // next one, continue searching there
+                    idx += 1;
                     found = true;
                     break;
                 } else {
                     // If no replacement, skip this match and continue searching
-                    cooked_str = &cooked_str[full_match.end()..];
+                    search_start = full_match.end();
                 }
             }
             if !found {
@@ -156,6 +268,157 @@ impl<'a> IconTemplateImportTransformer<'a> {
         }
     }
 
+    /// Replace the URLs of the `url()` tokens of a Lit `css` template with
+    /// the rewritten relative paths. The tag only accepts other `css`
+    /// templates as expressions, so they can't become `new URL()` expressions.
+    fn process_css_urls(
+        &mut self,
+        template: &mut TemplateLiteral<'a>,
+        ctx: &mut TraverseCtx<'a, ()>,
+    ) {
+        for quasi in template.quasis.iter_mut() {
+            let Some(cooked) = &quasi.value.cooked else {
+                continue;
+            };
+            let cooked = cooked.to_string();
+            let mut text = String::new();
+            let mut from = 0;
+            for range in style_urls(&cooked) {
+                let Some(replacement) = self.replacement_for(&cooked[range.clone()]) else {
+                    continue;
+                };
+                text.push_str(&cooked[from..range.start]);
+                text.push_str(&replacement);
+                from = range.end;
+            }
+            if from == 0 {
+                continue;
+            }
+            text.push_str(&cooked[from..]);
+            let tail = quasi.tail;
+            set_quasi_text(quasi, &text, tail, ctx);
+            self.made_replacements = true;
+        }
+    }
+
+    /// Rewrite the URLs of attributes that can hold several of them, like
+    /// `srcset` or the `url()` tokens of `style`, keeping the rest of the
+    /// value. `urls` finds them in the attribute value. With `interpolate`,
+    /// every URL becomes a `new URL()` expression, else it is replaced by the
+    /// rewritten relative path.
+    fn process_attribute_urls(
+        &mut self,
+        template: &mut TemplateLiteral<'a>,
+        attribute: &str,
+        urls: fn(&str) -> Vec<Range<usize>>,
+        interpolate: bool,
+        ctx: &mut TraverseCtx<'a, ()>,
+    ) {
+        let attribute_regex = attribute_regex(attribute);
+
+        let mut idx = 0;
+        while idx < template.quasis.len() {
+            let Some(cooked) = &template.quasis[idx].value.cooked else {
+                idx += 1;
+                continue;
+            };
+            let cooked_str = cooked.to_string();
+
+            // Every attribute in this quasi with a URL to replace
+            let attributes: Vec<UrlAttribute> = attribute_regex
+                .captures_iter(&cooked_str)
+                .filter_map(|caps| {
+                    let attribute = caps.get(0).unwrap();
+                    let value = caps.get(1).or(caps.get(2)).unwrap();
+                    let replacements: Vec<(Range<usize>, String)> = urls(value.as_str())
+                        .into_iter()
+                        .filter_map(|range| {
+                            let replacement =
+                                self.replacement_for(&value.as_str()[range.clone()])?;
+                            Some((
+                                value.start() + range.start..value.start() + range.end,
+                                replacement,
+                            ))
+                        })
+                        .collect();
+                    (!replacements.is_empty()).then(|| UrlAttribute {
+                        start: attribute.start(),
+                        original: value.as_str().to_string(),
+                        replacements,
+                    })
+                })
+                .collect();
+            if attributes.is_empty() {
+                idx += 1;
+                continue;
+            }
+
+            // The text up to the first replaced URL of an attribute, with a
+            // trace attribute in front of it when tracing
+            let text_before = |text: &mut String,
+                               template: &TemplateLiteral<'a>,
+                               attribute: &UrlAttribute,
+                               from: usize| {
+                text.push_str(&cooked_str[from..attribute.start]);
+                if self.trace_attributes && !element_is_traced(template, idx, text) {
+                    let original = attribute.original.replace('"', "&quot;");
+                    text.push_str(&format!("{}=\"{}\" ", TRACE_ATTRIBUTE, original));
+                }
+                text.push_str(&cooked_str[attribute.start..attribute.replacements[0].0.start]);
+            };
+
+            let is_tail = template.quasis[idx].tail;
+            if interpolate {
+                // Only the first attribute is handled here, the rest of the text
+                // ends up in the last inserted quasi and is searched next. The
+                // text between the URLs becomes the quasis around the inserted
+                // expressions.
+                let replacements = &attributes[0].replacements;
+                let mut before = String::new();
+                text_before(&mut before, template, &attributes[0], 0);
+                set_quasi_text(&mut template.quasis[idx], &before, false, ctx);
+                for (i, (range, replacement)) in replacements.iter().enumerate() {
+                    let text_end = replacements
+                        .get(i + 1)
+                        .map_or(cooked_str.len(), |(next, _)| next.start);
+                    let text = &cooked_str[range.end..text_end];
+                    let last = i + 1 == replacements.len();
+                    let element = ctx.ast.template_element(
+                        SPAN,
+                        oxc::ast::ast::TemplateElementValue {
+                            cooked: Some(ctx.ast.atom_from_strs_array([text])),
+                            raw: ctx.ast.atom_from_strs_array([text]),
+                        },
+                        last && is_tail,
+                    );
+                    let url_expression = create_url_expression(replacement, ctx);
+                    template.expressions.insert(idx + i, url_expression);
+                    template.quasis.insert(idx + i + 1, element);
+                }
+                idx += replacements.len();
+            } else {
+                let mut text = String::new();
+                let mut from = 0;
+                for attribute in &attributes {
+                    text_before(&mut text, template, attribute, from);
+                    let replacements = &attribute.replacements;
+                    for (i, (range, replacement)) in replacements.iter().enumerate() {
+                        text.push_str(replacement);
+                        let text_end = replacements
+                            .get(i + 1)
+                            .map_or(range.end, |(next, _)| next.start);
+                        text.push_str(&cooked_str[range.end..text_end]);
+                    }
+                    from = replacements.last().unwrap().0.end;
+                }
+                text.push_str(&cooked_str[from..]);
+                set_quasi_text(&mut template.quasis[idx], &text, is_tail, ctx);
+                idx += 1;
+            }
+            self.made_replacements = true;
+        }
+    }
+
     fn replace_src_with_url_expression(
         &mut self,
         template: &mut TemplateLiteral<'a>,
@@ -166,11 +429,11 @@ impl<'a> IconTemplateImportTransformer<'a> {
         ctx: &mut TraverseCtx<'a, ()>,
     ) {
         // Create the new template structure:
-        // 1. First quasi: content before src + 'src="'
+        // 1. First quasi: content before src, including the attribute name + '="'
         // 2. Expression: new URL(...)
         // 3. Second quasi: '"' + content after src + rest
 
-        let new_before = format!("{}src=\"", before_src);
+        let new_before = format!("{}=\"", before_src);
         let new_after = format!("\"{}", after_src);
 
         // Update current quasi to be the "before" part
@@ -182,7 +445,7 @@ impl<'a> IconTemplateImportTransformer<'a> {
         current_quasi.tail = false;
 
         // Create the new URL expression: new URL('./relative/path', import.meta.url)
-        let url_expression = self.create_url_expression(replacement_path, ctx);
+        let url_expression = create_url_expression(replacement_path, ctx);
 
         // Create the "after" template element
         let after_element = ctx.ast.template_element(
@@ -198,61 +461,100 @@ impl<'a> IconTemplateImportTransformer<'a> {
         template.expressions.insert(quasi_index, url_expression);
         template.quasis.insert(quasi_index + 1, after_element);
     }
+}
 
-    fn create_url_expression(
-        &self,
-        replacement_path: &str,
-        ctx: &mut TraverseCtx<'a, ()>,
-    ) -> Expression<'a> {
-        // Create: new URL('./relative/path', import.meta.url).href
-
-        // Create the URL identifier
-        let url_ident = ctx.ast.identifier_reference(SPAN, "URL");
-
-        // Create the first argument: string literal with the replacement path
-        let path_atom = ctx.ast.atom_from_strs_array([replacement_path]);
-        let path_literal = ctx.ast.string_literal(SPAN, path_atom, None);
-        let path_arg = oxc::ast::ast::Argument::StringLiteral(ctx.ast.alloc(path_literal));
-
-        // Create import.meta.url
-        let import_ident = ctx.ast.identifier_name(SPAN, "import");
-        let meta_ident = ctx.ast.identifier_name(SPAN, "meta");
-        let url_ident_name = ctx.ast.identifier_name(SPAN, "url");
-
-        let import_meta = ctx.ast.meta_property(SPAN, import_ident, meta_ident);
-        let import_meta_url = ctx.ast.static_member_expression(
-            SPAN,
-            Expression::MetaProperty(ctx.ast.alloc(import_meta)),
-            url_ident_name,
-            false,
-        );
-        let meta_url_arg =
-            oxc::ast::ast::Argument::StaticMemberExpression(ctx.ast.alloc(import_meta_url));
-
-        // Create arguments vector
-        let mut arguments = ctx.ast.vec_with_capacity(2);
-        arguments.push(path_arg);
-        arguments.push(meta_url_arg);
+/// The range of an attribute value that is a single URL, like `src`.
+fn whole_value(value: &str) -> Vec<Range<usize>> {
+    let start = value.len() - value.trim_start().len();
+    let end = value.trim_end().len();
+    (start < end).then_some(start..end).into_iter().collect()
+}
 
-        // Create the new expression
-        let new_expr = ctx.ast.new_expression(
-            SPAN,
-            Expression::Identifier(ctx.ast.alloc(url_ident)),
-            None as Option<
-                oxc::allocator::Box<'a, oxc::ast::ast::TSTypeParameterInstantiation<'a>>,
-            >,
-            arguments,
-        );
+/// `new URL("<replacement_path>", import.meta.url).href`, the URL of a file
+/// relative to the module.
+pub(crate) fn create_url_expression<'a>(
+    replacement_path: &str,
+    ctx: &mut TraverseCtx<'a, ()>,
+) -> Expression<'a> {
+    // Create: new URL('./relative/path', import.meta.url).href
+
+    // Create the URL identifier
+    let url_ident = ctx.ast.identifier_reference(SPAN, "URL");
+
+    // Create the first argument: string literal with the replacement path
+    let path_atom = ctx.ast.atom_from_strs_array([replacement_path]);
+    let path_literal = ctx.ast.string_literal(SPAN, path_atom, None);
+    let path_arg = oxc::ast::ast::Argument::StringLiteral(ctx.ast.alloc(path_literal));
+
+    // Create import.meta.url
+    let import_ident = ctx.ast.identifier_name(SPAN, "import");
+    let meta_ident = ctx.ast.identifier_name(SPAN, "meta");
+    let url_ident_name = ctx.ast.identifier_name(SPAN, "url");
+
+    let import_meta = ctx.ast.meta_property(SPAN, import_ident, meta_ident);
+    let import_meta_url = ctx.ast.static_member_expression(
+        SPAN,
+        Expression::MetaProperty(ctx.ast.alloc(import_meta)),
+        url_ident_name,
+        false,
+    );
+    let meta_url_arg =
+        oxc::ast::ast::Argument::StaticMemberExpression(ctx.ast.alloc(import_meta_url));
+
+    // Create arguments vector
+    let mut arguments = ctx.ast.vec_with_capacity(2);
+    arguments.push(path_arg);
+    arguments.push(meta_url_arg);
+
+    // Create the new expression
+    let new_expr = ctx.ast.new_expression(
+        SPAN,
+        Expression::Identifier(ctx.ast.alloc(url_ident)),
+        None as Option<oxc::allocator::Box<'a, oxc::ast::ast::TSTypeParameterInstantiation<'a>>>,
+        arguments,
+    );
+
+    // Wrap in StaticMemberExpression to access .href property
+    let href_ident = ctx.ast.identifier_name(SPAN, "href");
+    let static_member = ctx.ast.static_member_expression(
+        SPAN,
+        Expression::NewExpression(ctx.ast.alloc(new_expr)),
+        href_ident,
+        false,
+    );
+
+    Expression::StaticMemberExpression(ctx.ast.alloc(static_member))
+}
 
-        // Wrap in StaticMemberExpression to access .href property
-        let href_ident = ctx.ast.identifier_name(SPAN, "href");
-        let static_member = ctx.ast.static_member_expression(
-            SPAN,
-            Expression::NewExpression(ctx.ast.alloc(new_expr)),
-            href_ident,
-            false,
-        );
+/// Replace the text of a template element.
+fn set_quasi_text<'a>(
+    quasi: &mut TemplateElement<'a>,
+    text: &str,
+    tail: bool,
+    ctx: &mut TraverseCtx<'a, ()>,
+) {
+    quasi.value.cooked = Some(ctx.ast.atom_from_strs_array([text]));
+    quasi.value.raw = ctx.ast.atom_from_strs_array([text]);
+    quasi.tail = tail;
+}
 
-        Expression::StaticMemberExpression(ctx.ast.alloc(static_member))
+/// Check if the element that is open at the end of `before_src`, the start of
+/// quasi `quasi_index`, already has a trace attribute. The element can start
+/// in an earlier quasi when another of its attributes was rewritten.
+fn element_is_traced(template: &TemplateLiteral, quasi_index: usize, before_src: &str) -> bool {
+    let earlier = template.quasis[..quasi_index]
+        .iter()
+        .rev()
+        .map(|quasi| quasi.value.raw.as_str());
+    for text in std::iter::once(before_src).chain(earlier) {
+        match text.rfind(['<', '>']) {
+            Some(start) if text[start..].starts_with('<') => {
+                return text[start..].contains(TRACE_ATTRIBUTE);
+            }
+            Some(_) => return false,
+            None if text.contains(TRACE_ATTRIBUTE) => return true,
+            None => {}
+        }
     }
+    false
 }
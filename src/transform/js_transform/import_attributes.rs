@@ -0,0 +1,131 @@
+use oxc::ast::ast::{
+    ExportNamedDeclaration, ImportDeclarationSpecifier, ImportOrExportKind, ModuleExportName,
+    Program, Statement,
+};
+use oxc::semantic::SymbolFlags;
+use oxc::span::SPAN;
+use oxc_traverse::{ReusableTraverseCtx, Traverse, TraverseCtx};
+
+/// Splits re-exports with import attributes, like
+/// `export { default as tokens } from "./tokens.json" with { type: "json" }`,
+/// into an import with the attributes and a local export. Codegen drops the
+/// `with` clause of re-exports, and a JSON module loaded without
+/// `type: "json"` fails to load. Imports keep their attributes in codegen.
+#[derive(Default)]
+pub struct ImportAttributesTransformer {
+    made_replacements: bool,
+}
+
+impl ImportAttributesTransformer {
+    pub fn new() -> Self {
+        Self {
+            made_replacements: false,
+        }
+    }
+
+    /// Splits re-exports with import attributes and returns true if there
+    /// were any.
+    pub fn build<'a>(
+        &mut self,
+        program: &mut Program<'a>,
+        ctx: &mut ReusableTraverseCtx<'a, ()>,
+    ) -> bool {
+        oxc_traverse::traverse_mut_with_ctx(self, program, ctx);
+        self.made_replacements
+    }
+}
+
+/// Whether a statement is a re-export with import attributes.
+fn is_reexport_with_attributes(statement: &Statement) -> bool {
+    matches!(
+        statement,
+        Statement::ExportNamedDeclaration(declaration)
+            if declaration.source.is_some() && declaration.with_clause.is_some()
+    )
+}
+
+impl<'a> Traverse<'a, ()> for ImportAttributesTransformer {
+    fn exit_program(&mut self, program: &mut Program<'a>, ctx: &mut TraverseCtx<'a, ()>) {
+        if !program.body.iter().any(is_reexport_with_attributes) {
+            return;
+        }
+
+        let body = std::mem::replace(&mut program.body, ctx.ast.vec());
+        for statement in body {
+            let Statement::ExportNamedDeclaration(declaration) = statement else {
+                program.body.push(statement);
+                continue;
+            };
+            if declaration.source.is_none() || declaration.with_clause.is_none() {
+                program
+                    .body
+                    .push(Statement::ExportNamedDeclaration(declaration));
+                continue;
+            }
+            let ExportNamedDeclaration {
+                specifiers,
+                source,
+                with_clause,
+                ..
+            } = declaration.unbox();
+
+            // import { <local> as _reexport } from "<source>" with { ... }
+            let mut import_specifiers = ctx.ast.vec_with_capacity(specifiers.len());
+            let mut export_specifiers = ctx.ast.vec_with_capacity(specifiers.len());
+            for specifier in specifiers {
+                let binding = ctx.generate_uid_in_root_scope("reexport", SymbolFlags::Import);
+                let imported = match specifier.local {
+                    ModuleExportName::StringLiteral(literal) => {
+                        ModuleExportName::StringLiteral(literal)
+                    }
+                    local => ModuleExportName::IdentifierName(
+                        ctx.ast.identifier_name(SPAN, local.name()),
+                    ),
+                };
+                let import_specifier = ctx.ast.import_specifier(
+                    SPAN,
+                    imported,
+                    binding.create_binding_identifier(ctx),
+                    ImportOrExportKind::Value,
+                );
+                import_specifiers.push(ImportDeclarationSpecifier::ImportSpecifier(
+                    ctx.ast.alloc(import_specifier),
+                ));
+                // export { _reexport as <exported> }
+                export_specifiers.push(ctx.ast.export_specifier(
+                    SPAN,
+                    ModuleExportName::IdentifierReference(binding.create_read_reference(ctx)),
+                    specifier.exported,
+                    ImportOrExportKind::Value,
+                ));
+            }
+
+            let has_bindings = !import_specifiers.is_empty();
+            let import_declaration = ctx.ast.import_declaration(
+                SPAN,
+                has_bindings.then_some(import_specifiers),
+                source.unwrap(),
+                None,
+                with_clause,
+                ImportOrExportKind::Value,
+            );
+            program.body.push(Statement::ImportDeclaration(
+                ctx.ast.alloc(import_declaration),
+            ));
+            if has_bindings {
+                let export_declaration = ctx.ast.export_named_declaration(
+                    SPAN,
+                    None,
+                    export_specifiers,
+                    None,
+                    ImportOrExportKind::Value,
+                    None::<oxc::allocator::Box<'a, oxc::ast::ast::WithClause<'a>>>,
+                );
+                program.body.push(Statement::ExportNamedDeclaration(
+                    ctx.ast.alloc(export_declaration),
+                ));
+            }
+            self.made_replacements = true;
+        }
+    }
+}
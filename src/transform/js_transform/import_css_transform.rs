@@ -1,17 +1,21 @@
-use oxc::ast::ast::ImportDeclaration;
+use oxc::ast::ast::{ImportDeclaration, Program, Statement};
 use oxc::span::SPAN;
 use oxc_traverse::{ReusableTraverseCtx, Traverse, TraverseCtx};
 
+use super::lit_compat::{LIT_SPECIFIER, LitCompat, is_lit_source};
+
 // ...existing code...
 
 pub struct ImportCssTransformer {
     css_imported: bool,
+    lit_compat: LitCompat,
 }
 
 impl ImportCssTransformer {
-    pub fn new() -> Self {
+    pub fn new(lit_compat: LitCompat) -> Self {
         Self {
             css_imported: false,
+            lit_compat,
         }
     }
 
@@ -33,12 +37,12 @@ impl<'a> Traverse<'a, ()> for ImportCssTransformer {
     ) {
         let value = node.source.value.as_str();
 
-        // Check if the import source ends with "lit.all.mjs"
-        if !value.ends_with("lit.all.mjs") {
+        // Check if the import source is lit ("lit.all.mjs" or the bare specifier)
+        if !is_lit_source(value) {
             return;
         }
         // Check if "css" is already in the specifiers or has been added in this file
-        let css_already_imported = node.specifiers.as_ref().map_or(false, |specs| {
+        let css_already_imported = node.specifiers.as_ref().is_some_and(|specs| {
             specs.iter().any(|spec| {
                 if let oxc::ast::ast::ImportDeclarationSpecifier::ImportSpecifier(specific) = spec {
                     specific.imported.name() == "css"
@@ -55,6 +59,11 @@ impl<'a> Traverse<'a, ()> for ImportCssTransformer {
             return;
         }
 
+        // With lit 2 the import is added as `import { css } from "lit"` on exit
+        if self.lit_compat == LitCompat::Lit2 {
+            return;
+        }
+
         // Add "css" to the specifiers
         let css_export_name =
             oxc::ast::ast::ModuleExportName::IdentifierName(ctx.ast.identifier_name(SPAN, "css"));
@@ -72,4 +81,37 @@ impl<'a> Traverse<'a, ()> for ImportCssTransformer {
             ));
         self.css_imported = true;
     }
+
+    fn exit_program(&mut self, program: &mut Program<'a>, ctx: &mut TraverseCtx<'a, ()>) {
+        if self.lit_compat != LitCompat::Lit2 || self.css_imported {
+            return;
+        }
+
+        let css_export_name =
+            oxc::ast::ast::ModuleExportName::IdentifierName(ctx.ast.identifier_name(SPAN, "css"));
+        let css_binding_ident = ctx.ast.binding_identifier(SPAN, "css");
+        let import_specifier = ctx.ast.import_specifier(
+            SPAN,
+            css_export_name,
+            css_binding_ident,
+            oxc::ast::ast::ImportOrExportKind::Value,
+        );
+        let mut specifiers = ctx.ast.vec_with_capacity(1);
+        specifiers.push(oxc::ast::ast::ImportDeclarationSpecifier::ImportSpecifier(
+            ctx.ast.alloc(import_specifier),
+        ));
+        let import_declaration = ctx.ast.import_declaration(
+            SPAN,
+            Some(specifiers),
+            ctx.ast.string_literal(SPAN, LIT_SPECIFIER, None),
+            None,
+            None::<oxc::allocator::Box<'_, oxc::ast::ast::WithClause<'_>>>,
+            oxc::ast::ast::ImportOrExportKind::Value,
+        );
+        program.body.insert(
+            0,
+            Statement::ImportDeclaration(ctx.ast.alloc(import_declaration)),
+        );
+        self.css_imported = true;
+    }
 }
@@ -0,0 +1,89 @@
+use oxc::ast::ast::{ImportDeclarationSpecifier, Program, Statement};
+use serde::Deserialize;
+
+/// Which lit version the generated code targets.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum LitCompat {
+    /// Add generated imports to the vendored `lit.all.mjs` import
+    #[default]
+    #[serde(rename = "3")]
+    Lit3,
+    /// Import generated helpers from the bare `lit` specifier, for consumers
+    /// pinned to lit 2
+    #[serde(rename = "2")]
+    Lit2,
+}
+
+/// Bare specifier generated imports come from in lit 2 mode.
+pub const LIT_SPECIFIER: &str = "lit";
+
+/// Exports available in lit 2 that generated code may import.
+const LIT2_GENERATED_EXPORTS: &[&str] = &["css"];
+
+/// Exports of the vendored lit bundle that don't exist in lit 2.
+const LIT3_ONLY_EXPORTS: &[&str] = &["mathml", "unsafeMathML"];
+
+/// Check if an import source refers to lit.
+pub fn is_lit_source(source: &str) -> bool {
+    source == LIT_SPECIFIER || source.ends_with("lit.all.mjs")
+}
+
+/// Get the names imported from lit by top-level import declarations, as
+/// (source, imported name) pairs.
+pub fn lit_imports(program: &Program<'_>) -> Vec<(String, String)> {
+    let mut imports = Vec::new();
+    for stmt in &program.body {
+        let Statement::ImportDeclaration(decl) = stmt else {
+            continue;
+        };
+        if !is_lit_source(decl.source.value.as_str()) {
+            continue;
+        }
+        for spec in decl.specifiers.iter().flatten() {
+            if let ImportDeclarationSpecifier::ImportSpecifier(spec) = spec {
+                imports.push((
+                    decl.source.value.to_string(),
+                    spec.imported.name().to_string(),
+                ));
+            }
+        }
+    }
+    imports
+}
+
+/// Find imports of lit-3-only exports in upstream code.
+pub fn lit3_only_imports(upstream_imports: &[(String, String)]) -> Vec<String> {
+    let mut names: Vec<String> = upstream_imports
+        .iter()
+        .filter(|(_, name)| LIT3_ONLY_EXPORTS.contains(&name.as_str()))
+        .map(|(_, name)| name.clone())
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Check that the lit imports added by the transformers (names not imported in
+/// `upstream_imports`, which may since have had their source rewritten) stay
+/// within the lit 2 surface.
+pub fn validate_lit2_imports(
+    program: &Program<'_>,
+    upstream_imports: &[(String, String)],
+) -> Result<(), String> {
+    let unsupported: Vec<String> = lit_imports(program)
+        .into_iter()
+        .filter(|(_, name)| !upstream_imports.iter().any(|(_, n)| n == name))
+        .filter(|(source, name)| {
+            source != LIT_SPECIFIER || !LIT2_GENERATED_EXPORTS.contains(&name.as_str())
+        })
+        .map(|(source, name)| format!("{} from {:?}", name, source))
+        .collect();
+    if unsupported.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Generated code imports outside the lit 2 surface: {}",
+            unsupported.join(", ")
+        ))
+    }
+}
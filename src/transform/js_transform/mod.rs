@@ -1,9 +1,21 @@
 mod css_inline_transform;
+mod fetched_stylesheet;
 mod icon_template_import;
+mod import_attributes;
 mod import_css_transform;
+mod lit_compat;
+mod module_url_transform;
+mod privileged_imports;
 mod url_transform;
 
 pub(crate) use css_inline_transform::CssInlineTransformer;
+pub(crate) use fetched_stylesheet::FetchedStylesheetTransformer;
 pub(crate) use icon_template_import::IconTemplateImportTransformer;
+pub(crate) use import_attributes::ImportAttributesTransformer;
 pub(crate) use import_css_transform::ImportCssTransformer;
+pub use lit_compat::LitCompat;
+pub(crate) use lit_compat::{LIT_SPECIFIER, lit_imports, lit3_only_imports, validate_lit2_imports};
+pub(crate) use module_url_transform::ModuleUrlTransformer;
+pub(crate) use privileged_imports::PrivilegedImportTransformer;
+pub use privileged_imports::PrivilegedImports;
 pub(crate) use url_transform::UrlTransformer;
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+use oxc::ast::ast::{Argument, CallExpression, NewExpression};
+use oxc_traverse::{ReusableTraverseCtx, Traverse, TraverseCtx};
+
+use super::icon_template_import::create_url_expression;
+use crate::dependencies::js::{is_module_url, is_worker, stylesheet_fetch};
+
+/// Rewrites the first argument of `new URL("...", import.meta.url)` to the
+/// path of the file relative to the output module, and string script URLs of
+/// `new Worker("...")` and stylesheet URLs of `fetch("...")` to such a
+/// `new URL()`, since they would otherwise be relative to the document.
+pub struct ModuleUrlTransformer<'a> {
+    url_replacements: &'a HashMap<String, String>,
+    made_replacements: bool,
+}
+
+impl<'a> ModuleUrlTransformer<'a> {
+    pub fn new(url_replacements: &'a HashMap<String, String>) -> Self {
+        Self {
+            url_replacements,
+            made_replacements: false,
+        }
+    }
+
+    /// Rewrites module URLs and returns true if any of them changed.
+    pub fn build(
+        &mut self,
+        program: &mut oxc::ast::ast::Program<'a>,
+        ctx: &mut ReusableTraverseCtx<'a, ()>,
+    ) -> bool {
+        oxc_traverse::traverse_mut_with_ctx(self, program, ctx);
+        self.made_replacements
+    }
+}
+
+impl<'a> Traverse<'a, ()> for ModuleUrlTransformer<'a> {
+    fn enter_call_expression(
+        &mut self,
+        node: &mut CallExpression<'a>,
+        ctx: &mut TraverseCtx<'a, ()>,
+    ) {
+        let Some(replacement) = stylesheet_fetch(node)
+            .and_then(|literal| self.url_replacements.get(literal.value.as_str()))
+        else {
+            return;
+        };
+        node.arguments[0] = Argument::from(create_url_expression(replacement, ctx));
+        self.made_replacements = true;
+    }
+
+    fn enter_new_expression(
+        &mut self,
+        node: &mut NewExpression<'a>,
+        ctx: &mut TraverseCtx<'a, ()>,
+    ) {
+        if is_worker(node) {
+            if let Some(argument @ Argument::StringLiteral(_)) = node.arguments.first_mut()
+                && let Argument::StringLiteral(literal) = &*argument
+                && let Some(replacement) = self.url_replacements.get(literal.value.as_str())
+            {
+                *argument = Argument::from(create_url_expression(replacement, ctx));
+                self.made_replacements = true;
+            }
+            return;
+        }
+        if !is_module_url(node) {
+            return;
+        }
+        let Some(Argument::StringLiteral(literal)) = node.arguments.first_mut() else {
+            return;
+        };
+        match self.url_replacements.get(literal.value.as_str()) {
+            Some(replacement) if replacement != literal.value.as_str() => {
+                literal.value = ctx.ast.atom_from_strs_array([replacement.as_str()]);
+                // The raw text would take precedence over the new value in codegen
+                literal.raw = None;
+                self.made_replacements = true;
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+
+use oxc::ast::ast::{Argument, CallExpression, Expression, ObjectPropertyKind};
+use oxc::span::SPAN;
+use oxc_traverse::{ReusableTraverseCtx, Traverse, TraverseCtx};
+use serde::Deserialize;
+
+use crate::dependencies::js::{PrivilegedCall, privileged_call, privileged_specifiers};
+
+/// What happens to `ChromeUtils.importESModule` and
+/// `ChromeUtils.defineESModuleGetters` calls, which load privileged modules
+/// that don't run on the web.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PrivilegedImports {
+    /// Copy the modules and leave the calls untouched
+    #[default]
+    Keep,
+    /// Replace the calls with `await import()` of the copied modules
+    Import,
+    /// Like `Import`, but only for modules that have an alias, e.g. to a shim
+    /// module. The modules of other calls aren't copied.
+    Shim,
+}
+
+/// Replaces privileged module loads at the top level of a module with
+/// `await import()`: `ChromeUtils.importESModule("...")` becomes
+/// `await import("...")` and `ChromeUtils.defineESModuleGetters(lazy, { A:
+/// "..." })` becomes `Object.assign(lazy, { A: (await import("...")).A })`.
+///
+/// Calls inside functions, calls with a module that has no replacement and
+/// all calls in `PrivilegedImports::Keep` mode are left untouched, see
+/// `unhandled`.
+pub struct PrivilegedImportTransformer<'a> {
+    import_replacements: &'a HashMap<String, String>,
+    mode: PrivilegedImports,
+    /// Whether the program is a module, which top-level `await` needs
+    module: bool,
+    made_replacements: bool,
+    unhandled: Vec<String>,
+}
+
+impl<'a> PrivilegedImportTransformer<'a> {
+    pub fn new(import_replacements: &'a HashMap<String, String>, mode: PrivilegedImports) -> Self {
+        Self {
+            import_replacements,
+            mode,
+            module: false,
+            made_replacements: false,
+            unhandled: Vec::new(),
+        }
+    }
+
+    /// Rewrites privileged calls and returns true if any of them changed.
+    pub fn build(
+        &mut self,
+        program: &mut oxc::ast::ast::Program<'a>,
+        ctx: &mut ReusableTraverseCtx<'a, ()>,
+    ) -> bool {
+        self.module = program.source_type.is_module();
+        oxc_traverse::traverse_mut_with_ctx(self, program, ctx);
+        self.made_replacements
+    }
+
+    /// Specifiers of the privileged calls that were left untouched
+    pub fn unhandled(&self) -> &[String] {
+        &self.unhandled
+    }
+
+    /// `(await import("<path>"))`
+    fn await_import(&self, path: &str, ctx: &mut TraverseCtx<'a, ()>) -> Expression<'a> {
+        let path = ctx.ast.atom_from_strs_array([path]);
+        let source =
+            Expression::StringLiteral(ctx.ast.alloc(ctx.ast.string_literal(SPAN, path, None)));
+        let import = ctx.ast.expression_import(SPAN, source, None, None);
+        let awaited = ctx.ast.expression_await(SPAN, import);
+        ctx.ast.expression_parenthesized(SPAN, awaited)
+    }
+}
+
+/// Whether the getters of a `defineESModuleGetters` call, which are named
+/// after the export they load, have names known without running the code
+fn has_static_getter_names(call: &CallExpression) -> bool {
+    let Some(Argument::ObjectExpression(object)) = call.arguments.get(1) else {
+        return true;
+    };
+    object.properties.iter().all(|property| match property {
+        ObjectPropertyKind::ObjectProperty(property) => {
+            !matches!(property.value, Expression::StringLiteral(_))
+                || property.key.static_name().is_some()
+        }
+        ObjectPropertyKind::SpreadProperty(_) => true,
+    })
+}
+
+impl<'a> Traverse<'a, ()> for PrivilegedImportTransformer<'a> {
+    fn enter_expression(&mut self, expr: &mut Expression<'a>, ctx: &mut TraverseCtx<'a, ()>) {
+        let Expression::CallExpression(call) = expr else {
+            return;
+        };
+        let Some(kind) = privileged_call(call) else {
+            return;
+        };
+        let specifiers: Vec<String> = privileged_specifiers(call)
+            .iter()
+            .map(|literal| literal.value.to_string())
+            .collect();
+        let replacements: Option<Vec<String>> = specifiers
+            .iter()
+            .map(|specifier| self.import_replacements.get(specifier).cloned())
+            .collect();
+        let top_level =
+            self.module && ctx.current_hoist_scope_id() == ctx.scoping().root_scope_id();
+        let Some(replacements) = replacements.filter(|_| {
+            self.mode != PrivilegedImports::Keep
+                && top_level
+                && !specifiers.is_empty()
+                && has_static_getter_names(call)
+        }) else {
+            self.unhandled.extend(specifiers);
+            return;
+        };
+
+        match kind {
+            PrivilegedCall::ImportESModule => {
+                *expr = self.await_import(&replacements[0], ctx);
+            }
+            PrivilegedCall::DefineESModuleGetters => {
+                let Some(Argument::ObjectExpression(object)) = call.arguments.get_mut(1) else {
+                    return;
+                };
+                let mut replacements = replacements.iter();
+                for property in object.properties.iter_mut() {
+                    let ObjectPropertyKind::ObjectProperty(property) = property else {
+                        continue;
+                    };
+                    if !matches!(property.value, Expression::StringLiteral(_)) {
+                        continue;
+                    }
+                    let (Some(path), Some(name)) =
+                        (replacements.next(), property.key.static_name())
+                    else {
+                        break;
+                    };
+                    let name = ctx.ast.atom_from_strs_array([name.as_ref()]);
+                    let module = self.await_import(path, ctx);
+                    let export = ctx.ast.static_member_expression(
+                        SPAN,
+                        module,
+                        ctx.ast.identifier_name(SPAN, name),
+                        false,
+                    );
+                    property.value = Expression::StaticMemberExpression(ctx.ast.alloc(export));
+                }
+                let object_ident = ctx.ast.identifier_reference(SPAN, "Object");
+                let assign = ctx.ast.static_member_expression(
+                    SPAN,
+                    Expression::Identifier(ctx.ast.alloc(object_ident)),
+                    ctx.ast.identifier_name(SPAN, "assign"),
+                    false,
+                );
+                call.callee = Expression::StaticMemberExpression(ctx.ast.alloc(assign));
+            }
+        }
+        self.made_replacements = true;
+    }
+}
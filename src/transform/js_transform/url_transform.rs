@@ -1,23 +1,39 @@
 use std::collections::HashMap;
 
-use oxc::ast::ast::ImportDeclaration;
+use oxc::ast::ast::{
+    ExportAllDeclaration, ExportNamedDeclaration, Expression, ImportDeclaration, ImportExpression,
+};
 use oxc_traverse::{ReusableTraverseCtx, Traverse, TraverseCtx};
 
+use crate::utils::external::ExternalSpecifiers;
+
 pub struct UrlTransformer<'a> {
     url_replacements: &'a HashMap<String, String>,
+    external: &'a ExternalSpecifiers,
+    made_replacements: bool,
 }
 
 impl<'a> UrlTransformer<'a> {
-    pub fn new(url_replacements: &'a HashMap<String, String>) -> Self {
-        Self { url_replacements }
+    pub fn new(
+        url_replacements: &'a HashMap<String, String>,
+        external: &'a ExternalSpecifiers,
+    ) -> Self {
+        Self {
+            url_replacements,
+            external,
+            made_replacements: false,
+        }
     }
 
+    /// Rewrites the sources of import declarations, re-exports and `import()`
+    /// with a string literal, and returns true if any source actually changed.
     pub fn build(
         &mut self,
         program: &mut oxc::ast::ast::Program<'a>,
         ctx: &mut ReusableTraverseCtx<'a, ()>,
-    ) {
+    ) -> bool {
         oxc_traverse::traverse_mut_with_ctx(self, program, ctx);
+        self.made_replacements
     }
 }
 
@@ -28,17 +44,69 @@ impl<'a> Traverse<'a, ()> for UrlTransformer<'a> {
         ctx: &mut TraverseCtx<'a, ()>,
     ) {
         // replace node.source with the transformed URL
-        let value = node.source.value.as_str();
+        if let Some(replacement) = self.replacement_for(node.source.value.as_str()) {
+            node.source.value = ctx.ast.atom_from_strs_array([replacement]);
+            self.made_replacements = true;
+        }
+    }
+
+    fn enter_export_named_declaration(
+        &mut self,
+        node: &mut ExportNamedDeclaration<'a>,
+        ctx: &mut TraverseCtx<'a, ()>,
+    ) {
+        let Some(source) = &mut node.source else {
+            return;
+        };
+        if let Some(replacement) = self.replacement_for(source.value.as_str()) {
+            source.value = ctx.ast.atom_from_strs_array([replacement]);
+            self.made_replacements = true;
+        }
+    }
+
+    fn enter_export_all_declaration(
+        &mut self,
+        node: &mut ExportAllDeclaration<'a>,
+        ctx: &mut TraverseCtx<'a, ()>,
+    ) {
+        if let Some(replacement) = self.replacement_for(node.source.value.as_str()) {
+            node.source.value = ctx.ast.atom_from_strs_array([replacement]);
+            self.made_replacements = true;
+        }
+    }
 
-        // ignore if value == "lit.all.mjs"
-        if value == "lit.all.mjs" {
+    fn enter_import_expression(
+        &mut self,
+        node: &mut ImportExpression<'a>,
+        ctx: &mut TraverseCtx<'a, ()>,
+    ) {
+        let Expression::StringLiteral(source) = &mut node.source else {
             return;
+        };
+        if let Some(replacement) = self.replacement_for(source.value.as_str()) {
+            source.value = ctx.ast.atom_from_strs_array([replacement]);
+            // The raw text would take precedence over the new value in codegen
+            source.raw = None;
+            self.made_replacements = true;
+        }
+    }
+}
+
+impl<'a> UrlTransformer<'a> {
+    /// The new source of an import, if it changes
+    fn replacement_for(&self, value: &str) -> Option<&'a str> {
+        // external specifiers are left as they are
+        if self.external.is_external(value) {
+            return None;
         }
 
-        if let Some(replacement) = self.url_replacements.get(value) {
-            node.source.value = ctx.ast.atom_from_strs_array([replacement.as_str()]);
-        } else {
-            panic!("URL replacement not found for: {}", value);
+        match self.url_replacements.get(value) {
+            Some(replacement) if replacement == value => None,
+            Some(replacement) => Some(replacement.as_str()),
+            None => {
+                log::debug!("No replacement for import {}, leaving it untouched", value);
+                None
+            }
         }
     }
 }
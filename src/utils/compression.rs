@@ -0,0 +1,271 @@
+use serde::Deserialize;
+use std::io::Write;
+use std::path::Path;
+
+use crate::report::{BudgetBasis, FileReport};
+use crate::{CancellationToken, Error, Result};
+
+/// Compression formats that can be emitted next to text output files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Compression {
+    /// Brotli, written as `<file>.br`
+    #[serde(rename = "br")]
+    Brotli,
+    /// Gzip, written as `<file>.gz`
+    #[serde(rename = "gz")]
+    Gzip,
+}
+
+impl Compression {
+    /// File extension appended to the compressed sibling.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::Brotli => "br",
+            Compression::Gzip => "gz",
+        }
+    }
+
+    /// Compress the given bytes.
+    pub fn compress(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Compression::Brotli => {
+                let mut output = Vec::new();
+                {
+                    let mut writer = brotli::CompressorWriter::new(&mut output, 4096, 11, 22);
+                    writer.write_all(data)?;
+                }
+                Ok(output)
+            }
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Settings for the compression phase after all files are written.
+#[derive(Debug, Clone, Default)]
+pub struct CompressionSettings {
+    /// Formats to write as sibling files
+    pub precompress: Vec<Compression>,
+    /// Minimum size in bytes for a file to get compressed siblings
+    pub threshold: u64,
+    /// Basis used for budget checks, compressed sizes are computed for it even
+    /// when no siblings are written
+    pub budget_basis: BudgetBasis,
+    /// Stops compression between files with `Error::Cancelled`
+    pub cancellation: CancellationToken,
+}
+
+/// Check if a dist path is a text output that benefits from compression.
+pub fn is_text_output(dist_path: &Path) -> bool {
+    matches!(
+        dist_path.extension().and_then(|e| e.to_str()),
+        Some("js" | "mjs" | "css" | "json")
+    )
+}
+
+/// Compute compressed sizes for the written text files and write compressed
+/// siblings for files above the threshold. Must run once the written content
+/// is final. Files are processed in parallel.
+pub fn compress_outputs(
+    output_dir: &Path,
+    files: &mut [FileReport],
+    settings: &CompressionSettings,
+) -> Result<()> {
+    let mut formats = settings.precompress.clone();
+    match settings.budget_basis {
+        BudgetBasis::Gzip if !formats.contains(&Compression::Gzip) => {
+            formats.push(Compression::Gzip)
+        }
+        BudgetBasis::Brotli if !formats.contains(&Compression::Brotli) => {
+            formats.push(Compression::Brotli)
+        }
+        _ => {}
+    }
+    if formats.is_empty() {
+        return Ok(());
+    }
+
+    let mut candidates: Vec<&mut FileReport> = files
+        .iter_mut()
+        .filter(|f| is_text_output(&f.dist))
+        .collect();
+    if candidates.is_empty() {
+        return Ok(());
+    }
+
+    let threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let chunk_size = candidates.len().div_ceil(threads);
+
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = candidates
+            .chunks_mut(chunk_size)
+            .map(|chunk| {
+                let formats = &formats;
+                scope.spawn(move || -> Result<()> {
+                    for file in chunk.iter_mut() {
+                        settings.cancellation.check()?;
+                        compress_file(output_dir, file, formats, settings).map_err(|e| {
+                            Error::Custom(format!("Failed to compress output files: {e}"))
+                        })?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle
+                .join()
+                .map_err(|_| Error::Custom("Compression thread panicked".to_string()))??;
+        }
+        Ok(())
+    })
+}
+
+fn compress_file(
+    output_dir: &Path,
+    file: &mut FileReport,
+    formats: &[Compression],
+    settings: &CompressionSettings,
+) -> std::io::Result<()> {
+    let output_path = output_dir.join(&file.dist);
+    let content = std::fs::read(&output_path)?;
+
+    for format in formats {
+        let compressed = format.compress(&content)?;
+        let size = compressed.len() as u64;
+        match format {
+            Compression::Gzip => file.sizes.gzip = Some(size),
+            Compression::Brotli => file.sizes.brotli = Some(size),
+        }
+
+        if settings.precompress.contains(format) && content.len() as u64 >= settings.threshold {
+            let mut sibling = output_path.clone().into_os_string();
+            sibling.push(".");
+            sibling.push(format.extension());
+            std::fs::write(sibling, compressed)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::{BuildReport, FileOutcome};
+    use std::io::Read;
+    use std::path::PathBuf;
+
+    fn write_report(dir: &Path, files: &[(&str, &str)]) -> BuildReport {
+        let mut report = BuildReport::new();
+        for (dist, content) in files {
+            let path = dir.join(dist);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, content).unwrap();
+            report.record_file(
+                PathBuf::from(dist),
+                PathBuf::from(dist),
+                FileOutcome::Transformed,
+                content.len() as u64,
+            );
+        }
+        report
+    }
+
+    #[test]
+    fn test_precompress_writes_siblings_that_decompress() {
+        let dir = tempfile::tempdir().unwrap();
+        let css = ".a { color: red; }\n".repeat(100);
+        let mut report = write_report(
+            dir.path(),
+            &[("styles/a.css", &css), ("assets/icon.svg", "<svg/>")],
+        );
+        let settings = CompressionSettings {
+            precompress: vec![Compression::Brotli, Compression::Gzip],
+            threshold: 0,
+            budget_basis: BudgetBasis::Raw,
+            ..Default::default()
+        };
+        compress_outputs(dir.path(), &mut report.files, &settings).unwrap();
+
+        let mut gz = Vec::new();
+        flate2::read::GzDecoder::new(
+            std::fs::File::open(dir.path().join("styles/a.css.gz")).unwrap(),
+        )
+        .read_to_end(&mut gz)
+        .unwrap();
+        assert_eq!(gz, css.as_bytes());
+
+        let mut br = Vec::new();
+        brotli::Decompressor::new(
+            std::fs::File::open(dir.path().join("styles/a.css.br")).unwrap(),
+            4096,
+        )
+        .read_to_end(&mut br)
+        .unwrap();
+        assert_eq!(br, css.as_bytes());
+
+        // Binary outputs are left alone
+        assert!(!dir.path().join("assets/icon.svg.gz").exists());
+        assert!(report.files[0].sizes.gzip.is_some());
+        assert!(report.files[1].sizes.gzip.is_none());
+    }
+
+    #[test]
+    fn test_precompress_respects_threshold() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut report = write_report(dir.path(), &[("styles/small.css", ".a{}")]);
+        let settings = CompressionSettings {
+            precompress: vec![Compression::Gzip],
+            threshold: 1024,
+            budget_basis: BudgetBasis::Raw,
+            ..Default::default()
+        };
+        compress_outputs(dir.path(), &mut report.files, &settings).unwrap();
+        assert!(!dir.path().join("styles/small.css.gz").exists());
+        assert!(report.files[0].sizes.gzip.is_some());
+    }
+
+    #[test]
+    fn test_cancelled_compression_stops() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut report = write_report(dir.path(), &[("styles/a.css", ".a{}")]);
+        let settings = CompressionSettings {
+            precompress: vec![Compression::Gzip],
+            ..Default::default()
+        };
+        settings.cancellation.cancel();
+        let result = compress_outputs(dir.path(), &mut report.files, &settings);
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert!(!dir.path().join("styles/a.css.gz").exists());
+    }
+
+    #[test]
+    fn test_budget_uses_configured_basis() {
+        let dir = tempfile::tempdir().unwrap();
+        let js = "export const value = 'repeated';\n".repeat(200);
+        let mut report = write_report(dir.path(), &[("dependencies/a.mjs", &js)]);
+        let settings = CompressionSettings {
+            precompress: vec![],
+            threshold: 0,
+            budget_basis: BudgetBasis::Brotli,
+            ..Default::default()
+        };
+        compress_outputs(dir.path(), &mut report.files, &settings).unwrap();
+        assert!(!dir.path().join("dependencies/a.mjs.br").exists());
+
+        let raw = report.total_size(BudgetBasis::Raw);
+        let brotli = report.total_size(BudgetBasis::Brotli);
+        assert!(brotli < raw);
+        let budget = (brotli + raw) / 2;
+        assert!(report.check_budget(budget, BudgetBasis::Raw).is_err());
+        assert!(report.check_budget(budget, BudgetBasis::Brotli).is_ok());
+    }
+}
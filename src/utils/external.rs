@@ -0,0 +1,67 @@
+use glob::Pattern;
+
+use crate::transform::js_transform::LIT_SPECIFIER;
+
+/// Specifiers that are always external: the vendored lit bundle imported by
+/// its bare file name, and the bare lit specifier generated in lit 2 mode.
+const BUILTIN_EXTERNALS: &[&str] = &["lit.all.mjs", LIT_SPECIFIER];
+
+/// Import specifiers that mozcomp leaves untouched: they are not resolved, get
+/// no graph node, and are written to the output as is.
+#[derive(Debug, Clone)]
+pub struct ExternalSpecifiers {
+    patterns: Vec<Pattern>,
+}
+
+impl ExternalSpecifiers {
+    /// Create a matcher for the built-in externals and the given glob
+    /// patterns, e.g. `chrome://global/content/fluent/*`.
+    pub fn new(patterns: &[String]) -> Result<Self, glob::PatternError> {
+        let patterns = BUILTIN_EXTERNALS
+            .iter()
+            .copied()
+            .chain(patterns.iter().map(String::as_str))
+            .map(Pattern::new)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { patterns })
+    }
+
+    /// Also treat these exact specifiers as external, e.g. imports that
+    /// couldn't be resolved and are left untouched.
+    pub fn with_specifiers(mut self, specifiers: &[String]) -> Self {
+        self.patterns.extend(
+            specifiers
+                .iter()
+                .filter_map(|specifier| Pattern::new(&Pattern::escape(specifier.trim())).ok()),
+        );
+        self
+    }
+
+    /// Check if a specifier is external.
+    pub fn is_external(&self, specifier: &str) -> bool {
+        let specifier = specifier.trim();
+        self.patterns.iter().any(|p| p.matches(specifier))
+    }
+}
+
+impl Default for ExternalSpecifiers {
+    fn default() -> Self {
+        Self::new(&[]).expect("built-in external patterns are valid")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_external() {
+        let external =
+            ExternalSpecifiers::new(&["chrome://global/content/fluent/*".to_string()]).unwrap();
+        assert!(external.is_external("lit.all.mjs"));
+        assert!(external.is_external("lit"));
+        assert!(external.is_external("chrome://global/content/fluent/shim.mjs"));
+        assert!(!external.is_external("chrome://global/content/vendor/lit.all.mjs"));
+        assert!(!external.is_external("./lit.all.mjs"));
+    }
+}
@@ -24,10 +24,20 @@ use std::path::{Path, PathBuf};
 /// * `path` - The path to make relative to the current working directory.
 pub fn make_relative_to_cwd(path: &PathBuf) -> PathBuf {
     let cwd = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
-    let relative_path = pathdiff::diff_paths(&path, &cwd).unwrap_or(path.clone());
+    let relative_path = pathdiff::diff_paths(path, &cwd).unwrap_or(path.clone());
     normalize_path(&relative_path)
 }
 
+/// Converts a path to a string with forward slashes, for the places a path
+/// becomes an import specifier, a glob pattern or a URL. Windows accepts
+/// either separator, so this is lossless there.
+///
+/// # Arguments
+/// * `path` - The path to convert.
+pub fn to_slash(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
 /// Compute the relative path from one file to another.
 /// Both paths should be the dist paths (where files will be located).
 ///
@@ -43,7 +53,7 @@ pub fn compute_relative_path(from_path: &Path, to_path: &Path) -> String {
 
     match pathdiff::diff_paths(to_path, from_dir) {
         Some(relative_path) => {
-            let rel_str = relative_path.to_string_lossy().replace('\\', "/");
+            let rel_str = to_slash(&relative_path);
             if !rel_str.starts_with('.') {
                 // If the path does not start with '.' or '/', it's a same-folder or subfolder import
                 format!("./{}", rel_str)
@@ -53,7 +63,7 @@ pub fn compute_relative_path(from_path: &Path, to_path: &Path) -> String {
         }
         None => {
             // Fallback: use absolute path if relative path computation fails
-            to_path.to_string_lossy().replace('\\', "/")
+            to_slash(to_path)
         }
     }
 }
@@ -129,6 +139,24 @@ pub fn normalize_path(path: &Path) -> PathBuf {
     stack.iter().map(|c| c.as_os_str()).collect()
 }
 
+/// Computes a hash of the given content, used to detect identical files and
+/// in renamed output file names. The hash is FNV-1a, so it stays the same
+/// across runs and Rust releases.
+///
+/// # Arguments
+/// * `content` - The bytes to hash.
+///
+/// # Returns
+/// A 64-bit hash of the content.
+pub fn content_hash(content: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    content.iter().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+    })
+}
+
 /// Removes the given directory and all its contents, if it exists.
 ///
 /// # Arguments
@@ -155,7 +183,7 @@ mod tests {
         let cwd = std::env::current_dir().unwrap();
         let rel = make_relative_to_cwd(&cwd);
         // Should be "." or empty, depending on normalization
-        assert!(rel == PathBuf::from(".") || rel == PathBuf::from(""));
+        assert!(rel == Path::new(".") || rel == Path::new(""));
     }
 
     #[test]
@@ -202,4 +230,34 @@ mod tests {
         let norm = normalize_path(p);
         assert_eq!(norm, PathBuf::from("../../../../../test2"));
     }
+
+    #[test]
+    fn test_to_slash() {
+        assert_eq!(to_slash(Path::new("a/b/c.mjs")), "a/b/c.mjs");
+        assert_eq!(to_slash(Path::new(r"a\b/c.mjs")), "a/b/c.mjs");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_compute_relative_path_windows_separators() {
+        let from = Path::new(r"dist\components\moz-a\moz-a.mjs");
+        let to = Path::new("dist/dependencies/lit.all.mjs");
+        let rel = compute_relative_path(from, to);
+        assert_eq!(rel, "../../dependencies/lit.all.mjs");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_normalize_path_windows_separators() {
+        let norm = normalize_path(Path::new(r"foo\.\bar/..\baz"));
+        assert_eq!(norm, PathBuf::from(r"foo\baz"));
+        assert_eq!(norm, PathBuf::from("foo/baz"));
+    }
+
+    #[test]
+    fn test_content_hash_is_stable() {
+        assert_eq!(content_hash(b""), 0xcbf29ce484222325);
+        assert_eq!(content_hash(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(content_hash(b"foobar"), 0x85944171f73967e8);
+    }
 }
@@ -0,0 +1,157 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use super::jar_resolver::{IfdefUnknown, ParsedJar};
+
+/// Name of the cache file for parsed jar.mn files inside the cache directory.
+const JAR_CACHE_FILE: &str = "jar-mappings.json";
+
+/// A file that was read to parse a jar.mn file, as it was when it was read.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedInput {
+    path: PathBuf,
+    modified: SystemTime,
+    len: u64,
+}
+
+impl CachedInput {
+    fn new(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(Self {
+            path: path.to_path_buf(),
+            modified: metadata.modified().ok()?,
+            len: metadata.len(),
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedJar {
+    /// The jar.mn file and all files it includes
+    inputs: Vec<CachedInput>,
+    parsed: ParsedJar,
+}
+
+/// Parsed jar.mn files of earlier runs.
+///
+/// The cache is keyed on everything that changes the result of parsing apart
+/// from the files themselves: the Firefox root, the current directory that
+/// source paths are relative to, and the preprocessor conditions. A cache
+/// with a different key is discarded as a whole.
+pub struct JarCache {
+    file: PathBuf,
+    key: serde_json::Value,
+    jars: HashMap<String, CachedJar>,
+}
+
+impl JarCache {
+    /// Load the cache from `cache_dir`, or start an empty one if there is
+    /// none for the same key.
+    pub fn load(
+        cache_dir: &Path,
+        firefox_dir: &Path,
+        ifdef_config: &HashMap<String, bool>,
+        ifdef_unknown: IfdefUnknown,
+    ) -> Self {
+        let root = firefox_dir
+            .canonicalize()
+            .unwrap_or_else(|_| firefox_dir.to_path_buf());
+        let key = serde_json::json!({
+            "root": root,
+            "cwd": std::env::current_dir().ok(),
+            "ifdef_config": ifdef_config.iter().collect::<BTreeMap<_, _>>(),
+            "ifdef_unknown": format!("{:?}", ifdef_unknown),
+        });
+        let file = cache_dir.join(JAR_CACHE_FILE);
+        let jars = fs::read_to_string(&file)
+            .ok()
+            .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok())
+            .filter(|cache| cache.get("key") == Some(&key))
+            .and_then(|mut cache| serde_json::from_value(cache["jars"].take()).ok())
+            .unwrap_or_default();
+        Self { file, key, jars }
+    }
+
+    /// The cached result of a jar.mn file, if none of the files it was parsed
+    /// from changed since and its wildcards match the same files.
+    pub fn get(&self, jar_path: &str) -> Option<&ParsedJar> {
+        let cached = self.jars.get(jar_path)?;
+        (cached
+            .inputs
+            .iter()
+            .all(|input| CachedInput::new(&input.path).as_ref() == Some(input))
+            && cached.parsed.wildcards_unchanged())
+        .then_some(&cached.parsed)
+    }
+
+    /// Cache the result of a jar.mn file parsed from `inputs`. Nothing is
+    /// cached if the modification time of an input can't be read.
+    pub fn insert(&mut self, jar_path: &str, inputs: &[PathBuf], parsed: ParsedJar) {
+        let Some(inputs) = inputs
+            .iter()
+            .map(|path| CachedInput::new(path))
+            .collect::<Option<Vec<_>>>()
+        else {
+            return;
+        };
+        self.jars
+            .insert(jar_path.to_string(), CachedJar { inputs, parsed });
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        fs::create_dir_all(self.file.parent().unwrap())?;
+        let cache = serde_json::json!({ "key": self.key, "jars": self.jars });
+        fs::write(&self.file, cache.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_is_invalidated_by_changed_inputs_and_key() {
+        let root = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let jar = root.path().join("toolkit/jar.mn");
+        let include = root.path().join("toolkit/shared.inc.mn");
+        fs::create_dir_all(jar.parent().unwrap()).unwrap();
+        fs::write(&jar, "#include shared.inc.mn\n").unwrap();
+        fs::write(&include, "").unwrap();
+        let ifdef_config = HashMap::from([("NIGHTLY_BUILD".to_string(), true)]);
+
+        let mut cache = JarCache::load(
+            cache_dir.path(),
+            root.path(),
+            &ifdef_config,
+            IfdefUnknown::False,
+        );
+        assert!(cache.get("toolkit/jar.mn").is_none());
+        cache.insert(
+            "toolkit/jar.mn",
+            &[jar.clone(), include.clone()],
+            ParsedJar::default(),
+        );
+        cache.save().unwrap();
+
+        let load = |ifdef_config: &HashMap<String, bool>| {
+            JarCache::load(
+                cache_dir.path(),
+                root.path(),
+                ifdef_config,
+                IfdefUnknown::False,
+            )
+        };
+        assert!(load(&ifdef_config).get("toolkit/jar.mn").is_some());
+        let nightly_off = HashMap::from([("NIGHTLY_BUILD".to_string(), false)]);
+        assert!(load(&nightly_off).get("toolkit/jar.mn").is_none());
+
+        // A change to an included file invalidates the jar.mn file
+        fs::write(&include, "  content/global/a.js (a.js)\n").unwrap();
+        assert!(load(&ifdef_config).get("toolkit/jar.mn").is_none());
+    }
+}
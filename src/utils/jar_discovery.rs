@@ -0,0 +1,211 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use glob::Pattern;
+
+/// Name of the cache file for discovered jar.mn files inside the cache directory.
+const DISCOVERY_CACHE_FILE: &str = "jar-discovery.json";
+
+/// Directories that never contain jar.mn files worth registering: VCS metadata
+/// and other hidden directories, installed node packages and build objdirs.
+fn is_ignored_dir(name: &str) -> bool {
+    name.starts_with('.') || name == "node_modules" || name.starts_with("obj-")
+}
+
+/// Settings for finding jar.mn files below the Firefox root.
+#[derive(Debug, Clone)]
+pub struct JarDiscovery {
+    /// How many directory levels below the Firefox root are searched
+    pub max_depth: usize,
+    /// Glob patterns, relative to the Firefox root, of jar.mn files or
+    /// directories to leave out
+    pub exclude: Vec<String>,
+    /// Directory to cache the discovered files in between runs
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl JarDiscovery {
+    /// Find all jar.mn files below `firefox_root`, as paths relative to it,
+    /// sorted. The result is read from the cache directory if it holds a
+    /// discovery for the same root and settings whose files all still exist.
+    pub fn discover(&self, firefox_root: &Path) -> Result<Vec<String>, crate::Error> {
+        let exclude = self
+            .exclude
+            .iter()
+            .map(|p| Pattern::new(p))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let cache_key = self.cache_key(firefox_root);
+        let cache_file = self
+            .cache_dir
+            .as_ref()
+            .map(|d| d.join(DISCOVERY_CACHE_FILE));
+        if let Some(cache_file) = &cache_file
+            && let Some(jar_paths) = read_cache(cache_file, &cache_key, firefox_root)
+        {
+            log::debug!("Using {} cached jar.mn files", jar_paths.len());
+            return Ok(jar_paths);
+        }
+
+        let mut jar_paths = Vec::new();
+        walk(
+            firefox_root,
+            Path::new(""),
+            0,
+            self.max_depth,
+            &exclude,
+            &mut jar_paths,
+        )?;
+        jar_paths.sort();
+
+        if let Some(cache_file) = &cache_file {
+            fs::create_dir_all(cache_file.parent().unwrap())?;
+            let cache = serde_json::json!({ "key": cache_key, "jar_paths": jar_paths });
+            fs::write(cache_file, cache.to_string())?;
+        }
+        Ok(jar_paths)
+    }
+
+    fn cache_key(&self, firefox_root: &Path) -> serde_json::Value {
+        let root = firefox_root
+            .canonicalize()
+            .unwrap_or_else(|_| firefox_root.to_path_buf());
+        serde_json::json!({
+            "root": root,
+            "max_depth": self.max_depth,
+            "exclude": self.exclude,
+        })
+    }
+}
+
+/// Read a cached discovery, if it was made with the same key and none of its
+/// files were removed since.
+fn read_cache(
+    cache_file: &Path,
+    cache_key: &serde_json::Value,
+    firefox_root: &Path,
+) -> Option<Vec<String>> {
+    let content = fs::read_to_string(cache_file).ok()?;
+    let cache: serde_json::Value = serde_json::from_str(&content).ok()?;
+    if cache.get("key")? != cache_key {
+        return None;
+    }
+    let jar_paths: Vec<String> = cache
+        .get("jar_paths")?
+        .as_array()?
+        .iter()
+        .map(|p| p.as_str().map(String::from))
+        .collect::<Option<_>>()?;
+    jar_paths
+        .iter()
+        .all(|p| firefox_root.join(p).is_file())
+        .then_some(jar_paths)
+}
+
+fn walk(
+    firefox_root: &Path,
+    relative_dir: &Path,
+    depth: usize,
+    max_depth: usize,
+    exclude: &[Pattern],
+    jar_paths: &mut Vec<String>,
+) -> std::io::Result<()> {
+    let mut entries: Vec<fs::DirEntry> = fs::read_dir(firefox_root.join(relative_dir))?
+        .filter_map(|entry| entry.ok())
+        .collect();
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let relative = relative_dir.join(&name);
+        let relative_str = super::file_utils::to_slash(&relative);
+        if exclude.iter().any(|p| p.matches(&relative_str)) {
+            continue;
+        }
+
+        // Symlinked directories are not followed, they could loop
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            if depth < max_depth && !is_ignored_dir(&name) {
+                walk(
+                    firefox_root,
+                    &relative,
+                    depth + 1,
+                    max_depth,
+                    exclude,
+                    jar_paths,
+                )?;
+            }
+        } else if name == "jar.mn" {
+            log::debug!("Discovered jar.mn file {}", relative_str);
+            jar_paths.push(relative_str);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_jar(root: &Path, dir: &str) {
+        let dir = root.join(dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("jar.mn"), "").unwrap();
+    }
+
+    #[test]
+    fn test_discover_respects_depth_ignores_and_excludes() {
+        let root = tempfile::tempdir().unwrap();
+        write_jar(root.path(), "toolkit");
+        write_jar(root.path(), "security/manager/pki/resources");
+        write_jar(root.path(), "a/b/c/d/e");
+        write_jar(root.path(), ".git/x");
+        write_jar(root.path(), "node_modules/pkg");
+        write_jar(root.path(), "obj-x86_64/dist");
+        write_jar(root.path(), "browser/slow");
+
+        let discovery = JarDiscovery {
+            max_depth: 4,
+            exclude: vec!["browser/slow".to_string()],
+            cache_dir: None,
+        };
+        assert_eq!(
+            discovery.discover(root.path()).unwrap(),
+            vec!["security/manager/pki/resources/jar.mn", "toolkit/jar.mn"]
+        );
+    }
+
+    #[test]
+    fn test_discover_uses_cache() {
+        let root = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        write_jar(root.path(), "toolkit");
+
+        let discovery = JarDiscovery {
+            max_depth: 4,
+            exclude: Vec::new(),
+            cache_dir: Some(cache.path().to_path_buf()),
+        };
+        assert_eq!(discovery.discover(root.path()).unwrap(), ["toolkit/jar.mn"]);
+
+        // New files are only picked up once the cache is invalidated
+        write_jar(root.path(), "browser");
+        assert_eq!(discovery.discover(root.path()).unwrap(), ["toolkit/jar.mn"]);
+
+        fs::remove_file(root.path().join("toolkit/jar.mn")).unwrap();
+        assert_eq!(discovery.discover(root.path()).unwrap(), ["browser/jar.mn"]);
+
+        let deeper = JarDiscovery {
+            max_depth: 5,
+            ..discovery
+        };
+        write_jar(root.path(), "toolkit");
+        assert_eq!(
+            deeper.discover(root.path()).unwrap(),
+            ["browser/jar.mn", "toolkit/jar.mn"]
+        );
+    }
+}
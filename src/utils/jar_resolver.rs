@@ -1,8 +1,14 @@
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::Instant;
 use thiserror::Error;
 
+use super::jar_cache::JarCache;
+use crate::report::DiscoveredJar;
+
 #[derive(Debug, Error)]
 pub enum JarResolverError {
     #[error("Invalid chrome URL: {0}")]
@@ -14,53 +20,188 @@ pub enum JarResolverError {
     #[error("Unknown ifdef condition: {0}")]
     UnknownIfdefCondition(String),
 
+    #[error("Invalid #if expression: {0}")]
+    InvalidExpression(String),
+
     #[error("Unmatched #endif directive")]
     UnmatchedEndif,
 
+    #[error("Unmatched {0} directive")]
+    UnmatchedElse(String),
+
     #[error("Include file not found: {0}")]
     IncludeFileNotFound(String),
 
+    #[error("#error directive: {0}")]
+    ErrorDirective(String),
+
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
 }
 
+/// A chrome package that was registered with different paths in different
+/// jar sections.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegistrationConflict {
+    /// Registration key, e.g. `content:global`
+    pub key: String,
+    /// Path of the registration seen first
+    pub first_path: String,
+    /// jar.mn file and section of the registration seen first
+    pub first_origin: String,
+    /// Path of the conflicting registration
+    pub second_path: String,
+    /// jar.mn file and section of the conflicting registration
+    pub second_origin: String,
+}
+
+/// A chrome or resource URL that was mapped to different source files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MappingConflict {
+    pub url: String,
+    /// Source file of the mapping seen first
+    pub first_path: PathBuf,
+    /// jar.mn or moz.build file of the mapping seen first
+    pub first_origin: String,
+    /// Source file of the mapping that replaced it
+    pub second_path: PathBuf,
+    /// jar.mn or moz.build file of the mapping that replaced it
+    pub second_origin: String,
+}
+
+/// How a preprocessor condition of a jar.mn file is evaluated if it is neither
+/// in the ifdef config nor defined or undefined in the file itself.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IfdefUnknown {
+    /// Fail parsing the file
+    Error,
+    /// Treat the condition as undefined
+    #[default]
+    False,
+    /// Treat the condition as defined
+    True,
+}
+
+/// Where a URL prefix registered in a chrome.manifest file points to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ManifestTarget {
+    /// A directory, relative to the current working directory
+    Directory(PathBuf),
+    /// Another chrome:// or resource:// URL prefix
+    Url(String),
+}
+
+/// The locale that `@AB_CD@` and `%` sources of jar.mn files stand for.
+const LOCALE: &str = "en-US";
+
+/// How many overrides and URL registrations are followed to resolve a URL
+/// before giving up on a loop.
+const MAX_REDIRECTS: usize = 16;
+
+#[derive(Default)]
 pub struct JarResolver {
     /// Maps chrome/resource URLs to their corresponding file system paths.
     mappings: HashMap<String, PathBuf>,
+    /// URL prefixes registered in chrome.manifest files, e.g.
+    /// `resource://gre/`, and what they point to
+    url_prefixes: Vec<(String, ManifestTarget)>,
+    /// `override` lines of chrome.manifest files, from the overridden URL to
+    /// the URL to use instead
+    overrides: HashMap<String, String>,
+    /// Reverse index of `mappings`: every URL that maps to a file, sorted
+    urls_by_path: HashMap<PathBuf, Vec<String>>,
+    /// The jar.mn or moz.build file every URL of `mappings` comes from
+    origins: HashMap<String, String>,
+    /// URLs mapped to different files, the later mapping wins
+    mapping_conflicts: Vec<MappingConflict>,
+    /// Source files of `*` entries, which go through the preprocessor
+    preprocessed: HashSet<PathBuf>,
+    /// Parse statistics of every discovered jar.mn file
+    discovered_jars: Vec<DiscoveredJar>,
+}
+
+/// The files a `JarResolver` parses and how, see `JarResolver::new`.
+#[derive(Debug, Clone, Default)]
+pub struct JarResolverOptions<'a> {
+    /// jar.mn file paths, relative to the Firefox directory
+    pub jar_paths: &'a [&'a str],
+    /// jar.mn file paths found by discovery. Mappings of `jar_paths` take
+    /// precedence over them, and the ones that fail to parse are skipped
+    /// without a warning.
+    pub discovered: &'a [String],
+    /// moz.build file paths, relative to the Firefox directory
+    pub mozbuild_paths: &'a [&'a str],
+    /// chrome.manifest file paths, relative to the Firefox directory
+    pub manifest_paths: &'a [String],
+    /// Preprocessor conditions, on top of the default ones
+    pub ifdef_config: Option<HashMap<String, bool>>,
+    /// How conditions missing from `ifdef_config` are evaluated
+    pub ifdef_unknown: IfdefUnknown,
+    /// Directory to cache the parsed jar.mn files in. Files are parsed again
+    /// when they or one of their includes changed.
+    pub cache_dir: Option<&'a Path>,
 }
 
 impl JarResolver {
-    /// Constructs a new `JarResolver` by parsing jar.mn and moz.build files.
-    ///
-    /// # Arguments
-    /// * `firefox_dir` - Path to the Firefox source directory.
-    /// * `jar_paths` - List of jar.mn file paths (relative to firefox_dir).
-    /// * `mozbuild_paths` - List of moz.build file paths (relative to firefox_dir).
-    /// * `ifdef_config` - Optional map of preprocessor conditions.
+    /// Constructs a new `JarResolver` by parsing the jar.mn, moz.build and
+    /// chrome.manifest files of `options`, relative to `firefox_dir`.
     ///
     /// # Returns
     /// Returns a `JarResolver` with mappings from chrome/resource URLs to file paths.
-    pub fn new(
-        firefox_dir: &Path,
-        jar_paths: &[&str],
-        mozbuild_paths: &[&str],
-        ifdef_config: Option<HashMap<String, bool>>,
-    ) -> Result<Self, JarResolverError> {
-        let mut mappings = HashMap::new();
-        let mut chrome_registrations = HashMap::new();
-
-        let mut default_ifdef_config = HashMap::new();
-        default_ifdef_config.insert("MOZILLA_OFFICIAL".to_string(), true);
-        default_ifdef_config.insert("ANDROID".to_string(), false);
-        default_ifdef_config.insert("MOZ_GLEAN_ANDROID".to_string(), false);
-        default_ifdef_config.insert("MOZ_FENNEC".to_string(), false);
-        default_ifdef_config.insert("XP_MACOSX".to_string(), false);
-        default_ifdef_config.insert("RELEASE_OR_BETA".to_string(), true);
-
+    pub fn new(firefox_dir: &Path, options: JarResolverOptions) -> Result<Self, JarResolverError> {
+        let JarResolverOptions {
+            jar_paths,
+            discovered,
+            mozbuild_paths,
+            manifest_paths,
+            ifdef_config,
+            ifdef_unknown,
+            cache_dir,
+        } = options;
+        let mut tables = MappingTables::default();
+
+        let mut default_ifdef_config = default_ifdef_config();
         if let Some(config) = ifdef_config {
             default_ifdef_config.extend(config);
         }
 
+        let mut cache = cache_dir
+            .map(|dir| JarCache::load(dir, firefox_dir, &default_ifdef_config, ifdef_unknown));
+
+        // Discovered jar.mn files go first, so explicit ones override them
+        let mut discovered_jars = Vec::new();
+        for jar_path in discovered
+            .iter()
+            .filter(|p| !jar_paths.contains(&p.as_str()))
+        {
+            let started = Instant::now();
+            let mappings_before = tables.mappings.len();
+            let error = match load_jar(
+                firefox_dir,
+                jar_path,
+                &default_ifdef_config,
+                ifdef_unknown,
+                cache.as_mut(),
+            ) {
+                Ok(parsed) => {
+                    let error = parsed.error.clone();
+                    tables.add_jar(parsed, jar_path);
+                    error
+                }
+                Err(e) => Some(e.to_string()),
+            };
+            if let Some(e) = &error {
+                log::debug!("Skipping discovered jar.mn file {}: {}", jar_path, e);
+            }
+            discovered_jars.push(DiscoveredJar {
+                path: PathBuf::from(jar_path),
+                mappings: tables.mappings.len() - mappings_before,
+                parse_time: started.elapsed(),
+                error,
+            });
+        }
+
         // Process jar.mn files
         for jar_path in jar_paths {
             let full_jar_path = firefox_dir.join(jar_path);
@@ -73,37 +214,38 @@ impl JarResolver {
                 continue;
             }
 
-            match fs::read_to_string(&full_jar_path) {
-                Ok(content) => {
-                    // Process includes first
-                    let processed_content =
-                        process_includes(&content, &full_jar_path, firefox_dir)?;
-
-                    if let Err(e) = parse_jar_file(
-                        &processed_content,
-                        jar_path,
-                        firefox_dir,
-                        &mut mappings,
-                        &mut chrome_registrations,
-                        &default_ifdef_config,
-                    ) {
+            match load_jar(
+                firefox_dir,
+                jar_path,
+                &default_ifdef_config,
+                ifdef_unknown,
+                cache.as_mut(),
+            ) {
+                Ok(parsed) => {
+                    if let Some(e) = &parsed.error {
                         eprintln!(
                             "Error parsing jar.mn file {}: {}",
                             full_jar_path.display(),
                             e
                         );
                     }
+                    tables.add_jar(parsed, jar_path);
                 }
-                Err(e) => {
+                Err(JarResolverError::IoError(e)) => {
                     eprintln!(
                         "Error reading jar.mn file {}: {}",
                         full_jar_path.display(),
                         e
                     );
                 }
+                Err(e) => return Err(e),
             }
         }
 
+        if let Some(cache) = &cache {
+            cache.save()?;
+        }
+
         // Process moz.build files for resource URLs
         for mozbuild_path in mozbuild_paths {
             let full_mozbuild_path = firefox_dir.join(mozbuild_path);
@@ -118,15 +260,20 @@ impl JarResolver {
 
             match fs::read_to_string(&full_mozbuild_path) {
                 Ok(content) => {
-                    if let Err(e) =
-                        parse_mozbuild_file(&content, mozbuild_path, firefox_dir, &mut mappings)
-                    {
+                    let mut file_mappings = HashMap::new();
+                    if let Err(e) = parse_mozbuild_file(
+                        &content,
+                        mozbuild_path,
+                        firefox_dir,
+                        &mut file_mappings,
+                    ) {
                         eprintln!(
                             "Error parsing moz.build file {}: {}",
                             full_mozbuild_path.display(),
                             e
                         );
                     }
+                    tables.add_mappings(file_mappings, mozbuild_path);
                 }
                 Err(e) => {
                     eprintln!(
@@ -138,7 +285,93 @@ impl JarResolver {
             }
         }
 
-        Ok(JarResolver { mappings })
+        // Process chrome.manifest files for registrations outside of jar.mn files
+        let mut url_prefixes = Vec::new();
+        let mut overrides = HashMap::new();
+        for manifest_path in manifest_paths {
+            let full_manifest_path = firefox_dir.join(manifest_path);
+            match fs::read_to_string(&full_manifest_path) {
+                Ok(content) => parse_manifest_file(
+                    &content,
+                    manifest_path,
+                    firefox_dir,
+                    &mut url_prefixes,
+                    &mut overrides,
+                ),
+                Err(e) => {
+                    eprintln!(
+                        "Error reading chrome.manifest file {}: {}",
+                        full_manifest_path.display(),
+                        e
+                    );
+                }
+            }
+        }
+
+        let MappingTables {
+            mappings,
+            origins,
+            mapping_conflicts,
+            registrations: _,
+            registration_conflicts,
+            unknown_conditions,
+            preprocessed,
+        } = tables;
+
+        for conflict in &registration_conflicts {
+            eprintln!(
+                "Warning: chrome package {} registered as {} in {} and as {} in {}",
+                conflict.key,
+                conflict.first_path,
+                conflict.first_origin,
+                conflict.second_path,
+                conflict.second_origin
+            );
+        }
+
+        if !unknown_conditions.is_empty() {
+            let conditions: Vec<&str> = unknown_conditions.iter().map(String::as_str).collect();
+            eprintln!(
+                "Warning: unknown ifdef conditions, treated as {}: {}",
+                if ifdef_unknown == IfdefUnknown::True {
+                    "defined"
+                } else {
+                    "undefined"
+                },
+                conditions.join(", ")
+            );
+        }
+
+        Ok(JarResolver {
+            urls_by_path: urls_by_path(&mappings),
+            mappings,
+            url_prefixes,
+            overrides,
+            origins,
+            mapping_conflicts,
+            preprocessed,
+            discovered_jars,
+        })
+    }
+
+    /// URLs that different jar.mn or moz.build files mapped to different
+    /// source files. The mapping parsed last is used.
+    pub fn mapping_conflicts(&self) -> &[MappingConflict] {
+        &self.mapping_conflicts
+    }
+
+    /// Whether a source file is listed with `*` in a jar.mn file, i.e. goes
+    /// through the preprocessor before it is packaged.
+    pub fn is_preprocessed(&self, path: &Path) -> bool {
+        self.preprocessed
+            .contains(&super::file_utils::make_relative_to_cwd(
+                &path.to_path_buf(),
+            ))
+    }
+
+    /// Parse statistics of the discovered jar.mn files.
+    pub fn discovered_jars(&self) -> &[DiscoveredJar] {
+        &self.discovered_jars
     }
 
     /// Checks if a URL is a chrome or resource internal URL.
@@ -153,23 +386,202 @@ impl JarResolver {
     ///
     /// # Returns
     /// Returns the corresponding file system path, or an error if not found or invalid.
+    ///
+    /// Overridden URLs resolve to the file of the URL they are overridden
+    /// with. URLs without a file mapping fall back to the longest URL prefix
+    /// registered in a chrome.manifest file.
     pub fn resolve_path(&self, url: &str) -> Result<PathBuf, JarResolverError> {
         if !self.is_internal_url(url) {
             return Err(JarResolverError::InvalidChromeUrl(url.to_string()));
         }
 
-        self.mappings
-            .get(url)
+        // A query string or fragment doesn't change the file, e.g.
+        // `icon.svg#flip`
+        let mut current = url.split(['?', '#']).next().unwrap_or(url).to_string();
+        for _ in 0..MAX_REDIRECTS {
+            if let Some(target) = self.overrides.get(&current) {
+                current = target.clone();
+                continue;
+            }
+            if let Some(path) = self.mappings.get(&current) {
+                return Ok(path.clone());
+            }
+            let prefix = self
+                .url_prefixes
+                .iter()
+                .filter(|(prefix, _)| current.starts_with(prefix.as_str()))
+                .max_by_key(|(prefix, _)| prefix.len());
+            match prefix {
+                Some((prefix, ManifestTarget::Directory(dir))) => {
+                    return Ok(dir.join(&current[prefix.len()..]));
+                }
+                Some((prefix, ManifestTarget::Url(target))) => {
+                    current = format!("{}{}", target, &current[prefix.len()..]);
+                }
+                None => break,
+            }
+        }
+        Err(JarResolverError::NoMappingFound(url.to_string()))
+    }
+
+    /// Get all other URLs that map to the same file as `url`, sorted.
+    pub fn aliases(&self, url: &str) -> Vec<String> {
+        let Some(path) = self.mappings.get(url) else {
+            return Vec::new();
+        };
+        self.chrome_urls_for_path(path)
+            .iter()
+            .filter(|other| other.as_str() != url)
             .cloned()
-            .ok_or_else(|| JarResolverError::NoMappingFound(url.to_string()))
+            .collect()
+    }
+
+    /// Get the first, in sorted order, of the URLs that map to a file.
+    pub fn chrome_url_for_path(&self, path: &Path) -> Option<&str> {
+        self.chrome_urls_for_path(path).first().map(String::as_str)
+    }
+
+    /// Get all URLs that map to a file, sorted. The path is normalized like
+    /// the mapped paths, so it can be relative to the current directory or
+    /// absolute.
+    pub fn chrome_urls_for_path(&self, path: &Path) -> &[String] {
+        let path = super::file_utils::make_relative_to_cwd(&path.to_path_buf());
+        self.urls_by_path.get(&path).map_or(&[], Vec::as_slice)
+    }
+
+    /// Iterate over all URLs that map to a file and their paths, in no
+    /// particular order.
+    pub fn mappings(&self) -> impl Iterator<Item = (&str, &Path)> {
+        self.mappings
+            .iter()
+            .map(|(url, path)| (url.as_str(), path.as_path()))
+    }
+
+    /// All mappings of URLs starting with `prefix`, e.g.
+    /// `chrome://global/skin/icons/`, sorted by URL.
+    pub fn resolve_prefix(&self, prefix: &str) -> Vec<(String, PathBuf)> {
+        let mut mappings: Vec<(String, PathBuf)> = self
+            .mappings()
+            .filter(|(url, _)| url.starts_with(prefix))
+            .map(|(url, path)| (url.to_string(), path.to_path_buf()))
+            .collect();
+        mappings.sort();
+        mappings
+    }
+
+    /// The jar.mn or moz.build file, relative to the Firefox root, a URL's
+    /// mapping comes from.
+    pub fn mapping_origin(&self, url: &str) -> Option<&str> {
+        self.origins.get(url).map(String::as_str)
+    }
+}
+
+/// One entry of the mapping table, as listed by `mozcomp jars`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JarMapping {
+    pub url: String,
+    /// Source file, relative to the Firefox root when it lives inside it
+    pub path: PathBuf,
+    /// jar.mn or moz.build file the mapping comes from
+    pub origin: String,
+}
+
+/// Index the URLs of `mappings` by the file they map to.
+fn urls_by_path(mappings: &HashMap<String, PathBuf>) -> HashMap<PathBuf, Vec<String>> {
+    let mut urls_by_path: HashMap<PathBuf, Vec<String>> = HashMap::new();
+    for (url, path) in mappings {
+        urls_by_path
+            .entry(path.clone())
+            .or_default()
+            .push(url.clone());
+    }
+    for urls in urls_by_path.values_mut() {
+        urls.sort();
+    }
+    urls_by_path
+}
+
+/// Parses the registrations of a chrome.manifest file. `content`, `skin`,
+/// `locale` and `resource` lines register a URL prefix for a directory
+/// (relative to the manifest) or another internal URL, `override` lines
+/// redirect a single URL. Other directives and flags are ignored.
+fn parse_manifest_file(
+    content: &str,
+    manifest_path: &str,
+    firefox_dir: &Path,
+    url_prefixes: &mut Vec<(String, ManifestTarget)>,
+    overrides: &mut HashMap<String, String>,
+) {
+    let manifest_dir = Path::new(manifest_path).parent().unwrap_or(Path::new(""));
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let (prefix, location) = match parts.as_slice() {
+            ["content", package, location, ..] => {
+                (format!("chrome://{}/content/", package), *location)
+            }
+            ["skin" | "locale", package, _provider, location, ..] => {
+                (format!("chrome://{}/{}/", package, parts[0]), *location)
+            }
+            ["resource", name, location, ..] => (format!("resource://{}/", name), *location),
+            ["override", from, to, ..] => {
+                overrides.insert(from.to_string(), to.to_string());
+                continue;
+            }
+            _ => continue,
+        };
+
+        let target = if location.starts_with("chrome://") || location.starts_with("resource://") {
+            ManifestTarget::Url(with_trailing_slash(location))
+        } else if location.contains(':') {
+            // jar:, file: and other URLs don't point into the source tree
+            log::debug!(
+                "Skipping registration of {} in {}: {}",
+                prefix,
+                manifest_path,
+                location
+            );
+            continue;
+        } else {
+            let dir = firefox_dir.join(manifest_dir).join(location);
+            ManifestTarget::Directory(super::file_utils::make_relative_to_cwd(&dir))
+        };
+        url_prefixes.retain(|(existing, _)| *existing != prefix);
+        url_prefixes.push((prefix, target));
+    }
+}
+
+fn with_trailing_slash(url: &str) -> String {
+    if url.ends_with('/') {
+        url.to_string()
+    } else {
+        format!("{}/", url)
     }
 }
 
+/// Preprocessor conditions of a desktop build, which the ifdef config
+/// extends or overrides.
+pub(crate) fn default_ifdef_config() -> HashMap<String, bool> {
+    HashMap::from([
+        ("MOZILLA_OFFICIAL".to_string(), true),
+        ("ANDROID".to_string(), false),
+        ("MOZ_GLEAN_ANDROID".to_string(), false),
+        ("MOZ_FENNEC".to_string(), false),
+        ("XP_MACOSX".to_string(), false),
+        ("RELEASE_OR_BETA".to_string(), true),
+    ])
+}
+
 /// Recursively processes #include directives in jar.mn files.
 fn process_includes(
     content: &str,
     jar_file_path: &Path,
     firefox_dir: &Path,
+    included: &mut Vec<PathBuf>,
 ) -> Result<String, JarResolverError> {
     let mut result = String::new();
     let jar_dir = jar_file_path.parent().unwrap_or(Path::new(""));
@@ -190,9 +602,14 @@ fn process_includes(
             // Read and include the file content
             match fs::read_to_string(&full_include_path) {
                 Ok(include_content) => {
+                    included.push(full_include_path.clone());
                     // Recursively process includes in the included file
-                    let processed_include =
-                        process_includes(&include_content, &full_include_path, firefox_dir)?;
+                    let processed_include = process_includes(
+                        &include_content,
+                        &full_include_path,
+                        firefox_dir,
+                        included,
+                    )?;
                     result.push_str(&processed_include);
                     result.push('\n');
                 }
@@ -211,117 +628,108 @@ fn process_includes(
     Ok(result)
 }
 
-/// Parses a moz.build file for CONTENT_ACCESSIBLE_FILES and updates mappings.
+/// The `resource://` URL of the directory a moz.build install variable
+/// installs its files to, for the moz.build file at `mozbuild_path`.
+///
+/// `EXPORTS` installs headers into `dist/include`, which has no URL, so it is
+/// not listed.
+fn mozbuild_url_prefix(variable: &str, mozbuild_path: &str) -> Option<&'static str> {
+    match variable {
+        "CONTENT_ACCESSIBLE_FILES" => Some("resource://content-accessible/"),
+        // Installed to dist/bin/res
+        "RESOURCE_FILES" => Some("resource://gre-resources/"),
+        // Installed to dist/bin/modules
+        "EXTRA_JS_MODULES" => Some("resource://gre/modules/"),
+        // Installed to the final target, which is the app directory for
+        // files of the browser and the GRE directory for everything else
+        "FINAL_TARGET_FILES" if mozbuild_path.starts_with("browser/") => Some("resource:///"),
+        "FINAL_TARGET_FILES" => Some("resource://gre/"),
+        _ => None,
+    }
+}
+
+/// Parses a moz.build file for the lists of files installed to a location with
+/// a `resource://` URL and updates mappings.
+///
+/// Understands `=` and `+=` assignments of (multi-line) lists to the variables
+/// of `mozbuild_url_prefix`, including sub-directories like
+/// `RESOURCE_FILES.images` or `FINAL_TARGET_FILES["defaults"]`, which add to
+/// the URL path. Other Python is ignored.
 fn parse_mozbuild_file(
     content: &str,
     mozbuild_path: &str,
     firefox_dir: &Path,
     mappings: &mut HashMap<String, PathBuf>,
 ) -> Result<(), JarResolverError> {
+    let assignment_regex =
+        regex::Regex::new(r#"^([A-Z_]+)((?:\.\w+|\[\s*["'][^"']+["']\s*\])*)\s*\+?=\s*(.*)$"#)
+            .unwrap();
+    let key_regex = regex::Regex::new(r#"\.(\w+)|\[\s*["']([^"']+)["']\s*\]"#).unwrap();
     let mozbuild_dir = Path::new(mozbuild_path).parent().unwrap_or(Path::new(""));
-    let lines: Vec<&str> = content.lines().collect();
-    let mut i = 0;
+    let mut lines = content.lines();
 
-    while i < lines.len() {
-        let line = lines[i].trim();
+    while let Some(line) = lines.next() {
+        let Some(caps) = assignment_regex.captures(line.trim()) else {
+            continue;
+        };
+        let Some(url_prefix) = mozbuild_url_prefix(&caps[1], mozbuild_path) else {
+            continue;
+        };
 
-        // Look for CONTENT_ACCESSIBLE_FILES
-        if line.starts_with("CONTENT_ACCESSIBLE_FILES")
-            && (line.contains("+=") || line.contains("="))
-        {
-            // Check if the list starts on the same line or next line
-            if line.contains('[') {
-                // List starts on the same line
-                let start_bracket = line.find('[').unwrap();
-                let after_bracket = &line[start_bracket + 1..];
-
-                if after_bracket.trim().starts_with(']') {
-                    // Empty list on same line
-                    i += 1;
-                    continue;
-                }
+        // The dotted or subscripted keys are sub-directories of the install location
+        let mut url_prefix = url_prefix.to_string();
+        for key in key_regex.captures_iter(&caps[2]) {
+            let key = key.get(1).or_else(|| key.get(2)).unwrap().as_str();
+            url_prefix.push_str(key);
+            url_prefix.push('/');
+        }
 
-                // Parse items starting from this line
-                i = parse_content_accessible_files(&lines, i, mozbuild_dir, firefox_dir, mappings)?;
-            } else {
-                // Look for opening bracket on subsequent lines
-                i += 1;
-                while i < lines.len() {
-                    let next_line = lines[i].trim();
-                    if next_line.contains('[') {
-                        i = parse_content_accessible_files(
-                            &lines,
-                            i,
-                            mozbuild_dir,
-                            firefox_dir,
-                            mappings,
-                        )?;
-                        break;
-                    }
-                    i += 1;
-                }
-            }
-        } else {
-            i += 1;
+        // Collect the list, which can continue on the following lines
+        let mut list = strip_python_comment(&caps[3]).to_string();
+        if !list.contains('[') {
+            continue;
+        }
+        while !list.contains(']') {
+            let Some(next_line) = lines.next() else {
+                break;
+            };
+            list.push_str(strip_python_comment(next_line));
+            list.push(',');
         }
+        let start = list.find('[').unwrap() + 1;
+        let end = list.rfind(']').unwrap_or(list.len()).max(start);
+        parse_file_list(
+            &list[start..end],
+            &url_prefix,
+            mozbuild_dir,
+            firefox_dir,
+            mappings,
+        )?;
     }
 
     Ok(())
 }
 
-/// Helper for parsing the CONTENT_ACCESSIBLE_FILES list in moz.build files.
-fn parse_content_accessible_files(
-    lines: &[&str],
-    start_idx: usize,
-    mozbuild_dir: &Path,
-    firefox_dir: &Path,
-    mappings: &mut HashMap<String, PathBuf>,
-) -> Result<usize, JarResolverError> {
-    let mut i = start_idx;
-    let mut in_list = false;
-
-    while i < lines.len() {
-        let line = lines[i].trim();
-
-        if line.contains('[') {
-            in_list = true;
-            // Process any files on the same line after the bracket
-            if let Some(bracket_pos) = line.find('[') {
-                let after_bracket = &line[bracket_pos + 1..];
-                if let Some(close_bracket) = after_bracket.find(']') {
-                    // Complete list on one line
-                    let files_str = &after_bracket[..close_bracket];
-                    parse_file_list(files_str, mozbuild_dir, firefox_dir, mappings)?;
-                    return Ok(i + 1);
-                } else {
-                    // List continues on next lines
-                    let files_str = after_bracket;
-                    parse_file_list(files_str, mozbuild_dir, firefox_dir, mappings)?;
-                }
-            }
-        } else if in_list {
-            if line.contains(']') {
-                // End of list
-                if let Some(close_bracket) = line.find(']') {
-                    let files_str = &line[..close_bracket];
-                    parse_file_list(files_str, mozbuild_dir, firefox_dir, mappings)?;
-                }
-                return Ok(i + 1);
-            } else {
-                // Continue parsing files
-                parse_file_list(line, mozbuild_dir, firefox_dir, mappings)?;
-            }
+/// Strip a trailing `#` comment from a line of Python, ignoring `#` in strings.
+fn strip_python_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (index, c) in line.char_indices() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(open)) if c == open => quote = None,
+            ('#', None) => return &line[..index],
+            _ => {}
         }
-
-        i += 1;
     }
-
-    Ok(i)
+    line
 }
 
-/// Parses a comma-separated list of files and updates mappings for resource URLs.
+/// Parses a comma-separated list of files and updates mappings for resource
+/// URLs. The files are installed flat, so their URL is `url_prefix` followed by
+/// the file name.
 fn parse_file_list(
     files_str: &str,
+    url_prefix: &str,
     mozbuild_dir: &Path,
     firefox_dir: &Path,
     mappings: &mut HashMap<String, PathBuf>,
@@ -330,196 +738,758 @@ fn parse_file_list(
     for file_part in files_str.split(',') {
         let file_path = file_part.trim().trim_matches('"').trim_matches('\'');
 
-        if file_path.is_empty() {
+        // Entries starting with `!` are generated by the build
+        if file_path.is_empty() || file_path.starts_with('!') {
             continue;
         }
 
-        // Build the full source path
-        let source_path = mozbuild_dir.join(file_path);
+        // Build the full source path, `/` starts at the Firefox root
+        let source_path = match file_path.strip_prefix('/') {
+            Some(from_root) => PathBuf::from(from_root),
+            None => mozbuild_dir.join(file_path),
+        };
         let full_source_path = firefox_dir.join(&source_path);
 
         // Make the path relative to the current working directory
         let rel_source_path = super::file_utils::make_relative_to_cwd(&full_source_path);
 
         // Extract filename for the resource URL
-        if let Some(filename) = Path::new(file_path).file_name() {
-            if let Some(filename_str) = filename.to_str() {
-                let resource_url = format!("resource://content-accessible/{}", filename_str);
-                mappings.insert(resource_url, rel_source_path);
-            }
+        if let Some(filename) = Path::new(file_path).file_name()
+            && let Some(filename_str) = filename.to_str()
+        {
+            let resource_url = format!("{}{}", url_prefix, filename_str);
+            mappings.insert(resource_url, rel_source_path);
         }
     }
 
     Ok(())
 }
 
-// Update parse_jar_file and related functions to use PathBuf for mappings
-/// Parses a jar.mn file and updates mappings and chrome registrations.
+/// The result of parsing one jar.mn file and its includes, as kept in the
+/// jar cache.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ParsedJar {
+    mappings: HashMap<String, PathBuf>,
+    /// Path and origin of the first registration of every chrome package
+    registrations: HashMap<String, (String, String)>,
+    /// Registrations of the file that conflict with each other
+    registration_conflicts: Vec<RegistrationConflict>,
+    unknown_conditions: BTreeSet<String>,
+    preprocessed: HashSet<PathBuf>,
+    /// The wildcard sources that were expanded, with the files they matched
+    wildcards: BTreeMap<String, Vec<PathBuf>>,
+    /// Why parsing stopped early, the mappings up to there are kept
+    error: Option<String>,
+}
+
+impl ParsedJar {
+    /// Whether every wildcard source still matches the same files.
+    pub fn wildcards_unchanged(&self) -> bool {
+        self.wildcards
+            .iter()
+            .all(|(pattern, files)| expand_wildcard(pattern).ok().as_ref() == Some(files))
+    }
+}
+
+/// Parse a jar.mn file and its includes. Returns the result and every file
+/// that was read for it.
+fn parse_jar(
+    firefox_dir: &Path,
+    jar_path: &str,
+    ifdef_config: &HashMap<String, bool>,
+    ifdef_unknown: IfdefUnknown,
+) -> Result<(ParsedJar, Vec<PathBuf>), JarResolverError> {
+    let full_jar_path = firefox_dir.join(jar_path);
+    let content = fs::read_to_string(&full_jar_path)?;
+    let mut inputs = vec![full_jar_path.clone()];
+    let content = process_includes(&content, &full_jar_path, firefox_dir, &mut inputs)?;
+
+    let mut parsed = ParsedJar::default();
+    let result = parse_jar_file(
+        &content,
+        jar_path,
+        firefox_dir,
+        &mut parsed,
+        ifdef_config,
+        ifdef_unknown,
+    );
+    parsed.error = result.err().map(|e| e.to_string());
+    Ok((parsed, inputs))
+}
+
+/// Parse a jar.mn file, or take the result from the cache if neither the file
+/// nor its includes changed since it was cached.
+fn load_jar(
+    firefox_dir: &Path,
+    jar_path: &str,
+    ifdef_config: &HashMap<String, bool>,
+    ifdef_unknown: IfdefUnknown,
+    cache: Option<&mut JarCache>,
+) -> Result<ParsedJar, JarResolverError> {
+    if let Some(parsed) = cache.as_deref().and_then(|cache| cache.get(jar_path)) {
+        log::debug!("Using cached mappings of {}", jar_path);
+        return Ok(parsed.clone());
+    }
+    let (parsed, inputs) = parse_jar(firefox_dir, jar_path, ifdef_config, ifdef_unknown)?;
+    if let Some(cache) = cache {
+        cache.insert(jar_path, &inputs, parsed.clone());
+    }
+    Ok(parsed)
+}
+
+/// Everything collected from the parsed jar.mn and moz.build files.
+#[derive(Default)]
+struct MappingTables {
+    mappings: HashMap<String, PathBuf>,
+    origins: HashMap<String, String>,
+    mapping_conflicts: Vec<MappingConflict>,
+    registrations: HashMap<String, (String, String)>,
+    registration_conflicts: Vec<RegistrationConflict>,
+    unknown_conditions: BTreeSet<String>,
+    preprocessed: HashSet<PathBuf>,
+}
+
+impl MappingTables {
+    /// Adds a parsed jar.mn file, recording registrations of packages that
+    /// were registered with a different path before as conflicts.
+    fn add_jar(&mut self, parsed: ParsedJar, origin: &str) {
+        self.registration_conflicts
+            .extend(parsed.registration_conflicts);
+        let mut registrations: Vec<_> = parsed.registrations.into_iter().collect();
+        registrations.sort();
+        for (key, (path, registration_origin)) in registrations {
+            match self.registrations.entry(key) {
+                Entry::Occupied(first) if first.get().0 != path => {
+                    let (first_path, first_origin) = first.get().clone();
+                    self.registration_conflicts.push(RegistrationConflict {
+                        key: first.key().clone(),
+                        first_path,
+                        first_origin,
+                        second_path: path,
+                        second_origin: registration_origin,
+                    });
+                }
+                Entry::Occupied(_) => {}
+                Entry::Vacant(entry) => {
+                    entry.insert((path, registration_origin));
+                }
+            }
+        }
+        self.unknown_conditions.extend(parsed.unknown_conditions);
+        self.preprocessed.extend(parsed.preprocessed);
+        self.add_mappings(parsed.mappings, origin);
+    }
+
+    /// Adds the mappings of one jar.mn or moz.build file, recording the file
+    /// as their origin. URLs already mapped to a different source file are
+    /// recorded as conflicts and mapped to the new file.
+    fn add_mappings(&mut self, file_mappings: HashMap<String, PathBuf>, origin: &str) {
+        let mut file_mappings: Vec<_> = file_mappings.into_iter().collect();
+        file_mappings.sort();
+        for (url, path) in file_mappings {
+            if let Some(first_path) = self.mappings.get(&url)
+                && *first_path != path
+            {
+                self.mapping_conflicts.push(MappingConflict {
+                    url: url.clone(),
+                    first_path: first_path.clone(),
+                    first_origin: self.origins[&url].clone(),
+                    second_path: path.clone(),
+                    second_origin: origin.to_string(),
+                });
+            }
+            self.origins.insert(url.clone(), origin.to_string());
+            self.mappings.insert(url, path);
+        }
+    }
+}
+
+/// Parses a jar.mn file into `parsed`.
+///
+/// Chrome registrations are scoped to the jar section they appear in, so file
+/// lines only build URLs from the registrations seen so far in their own
+/// section. `parsed.registrations` keeps the path and origin of every
+/// registration across sections and files to detect conflicting registrations.
 fn parse_jar_file(
     content: &str,
     jar_path: &str,
     firefox_dir: &Path,
-    mappings: &mut HashMap<String, PathBuf>,
-    chrome_registrations: &mut HashMap<String, ChromeRegistration>,
+    parsed: &mut ParsedJar,
     ifdef_config: &HashMap<String, bool>,
+    ifdef_unknown: IfdefUnknown,
 ) -> Result<(), JarResolverError> {
     let lines: Vec<&str> = content.lines().collect();
     let jar_dir = Path::new(jar_path).parent().unwrap_or(Path::new(""));
     let mut current_jar: Option<String> = None;
-    let mut ifdef_stack = Vec::new();
-    let mut currently_included = true;
+    // Whether the current section lists Fluent files, `[localization] x.jar:`
+    let mut in_localization = false;
+    let mut chrome_registrations = HashMap::new();
+    let mut conditionals = Conditionals::new(ifdef_config, ifdef_unknown);
 
     for line in lines {
-        let line = line.trim();
-
-        // Skip empty lines and handle comments/preprocessor directives
-        if line.is_empty() || line.starts_with('#') {
-            if line.starts_with("#ifdef ") || line.starts_with("#ifndef ") {
-                let is_ifdef = line.starts_with("#ifdef ");
-                let condition = if is_ifdef {
-                    line.strip_prefix("#ifdef ").unwrap().trim()
-                } else {
-                    line.strip_prefix("#ifndef ").unwrap().trim()
-                };
+        let mut line = line.trim();
 
-                let condition_value = ifdef_config.get(condition).ok_or_else(|| {
-                    JarResolverError::UnknownIfdefCondition(condition.to_string())
-                })?;
-
-                let should_include = if is_ifdef {
-                    *condition_value
-                } else {
-                    !*condition_value
-                };
-
-                ifdef_stack.push(currently_included);
-                currently_included = currently_included && should_include;
-            } else if line == "#endif" {
-                if ifdef_stack.is_empty() {
-                    return Err(JarResolverError::UnmatchedEndif);
-                }
-                currently_included = ifdef_stack.pop().unwrap();
+        // `#expand` lines are regular lines with `__NAME__` variables in them
+        let expanded;
+        if let Some(rest) = line.strip_prefix("#expand ") {
+            if !conditionals.included {
+                continue;
             }
+            expanded = conditionals.defines.expand(rest.trim());
+            line = &expanded;
+        } else if line.is_empty() || line.starts_with('#') {
+            // Skip empty lines and handle comments/preprocessor directives
+            let (directive, argument) = line
+                .split_once(char::is_whitespace)
+                .map_or((line, ""), |(directive, argument)| {
+                    (directive, argument.trim())
+                });
+            conditionals.apply(directive, argument)?;
             continue;
         }
 
         // Skip if currently excluded by ifdef
-        if !currently_included {
+        if !conditionals.included {
             continue;
         }
 
-        // Skip lines starting with * (marked as special)
-        if line.starts_with('*') {
-            continue;
+        // Entries starting with * go through the preprocessor, their URL and
+        // source are the same as for other entries. A following + only forces
+        // overwriting the packaged file.
+        let is_preprocessed = line.starts_with('*');
+        if let Some(rest) = line.strip_prefix('*') {
+            line = rest.trim_start();
+        }
+        if let Some(rest) = line.strip_prefix('+') {
+            line = rest.trim_start();
+        }
+
+        // Locale files are packaged for the en-US build
+        let localized;
+        if line.contains("@AB_CD@") {
+            localized = line.replace("@AB_CD@", LOCALE);
+            line = &localized;
         }
 
         // Check if this is a jar declaration
         if line.ends_with(".jar:") {
             current_jar = Some(line.strip_suffix(':').unwrap().to_string());
+            in_localization = line.starts_with("[localization]");
+            chrome_registrations.clear();
             continue;
         }
 
         // Handle chrome registration lines (starting with %)
         if line.starts_with('%') {
-            parse_registration_line(line, jar_dir, chrome_registrations)?;
+            if let Some(key) = parse_registration_line(line, jar_dir, &mut chrome_registrations)? {
+                let path = chrome_registrations[&key].path.clone();
+                let origin = format!(
+                    "{} ({})",
+                    jar_path,
+                    current_jar.as_deref().unwrap_or("no section")
+                );
+                match parsed.registrations.get(&key) {
+                    Some((first_path, first_origin)) if *first_path != path => {
+                        parsed.registration_conflicts.push(RegistrationConflict {
+                            key,
+                            first_path: first_path.clone(),
+                            first_origin: first_origin.clone(),
+                            second_path: path,
+                            second_origin: origin,
+                        });
+                    }
+                    Some(_) => {}
+                    None => {
+                        parsed.registrations.insert(key, (path, origin));
+                    }
+                }
+            }
             continue;
         }
 
         // Handle file mapping lines
         if current_jar.is_some() && line.contains('/') {
-            parse_file_line(line, jar_dir, firefox_dir, mappings, chrome_registrations)?;
+            let url_for = |destination: &str| {
+                if in_localization {
+                    Some(format!(
+                        "{}{}",
+                        localization_url_prefix(jar_path),
+                        destination
+                    ))
+                } else {
+                    build_chrome_url(destination, &chrome_registrations)
+                }
+            };
+            let source = parse_file_line(
+                line,
+                jar_dir,
+                firefox_dir,
+                &mut parsed.mappings,
+                &mut parsed.wildcards,
+                &url_for,
+            )?;
+            if let Some(source) = source
+                && is_preprocessed
+            {
+                parsed.preprocessed.insert(source);
+            }
         }
     }
 
+    parsed
+        .unknown_conditions
+        .extend(conditionals.defines.unknown_conditions);
     Ok(())
 }
 
-/// Represents a chrome registration (content, skin, or locale) from a jar.mn file.
-#[derive(Debug, Clone)]
-struct ChromeRegistration {
-    registration_type: String,
-    package_name: String,
-    path: String,
-    #[allow(dead_code)]
-    provider_name: String,
-    #[allow(dead_code)]
-    flags: Vec<String>,
+/// An `#ifdef`/`#ifndef` block that is open while parsing a jar.mn file.
+struct ConditionalBlock {
+    /// Whether the lines around the block are included
+    parent_included: bool,
+    /// Whether one of the block's branches was included already, so later
+    /// `#elifdef`/`#else` branches are not
+    branch_taken: bool,
 }
 
-/// Parses a chrome registration line (starting with %) and updates registrations.
-fn parse_registration_line(
-    line: &str,
-    _jar_dir: &Path, // Marked as unused with an underscore
-    chrome_registrations: &mut HashMap<String, ChromeRegistration>,
-) -> Result<(), JarResolverError> {
-    // Remove the leading %
-    let line = line.strip_prefix('%').unwrap().trim();
-    let parts: Vec<&str> = line.split_whitespace().collect();
+/// The open `#if` blocks and the variables while preprocessing a file.
+pub(crate) struct Conditionals {
+    stack: Vec<ConditionalBlock>,
+    /// Whether the current line is included
+    pub(crate) included: bool,
+    pub(crate) defines: Defines,
+}
 
-    if parts.len() < 3 {
-        return Ok(()); // Skip malformed registration lines
+impl Conditionals {
+    pub(crate) fn new(ifdef_config: &HashMap<String, bool>, unknown: IfdefUnknown) -> Self {
+        Self {
+            stack: Vec::new(),
+            included: true,
+            defines: Defines::new(ifdef_config, unknown),
+        }
     }
 
-    let registration_type = parts[0].to_string(); // e.g., "content", "skin", "locale"
-    let package_name = parts[1].to_string(); // e.g., "global", "browser"  
+    /// Apply a conditional, `#define` or `#undef` directive. Returns false
+    /// for other directives.
+    pub(crate) fn apply(
+        &mut self,
+        directive: &str,
+        argument: &str,
+    ) -> Result<bool, JarResolverError> {
+        match directive {
+            "#if" | "#ifdef" | "#ifndef" => {
+                // Conditions inside excluded blocks are not evaluated
+                let condition = self.included
+                    && match directive {
+                        "#if" => self.defines.evaluate(argument)?,
+                        _ => self.defines.is_defined(argument)? == (directive == "#ifdef"),
+                    };
+                self.stack.push(ConditionalBlock {
+                    parent_included: self.included,
+                    branch_taken: condition,
+                });
+                self.included = condition;
+            }
+            "#elif" | "#elifdef" | "#elifndef" | "#else" => {
+                let block = self
+                    .stack
+                    .last_mut()
+                    .ok_or_else(|| JarResolverError::UnmatchedElse(directive.to_string()))?;
+                let condition = block.parent_included
+                    && !block.branch_taken
+                    && match directive {
+                        "#else" => true,
+                        "#elif" => self.defines.evaluate(argument)?,
+                        _ => self.defines.is_defined(argument)? == (directive == "#elifdef"),
+                    };
+                block.branch_taken |= condition;
+                self.included = condition;
+            }
+            "#endif" => {
+                let block = self.stack.pop().ok_or(JarResolverError::UnmatchedEndif)?;
+                self.included = block.parent_included;
+            }
+            "#define" => {
+                if self.included {
+                    let (name, value) = argument
+                        .split_once(char::is_whitespace)
+                        .unwrap_or((argument, ""));
+                    self.defines.define(name, Some(value.trim()));
+                }
+            }
+            "#undef" => {
+                if self.included {
+                    self.defines.define(argument, None);
+                }
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
 
-    // For content/skin/locale registrations, the format can be:
-    // content packagename path [flags...]
-    // skin packagename skinname path [flags...]
-    // locale packagename localename path [flags...]
+/// Preprocessor variables of a jar.mn file.
+pub(crate) struct Defines {
+    /// `Some(value)` if defined, `None` if known to be undefined
+    values: HashMap<String, Option<String>>,
+    unknown: IfdefUnknown,
+    /// Conditions evaluated by the `unknown` policy
+    unknown_conditions: BTreeSet<String>,
+}
 
-    let (provider_name, path, flags) = if registration_type == "content" {
-        // content global %content/global/ contentaccessible=yes
-        if parts.len() < 3 {
-            return Ok(());
-        }
-        let path = parts[2].to_string();
-        let flags = parts
-            .get(3..)
-            .unwrap_or(&[])
-            .iter()
-            .map(|s| s.to_string())
-            .collect();
-        (String::new(), path, flags)
-    } else {
-        // skin/locale format: type package provider path [flags...]
-        if parts.len() < 4 {
-            return Ok(());
-        }
-        let provider_name = parts[2].to_string();
-        let path = parts[3].to_string();
-        let flags = parts
-            .get(4..)
-            .unwrap_or(&[])
+impl Defines {
+    /// Conditions enabled in the ifdef config are defined as 1, like `-D`
+    /// flags of the build's preprocessor.
+    fn new(ifdef_config: &HashMap<String, bool>, unknown: IfdefUnknown) -> Self {
+        let values = ifdef_config
             .iter()
-            .map(|s| s.to_string())
+            .map(|(name, enabled)| (name.clone(), enabled.then(|| "1".to_string())))
             .collect();
-        (provider_name, path, flags)
-    };
+        Self {
+            values,
+            unknown,
+            unknown_conditions: BTreeSet::new(),
+        }
+    }
 
-    let registration = ChromeRegistration {
-        registration_type: registration_type.clone(),
-        package_name: package_name.clone(),
-        provider_name,
-        path: path.clone(),
+    fn define(&mut self, name: &str, value: Option<&str>) {
+        self.values
+            .insert(name.to_string(), value.map(str::to_string));
+    }
+
+    /// Whether a variable is defined, evaluating unknown ones by the policy.
+    fn is_defined(&mut self, name: &str) -> Result<bool, JarResolverError> {
+        if let Some(value) = self.values.get(name) {
+            return Ok(value.is_some());
+        }
+        if self.unknown == IfdefUnknown::Error {
+            return Err(JarResolverError::UnknownIfdefCondition(name.to_string()));
+        }
+        self.unknown_conditions.insert(name.to_string());
+        Ok(self.unknown == IfdefUnknown::True)
+    }
+
+    /// Evaluate the expression of an `#if` or `#elif` line.
+    fn evaluate(&mut self, expression: &str) -> Result<bool, JarResolverError> {
+        let tokens = tokenize(expression)?;
+        let mut parser = ExpressionParser {
+            tokens: &tokens,
+            position: 0,
+            defines: self,
+        };
+        let value = parser.or()?;
+        if parser.position != tokens.len() {
+            return Err(JarResolverError::InvalidExpression(expression.to_string()));
+        }
+        parser.truthy(value)
+    }
+
+    /// Replace the `__NAME__` tokens of defined variables in an `#expand`
+    /// line. Tokens of undefined variables are left as they are.
+    pub(crate) fn expand(&self, line: &str) -> String {
+        self.replace_variables(line, r"__(\w+?)__", "#expand")
+    }
+
+    /// Replace the `@NAME@` tokens of defined variables in a line of a file
+    /// with `#filter substitution`. Tokens of undefined variables are left as
+    /// they are.
+    pub(crate) fn substitute(&self, line: &str) -> String {
+        self.replace_variables(line, r"@(\w+)@", "substituted")
+    }
+
+    fn replace_variables(&self, line: &str, pattern: &str, kind: &str) -> String {
+        let variable_regex = regex::Regex::new(pattern).unwrap();
+        variable_regex
+            .replace_all(line, |caps: &regex::Captures| {
+                match self.values.get(&caps[1]) {
+                    Some(Some(value)) => value.clone(),
+                    _ => {
+                        log::debug!("Undefined variable {} in {} line: {}", &caps[1], kind, line);
+                        caps[0].to_string()
+                    }
+                }
+            })
+            .into_owned()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    /// A variable name or a literal value
+    Word(String),
+    Not,
+    And,
+    Or,
+    Equal,
+    NotEqual,
+    Open,
+    Close,
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, JarResolverError> {
+    let invalid = || JarResolverError::InvalidExpression(expression.to_string());
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '!' if chars.next_if_eq(&'=').is_some() => Token::NotEqual,
+            '!' => Token::Not,
+            '=' if chars.next_if_eq(&'=').is_some() => Token::Equal,
+            '&' if chars.next_if_eq(&'&').is_some() => Token::And,
+            '|' if chars.next_if_eq(&'|').is_some() => Token::Or,
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut word = c.to_string();
+                while let Some(c) =
+                    chars.next_if(|c| c.is_alphanumeric() || matches!(c, '_' | '.' | '-'))
+                {
+                    word.push(c);
+                }
+                Token::Word(word)
+            }
+            _ => return Err(invalid()),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// An operand of an `#if` expression: an evaluated condition, or a word that
+/// is a variable in a boolean context and a value in a comparison.
+enum Operand {
+    Bool(bool),
+    Word(String),
+}
+
+/// Recursive descent evaluator for `#if` expressions, with the precedence of
+/// the build's preprocessor: `!` over `==`/`!=` over `&&` over `||`.
+struct ExpressionParser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    defines: &'a mut Defines,
+}
+
+impl ExpressionParser<'_> {
+    fn invalid(&self) -> JarResolverError {
+        JarResolverError::InvalidExpression(format!("{:?}", self.tokens))
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.tokens.get(self.position) == Some(token) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn or(&mut self) -> Result<Operand, JarResolverError> {
+        let mut value = self.and()?;
+        while self.eat(&Token::Or) {
+            let left = self.truthy(value)?;
+            let right = self.and().and_then(|right| self.truthy(right))?;
+            value = Operand::Bool(left || right);
+        }
+        Ok(value)
+    }
+
+    fn and(&mut self) -> Result<Operand, JarResolverError> {
+        let mut value = self.comparison()?;
+        while self.eat(&Token::And) {
+            let left = self.truthy(value)?;
+            let right = self.comparison().and_then(|right| self.truthy(right))?;
+            value = Operand::Bool(left && right);
+        }
+        Ok(value)
+    }
+
+    fn comparison(&mut self) -> Result<Operand, JarResolverError> {
+        let left = self.unary()?;
+        let equal = if self.eat(&Token::Equal) {
+            true
+        } else if self.eat(&Token::NotEqual) {
+            false
+        } else {
+            return Ok(left);
+        };
+        let right = self.unary()?;
+        Ok(Operand::Bool(
+            (self.value(&left) == self.value(&right)) == equal,
+        ))
+    }
+
+    fn unary(&mut self) -> Result<Operand, JarResolverError> {
+        if self.eat(&Token::Not) {
+            let value = self.unary()?;
+            return Ok(Operand::Bool(!self.truthy(value)?));
+        }
+        if self.eat(&Token::Open) {
+            let value = self.or()?;
+            if !self.eat(&Token::Close) {
+                return Err(self.invalid());
+            }
+            return Ok(Operand::Bool(self.truthy(value)?));
+        }
+        let Some(Token::Word(word)) = self.tokens.get(self.position) else {
+            return Err(self.invalid());
+        };
+        self.position += 1;
+        if word == "defined" {
+            let name = match self.tokens.get(self.position..self.position + 3) {
+                Some([Token::Open, Token::Word(name), Token::Close]) => name,
+                _ => return Err(self.invalid()),
+            };
+            self.position += 3;
+            return Ok(Operand::Bool(self.defines.is_defined(name)?));
+        }
+        Ok(Operand::Word(word.clone()))
+    }
+
+    /// The value of an operand in a comparison. Words that aren't variables
+    /// are literals, and undefined variables are empty.
+    fn value(&self, operand: &Operand) -> String {
+        match operand {
+            Operand::Bool(value) => (*value as u8).to_string(),
+            Operand::Word(word) => match self.defines.values.get(word) {
+                Some(value) => value.clone().unwrap_or_default(),
+                None => word.clone(),
+            },
+        }
+    }
+
+    /// Whether an operand is true in a boolean context. Numbers are true
+    /// unless 0, variables if they are defined with a value other than 0.
+    fn truthy(&mut self, operand: Operand) -> Result<bool, JarResolverError> {
+        match operand {
+            Operand::Bool(value) => Ok(value),
+            Operand::Word(word) if word.chars().all(|c| c.is_ascii_digit()) => Ok(word != "0"),
+            Operand::Word(word) => match self.defines.values.get(&word) {
+                Some(value) => Ok(value.as_deref().is_some_and(|value| value != "0")),
+                None => self.defines.is_defined(&word),
+            },
+        }
+    }
+}
+
+/// Represents a chrome registration (content, skin, locale or resource) from a
+/// jar.mn file.
+#[derive(Debug, Clone)]
+struct ChromeRegistration {
+    registration_type: String,
+    package_name: String,
+    path: String,
+    #[allow(dead_code)]
+    provider_name: String,
+    #[allow(dead_code)]
+    flags: Vec<String>,
+}
+
+impl ChromeRegistration {
+    /// The directory of the jar the package is registered to, e.g.
+    /// `skin/classic/browser` for `%skin/classic/browser/`. The leading `%`
+    /// stands for the root of the jar, paths without it are relative to the
+    /// manifest outside of the jar and can't contain jar.mn destinations.
+    fn jar_directory(&self) -> Option<&str> {
+        let path = self.path.strip_prefix('%')?;
+        Some(path.trim_start_matches("./").trim_end_matches('/'))
+    }
+
+    /// The URL of a file at `relative_path` below the package's directory.
+    fn url(&self, relative_path: &str) -> String {
+        match self.registration_type.as_str() {
+            "resource" => format!("resource://{}/{}", self.package_name, relative_path),
+            // The skin and locale names, e.g. classic/1.0 or en-US, are not
+            // part of chrome URLs
+            registration_type => format!(
+                "chrome://{}/{}/{}",
+                self.package_name, registration_type, relative_path
+            ),
+        }
+    }
+}
+
+/// Parses a chrome registration line (starting with %) and updates registrations.
+/// Returns the key of the added registration, if the line registered a package.
+fn parse_registration_line(
+    line: &str,
+    _jar_dir: &Path, // Marked as unused with an underscore
+    chrome_registrations: &mut HashMap<String, ChromeRegistration>,
+) -> Result<Option<String>, JarResolverError> {
+    // Remove the leading %
+    let line = line.strip_prefix('%').unwrap().trim();
+    let parts: Vec<&str> = line.split_whitespace().collect();
+
+    if parts.len() < 3 {
+        return Ok(None); // Skip malformed registration lines
+    }
+
+    let registration_type = parts[0].to_string(); // e.g., "content", "skin", "locale"
+    let package_name = parts[1].to_string(); // e.g., "global", "browser"  
+
+    // For content/skin/locale/resource registrations, the format can be:
+    // content packagename path [flags...]
+    // resource packagename path [flags...]
+    // skin packagename skinname path [flags...]
+    // locale packagename localename path [flags...]
+
+    let (provider_name, path, flags) =
+        if matches!(registration_type.as_str(), "content" | "resource") {
+            // content global %content/global/ contentaccessible=yes
+            // resource pdf.js %pdfjs/
+            if parts.len() < 3 {
+                return Ok(None);
+            }
+            let path = parts[2].to_string();
+            let flags = parts
+                .get(3..)
+                .unwrap_or(&[])
+                .iter()
+                .map(|s| s.to_string())
+                .collect();
+            (String::new(), path, flags)
+        } else {
+            // skin/locale format: type package provider path [flags...]
+            if parts.len() < 4 {
+                return Ok(None);
+            }
+            let provider_name = parts[2].to_string();
+            let path = parts[3].to_string();
+            let flags = parts
+                .get(4..)
+                .unwrap_or(&[])
+                .iter()
+                .map(|s| s.to_string())
+                .collect();
+            (provider_name, path, flags)
+        };
+
+    let registration = ChromeRegistration {
+        registration_type: registration_type.clone(),
+        package_name: package_name.clone(),
+        provider_name,
+        path: path.clone(),
         flags,
     };
 
     // Use registration type and package as key
     let key = format!("{}:{}", registration_type, package_name);
-    chrome_registrations.insert(key, registration);
+    chrome_registrations.insert(key.clone(), registration);
 
-    Ok(())
+    Ok(Some(key))
 }
 
-/// Parses a file mapping line in a jar.mn file and updates mappings.
+/// Parses a file mapping line in a jar.mn file and updates mappings. Returns
+/// the source path of the added mapping, `None` for wildcard entries and
+/// lines without a registration.
 fn parse_file_line(
     line: &str,
     jar_dir: &Path,
     firefox_dir: &Path,
     mappings: &mut HashMap<String, PathBuf>,
-    chrome_registrations: &HashMap<String, ChromeRegistration>,
-) -> Result<(), JarResolverError> {
+    wildcards: &mut BTreeMap<String, Vec<PathBuf>>,
+    url_for: &dyn Fn(&str) -> Option<String>,
+) -> Result<Option<PathBuf>, JarResolverError> {
     let line = line.trim();
 
     // Parse the line format: destination_path (source_path) or just destination_path
@@ -540,6 +1510,9 @@ fn parse_file_line(
         if src.starts_with('/') {
             // Absolute path from Firefox root
             PathBuf::from(src.strip_prefix('/').unwrap_or(src))
+        } else if let Some(src) = src.strip_prefix('%') {
+            // Relative to the locale's directory next to the jar.mn file
+            jar_dir.join(LOCALE).join(src)
         } else {
             // Relative to jar directory
             jar_dir.join(src)
@@ -552,60 +1525,132 @@ fn parse_file_line(
         jar_dir.join(filename)
     };
 
+    if source_path.to_string_lossy().contains('*') {
+        insert_wildcard_mappings(
+            destination,
+            &source_path,
+            firefox_dir,
+            mappings,
+            wildcards,
+            url_for,
+        );
+        return Ok(None);
+    }
+
     // Build chrome URL from destination path
-    if let Some(chrome_url) = build_chrome_url(destination, chrome_registrations) {
-        let full_source_path = firefox_dir.join(&source_path);
-        // Make the path relative to the current working directory
-        let rel_source_path = super::file_utils::make_relative_to_cwd(&full_source_path);
-        mappings.insert(chrome_url, rel_source_path);
+    let Some(chrome_url) = url_for(destination) else {
+        return Ok(None);
+    };
+    let full_source_path = firefox_dir.join(&source_path);
+    // Make the path relative to the current working directory
+    let rel_source_path = super::file_utils::make_relative_to_cwd(&full_source_path);
+    mappings.insert(chrome_url, rel_source_path.clone());
+    Ok(Some(rel_source_path))
+}
+
+/// Adds a mapping for every file matching a wildcard source, like the jar
+/// maker does: `*` matches within one directory, `**` any number of
+/// directories. The path of a file below the part of the source before the
+/// first wildcard is appended to the part of the destination before its first
+/// wildcard.
+fn insert_wildcard_mappings(
+    destination: &str,
+    source_pattern: &Path,
+    firefox_dir: &Path,
+    mappings: &mut HashMap<String, PathBuf>,
+    wildcards: &mut BTreeMap<String, Vec<PathBuf>>,
+    url_for: &dyn Fn(&str) -> Option<String>,
+) {
+    let static_prefix = |path: &str| -> String {
+        path.split('/')
+            .take_while(|component| !component.contains('*'))
+            .collect::<Vec<_>>()
+            .join("/")
+    };
+    let source_pattern = super::file_utils::to_slash(source_pattern);
+    let source_prefix = firefox_dir.join(static_prefix(&source_pattern));
+    let destination_prefix = static_prefix(destination);
+
+    // A trailing `**` matches files too, glob's only matches directories
+    let full_pattern = if source_pattern.ends_with("**") {
+        firefox_dir.join(&source_pattern).join("*")
+    } else {
+        firefox_dir.join(&source_pattern)
+    };
+    let full_pattern = super::file_utils::to_slash(&full_pattern);
+    let Ok(paths) = expand_wildcard(&full_pattern) else {
+        log::debug!("Invalid wildcard in jar.mn line: {}", source_pattern);
+        return;
+    };
+    wildcards.insert(full_pattern, paths.clone());
+    for path in paths {
+        let Ok(relative) = path.strip_prefix(&source_prefix) else {
+            continue;
+        };
+        let relative = super::file_utils::to_slash(relative);
+        let file_destination = if destination_prefix.is_empty() {
+            relative
+        } else {
+            format!("{}/{}", destination_prefix, relative)
+        };
+        if let Some(chrome_url) = url_for(&file_destination) {
+            mappings.insert(chrome_url, super::file_utils::make_relative_to_cwd(&path));
+        }
     }
+}
 
-    Ok(())
+/// The files a wildcard source matches, sorted.
+fn expand_wildcard(pattern: &str) -> Result<Vec<PathBuf>, glob::PatternError> {
+    let mut paths: Vec<PathBuf> = glob::glob(pattern)?
+        .flatten()
+        .filter(|p| p.is_file())
+        .collect();
+    paths.sort();
+    Ok(paths)
 }
 
-/// Builds a chrome URL from a destination path and chrome registrations.
+/// Builds a chrome or resource URL from a destination path and the
+/// registrations of the jar section.
+///
+/// The registration with the longest jar directory the destination is in is
+/// used, e.g. `locale/en-US/global-platform/unix/` over
+/// `locale/en-US/global/`. The URL is `chrome://<package>/<type>/<path below
+/// the directory>`, or `resource://<package>/<path below the directory>` for
+/// resource registrations.
 fn build_chrome_url(
     destination: &str,
     chrome_registrations: &HashMap<String, ChromeRegistration>,
 ) -> Option<String> {
-    // Parse destination path to find chrome registration
-    let parts: Vec<&str> = destination.split('/').collect();
-    if parts.is_empty() {
-        return None;
-    }
-
-    let chrome_type = parts[0]; // e.g., "skin", "content", "locale"
-
-    // Look for matching chrome registration by type
-    for registration in chrome_registrations.values() {
-        if registration.registration_type == chrome_type {
-            // The destination path should match the registration path pattern
-            // For example: if registration path is %skin/classic/browser/
-            // and destination is skin/classic/browser/monitor-border.png
-            // then the chrome URL should be chrome://browser/skin/monitor-border.png
-
-            let reg_path = registration.path.trim_start_matches('%');
-            let reg_path = reg_path.trim_end_matches('/');
-
-            // Check if the destination path starts with the registration path
-            if destination.starts_with(reg_path) {
-                // Extract the relative path after the registration path
-                let relative_path = destination
-                    .strip_prefix(reg_path)
-                    .unwrap_or("")
-                    .trim_start_matches('/')
-                    .to_string();
-
-                // Build the chrome URL: chrome://package/type/relative_path
-                return Some(format!(
-                    "chrome://{}/{}/{}",
-                    registration.package_name, chrome_type, relative_path
-                ));
+    chrome_registrations
+        .values()
+        .filter_map(|registration| {
+            let jar_directory = registration.jar_directory()?;
+            let relative_path = destination.strip_prefix(jar_directory)?;
+            if !jar_directory.is_empty()
+                && !relative_path.is_empty()
+                && !relative_path.starts_with('/')
+            {
+                return None;
             }
-        }
-    }
+            let url = registration.url(relative_path.trim_start_matches('/'));
+            Some((jar_directory.len(), url))
+        })
+        // Ties go to the smallest URL so the result doesn't depend on the
+        // order of the map
+        .max_by(|(a_len, a_url), (b_len, b_url)| a_len.cmp(b_len).then_with(|| b_url.cmp(a_url)))
+        .map(|(_, url)| url)
+}
 
-    None
+/// The URL prefix of the Fluent files in `[localization]` sections, which are
+/// packaged to `localization/<locale>/` of the app for browser/ and of the GRE
+/// for everything else.
+fn localization_url_prefix(jar_path: &str) -> String {
+    let root = if jar_path.starts_with("browser/") {
+        "resource:///"
+    } else {
+        "resource://gre/"
+    };
+    format!("{}localization/{}/", root, LOCALE)
 }
 
 // =====================
@@ -621,6 +1666,7 @@ mod tests {
     fn test_is_internal_url() {
         let test_jr = JarResolver {
             mappings: HashMap::new(),
+            ..Default::default()
         };
         assert!(test_jr.is_internal_url("chrome://foo/bar"));
         assert!(test_jr.is_internal_url("resource://foo/bar"));
@@ -632,6 +1678,7 @@ mod tests {
     fn test_resolve_path_missing() {
         let test_jr = JarResolver {
             mappings: HashMap::new(),
+            ..Default::default()
         };
         let err = test_jr.resolve_path("chrome://foo/bar").unwrap_err();
         match err {
@@ -640,10 +1687,34 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_resolve_path_ignores_query_and_fragment() {
+        let test_jr = JarResolver {
+            mappings: HashMap::from([(
+                "chrome://global/skin/icons/arrow-down.svg".to_string(),
+                PathBuf::from("toolkit/themes/shared/icons/arrow-down.svg"),
+            )]),
+            ..Default::default()
+        };
+        for url in [
+            "chrome://global/skin/icons/arrow-down.svg#flip",
+            "chrome://global/skin/icons/arrow-down.svg?size=16",
+            "chrome://global/skin/icons/arrow-down.svg?size=16#flip",
+        ] {
+            assert_eq!(
+                test_jr.resolve_path(url).unwrap(),
+                PathBuf::from("toolkit/themes/shared/icons/arrow-down.svg"),
+                "{}",
+                url
+            );
+        }
+    }
+
     #[test]
     fn test_resolve_path_invalid_url() {
         let test_jr = JarResolver {
             mappings: HashMap::new(),
+            ..Default::default()
         };
         let err = test_jr.resolve_path("http://example.com").unwrap_err();
         match err {
@@ -652,6 +1723,52 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_aliases() {
+        let mut mappings = HashMap::new();
+        let path = PathBuf::from("toolkit/content/widgets/moz-button.mjs");
+        mappings.insert(
+            "chrome://global/content/elements/moz-button.mjs".to_string(),
+            path.clone(),
+        );
+        mappings.insert(
+            "resource://content-accessible/moz-button.mjs".to_string(),
+            path,
+        );
+        mappings.insert(
+            "chrome://global/content/other.mjs".to_string(),
+            PathBuf::from("other.mjs"),
+        );
+        let test_jr = JarResolver {
+            urls_by_path: urls_by_path(&mappings),
+            mappings,
+            ..Default::default()
+        };
+        assert_eq!(
+            test_jr.chrome_urls_for_path(Path::new("toolkit/content/widgets/moz-button.mjs")),
+            [
+                "chrome://global/content/elements/moz-button.mjs",
+                "resource://content-accessible/moz-button.mjs",
+            ]
+        );
+        assert_eq!(
+            test_jr.chrome_url_for_path(Path::new("./other.mjs")),
+            Some("chrome://global/content/other.mjs")
+        );
+        assert_eq!(test_jr.chrome_url_for_path(Path::new("missing.mjs")), None);
+        assert_eq!(test_jr.mappings().count(), 3);
+        assert_eq!(
+            test_jr.aliases("chrome://global/content/elements/moz-button.mjs"),
+            vec!["resource://content-accessible/moz-button.mjs".to_string()]
+        );
+        assert!(
+            test_jr
+                .aliases("chrome://global/content/other.mjs")
+                .is_empty()
+        );
+        assert!(test_jr.aliases("chrome://missing/content/x.mjs").is_empty());
+    }
+
     #[test]
     fn test_build_chrome_url_content() {
         let mut regs = HashMap::new();
@@ -676,6 +1793,68 @@ mod tests {
         assert_eq!(url2, None);
     }
 
+    #[test]
+    fn test_build_chrome_url_registration_formats() {
+        // Registration lines of toolkit/content/jar.mn, browser/themes/*/jar.mn
+        // and toolkit/locales/jar.mn, and a destination in each package
+        let cases = [
+            (
+                "% content global %content/global/ contentaccessible=yes",
+                "content/global/elements/moz-button.mjs",
+                Some("chrome://global/content/elements/moz-button.mjs"),
+            ),
+            (
+                "% content mozapps %content/mozapps/",
+                "content/mozapps/extensions/aboutaddons.js",
+                Some("chrome://mozapps/content/extensions/aboutaddons.js"),
+            ),
+            (
+                "% skin browser classic/1.0 %skin/classic/browser/",
+                "skin/classic/browser/monitor-border.png",
+                Some("chrome://browser/skin/monitor-border.png"),
+            ),
+            (
+                "% skin global classic/1.0 %skin/classic/global/",
+                "skin/classic/global/icons/close.svg",
+                Some("chrome://global/skin/icons/close.svg"),
+            ),
+            (
+                "% locale global en-US %locale/en-US/global/",
+                "locale/en-US/global/aboutReader.properties",
+                Some("chrome://global/locale/aboutReader.properties"),
+            ),
+            (
+                "% resource pdf.js %content/",
+                "content/build/pdf.mjs",
+                Some("resource://pdf.js/build/pdf.mjs"),
+            ),
+            // Relative to the manifest, outside of the jar
+            (
+                "% content branding content/branding/",
+                "content/branding/about.png",
+                None,
+            ),
+            // Only whole directories match
+            (
+                "% content global %content/global/",
+                "content/global-extra/file.js",
+                None,
+            ),
+        ];
+        for (registration, destination, expected) in cases {
+            let mut regs = HashMap::new();
+            parse_registration_line(registration, Path::new("."), &mut regs)
+                .unwrap()
+                .unwrap();
+            assert_eq!(
+                build_chrome_url(destination, &regs).as_deref(),
+                expected,
+                "{}",
+                registration
+            );
+        }
+    }
+
     #[test]
     fn test_parse_registration_line_content() {
         let mut regs = HashMap::new();
@@ -709,7 +1888,8 @@ mod tests {
             Path::new("src"),
             Path::new("firefox"),
             &mut mappings,
-            &regs,
+            &mut BTreeMap::new(),
+            &|destination| build_chrome_url(destination, &regs),
         );
         assert!(res.is_ok());
         assert!(mappings.contains_key("chrome://global/content/aboutAbout.js"));
@@ -735,7 +1915,8 @@ mod tests {
             Path::new("src"),
             Path::new("firefox"),
             &mut mappings,
-            &regs,
+            &mut BTreeMap::new(),
+            &|destination| build_chrome_url(destination, &regs),
         );
         assert!(res.is_ok());
         assert!(mappings.contains_key("chrome://global/skin/icons/eye.svg"));
@@ -746,12 +1927,14 @@ mod tests {
         let mut mappings = HashMap::new();
         let mozbuild_dir = Path::new("src/module");
         let firefox_dir = Path::new("firefox");
+        let url_prefix = "resource://content-accessible/";
         // Empty string should not panic
-        let res = parse_file_list("", mozbuild_dir, firefox_dir, &mut mappings);
+        let res = parse_file_list("", url_prefix, mozbuild_dir, firefox_dir, &mut mappings);
         assert!(res.is_ok());
         // Basic file
         let res2 = parse_file_list(
             "'res/close-12.svg'",
+            url_prefix,
             mozbuild_dir,
             firefox_dir,
             &mut mappings,
@@ -759,4 +1942,1088 @@ mod tests {
         assert!(res2.is_ok());
         assert!(mappings.contains_key("resource://content-accessible/close-12.svg"));
     }
+
+    #[test]
+    fn test_parse_mozbuild_file_install_variables() {
+        let content = r#"
+with Files("**"):
+    BUG_COMPONENT = ("Core", "Layout")
+
+CONTENT_ACCESSIBLE_FILES += ["res/close-12.svg"]
+
+RESOURCE_FILES += [
+    "contenteditable.css",
+    "designmode.css",  # comment, with a comma
+]
+
+RESOURCE_FILES.images += [
+    "images/broken-image.png",
+]
+
+EXTRA_JS_MODULES += ["Foo.sys.mjs"]
+EXTRA_JS_MODULES.translations += [
+    "/toolkit/components/translations/Bar.sys.mjs",
+]
+
+FINAL_TARGET_FILES["defaults"].settings += ["settings.json"]
+FINAL_TARGET_FILES.actors += ["!Generated.sys.mjs"]
+
+EXPORTS.mozilla += ["Header.h"]
+"#;
+        let mut mappings = HashMap::new();
+        parse_mozbuild_file(
+            content,
+            "layout/style/moz.build",
+            Path::new(""),
+            &mut mappings,
+        )
+        .unwrap();
+        let mut urls: Vec<(&str, &Path)> = mappings
+            .iter()
+            .map(|(url, path)| (url.as_str(), path.as_path()))
+            .collect();
+        urls.sort();
+        let expected = [
+            (
+                "resource://content-accessible/close-12.svg",
+                "layout/style/res/close-12.svg",
+            ),
+            (
+                "resource://gre-resources/contenteditable.css",
+                "layout/style/contenteditable.css",
+            ),
+            (
+                "resource://gre-resources/designmode.css",
+                "layout/style/designmode.css",
+            ),
+            (
+                "resource://gre-resources/images/broken-image.png",
+                "layout/style/images/broken-image.png",
+            ),
+            (
+                "resource://gre/defaults/settings/settings.json",
+                "layout/style/settings.json",
+            ),
+            (
+                "resource://gre/modules/Foo.sys.mjs",
+                "layout/style/Foo.sys.mjs",
+            ),
+            (
+                "resource://gre/modules/translations/Bar.sys.mjs",
+                "toolkit/components/translations/Bar.sys.mjs",
+            ),
+        ];
+        assert_eq!(urls.len(), expected.len(), "{:?}", urls);
+        for ((url, path), (expected_url, expected_path)) in urls.iter().zip(expected) {
+            assert_eq!(*url, expected_url);
+            assert!(path.ends_with(expected_path), "{} -> {:?}", url, path);
+        }
+
+        let mut browser_mappings = HashMap::new();
+        parse_mozbuild_file(
+            "FINAL_TARGET_FILES.modules += [\"About.sys.mjs\"]\n",
+            "browser/modules/moz.build",
+            Path::new(""),
+            &mut browser_mappings,
+        )
+        .unwrap();
+        assert!(browser_mappings.contains_key("resource:///modules/About.sys.mjs"));
+    }
+
+    const TWO_SECTION_JAR: &str = "\
+first.jar:
+% content foo %content/shared/
+  content/shared/a.js (a.js)
+second.jar:
+% content foo %content/other/
+% content bar %content/shared/
+  content/shared/b.js (b.js)
+";
+
+    fn parse_two_sections() -> (HashMap<String, PathBuf>, Vec<RegistrationConflict>) {
+        let mut parsed = ParsedJar::default();
+        parse_jar_file(
+            TWO_SECTION_JAR,
+            "toolkit/jar.mn",
+            Path::new("firefox"),
+            &mut parsed,
+            &HashMap::new(),
+            IfdefUnknown::Error,
+        )
+        .unwrap();
+        (parsed.mappings, parsed.registration_conflicts)
+    }
+
+    #[test]
+    fn test_parse_jar_file_scopes_registrations_per_section() {
+        let (mappings, _) = parse_two_sections();
+        assert!(
+            mappings["chrome://foo/content/a.js"].ends_with("toolkit/a.js"),
+            "first section maps through its own registration"
+        );
+        // The second section re-registers foo elsewhere and adds bar for the
+        // shared path, so b.js must only be reachable through bar
+        assert!(mappings["chrome://bar/content/b.js"].ends_with("toolkit/b.js"));
+        assert!(!mappings.contains_key("chrome://foo/content/b.js"));
+        assert_eq!(mappings.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_jar_file_expands_defines() {
+        let content = "\
+#define APP browser
+#define SKIN classic
+#define UNUSED
+#undef UNUSED
+#filter substitution
+
+toolkit.jar:
+% content global %content/global/
+#expand   content/global/__APP__-__SKIN__.css (skin/__APP__.css)
+#ifdef UNUSED
+  content/global/unused.js (unused.js)
+#endif
+#ifdef MOZILLA_OFFICIAL
+#expand   content/global/__MOZILLA_OFFICIAL__/__MISSING__.js (official.js)
+#endif
+";
+        let mut ifdef_config = HashMap::new();
+        ifdef_config.insert("MOZILLA_OFFICIAL".to_string(), true);
+        let mut parsed = ParsedJar::default();
+        parse_jar_file(
+            content,
+            "toolkit/jar.mn",
+            Path::new("firefox"),
+            &mut parsed,
+            &ifdef_config,
+            IfdefUnknown::Error,
+        )
+        .unwrap();
+
+        assert!(
+            parsed.mappings["chrome://global/content/browser-classic.css"]
+                .ends_with("toolkit/skin/browser.css")
+        );
+        assert!(
+            parsed.mappings["chrome://global/content/1/__MISSING__.js"]
+                .ends_with("toolkit/official.js")
+        );
+        assert_eq!(parsed.mappings.len(), 2);
+    }
+
+    /// Parse a jar.mn section with the given ifdef config and return the names
+    /// of the files it maps, sorted.
+    fn included_files(lines: &str, config: &[(&str, bool)]) -> Vec<String> {
+        let content = format!("toolkit.jar:\n% content global %content/global/\n{}", lines);
+        let ifdef_config = config
+            .iter()
+            .map(|(name, value)| (name.to_string(), *value))
+            .collect();
+        let mut parsed = ParsedJar::default();
+        parse_jar_file(
+            &content,
+            "toolkit/jar.mn",
+            Path::new("firefox"),
+            &mut parsed,
+            &ifdef_config,
+            IfdefUnknown::Error,
+        )
+        .unwrap();
+        let mut files: Vec<String> = parsed
+            .mappings
+            .keys()
+            .map(|url| {
+                url.trim_start_matches("chrome://global/content/")
+                    .to_string()
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_parse_jar_file_conditionals() {
+        const IF_ELSE: &str = "\
+#ifdef OFFICIAL
+  content/global/official.svg (official.svg)
+#else
+  content/global/unofficial.svg (unofficial.svg)
+#endif
+";
+        const ELIF: &str = "\
+#ifdef NIGHTLY
+  content/global/nightly.svg (nightly.svg)
+#elifdef BETA
+  content/global/beta.svg (beta.svg)
+#elifndef RELEASE
+  content/global/dev.svg (dev.svg)
+#else
+  content/global/release.svg (release.svg)
+#endif
+";
+        const NESTED: &str = "\
+#ifdef OUTER
+  content/global/outer.js (outer.js)
+#ifndef INNER
+  content/global/not-inner.js (not-inner.js)
+#else
+  content/global/inner.js (inner.js)
+#endif
+#else
+#ifdef INNER
+  content/global/only-inner.js (only-inner.js)
+#else
+  content/global/neither.js (neither.js)
+#endif
+#endif
+  content/global/always.js (always.js)
+";
+        type Config<'a> = &'a [(&'a str, bool)];
+        let cases: &[(&str, Config, &[&str])] = &[
+            (IF_ELSE, &[("OFFICIAL", true)], &["official.svg"]),
+            (IF_ELSE, &[("OFFICIAL", false)], &["unofficial.svg"]),
+            (
+                ELIF,
+                &[("NIGHTLY", true), ("BETA", true), ("RELEASE", false)],
+                &["nightly.svg"],
+            ),
+            (
+                ELIF,
+                &[("NIGHTLY", false), ("BETA", true), ("RELEASE", false)],
+                &["beta.svg"],
+            ),
+            (
+                ELIF,
+                &[("NIGHTLY", false), ("BETA", false), ("RELEASE", false)],
+                &["dev.svg"],
+            ),
+            (
+                ELIF,
+                &[("NIGHTLY", false), ("BETA", false), ("RELEASE", true)],
+                &["release.svg"],
+            ),
+            (
+                NESTED,
+                &[("OUTER", true), ("INNER", true)],
+                &["always.js", "inner.js", "outer.js"],
+            ),
+            (
+                NESTED,
+                &[("OUTER", true), ("INNER", false)],
+                &["always.js", "not-inner.js", "outer.js"],
+            ),
+            (
+                NESTED,
+                &[("OUTER", false), ("INNER", true)],
+                &["always.js", "only-inner.js"],
+            ),
+            (
+                NESTED,
+                &[("OUTER", false), ("INNER", false)],
+                &["always.js", "neither.js"],
+            ),
+            // Conditions in excluded blocks are not evaluated, so they can be unknown
+            (
+                "#ifdef OFF\n#ifdef UNKNOWN\n  content/global/a.js (a.js)\n#else\n  content/global/b.js (b.js)\n#endif\n#endif\n",
+                &[("OFF", false)],
+                &[],
+            ),
+        ];
+        for (lines, config, expected) in cases {
+            assert_eq!(
+                included_files(lines, config),
+                *expected,
+                "{:?} with {}",
+                config,
+                lines
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_jar_file_unmatched_conditionals() {
+        for lines in ["#else\n", "#elifdef A\n", "#ifdef A\n#endif\n#endif\n"] {
+            let result = parse_jar_file(
+                lines,
+                "toolkit/jar.mn",
+                Path::new("firefox"),
+                &mut ParsedJar::default(),
+                &HashMap::from([("A".to_string(), true)]),
+                IfdefUnknown::Error,
+            );
+            assert!(
+                matches!(
+                    result,
+                    Err(JarResolverError::UnmatchedElse(_) | JarResolverError::UnmatchedEndif)
+                ),
+                "{}",
+                lines
+            );
+        }
+    }
+
+    #[test]
+    fn test_evaluate_if_expressions() {
+        let mut defines = Defines::new(
+            &HashMap::from([
+                ("A".to_string(), true),
+                ("B".to_string(), false),
+                ("C".to_string(), true),
+            ]),
+            IfdefUnknown::Error,
+        );
+        defines.define("CHANNEL", Some("nightly"));
+        defines.define("ZERO", Some("0"));
+        let cases = [
+            ("A", true),
+            ("B", false),
+            ("ZERO", false),
+            ("1", true),
+            ("0", false),
+            ("defined(A)", true),
+            ("defined(ZERO)", true),
+            ("!defined(B)", true),
+            ("!A", false),
+            ("!!A", true),
+            // && binds tighter than ||
+            ("A || B && B", true),
+            ("B && B || A", true),
+            ("(A || B) && B", false),
+            ("!B && A", true),
+            ("!(A && B)", true),
+            ("A && C && !B", true),
+            // Comparisons bind tighter than && and ||
+            ("CHANNEL == nightly", true),
+            ("CHANNEL != nightly", false),
+            ("CHANNEL == beta || A", true),
+            ("B && CHANNEL == nightly", false),
+            ("A == 1", true),
+            ("B == 1", false),
+            ("(CHANNEL == nightly) == 1", true),
+        ];
+        for (expression, expected) in cases {
+            assert_eq!(
+                defines.evaluate(expression).unwrap(),
+                expected,
+                "{}",
+                expression
+            );
+        }
+    }
+
+    #[test]
+    fn test_evaluate_malformed_if_expressions() {
+        let mut defines = Defines::new(
+            &HashMap::from([("A".to_string(), true)]),
+            IfdefUnknown::Error,
+        );
+        for expression in [
+            "",
+            "A &&",
+            "|| A",
+            "(A",
+            "A)",
+            "A B",
+            "defined A",
+            "defined(A",
+            "defined()",
+            "A & A",
+            "A = A",
+            "A == ",
+            "!",
+            "A # B",
+        ] {
+            assert!(
+                matches!(
+                    defines.evaluate(expression),
+                    Err(JarResolverError::InvalidExpression(_))
+                ),
+                "{}",
+                expression
+            );
+        }
+    }
+
+    #[test]
+    fn test_evaluate_unknown_symbols() {
+        let mut strict = Defines::new(&HashMap::new(), IfdefUnknown::Error);
+        assert!(matches!(
+            strict.evaluate("defined(UNKNOWN)"),
+            Err(JarResolverError::UnknownIfdefCondition(name)) if name == "UNKNOWN"
+        ));
+        assert!(matches!(
+            strict.evaluate("!UNKNOWN"),
+            Err(JarResolverError::UnknownIfdefCondition(_))
+        ));
+        // Words that aren't variables are literals in comparisons
+        assert!(strict.evaluate("nightly == nightly").unwrap());
+
+        let mut undefined = Defines::new(&HashMap::new(), IfdefUnknown::False);
+        assert!(!undefined.evaluate("defined(UNKNOWN)").unwrap());
+        assert!(undefined.evaluate("!UNKNOWN").unwrap());
+
+        let mut defined = Defines::new(&HashMap::new(), IfdefUnknown::True);
+        assert!(defined.evaluate("defined(UNKNOWN)").unwrap());
+        assert!(!defined.evaluate("!UNKNOWN").unwrap());
+        assert_eq!(
+            defined.unknown_conditions,
+            BTreeSet::from(["UNKNOWN".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_parse_jar_file_collects_unknown_conditions() {
+        let content = "\
+toolkit.jar:
+% content global %content/global/
+#ifdef NIGHTLY_BUILD
+  content/global/nightly.js (nightly.js)
+#endif
+#if defined(EARLY_BETA) || NIGHTLY_BUILD
+  content/global/beta.js (beta.js)
+#endif
+#ifndef XP_WIN
+  content/global/unix.js (unix.js)
+#endif
+";
+        for (policy, expected) in [
+            (IfdefUnknown::False, vec!["unix.js"]),
+            (IfdefUnknown::True, vec!["beta.js", "nightly.js"]),
+        ] {
+            let mut parsed = ParsedJar::default();
+            parse_jar_file(
+                content,
+                "toolkit/jar.mn",
+                Path::new("firefox"),
+                &mut parsed,
+                &HashMap::new(),
+                policy,
+            )
+            .unwrap();
+            let mut files: Vec<&str> = parsed
+                .mappings
+                .keys()
+                .map(|url| url.trim_start_matches("chrome://global/content/"))
+                .collect();
+            files.sort();
+            assert_eq!(files, expected);
+            assert_eq!(
+                parsed.unknown_conditions.into_iter().collect::<Vec<_>>(),
+                ["EARLY_BETA", "NIGHTLY_BUILD", "XP_WIN"]
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_jar_file_if_and_elif() {
+        let lines = "\
+#if A && !defined(B)
+  content/global/a.js (a.js)
+#elif C
+  content/global/c.js (c.js)
+#else
+  content/global/d.js (d.js)
+#endif
+#define CHANNEL beta
+#if CHANNEL == beta
+  content/global/beta.js (beta.js)
+#endif
+";
+        assert_eq!(
+            included_files(lines, &[("A", true), ("B", false), ("C", true)]),
+            ["a.js", "beta.js"]
+        );
+        assert_eq!(
+            included_files(lines, &[("A", true), ("B", true), ("C", true)]),
+            ["beta.js", "c.js"]
+        );
+        assert_eq!(
+            included_files(lines, &[("A", false), ("B", false), ("C", false)]),
+            ["beta.js", "d.js"]
+        );
+    }
+
+    #[test]
+    fn test_parse_jar_file_preprocessed_entries() {
+        let content = "\
+toolkit.jar:
+% skin global classic/1.0 %skin/classic/global/
+* skin/classic/global/global.css (global.css)
+*  skin/classic/global/popup.css (../shared/popup.css)
+  skin/classic/global/plain.css (plain.css)
+";
+        let mut parsed = ParsedJar::default();
+        parse_jar_file(
+            content,
+            "toolkit/themes/jar.mn",
+            Path::new("firefox"),
+            &mut parsed,
+            &HashMap::new(),
+            IfdefUnknown::Error,
+        )
+        .unwrap();
+
+        assert_eq!(
+            parsed.mappings.get("chrome://global/skin/global.css"),
+            Some(&PathBuf::from("firefox/toolkit/themes/global.css"))
+        );
+        assert_eq!(
+            parsed.mappings.get("chrome://global/skin/popup.css"),
+            Some(&PathBuf::from("firefox/toolkit/shared/popup.css"))
+        );
+        assert_eq!(
+            parsed.preprocessed,
+            HashSet::from([
+                PathBuf::from("firefox/toolkit/themes/global.css"),
+                PathBuf::from("firefox/toolkit/shared/popup.css"),
+            ])
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_parse_jar_file_windows_separators() {
+        let root = tempfile::tempdir().unwrap();
+        for path in ["toolkit/icons/a.svg", "toolkit/icons/nested/b.svg"] {
+            let path = root.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        let content = "\
+toolkit.jar:
+% content global %content/global/
+  content/global/icons/** (icons/**)
+";
+        let mut parsed = ParsedJar::default();
+        parse_jar_file(
+            content,
+            r"toolkit\jar.mn",
+            root.path(),
+            &mut parsed,
+            &HashMap::new(),
+            IfdefUnknown::Error,
+        )
+        .unwrap();
+
+        assert!(
+            parsed.mappings["chrome://global/content/icons/nested/b.svg"]
+                .ends_with(r"toolkit\icons\nested\b.svg")
+        );
+        assert!(
+            parsed
+                .mappings
+                .contains_key("chrome://global/content/icons/a.svg")
+        );
+    }
+
+    #[test]
+    fn test_parse_jar_file_expands_wildcards() {
+        let root = tempfile::tempdir().unwrap();
+        for path in [
+            "toolkit/icons/a.svg",
+            "toolkit/icons/b.svg",
+            "toolkit/icons/nested/c.svg",
+            "toolkit/prefs/main.js",
+            "toolkit/prefs/panes/general.js",
+            "toolkit/prefs/panes/deep/privacy.js",
+        ] {
+            let path = root.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        let content = "\
+toolkit.jar:
+% content global %content/global/
+  content/global/icons/*.svg (icons/*.svg)
+  content/global/prefs/** (prefs/**)
+";
+        let mut parsed = ParsedJar::default();
+        parse_jar_file(
+            content,
+            "toolkit/jar.mn",
+            root.path(),
+            &mut parsed,
+            &HashMap::new(),
+            IfdefUnknown::Error,
+        )
+        .unwrap();
+
+        let mut urls: Vec<&String> = parsed.mappings.keys().collect();
+        urls.sort();
+        assert_eq!(
+            urls,
+            vec![
+                "chrome://global/content/icons/a.svg",
+                "chrome://global/content/icons/b.svg",
+                "chrome://global/content/prefs/main.js",
+                "chrome://global/content/prefs/panes/deep/privacy.js",
+                "chrome://global/content/prefs/panes/general.js",
+            ]
+        );
+        assert!(
+            parsed.mappings["chrome://global/content/prefs/panes/general.js"]
+                .ends_with("toolkit/prefs/panes/general.js")
+        );
+    }
+
+    #[test]
+    fn test_parse_jar_file_expands_same_directory_wildcards() {
+        let root = tempfile::tempdir().unwrap();
+        for path in ["toolkit/a.css", "toolkit/b.css", "toolkit/c.js"] {
+            let path = root.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        let content = "\
+toolkit.jar:
+% skin global classic/1.0 %skin/classic/global/
+  skin/classic/global/*.css
+";
+        let mut parsed = ParsedJar::default();
+        parse_jar_file(
+            content,
+            "toolkit/jar.mn",
+            root.path(),
+            &mut parsed,
+            &HashMap::new(),
+            IfdefUnknown::Error,
+        )
+        .unwrap();
+
+        let mut urls: Vec<&String> = parsed.mappings.keys().collect();
+        urls.sort();
+        assert_eq!(
+            urls,
+            vec!["chrome://global/skin/a.css", "chrome://global/skin/b.css",]
+        );
+    }
+
+    #[test]
+    fn test_conflicting_mappings_are_recorded() {
+        let root = tempfile::tempdir().unwrap();
+        for (jar_path, content) in [
+            (
+                "toolkit/themes/shared/jar.mn",
+                "\
+toolkit.jar:
+% skin global classic/1.0 %skin/classic/global/
+  skin/classic/global/icons/close.svg (icons/close.svg)
+  skin/classic/global/icons/open.svg (icons/open.svg)
+",
+            ),
+            (
+                "browser/themes/shared/jar.mn",
+                "\
+toolkit.jar:
+% skin global classic/1.0 %skin/classic/global/
+  skin/classic/global/icons/close.svg (icons/close-16.svg)
+  skin/classic/global/icons/open.svg (/toolkit/themes/shared/icons/open.svg)
+",
+            ),
+        ] {
+            let path = root.path().join(jar_path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+
+        let resolver = JarResolver::new(
+            root.path(),
+            JarResolverOptions {
+                jar_paths: &[
+                    "toolkit/themes/shared/jar.mn",
+                    "browser/themes/shared/jar.mn",
+                ],
+                ifdef_unknown: IfdefUnknown::Error,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        // The same file mapped twice is not a conflict
+        let conflicts = resolver.mapping_conflicts();
+        assert_eq!(conflicts.len(), 1);
+        let conflict = &conflicts[0];
+        assert_eq!(conflict.url, "chrome://global/skin/icons/close.svg");
+        assert!(
+            conflict
+                .first_path
+                .ends_with("toolkit/themes/shared/icons/close.svg")
+        );
+        assert_eq!(conflict.first_origin, "toolkit/themes/shared/jar.mn");
+        assert!(
+            conflict
+                .second_path
+                .ends_with("browser/themes/shared/icons/close-16.svg")
+        );
+        assert_eq!(conflict.second_origin, "browser/themes/shared/jar.mn");
+        assert_eq!(
+            resolver
+                .resolve_path("chrome://global/skin/icons/close.svg")
+                .unwrap(),
+            conflict.second_path
+        );
+    }
+
+    #[test]
+    fn test_resolve_prefix_and_mapping_origins() {
+        let root = tempfile::tempdir().unwrap();
+        for (path, content) in [
+            (
+                "toolkit/themes/shared/jar.mn",
+                "\
+toolkit.jar:
+% skin global classic/1.0 %skin/classic/global/
+  skin/classic/global/icons/close.svg (icons/close.svg)
+  skin/classic/global/icons/open.svg (icons/open.svg)
+  skin/classic/global/global.css (global.css)
+",
+            ),
+            (
+                "toolkit/modules/moz.build",
+                "EXTRA_JS_MODULES += [\"Foo.sys.mjs\"]\n",
+            ),
+        ] {
+            let path = root.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+
+        let resolver = JarResolver::new(
+            root.path(),
+            JarResolverOptions {
+                jar_paths: &["toolkit/themes/shared/jar.mn"],
+                mozbuild_paths: &["toolkit/modules/moz.build"],
+                ifdef_unknown: IfdefUnknown::Error,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let icons: Vec<String> = resolver
+            .resolve_prefix("chrome://global/skin/icons/")
+            .into_iter()
+            .map(|(url, path)| {
+                assert!(
+                    path.parent()
+                        .unwrap()
+                        .ends_with("toolkit/themes/shared/icons")
+                );
+                url
+            })
+            .collect();
+        assert_eq!(
+            icons,
+            [
+                "chrome://global/skin/icons/close.svg",
+                "chrome://global/skin/icons/open.svg"
+            ]
+        );
+        assert!(resolver.resolve_prefix("chrome://browser/").is_empty());
+
+        assert_eq!(
+            resolver.mapping_origin("chrome://global/skin/global.css"),
+            Some("toolkit/themes/shared/jar.mn")
+        );
+        assert_eq!(
+            resolver.mapping_origin("resource://gre/modules/Foo.sys.mjs"),
+            Some("toolkit/modules/moz.build")
+        );
+        assert_eq!(resolver.mapping_origin("chrome://global/skin/x.css"), None);
+    }
+
+    #[test]
+    fn test_parsed_jar_files_are_cached() {
+        let root = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let write = |path: &str, content: &str| {
+            let path = root.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        };
+        write(
+            "toolkit/jar.mn",
+            "\
+toolkit.jar:
+% content global %content/global/
+#include shared.inc.mn
+#ifdef NIGHTLY_BUILD
+  content/global/nightly.js (nightly.js)
+#endif
+",
+        );
+        write("toolkit/shared.inc.mn", "  content/global/a.js (a.js)\n");
+
+        let urls = |nightly: bool| {
+            let resolver = JarResolver::new(
+                root.path(),
+                JarResolverOptions {
+                    jar_paths: &["toolkit/jar.mn"],
+                    ifdef_config: Some(HashMap::from([("NIGHTLY_BUILD".to_string(), nightly)])),
+                    ifdef_unknown: IfdefUnknown::Error,
+                    cache_dir: Some(cache_dir.path()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            let mut urls: Vec<String> = resolver
+                .mappings()
+                .map(|(url, _)| url.to_string())
+                .collect();
+            urls.sort();
+            urls
+        };
+
+        assert_eq!(urls(false), ["chrome://global/content/a.js"]);
+        assert!(cache_dir.path().join("jar-mappings.json").is_file());
+        assert_eq!(urls(false), ["chrome://global/content/a.js"]);
+        assert_eq!(
+            urls(true),
+            [
+                "chrome://global/content/a.js",
+                "chrome://global/content/nightly.js"
+            ]
+        );
+
+        write("toolkit/shared.inc.mn", "  content/global/b.js (b.js)\n");
+        assert_eq!(
+            urls(true),
+            [
+                "chrome://global/content/b.js",
+                "chrome://global/content/nightly.js"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cached_wildcards_see_added_and_removed_files() {
+        let root = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let write = |path: &str, content: &str| {
+            let path = root.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        };
+        write(
+            "toolkit/jar.mn",
+            "\
+toolkit.jar:
+% content global %content/global/
+  content/global/icons/** (icons/**)
+",
+        );
+        write("toolkit/icons/a.svg", "");
+
+        let urls = || {
+            let resolver = JarResolver::new(
+                root.path(),
+                JarResolverOptions {
+                    jar_paths: &["toolkit/jar.mn"],
+                    ifdef_unknown: IfdefUnknown::Error,
+                    cache_dir: Some(cache_dir.path()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            let mut urls: Vec<String> = resolver
+                .mappings()
+                .map(|(url, _)| url.to_string())
+                .collect();
+            urls.sort();
+            urls
+        };
+
+        assert_eq!(urls(), ["chrome://global/content/icons/a.svg"]);
+        // A file in a new subdirectory doesn't change the jar.mn file
+        write("toolkit/icons/nested/b.svg", "");
+        assert_eq!(
+            urls(),
+            [
+                "chrome://global/content/icons/a.svg",
+                "chrome://global/content/icons/nested/b.svg"
+            ]
+        );
+        fs::remove_file(root.path().join("toolkit/icons/a.svg")).unwrap();
+        assert_eq!(urls(), ["chrome://global/content/icons/nested/b.svg"]);
+    }
+
+    #[test]
+    fn test_locale_sections() {
+        let root = tempfile::tempdir().unwrap();
+        let write = |path: &str| {
+            let path = root.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        };
+        for path in [
+            "toolkit/locales/en-US/toolkit/global/mozButton.ftl",
+            "toolkit/locales/en-US/toolkit/about/aboutAddons.ftl",
+            "toolkit/locales/en-US/crashreporter/crashreporter.ftl",
+        ] {
+            write(path);
+        }
+        // From toolkit/locales/jar.mn
+        fs::write(
+            root.path().join("toolkit/locales/jar.mn"),
+            "\
+[localization] @AB_CD@.jar:
+  crashreporter                                    (%crashreporter/**/*.ftl)
+  toolkit                                          (%toolkit/**/*.ftl)
+
+@AB_CD@.jar:
+% locale global @AB_CD@ %locale/@AB_CD@/global/
+% locale alerts @AB_CD@ %locale/@AB_CD@/alerts/
+% locale global-platform @AB_CD@ %locale/@AB_CD@/global-platform/unix/ os=LikeUnix os=Android
+% locale mozapps @AB_CD@ %locale/@AB_CD@/mozapps/
+  locale/@AB_CD@/global/aboutReader.properties          (%chrome/global/aboutReader.properties)
++ locale/@AB_CD@/global/commonDialogs.properties       (%chrome/global/commonDialogs.properties)
+  locale/@AB_CD@/global-platform/unix/platformKeys.properties  (%chrome/global-platform/unix/platformKeys.properties)
+  locale/@AB_CD@/alerts/alert.properties                (%chrome/alerts/alert.properties)
+* locale/@AB_CD@/mozapps/downloads/downloads.properties (%chrome/mozapps/downloads/downloads.properties)
+",
+        )
+        .unwrap();
+
+        let resolver = JarResolver::new(
+            root.path(),
+            JarResolverOptions {
+                jar_paths: &["toolkit/locales/jar.mn"],
+                ifdef_unknown: IfdefUnknown::Error,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        for (url, source) in [
+            (
+                "chrome://global/locale/aboutReader.properties",
+                "chrome/global/aboutReader.properties",
+            ),
+            (
+                "chrome://global/locale/commonDialogs.properties",
+                "chrome/global/commonDialogs.properties",
+            ),
+            (
+                "chrome://global-platform/locale/platformKeys.properties",
+                "chrome/global-platform/unix/platformKeys.properties",
+            ),
+            (
+                "chrome://alerts/locale/alert.properties",
+                "chrome/alerts/alert.properties",
+            ),
+            (
+                "chrome://mozapps/locale/downloads/downloads.properties",
+                "chrome/mozapps/downloads/downloads.properties",
+            ),
+            (
+                "resource://gre/localization/en-US/toolkit/global/mozButton.ftl",
+                "toolkit/global/mozButton.ftl",
+            ),
+            (
+                "resource://gre/localization/en-US/toolkit/about/aboutAddons.ftl",
+                "toolkit/about/aboutAddons.ftl",
+            ),
+            (
+                "resource://gre/localization/en-US/crashreporter/crashreporter.ftl",
+                "crashreporter/crashreporter.ftl",
+            ),
+        ] {
+            let path = resolver.resolve_path(url).unwrap();
+            assert!(
+                path.ends_with(Path::new("toolkit/locales/en-US").join(source)),
+                "{}: {}",
+                url,
+                path.display()
+            );
+        }
+        // global doesn't swallow global-platform
+        assert!(
+            resolver
+                .resolve_path("chrome://global/locale/-platform/unix/platformKeys.properties")
+                .is_err()
+        );
+        assert_eq!(resolver.mappings().count(), 8);
+    }
+
+    #[test]
+    fn test_chrome_manifest_registrations_and_overrides() {
+        let root = tempfile::tempdir().unwrap();
+        for path in [
+            "toolkit/modules/Foo.sys.mjs",
+            "toolkit/content/widgets/a.mjs",
+            "toolkit/content/widgets/b.mjs",
+        ] {
+            let path = root.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        fs::write(
+            root.path().join("toolkit/chrome.manifest"),
+            "\
+# comment
+resource gre ./
+resource gre-modules resource://gre/modules
+content global content/
+skin global classic/1.0 jar:chrome/classic.jar!/skin/
+override chrome://global/content/old.mjs chrome://global/content/widgets/b.mjs
+override chrome://global/content/loop.mjs chrome://global/content/loop.mjs
+",
+        )
+        .unwrap();
+
+        let resolver = JarResolver::new(
+            root.path(),
+            JarResolverOptions {
+                manifest_paths: &["toolkit/chrome.manifest".to_string()],
+                ifdef_unknown: IfdefUnknown::Error,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let resolve = |url: &str| {
+            resolver
+                .resolve_path(url)
+                .map(|p| p.canonicalize().unwrap())
+        };
+        let expected = |path: &str| root.path().join(path).canonicalize().unwrap();
+        assert_eq!(
+            resolve("resource://gre/modules/Foo.sys.mjs").unwrap(),
+            expected("toolkit/modules/Foo.sys.mjs")
+        );
+        assert_eq!(
+            resolve("resource://gre-modules/Foo.sys.mjs").unwrap(),
+            expected("toolkit/modules/Foo.sys.mjs")
+        );
+        assert_eq!(
+            resolve("chrome://global/content/widgets/a.mjs").unwrap(),
+            expected("toolkit/content/widgets/a.mjs")
+        );
+        assert_eq!(
+            resolve("chrome://global/content/old.mjs").unwrap(),
+            expected("toolkit/content/widgets/b.mjs")
+        );
+        assert!(matches!(
+            resolver.resolve_path("chrome://global/skin/a.css"),
+            Err(JarResolverError::NoMappingFound(_))
+        ));
+        assert!(matches!(
+            resolver.resolve_path("chrome://global/content/loop.mjs"),
+            Err(JarResolverError::NoMappingFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_jar_file_reports_registration_conflicts() {
+        let (_, conflicts) = parse_two_sections();
+        assert_eq!(
+            conflicts,
+            vec![RegistrationConflict {
+                key: "content:foo".to_string(),
+                first_path: "%content/shared/".to_string(),
+                first_origin: "toolkit/jar.mn (first.jar)".to_string(),
+                second_path: "%content/other/".to_string(),
+                second_origin: "toolkit/jar.mn (second.jar)".to_string(),
+            }]
+        );
+    }
 }
@@ -0,0 +1,111 @@
+use glob::Pattern;
+
+/// Extensions of the files a chrome:// or resource:// string literal can
+/// load that are worth copying.
+const HANDLED_EXTENSIONS: &[&str] = &[
+    "mjs", "js", "css", "svg", "png", "jpg", "jpeg", "gif", "webp", "avif", "ico", "woff", "woff2",
+    "ttf", "otf", "json", "ftl",
+];
+
+/// Extensions of the fixture files stories refer to by relative paths, like
+/// icons and stylesheets.
+const ASSET_EXTENSIONS: &[&str] = &[
+    "css", "svg", "png", "jpg", "jpeg", "gif", "webp", "avif", "ico", "woff", "woff2", "ttf",
+    "otf", "ftl",
+];
+
+/// Whether a string literal is a relative path to an asset, like
+/// `./fixtures/avatar.png`.
+pub fn is_relative_asset(literal: &str) -> bool {
+    if !literal.starts_with("./") && !literal.starts_with("../") {
+        return false;
+    }
+    let path = literal.split(['?', '#']).next().unwrap_or(literal);
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    file_name.rsplit_once('.').is_some_and(|(_, extension)| {
+        ASSET_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+    })
+}
+
+/// Decides which chrome:// and resource:// string literals count as
+/// dependencies. Unlike imports, a literal may just mention a URL, e.g. in a
+/// telemetry label or a comparison with `document.documentURI`.
+#[derive(Debug, Clone, Default)]
+pub struct LiteralFilter {
+    ignore: Vec<Pattern>,
+}
+
+impl LiteralFilter {
+    /// Create a filter that also ignores literals matching the given glob
+    /// patterns, e.g. `chrome://browser/content/*.xhtml`.
+    pub fn new(ignore: &[String]) -> Result<Self, glob::PatternError> {
+        let ignore = ignore
+            .iter()
+            .map(|pattern| Pattern::new(pattern))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { ignore })
+    }
+
+    /// Why a literal isn't a dependency, or `None` if it is one.
+    pub fn skip_reason(&self, literal: &str) -> Option<&'static str> {
+        if !literal.starts_with("chrome://") && !literal.starts_with("resource://") {
+            return Some("not a chrome:// or resource:// URL");
+        }
+        if self.ignore.iter().any(|p| p.matches(literal)) {
+            return Some("matches ignore_literals");
+        }
+        let path = literal.split(['?', '#']).next().unwrap_or(literal);
+        let file_name = path.rsplit('/').next().unwrap_or(path);
+        match file_name.rsplit_once('.') {
+            Some((_, extension))
+                if HANDLED_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()) =>
+            {
+                None
+            }
+            _ => Some("not a file type mozcomp handles"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skip_reason() {
+        let filter =
+            LiteralFilter::new(&["chrome://global/skin/icons/ignored-*".to_string()]).unwrap();
+        assert_eq!(
+            filter.skip_reason("chrome://global/skin/icons/close.svg"),
+            None
+        );
+        assert_eq!(filter.skip_reason("resource://gre/modules/A.sys.mjs"), None);
+        assert_eq!(
+            filter.skip_reason("chrome://global/skin/icons/a.SVG#flip"),
+            None
+        );
+        assert!(
+            filter
+                .skip_reason("chrome://browser/content/browser.xhtml")
+                .is_some()
+        );
+        assert!(filter.skip_reason("chrome://global/content/").is_some());
+        assert!(
+            filter
+                .skip_reason("chrome://global/skin/icons/ignored-a.svg")
+                .is_some()
+        );
+        assert!(filter.skip_reason("./close.svg").is_some());
+    }
+
+    #[test]
+    fn test_is_relative_asset() {
+        assert!(is_relative_asset("./fixtures/avatar.png"));
+        assert!(is_relative_asset("../shared/a.SVG#flip"));
+        assert!(is_relative_asset("./moz-a.css"));
+        assert!(!is_relative_asset("./helper.mjs"));
+        assert!(!is_relative_asset("fixtures/avatar.png"));
+        assert!(!is_relative_asset("chrome://global/skin/icons/edit.svg"));
+        assert!(!is_relative_asset("./fixtures/"));
+    }
+}
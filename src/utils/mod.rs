@@ -1,3 +1,9 @@
+pub mod compression;
+pub mod external;
 pub mod file_utils;
+pub mod jar_cache;
+pub mod jar_discovery;
 pub mod jar_resolver;
+pub mod literals;
 pub mod path_finder;
+pub mod preprocessor;
@@ -1,7 +1,50 @@
+use crate::resolver::Resolver;
 use crate::utils::jar_resolver::JarResolver;
-use std::path::PathBuf;
+use serde::Deserialize;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
 use thiserror::Error;
 
+/// How many aliases an import is followed through before giving up
+const MAX_ALIAS_DEPTH: usize = 16;
+
+/// Directories, relative to the Firefox root, that files of unmapped chrome
+/// URLs are conventionally found in, by URL prefix
+const FALLBACK_LOCATIONS: &[(&str, &[&str])] = &[
+    (
+        "chrome://global/content/",
+        &["toolkit/content", "toolkit/content/widgets"],
+    ),
+    (
+        "chrome://global/skin/",
+        &[
+            "toolkit/themes/shared",
+            "toolkit/themes/linux/global",
+            "toolkit/themes/osx/global",
+            "toolkit/themes/windows/global",
+        ],
+    ),
+    ("chrome://browser/content/", &["browser/base/content"]),
+    ("chrome://browser/skin/", &["browser/themes/shared"]),
+];
+
+/// What a run does with imports that can't be resolved to a file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingFiles {
+    /// Fail the run, listing the unresolved imports
+    #[default]
+    Error,
+    /// Leave the import untouched in the output and list it in the report
+    Warn,
+    /// Write an empty placeholder file for the import and list it in the
+    /// report
+    Stub,
+}
+
 #[derive(Debug, Error)]
 pub enum PathFinderError {
     #[error("Chrome mapping not found for URL: {0}")]
@@ -14,19 +57,293 @@ pub enum PathFinderError {
     UnsupportedImportFormat(String),
     #[error("File does not exist: {0}")]
     FileNotFound(PathBuf),
+    #[error(
+        "Chrome mapping not found for URL: {url}, and the fallback search found several candidates: {}",
+        .candidates.iter().map(|c| c.display().to_string()).collect::<Vec<_>>().join(", ")
+    )]
+    AmbiguousFallback {
+        url: String,
+        candidates: Vec<PathBuf>,
+    },
+    #[error(
+        "Could not resolve bare specifier '{specifier}' against the module roots: {}",
+        .roots.join(", ")
+    )]
+    UnresolvedBareSpecifier {
+        specifier: String,
+        roots: Vec<String>,
+    },
+    #[error("'{specifier}' imported by {from} only matches {corrected} when ignoring case")]
+    CaseMismatch {
+        specifier: String,
+        from: PathBuf,
+        corrected: PathBuf,
+    },
+    #[error(
+        "'{specifier}' matches several files, add an alias to pick one: {}",
+        .candidates.iter().map(|c| c.display().to_string()).collect::<Vec<_>>().join(", ")
+    )]
+    Ambiguous {
+        specifier: String,
+        candidates: Vec<PathBuf>,
+    },
+    #[error("Could not resolve '{specifier}': {reason}")]
+    CustomResolverFailed { specifier: String, reason: String },
+    #[error("Aliases don't resolve: {0}")]
+    AliasCycle(String),
+}
+
+/// The steps taken to resolve an import and where they led, see
+/// `PathFinder::explain`.
+#[derive(Debug, Clone)]
+pub struct ResolutionTrace {
+    /// The file containing the import
+    pub importer: PathBuf,
+    /// The import specifier as written
+    pub specifier: String,
+    /// Each step that was attempted, in order
+    pub steps: Vec<String>,
+    /// The resolved path, relative to the current directory, or why
+    /// resolution failed
+    pub outcome: Result<PathBuf, String>,
+}
+
+impl std::fmt::Display for ResolutionTrace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Resolving '{}' imported by {}",
+            self.specifier,
+            self.importer.display()
+        )?;
+        for (i, step) in self.steps.iter().enumerate() {
+            writeln!(f, "  {}. {}", i + 1, step)?;
+        }
+        match &self.outcome {
+            Ok(path) => writeln!(f, "Resolved to {}", path.display()),
+            Err(error) => writeln!(f, "Failed: {}", error),
+        }
+    }
+}
+
+/// Where the aliases of an import lead.
+enum Aliased {
+    /// Another specifier, resolved like an import
+    Specifier(String),
+    /// A file system path
+    Path(PathBuf),
 }
 
 pub struct PathFinder {
     jar_resolver: JarResolver,
+    /// Firefox source root, which specifiers with a leading slash are
+    /// relative to
+    firefox_root: PathBuf,
+    /// The Firefox root with symlinks resolved
+    canonical_root: PathBuf,
+    /// Resolve relative paths through symlinks instead of lexically
+    follow_symlinks: bool,
+    /// Search for files of unmapped chrome URLs in conventional locations
+    fallback_search: bool,
+    /// URLs that were resolved by the fallback search and warned about
+    fallback_warned: RefCell<HashSet<String>>,
+    /// Prefixes of bare specifiers and the directories they resolve to,
+    /// longest prefix first
+    module_roots: Vec<(String, PathBuf)>,
+    /// Extensions, with their leading dot, tried when an import doesn't
+    /// match a file on disk
+    extension_fallbacks: Vec<String>,
+    /// Fail instead of warning when a file only matches an import when
+    /// ignoring case
+    strict_case: bool,
+    /// Specifiers, or prefixes ending with `/`, that are replaced with a path
+    /// or another specifier before resolution, longest first
+    aliases: Vec<(String, String)>,
+    /// Aliases that imports were resolved through
+    aliases_hit: RefCell<BTreeSet<String>>,
+    /// Custom resolvers consulted before the built-in rules, in order
+    resolvers: Vec<Arc<dyn Resolver>>,
+    /// Steps of the resolution in progress, collected while explaining one
+    trace: RefCell<Option<Vec<String>>>,
+    /// Explain imports that fail to resolve in their error
+    trace_failures: bool,
+    /// Paths that imports resolved to, by importing directory and specifier.
+    /// Paths are relative to the current directory at the time they were
+    /// resolved.
+    cache: RefCell<HashMap<(PathBuf, String), PathBuf>>,
+    /// Number of lookups answered from and missing in `cache`
+    cache_hits: Cell<usize>,
+    cache_misses: Cell<usize>,
 }
 
 impl PathFinder {
-    /// Create a new PathFinder with a JarResolver
-    pub fn new(jar_resolver: JarResolver) -> Self {
-        Self { jar_resolver }
+    /// Create a new PathFinder with a JarResolver for the Firefox source tree
+    /// at `firefox_root`
+    pub fn new(jar_resolver: JarResolver, firefox_root: &Path) -> Self {
+        Self {
+            jar_resolver,
+            firefox_root: firefox_root.to_path_buf(),
+            canonical_root: firefox_root
+                .canonicalize()
+                .unwrap_or_else(|_| firefox_root.to_path_buf()),
+            follow_symlinks: true,
+            fallback_search: false,
+            fallback_warned: RefCell::new(HashSet::new()),
+            module_roots: Vec::new(),
+            extension_fallbacks: Vec::new(),
+            strict_case: false,
+            aliases: Vec::new(),
+            aliases_hit: RefCell::new(BTreeSet::new()),
+            resolvers: Vec::new(),
+            trace: RefCell::new(None),
+            trace_failures: false,
+            cache: RefCell::new(HashMap::new()),
+            cache_hits: Cell::new(0),
+            cache_misses: Cell::new(0),
+        }
+    }
+
+    /// Try `extensions` (e.g. `.mjs`) in place of the extension of imports
+    /// whose file doesn't exist, after the missing extension of imports
+    /// without one, and `index` files with them for imports of directories
+    pub fn with_extension_fallbacks(mut self, extensions: &[String]) -> Self {
+        self.extension_fallbacks = extensions.to_vec();
+        self
+    }
+
+    /// Resolve bare specifiers like `toolkit-widgets/moz-support-link.mjs` by
+    /// their first path segments, using `module_roots` that map a prefix to a
+    /// directory relative to the Firefox root
+    pub fn with_module_roots(mut self, module_roots: &HashMap<String, String>) -> Self {
+        self.module_roots = module_roots
+            .iter()
+            .map(|(prefix, dir)| {
+                (
+                    prefix.trim_end_matches('/').to_string(),
+                    self.firefox_root.join(dir),
+                )
+            })
+            .collect();
+        self.module_roots
+            .sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        self
+    }
+
+    /// Consult `resolvers` in order before the built-in handling of internal
+    /// URLs and relative paths
+    pub fn with_resolvers(mut self, resolvers: &[Arc<dyn Resolver>]) -> Self {
+        self.resolvers = resolvers.to_vec();
+        self
+    }
+
+    /// Replace specifiers before they are resolved. Keys ending with `/` are
+    /// prefixes, like in import maps. Targets containing `://` are specifiers
+    /// that are resolved in turn, others are file system paths.
+    pub fn with_aliases(mut self, aliases: &HashMap<String, String>) -> Self {
+        self.aliases = aliases
+            .iter()
+            .map(|(key, target)| (key.clone(), target.clone()))
+            .collect();
+        self.aliases
+            .sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        self
+    }
+
+    /// The aliases that imports were resolved through, sorted
+    pub fn aliases_hit(&self) -> Vec<String> {
+        self.aliases_hit.borrow().iter().cloned().collect()
+    }
+
+    /// Resolve relative paths through symlinks (the default), warning when
+    /// that leaves the Firefox root, or lexically, keeping the symlinked path
+    /// as the file's identity
+    pub fn with_follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.follow_symlinks = follow_symlinks;
+        self
     }
 
-    /// Resolve an import string to a PathBuf relative to the current working directory
+    /// Fail with `PathFinderError::CaseMismatch` when an import only matches a
+    /// file when ignoring case, instead of warning and using the file
+    pub fn with_strict_case(mut self, strict_case: bool) -> Self {
+        self.strict_case = strict_case;
+        self
+    }
+
+    /// Search the conventional source locations below the Firefox root for
+    /// chrome URLs that no jar.mn file maps
+    pub fn with_fallback_search(mut self) -> Self {
+        self.fallback_search = true;
+        self
+    }
+
+    /// Let `resolve` explain imports that fail to resolve with a
+    /// `ResolutionTrace` in the error message
+    pub fn with_failure_traces(mut self, trace_failures: bool) -> Self {
+        self.trace_failures = trace_failures;
+        self
+    }
+
+    /// Resolve an import like `get_path`, with the trace of the resolution
+    /// in the error message if failure traces are enabled
+    pub fn resolve(&self, current_file: &Path, import_string: &str) -> Result<PathBuf, String> {
+        if !self.trace_failures {
+            return self
+                .get_path(current_file, import_string)
+                .map_err(|e| e.to_string());
+        }
+        let trace = self.explain(current_file, import_string);
+        match trace.outcome {
+            Ok(path) => Ok(path),
+            Err(ref error) => Err(format!("{}\n{}", error, trace)),
+        }
+    }
+
+    /// Resolve an import like `get_path` and record each step that was
+    /// attempted, to see why it resolves to a file or fails.
+    pub fn explain(&self, current_file: &Path, import_string: &str) -> ResolutionTrace {
+        *self.trace.borrow_mut() = Some(Vec::new());
+        let outcome = self.get_path(current_file, import_string);
+        let steps = self.trace.borrow_mut().take().unwrap_or_default();
+        ResolutionTrace {
+            importer: current_file.to_path_buf(),
+            specifier: import_string.to_string(),
+            steps,
+            outcome: outcome.map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Record a resolution step if a trace is being collected.
+    fn trace(&self, step: impl FnOnce() -> String) {
+        if let Some(steps) = self.trace.borrow_mut().as_mut() {
+            steps.push(step());
+        }
+    }
+
+    /// Resolve several imports of a file, in order, like `resolve`.
+    pub fn get_paths(
+        &self,
+        current_file: &Path,
+        import_strings: &[&str],
+    ) -> Vec<Result<PathBuf, String>> {
+        import_strings
+            .iter()
+            .map(|import_string| self.resolve(current_file, import_string))
+            .collect()
+    }
+
+    /// Log how many resolutions were answered from the cache.
+    pub fn log_cache_statistics(&self) {
+        log::debug!(
+            "Resolution cache: {} hits, {} misses, {} entries",
+            self.cache_hits.get(),
+            self.cache_misses.get(),
+            self.cache.borrow().len()
+        );
+    }
+
+    /// Resolve an import string to a PathBuf relative to the current working directory.
+    /// Successful resolutions are cached by importing directory and specifier;
+    /// failures are retried.
     ///
     /// # Arguments
     /// * `current_file` - The file that contains the import statement
@@ -36,35 +353,172 @@ impl PathFinder {
     /// The resolved PathBuf
     pub fn get_path(
         &self,
-        current_file: &PathBuf,
+        current_file: &Path,
+        import_string: &str,
+    ) -> Result<PathBuf, PathFinderError> {
+        // Traces need every step, so they bypass the cache. Internal URLs
+        // resolve the same from every directory.
+        let tracing = self.trace.borrow().is_some();
+        let dir = match self.jar_resolver.is_internal_url(import_string.trim()) {
+            true => Path::new(""),
+            false => current_file.parent().unwrap_or(Path::new("")),
+        };
+        let key = (dir.to_path_buf(), import_string.to_string());
+        if !tracing {
+            if let Some(path) = self.cache.borrow().get(&key) {
+                self.cache_hits.set(self.cache_hits.get() + 1);
+                return Ok(path.clone());
+            }
+            self.cache_misses.set(self.cache_misses.get() + 1);
+        }
+
+        let path = self.resolve_uncached(current_file, import_string)?;
+        if !tracing {
+            self.cache.borrow_mut().insert(key, path.clone());
+        }
+        Ok(path)
+    }
+
+    fn resolve_uncached(
+        &self,
+        current_file: &Path,
         import_string: &str,
     ) -> Result<PathBuf, PathFinderError> {
+        // Query strings and fragments (`icon.svg#check`) aren't part of the file
         let import_string = import_string.trim();
+        let import_string = import_string
+            .split(['?', '#'])
+            .next()
+            .unwrap_or(import_string);
 
         if import_string.is_empty() {
             return Err(PathFinderError::EmptyImportString);
         }
 
-        let resolved_path = if self.jar_resolver.is_internal_url(import_string) {
-            self.jar_resolver
-                .resolve_path(import_string)
-                .map_err(|e| match e {
-                    crate::utils::jar_resolver::JarResolverError::InvalidChromeUrl(url) => {
-                        PathFinderError::UnsupportedImportFormat(url)
-                    }
-                    crate::utils::jar_resolver::JarResolverError::NoMappingFound(url) => {
-                        PathFinderError::ChromeMappingNotFound(url)
-                    }
-                    _ => PathFinderError::UnsupportedImportFormat(import_string.to_string()),
-                })?
+        let aliased = self.resolve_aliases(import_string)?;
+        let import_string = match &aliased {
+            Some(Aliased::Specifier(specifier)) => specifier.as_str(),
+            _ => import_string,
+        };
+
+        let custom = match aliased {
+            Some(Aliased::Path(_)) => None,
+            _ => self
+                .resolvers
+                .iter()
+                .find_map(|resolver| resolver.resolve(current_file, import_string)),
+        };
+
+        let resolved_path = if let Some(Aliased::Path(path)) = &aliased {
+            path.clone()
+        } else if let Some(custom) = custom {
+            let path = custom.map_err(|reason| {
+                self.trace(|| format!("custom resolver failed: {}", reason));
+                PathFinderError::CustomResolverFailed {
+                    specifier: import_string.to_string(),
+                    reason,
+                }
+            })?;
+            self.trace(|| format!("custom resolver: {}", path.display()));
+            path
+        } else if self.jar_resolver.is_internal_url(import_string) {
+            self.trace(|| format!("'{}' is an internal URL", import_string));
+            match self.jar_resolver.resolve_path(import_string) {
+                Ok(path) => {
+                    self.trace(|| {
+                        let origin = self.jar_resolver.mapping_origin(import_string);
+                        format!(
+                            "jar mapping hit: {}{}",
+                            path.display(),
+                            origin.map_or(String::new(), |origin| format!(" (from {})", origin))
+                        )
+                    });
+                    path
+                }
+                Err(crate::utils::jar_resolver::JarResolverError::InvalidChromeUrl(url)) => {
+                    self.trace(|| "not a valid chrome URL".to_string());
+                    return Err(PathFinderError::UnsupportedImportFormat(url));
+                }
+                Err(crate::utils::jar_resolver::JarResolverError::NoMappingFound(url)) => {
+                    self.trace(|| "no jar mapping".to_string());
+                    self.fallback_path(import_string)?
+                        .ok_or(PathFinderError::ChromeMappingNotFound(url))?
+                }
+                Err(_) => {
+                    return Err(PathFinderError::UnsupportedImportFormat(
+                        import_string.to_string(),
+                    ));
+                }
+            }
         } else if self.is_relative_path(import_string) {
-            self.resolve_relative_path(current_file, import_string)?
+            let relative = self.resolve_relative_path(current_file, import_string)?;
+            if relative.exists() || self.module_roots.is_empty() || !is_bare(import_string) {
+                relative
+            } else {
+                self.trace(|| format!("{} doesn't exist", relative.display()));
+                self.resolve_bare_specifier(import_string)?
+            }
+        } else if !self.module_roots.is_empty() {
+            self.resolve_bare_specifier(import_string)?
         } else {
+            self.trace(|| "neither an internal URL nor a relative path".to_string());
             return Err(PathFinderError::UnsupportedImportFormat(
                 import_string.to_string(),
             ));
         };
 
+        // An exact match wins over extension fallbacks, which win over case
+        // insensitive matches. Several matches of the same kind are ambiguous.
+        let resolved_path = if resolved_path.is_file() {
+            resolved_path
+        } else {
+            match self.extension_fallbacks(&resolved_path).as_slice() {
+                [] => {
+                    self.trace(|| format!("no file at {}", resolved_path.display()));
+                    resolved_path
+                }
+                [path] => {
+                    self.trace(|| format!("extension fallback: {}", path.display()));
+                    path.clone()
+                }
+                candidates => {
+                    return Err(PathFinderError::Ambiguous {
+                        specifier: import_string.to_string(),
+                        candidates: candidates.to_vec(),
+                    });
+                }
+            }
+        };
+        let resolved_path = match resolved_path.exists() {
+            true => resolved_path,
+            false => match case_insensitive_matches(&resolved_path).as_slice() {
+                [] => resolved_path,
+                [corrected] if self.strict_case => {
+                    return Err(PathFinderError::CaseMismatch {
+                        specifier: import_string.to_string(),
+                        from: current_file.to_path_buf(),
+                        corrected: corrected.clone(),
+                    });
+                }
+                [corrected] => {
+                    self.trace(|| format!("case-insensitive match: {}", corrected.display()));
+                    eprintln!(
+                        "Warning: '{}' imported by {} only matches {} when ignoring case",
+                        import_string,
+                        current_file.display(),
+                        corrected.display()
+                    );
+                    corrected.clone()
+                }
+                candidates => {
+                    return Err(PathFinderError::Ambiguous {
+                        specifier: import_string.to_string(),
+                        candidates: candidates.to_vec(),
+                    });
+                }
+            },
+        };
+
         // Convert to relative path from current working directory using file_utils
         let rel_source_path = super::file_utils::make_relative_to_cwd(&resolved_path);
 
@@ -76,6 +530,199 @@ impl PathFinder {
         Ok(rel_source_path)
     }
 
+    /// Follow the aliases of a specifier, if it has any.
+    fn resolve_aliases(&self, specifier: &str) -> Result<Option<Aliased>, PathFinderError> {
+        let mut chain = vec![specifier.to_string()];
+        while let Some((key, target)) = self.alias_target(chain.last().unwrap()) {
+            self.aliases_hit.borrow_mut().insert(key.to_string());
+            self.trace(|| format!("alias {}: {} -> {}", key, chain.last().unwrap(), target));
+            let looped = chain.contains(&target);
+            chain.push(target);
+            if looped || chain.len() > MAX_ALIAS_DEPTH {
+                return Err(PathFinderError::AliasCycle(chain.join(" -> ")));
+            }
+            let target = chain.last().unwrap();
+            if !target.contains("://") {
+                return Ok(Some(Aliased::Path(PathBuf::from(target))));
+            }
+        }
+        Ok((chain.len() > 1).then(|| Aliased::Specifier(chain.pop().unwrap())))
+    }
+
+    /// Whether a specifier is replaced by an alias before it is resolved
+    pub fn has_alias(&self, specifier: &str) -> bool {
+        self.alias_target(specifier.trim()).is_some()
+    }
+
+    /// Whether a specifier names a file: a path, or a URL that the jar
+    /// mappings, an alias or a custom resolver handle. URLs with other
+    /// schemes, like `moz-icon:`, don't.
+    pub fn names_file(&self, current_file: &Path, specifier: &str) -> bool {
+        let specifier = specifier.trim();
+        !has_scheme(specifier)
+            || self.is_internal_url(specifier)
+            || self.has_alias(specifier)
+            || self
+                .resolvers
+                .iter()
+                .any(|resolver| resolver.resolve(current_file, specifier).is_some())
+    }
+
+    /// The alias matching a specifier, with what it is replaced with
+    fn alias_target(&self, specifier: &str) -> Option<(&str, String)> {
+        self.aliases.iter().find_map(|(key, target)| {
+            if key.ends_with('/') {
+                let rest = specifier.strip_prefix(key.as_str())?;
+                Some((key.as_str(), format!("{}{}", target, rest)))
+            } else {
+                (specifier == key).then(|| (key.as_str(), target.clone()))
+            }
+        })
+    }
+
+    /// The files that exist with one of the fallback extensions, in the order
+    /// of the fallbacks
+    fn extension_fallbacks(&self, path: &Path) -> Vec<PathBuf> {
+        let candidates: Vec<PathBuf> = if path.is_dir() {
+            self.extension_fallbacks
+                .iter()
+                .map(|ext| path.join(format!("index{}", ext)))
+                .collect()
+        } else {
+            let (Some(file_name), Some(stem)) = (path.file_name(), path.file_stem()) else {
+                return Vec::new();
+            };
+            let (file_name, stem) = (file_name.to_string_lossy(), stem.to_string_lossy());
+            let mut candidates: Vec<PathBuf> = Vec::new();
+            if path.extension().is_some() {
+                candidates.extend(
+                    self.extension_fallbacks
+                        .iter()
+                        .map(|ext| path.with_file_name(format!("{}{}", stem, ext))),
+                );
+            }
+            candidates.extend(
+                self.extension_fallbacks
+                    .iter()
+                    .map(|ext| path.with_file_name(format!("{}{}", file_name, ext))),
+            );
+            candidates
+        };
+        let mut found: Vec<PathBuf> = Vec::new();
+        for candidate in candidates {
+            if candidate != path && candidate.is_file() && !found.contains(&candidate) {
+                log::debug!(
+                    "{} matches {} by extension fallback",
+                    candidate.display(),
+                    path.display()
+                );
+                found.push(candidate);
+            }
+        }
+        found
+    }
+
+    /// Resolve a bare specifier against the module root with the longest
+    /// matching prefix that contains the file
+    fn resolve_bare_specifier(&self, specifier: &str) -> Result<PathBuf, PathFinderError> {
+        self.module_roots
+            .iter()
+            .filter_map(|(prefix, dir)| {
+                let rest = specifier.strip_prefix(prefix.as_str())?.strip_prefix('/')?;
+                Some(dir.join(rest))
+            })
+            .find(|candidate| {
+                let exists = candidate.exists();
+                self.trace(|| {
+                    format!(
+                        "module root candidate {}: {}",
+                        candidate.display(),
+                        if exists { "found" } else { "missing" }
+                    )
+                });
+                exists
+            })
+            .ok_or_else(|| PathFinderError::UnresolvedBareSpecifier {
+                specifier: specifier.to_string(),
+                roots: self
+                    .module_roots
+                    .iter()
+                    .map(|(prefix, dir)| format!("{} -> {}", prefix, dir.display()))
+                    .collect(),
+            })
+    }
+
+    /// Find the file of an unmapped chrome URL in the conventional source
+    /// locations, if the fallback search is enabled. Several candidates are an
+    /// error, none is `Ok(None)`.
+    fn fallback_path(&self, url: &str) -> Result<Option<PathBuf>, PathFinderError> {
+        if !self.fallback_search {
+            return Ok(None);
+        }
+        let root = &self.firefox_root;
+        let url = url.split(['?', '#']).next().unwrap_or(url);
+        let Some((prefix, locations)) = FALLBACK_LOCATIONS
+            .iter()
+            .find(|(prefix, _)| url.starts_with(prefix))
+        else {
+            return Ok(None);
+        };
+        let tail = &url[prefix.len()..];
+        let candidates: Vec<PathBuf> = locations
+            .iter()
+            .map(|location| root.join(location).join(tail))
+            .filter(|candidate| candidate.is_file())
+            .collect();
+        self.trace(|| format!("fallback search found {} candidate(s)", candidates.len()));
+        match candidates.as_slice() {
+            [] => Ok(None),
+            [path] => {
+                if self.fallback_warned.borrow_mut().insert(url.to_string()) {
+                    let jar = nearest_jar_manifest(root, path).map_or_else(
+                        || "the jar.mn file that packages it".to_string(),
+                        |jar| jar.display().to_string(),
+                    );
+                    eprintln!(
+                        "Warning: {} is not mapped by any configured jar.mn file, using {} found by the fallback search. Add {} to jar_paths.",
+                        url,
+                        path.display(),
+                        jar
+                    );
+                }
+                Ok(Some(path.clone()))
+            }
+            _ => Err(PathFinderError::AmbiguousFallback {
+                url: url.to_string(),
+                candidates,
+            }),
+        }
+    }
+
+    /// The Firefox source root that root-relative specifiers resolve against
+    pub fn firefox_root(&self) -> &Path {
+        &self.firefox_root
+    }
+
+    /// Check if an import string is a chrome:// or resource:// URL
+    pub fn is_internal_url(&self, import_string: &str) -> bool {
+        self.jar_resolver.is_internal_url(import_string.trim())
+    }
+
+    /// Get the other chrome/resource URLs that map to the same file as `url`
+    pub fn url_aliases(&self, url: &str) -> Vec<String> {
+        self.jar_resolver.aliases(url.trim())
+    }
+
+    /// Get the chrome/resource URLs that map to a file, sorted
+    pub fn urls_for_path(&self, path: &Path) -> &[String] {
+        self.jar_resolver.chrome_urls_for_path(path)
+    }
+
+    /// Check if a source file goes through the build's preprocessor
+    pub fn is_preprocessed(&self, path: &Path) -> bool {
+        self.jar_resolver.is_preprocessed(path)
+    }
+
     /// Check if an import string represents a relative path
     fn is_relative_path(&self, import_string: &str) -> bool {
         import_string.starts_with("./") || 
@@ -94,44 +741,83 @@ impl PathFinder {
     /// Resolve a relative path based on the current file location
     fn resolve_relative_path(
         &self,
-        current_file: &PathBuf,
+        current_file: &Path,
         import_string: &str,
     ) -> Result<PathBuf, PathFinderError> {
         let current_dir =
             current_file
                 .parent()
                 .ok_or_else(|| PathFinderError::RelativePathResolutionFailed {
-                    from: current_file.clone(),
+                    from: current_file.to_path_buf(),
                     import: import_string.to_string(),
                 })?;
 
         // Handle different relative path formats
-        let resolved = if import_string.starts_with('/') {
-            // Absolute path from root
-            PathBuf::from(import_string)
+        let resolved = if let Some(root_relative) = import_string.strip_prefix('/') {
+            // Relative to the Firefox root, like source paths in jar.mn files,
+            // unless only the absolute path exists
+            let in_root = self.firefox_root.join(root_relative);
+            let absolute = PathBuf::from(import_string);
+            if !in_root.exists() && absolute.exists() {
+                absolute
+            } else {
+                in_root
+            }
         } else {
             // Relative path from current directory
             current_dir.join(import_string)
         };
 
+        self.trace(|| format!("relative path: {}", resolved.display()));
+
+        if !self.follow_symlinks {
+            // Made absolute first so leading `..` of cwd-relative paths survive
+            return std::path::absolute(&resolved)
+                .and_then(|absolute| self.manually_resolve_path(&absolute))
+                .map_err(|_| PathFinderError::RelativePathResolutionFailed {
+                    from: current_file.to_path_buf(),
+                    import: import_string.to_string(),
+                });
+        }
+
         // Canonicalize to resolve .. and . components
-        let canonical = resolved
-            .canonicalize()
-            .or_else(|_| {
-                // If canonicalize fails, try manual resolution
-                self.manually_resolve_path(&resolved)
-            })
-            .map_err(|_| PathFinderError::RelativePathResolutionFailed {
-                from: current_file.clone(),
-                import: import_string.to_string(),
-            })?;
+        let canonical = match resolved.canonicalize() {
+            Ok(canonical) => {
+                // Only warn about links inside the tree pointing out of it
+                let lexical = std::path::absolute(&resolved)
+                    .and_then(|absolute| self.manually_resolve_path(&absolute))
+                    .unwrap_or_default();
+                let inside_root = std::path::absolute(&self.firefox_root)
+                    .is_ok_and(|root| lexical.starts_with(root))
+                    || lexical.starts_with(&self.canonical_root);
+                if inside_root && !canonical.starts_with(&self.canonical_root) {
+                    eprintln!(
+                        "Warning: '{}' imported by {} resolves through a symlink to {}, outside the Firefox root",
+                        import_string,
+                        current_file.display(),
+                        canonical.display()
+                    );
+                }
+                Ok(canonical)
+            }
+            // If canonicalize fails, try manual resolution
+            Err(_) => {
+                self.trace(|| "canonicalize failed, resolving .. manually".to_string());
+                std::path::absolute(&resolved)
+                    .and_then(|absolute| self.manually_resolve_path(&absolute))
+            }
+        }
+        .map_err(|_| PathFinderError::RelativePathResolutionFailed {
+            from: current_file.to_path_buf(),
+            import: import_string.to_string(),
+        })?;
 
         Ok(canonical)
     }
 
     /// Manually resolve path components when canonicalize fails
     /// This handles cases where the file might not exist yet but we still want to resolve the path
-    fn manually_resolve_path(&self, path: &PathBuf) -> Result<PathBuf, std::io::Error> {
+    fn manually_resolve_path(&self, path: &Path) -> Result<PathBuf, std::io::Error> {
         let mut components = Vec::new();
 
         for component in path.components() {
@@ -160,3 +846,640 @@ impl PathFinder {
         Ok(result)
     }
 }
+
+/// The existing paths that match `path` when ignoring the case of its
+/// components, sorted. Directories are scanned only for components that don't
+/// exist as written.
+fn case_insensitive_matches(path: &Path) -> Vec<PathBuf> {
+    let mut matches = vec![PathBuf::new()];
+    for component in path.components() {
+        let Component::Normal(name) = component else {
+            matches.iter_mut().for_each(|m| m.push(component));
+            continue;
+        };
+        let Some(name) = name.to_str() else {
+            return Vec::new();
+        };
+        matches = matches
+            .into_iter()
+            .flat_map(|corrected| {
+                let exact = corrected.join(name);
+                if exact.symlink_metadata().is_ok() {
+                    return vec![exact];
+                }
+                let dir = if corrected.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    corrected.as_path()
+                };
+                let Ok(entries) = fs::read_dir(dir) else {
+                    return Vec::new();
+                };
+                entries
+                    .filter_map(|entry| {
+                        let file_name = entry.ok()?.file_name();
+                        file_name
+                            .to_str()?
+                            .eq_ignore_ascii_case(name)
+                            .then(|| corrected.join(&file_name))
+                    })
+                    .collect()
+            })
+            .collect();
+    }
+    matches.sort();
+    matches
+}
+
+/// Whether an import string has no explicit relative or absolute prefix
+fn is_bare(import_string: &str) -> bool {
+    !(import_string.starts_with("./")
+        || import_string.starts_with("../")
+        || import_string.starts_with('/'))
+}
+
+/// Whether an import string starts with a URL scheme, like `moz-icon:`
+fn has_scheme(import_string: &str) -> bool {
+    import_string.split_once(':').is_some_and(|(scheme, _)| {
+        // A single letter is a Windows drive
+        scheme.len() > 1
+            && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    })
+}
+
+/// The jar.mn file in the closest directory above `path`, relative to `root`
+fn nearest_jar_manifest(root: &Path, path: &Path) -> Option<PathBuf> {
+    path.ancestors()
+        .skip(1)
+        .take_while(|dir| dir.starts_with(root))
+        .map(|dir| dir.join("jar.mn"))
+        .find(|jar| jar.is_file())
+        .map(|jar| jar.strip_prefix(root).unwrap_or(&jar).to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn write_files(root: &Path, files: &[&str]) {
+        for file in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+    }
+
+    #[test]
+    fn test_fallback_search_for_unmapped_chrome_urls() {
+        let root = tempfile::tempdir().unwrap();
+        write_files(
+            root.path(),
+            &[
+                "toolkit/themes/shared/icons/close.svg",
+                "toolkit/themes/shared/jar.inc.mn",
+                "toolkit/content/jar.mn",
+                "toolkit/content/widgets/moz-a/moz-a.mjs",
+                "toolkit/themes/shared/icons/arrow.svg",
+                "toolkit/themes/windows/global/icons/arrow.svg",
+            ],
+        );
+        let current_file = root.path().join("toolkit/content/widgets/moz-a/moz-a.mjs");
+
+        let path_finder = PathFinder::new(JarResolver::default(), root.path());
+        assert!(matches!(
+            path_finder.get_path(&current_file, "chrome://global/skin/icons/close.svg"),
+            Err(PathFinderError::ChromeMappingNotFound(_))
+        ));
+
+        let path_finder =
+            PathFinder::new(JarResolver::default(), root.path()).with_fallback_search();
+        let path = path_finder
+            .get_path(&current_file, "chrome://global/skin/icons/close.svg#flip")
+            .unwrap();
+        assert!(
+            path.ends_with("toolkit/themes/shared/icons/close.svg"),
+            "{}",
+            path.display()
+        );
+        let path = path_finder
+            .get_path(&current_file, "chrome://global/content/moz-a/moz-a.mjs")
+            .unwrap();
+        assert!(
+            path.ends_with("toolkit/content/widgets/moz-a/moz-a.mjs"),
+            "{}",
+            path.display()
+        );
+        assert_eq!(
+            nearest_jar_manifest(
+                root.path(),
+                &root.path().join("toolkit/content/widgets/moz-a/moz-a.mjs")
+            ),
+            Some(PathBuf::from("toolkit/content/jar.mn"))
+        );
+
+        match path_finder.get_path(&current_file, "chrome://global/skin/icons/arrow.svg") {
+            Err(PathFinderError::AmbiguousFallback { candidates, .. }) => {
+                assert_eq!(candidates.len(), 2)
+            }
+            other => panic!("expected an ambiguous fallback, got {:?}", other),
+        }
+        assert!(matches!(
+            path_finder.get_path(&current_file, "chrome://global/skin/icons/missing.svg"),
+            Err(PathFinderError::ChromeMappingNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_bare_specifiers_resolve_against_module_roots() {
+        let root = tempfile::tempdir().unwrap();
+        write_files(
+            root.path(),
+            &[
+                "toolkit/content/widgets/moz-a/moz-a.mjs",
+                "toolkit/content/widgets/moz-support-link/moz-support-link.mjs",
+                "toolkit/content/widgets/lit.all.mjs",
+                "toolkit/content/widgets/moz-a/toolkit-widgets/local.mjs",
+            ],
+        );
+        let current_file = root.path().join("toolkit/content/widgets/moz-a/moz-a.mjs");
+        let module_roots = HashMap::from([
+            (
+                "toolkit-widgets".to_string(),
+                "toolkit/content/widgets".to_string(),
+            ),
+            ("lit/".to_string(), "toolkit/content/widgets".to_string()),
+        ]);
+        let path_finder =
+            PathFinder::new(JarResolver::default(), root.path()).with_module_roots(&module_roots);
+
+        let path = path_finder
+            .get_path(
+                &current_file,
+                "toolkit-widgets/moz-support-link/moz-support-link.mjs",
+            )
+            .unwrap();
+        assert!(
+            path.ends_with("toolkit/content/widgets/moz-support-link/moz-support-link.mjs"),
+            "{}",
+            path.display()
+        );
+        let path = path_finder
+            .get_path(&current_file, "lit/lit.all.mjs")
+            .unwrap();
+        assert!(path.ends_with("toolkit/content/widgets/lit.all.mjs"));
+
+        // Files next to the importing file take precedence
+        let path = path_finder
+            .get_path(&current_file, "toolkit-widgets/local.mjs")
+            .unwrap();
+        assert!(path.ends_with("moz-a/toolkit-widgets/local.mjs"));
+
+        let error = path_finder
+            .get_path(&current_file, "toolkit-widgets/missing.mjs")
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            PathFinderError::UnresolvedBareSpecifier { .. }
+        ));
+        let message = error.to_string();
+        assert!(message.contains("toolkit-widgets -> "), "{}", message);
+        assert!(message.contains("lit -> "), "{}", message);
+
+        // Without module roots bare specifiers stay unsupported
+        assert!(matches!(
+            PathFinder::new(JarResolver::default(), root.path())
+                .get_path(&current_file, "lit.all.mjs"),
+            Err(PathFinderError::UnsupportedImportFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_extension_fallbacks() {
+        let root = tempfile::tempdir().unwrap();
+        write_files(
+            root.path(),
+            &[
+                "widgets/moz-a/moz-a.mjs",
+                "widgets/moz-a/helper.mjs",
+                "widgets/moz-a/legacy.js",
+                "widgets/moz-a/styles.css",
+                "widgets/shared/index.mjs",
+            ],
+        );
+        let current_file = root.path().join("widgets/moz-a/moz-a.mjs");
+        let path_finder = PathFinder::new(JarResolver::default(), root.path())
+            .with_extension_fallbacks(&[".mjs".to_string(), ".js".to_string(), ".css".to_string()]);
+
+        for (specifier, expected) in [
+            ("./helper.js", "widgets/moz-a/helper.mjs"),
+            ("./legacy.mjs", "widgets/moz-a/legacy.js"),
+            ("./helper", "widgets/moz-a/helper.mjs"),
+            ("./styles", "widgets/moz-a/styles.css"),
+            ("../shared", "widgets/shared/index.mjs"),
+            ("./moz-a.mjs", "widgets/moz-a/moz-a.mjs"),
+        ] {
+            let path = path_finder.get_path(&current_file, specifier).unwrap();
+            assert!(
+                path.ends_with(expected),
+                "{}: {}",
+                specifier,
+                path.display()
+            );
+        }
+        assert!(matches!(
+            path_finder.get_path(&current_file, "./missing.js"),
+            Err(PathFinderError::FileNotFound(_))
+        ));
+        assert!(matches!(
+            PathFinder::new(JarResolver::default(), root.path())
+                .get_path(&current_file, "./helper.js"),
+            Err(PathFinderError::FileNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_query_and_fragment_are_ignored() {
+        let root = tempfile::tempdir().unwrap();
+        write_files(
+            root.path(),
+            &[
+                "widgets/moz-a/moz-a.mjs",
+                "widgets/moz-a/icons/check.svg",
+                "widgets/shared.css",
+            ],
+        );
+        let current_file = root.path().join("widgets/moz-a/moz-a.mjs");
+        let path_finder = PathFinder::new(JarResolver::default(), root.path());
+
+        let path = path_finder
+            .get_path(&current_file, "./icons/check.svg#check")
+            .unwrap();
+        assert!(path.ends_with("widgets/moz-a/icons/check.svg"));
+        let path = path_finder
+            .get_path(&current_file, "../shared.css?cache=bust")
+            .unwrap();
+        assert!(path.ends_with("widgets/shared.css"));
+    }
+
+    #[test]
+    fn test_leading_slash_is_relative_to_the_firefox_root() {
+        let root = tempfile::tempdir().unwrap();
+        write_files(
+            root.path(),
+            &[
+                "toolkit/content/widgets/moz-a/moz-a.mjs",
+                "toolkit/content/widgets/vendor/lit.all.mjs",
+            ],
+        );
+        let current_file = root.path().join("toolkit/content/widgets/moz-a/moz-a.mjs");
+        let path_finder = PathFinder::new(JarResolver::default(), root.path());
+
+        let path = path_finder
+            .get_path(&current_file, "/toolkit/content/widgets/vendor/lit.all.mjs")
+            .unwrap();
+        assert!(
+            path.ends_with("toolkit/content/widgets/vendor/lit.all.mjs"),
+            "{}",
+            path.display()
+        );
+        assert!(!Path::new("/toolkit/content/widgets/vendor/lit.all.mjs").exists());
+
+        // Absolute paths still resolve when they exist outside the root
+        let outside = tempfile::tempdir().unwrap();
+        write_files(outside.path(), &["shared.mjs"]);
+        let absolute = outside.path().join("shared.mjs");
+        let path = path_finder
+            .get_path(&current_file, absolute.to_str().unwrap())
+            .unwrap();
+        assert!(path.ends_with("shared.mjs"), "{}", path.display());
+
+        assert!(matches!(
+            path_finder.get_path(&current_file, "/toolkit/content/missing.mjs"),
+            Err(PathFinderError::FileNotFound(path)) if path.starts_with(root.path())
+        ));
+    }
+
+    #[test]
+    fn test_follow_symlinks() {
+        let root = tempfile::tempdir().unwrap();
+        write_files(
+            root.path(),
+            &["widgets/moz-a/moz-a.mjs", "shared/styles/common.css"],
+        );
+        std::os::unix::fs::symlink(
+            root.path().join("shared/styles"),
+            root.path().join("widgets/moz-a/styles"),
+        )
+        .unwrap();
+        let current_file = root.path().join("widgets/moz-a/moz-a.mjs");
+
+        let path_finder = PathFinder::new(JarResolver::default(), root.path());
+        let path = path_finder
+            .get_path(&current_file, "./styles/common.css")
+            .unwrap();
+        assert!(
+            path.ends_with("shared/styles/common.css"),
+            "{}",
+            path.display()
+        );
+
+        // Lexical resolution keeps the symlinked path as the identity
+        let path_finder =
+            PathFinder::new(JarResolver::default(), root.path()).with_follow_symlinks(false);
+        let path = path_finder
+            .get_path(&current_file, "./styles/../styles/common.css")
+            .unwrap();
+        assert!(
+            path.ends_with("widgets/moz-a/styles/common.css"),
+            "{}",
+            path.display()
+        );
+    }
+
+    #[test]
+    fn test_names_file() {
+        let root = tempfile::tempdir().unwrap();
+        let current_file = root.path().join("styles/a.css");
+        let aliases = HashMap::from([("theme:".to_string(), "./theme.css".to_string())]);
+        let resolvers: Vec<Arc<dyn Resolver>> = vec![Arc::new(
+            crate::resolver::PrefixResolver::new("brand://", root.path().join("brand")),
+        )];
+        let path_finder = PathFinder::new(JarResolver::default(), root.path())
+            .with_aliases(&aliases)
+            .with_resolvers(&resolvers);
+
+        for specifier in [
+            "./icon.svg",
+            "icons/icon.svg",
+            "C:/icons/icon.svg",
+            "chrome://global/skin/icons/missing.svg",
+            "theme:",
+            "brand://logo.svg",
+        ] {
+            assert!(
+                path_finder.names_file(&current_file, specifier),
+                "{specifier}"
+            );
+        }
+        for specifier in [
+            "moz-icon://stock/a?size=16",
+            "page-icon:https://example.com/",
+        ] {
+            assert!(
+                !path_finder.names_file(&current_file, specifier),
+                "{specifier}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_ambiguous_resolution() {
+        let root = tempfile::tempdir().unwrap();
+        write_files(
+            root.path(),
+            &[
+                "widgets/moz-a/moz-a.mjs",
+                "widgets/moz-a/foo.js",
+                "widgets/moz-a/foo.mjs",
+                "widgets/vendor/foo.mjs",
+            ],
+        );
+        let current_file = root.path().join("widgets/moz-a/moz-a.mjs");
+        let path_finder = PathFinder::new(JarResolver::default(), root.path())
+            .with_extension_fallbacks(&[".mjs".to_string(), ".js".to_string()]);
+
+        // The exact extension wins over fallback extensions
+        let path = path_finder.get_path(&current_file, "./foo.js").unwrap();
+        assert!(path.ends_with("moz-a/foo.js"), "{}", path.display());
+
+        // Without an exact match, both fallbacks are listed
+        let error = path_finder
+            .get_path(&current_file, "./foo")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("foo.js") && error.contains("foo.mjs"),
+            "{}",
+            error
+        );
+
+        // An alias wins over the file in the tree
+        let aliases = HashMap::from([(
+            "./foo".to_string(),
+            root.path()
+                .join("widgets/vendor/foo.mjs")
+                .to_string_lossy()
+                .into_owned(),
+        )]);
+        let path = path_finder
+            .with_aliases(&aliases)
+            .get_path(&current_file, "./foo")
+            .unwrap();
+        assert!(path.ends_with("vendor/foo.mjs"), "{}", path.display());
+    }
+
+    #[test]
+    fn test_case_insensitive_fallback() {
+        let root = tempfile::tempdir().unwrap();
+        write_files(
+            root.path(),
+            &[
+                "widgets/moz-a/moz-a.mjs",
+                "widgets/moz-a/icons/check.svg",
+                "widgets/moz-a/both/a.svg",
+                "widgets/moz-a/both/A.svg",
+            ],
+        );
+        let current_file = root.path().join("widgets/moz-a/moz-a.mjs");
+
+        let path_finder = PathFinder::new(JarResolver::default(), root.path());
+        let path = path_finder
+            .get_path(&current_file, "./Icons/Check.svg")
+            .unwrap();
+        assert!(
+            path.ends_with("widgets/moz-a/icons/check.svg"),
+            "{}",
+            path.display()
+        );
+        // Ambiguous matches aren't corrected
+        match path_finder.get_path(&current_file, "./both/a.SVG") {
+            Err(PathFinderError::Ambiguous { candidates, .. }) => {
+                assert_eq!(candidates.len(), 2)
+            }
+            other => panic!("expected an ambiguous match, got {:?}", other),
+        }
+
+        let strict = PathFinder::new(JarResolver::default(), root.path()).with_strict_case(true);
+        match strict.get_path(&current_file, "./Icons/Check.svg") {
+            Err(PathFinderError::CaseMismatch {
+                specifier,
+                corrected,
+                ..
+            }) => {
+                assert_eq!(specifier, "./Icons/Check.svg");
+                assert!(corrected.ends_with("widgets/moz-a/icons/check.svg"));
+            }
+            other => panic!("expected a case mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_aliases() {
+        let root = tempfile::tempdir().unwrap();
+        let vendor = tempfile::tempdir().unwrap();
+        write_files(root.path(), &["widgets/moz-a/moz-a.mjs"]);
+        write_files(
+            vendor.path(),
+            &["lit.all.mjs", "shims/AppConstants.sys.mjs"],
+        );
+        let current_file = root.path().join("widgets/moz-a/moz-a.mjs");
+        let vendor_dir = vendor.path().to_str().unwrap();
+        let aliases = HashMap::from([
+            (
+                "chrome://global/content/vendor/lit.all.mjs".to_string(),
+                format!("{}/lit.all.mjs", vendor_dir),
+            ),
+            (
+                "resource://gre/modules/".to_string(),
+                "chrome://shims/".to_string(),
+            ),
+            (
+                "chrome://shims/".to_string(),
+                format!("{}/shims/", vendor_dir),
+            ),
+            (
+                "chrome://a/a.mjs".to_string(),
+                "chrome://b/b.mjs".to_string(),
+            ),
+            (
+                "chrome://b/b.mjs".to_string(),
+                "chrome://a/a.mjs".to_string(),
+            ),
+        ]);
+        let path_finder =
+            PathFinder::new(JarResolver::default(), root.path()).with_aliases(&aliases);
+
+        let path = path_finder
+            .get_path(&current_file, "chrome://global/content/vendor/lit.all.mjs")
+            .unwrap();
+        assert!(path.ends_with("lit.all.mjs"), "{}", path.display());
+        let path = path_finder
+            .get_path(&current_file, "resource://gre/modules/AppConstants.sys.mjs")
+            .unwrap();
+        assert!(
+            path.ends_with("shims/AppConstants.sys.mjs"),
+            "{}",
+            path.display()
+        );
+        assert!(matches!(
+            path_finder.get_path(&current_file, "chrome://a/a.mjs"),
+            Err(PathFinderError::AliasCycle(_))
+        ));
+        assert_eq!(
+            path_finder.aliases_hit(),
+            [
+                "chrome://a/a.mjs",
+                "chrome://b/b.mjs",
+                "chrome://global/content/vendor/lit.all.mjs",
+                "chrome://shims/",
+                "resource://gre/modules/",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_explain_records_resolution_steps() {
+        let root = tempfile::tempdir().unwrap();
+        write_files(
+            root.path(),
+            &["widgets/moz-a/moz-a.mjs", "widgets/moz-a/helper.mjs"],
+        );
+        fs::write(
+            root.path().join("widgets/jar.mn"),
+            "toolkit.jar:\n% content global %content/global/\n  content/global/helper.mjs (moz-a/helper.mjs)\n",
+        )
+        .unwrap();
+        let current_file = root.path().join("widgets/moz-a/moz-a.mjs");
+        let jar_resolver = JarResolver::new(
+            root.path(),
+            crate::utils::jar_resolver::JarResolverOptions {
+                jar_paths: &["widgets/jar.mn"],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let path_finder = PathFinder::new(jar_resolver, root.path())
+            .with_extension_fallbacks(&[".mjs".to_string()]);
+
+        let trace = path_finder.explain(&current_file, "./helper.js");
+        assert!(trace.outcome.as_ref().unwrap().ends_with("helper.mjs"));
+        assert!(
+            trace.steps[0].starts_with("relative path: "),
+            "{:?}",
+            trace.steps
+        );
+        assert!(
+            trace
+                .steps
+                .iter()
+                .any(|step| step.starts_with("extension fallback: "))
+        );
+
+        let trace = path_finder.explain(&current_file, "chrome://global/content/helper.mjs");
+        assert!(
+            trace.steps[1].starts_with("jar mapping hit: "),
+            "{:?}",
+            trace.steps
+        );
+        let trace = path_finder.explain(&current_file, "chrome://global/content/missing.mjs");
+        assert!(trace.outcome.is_err());
+        assert!(
+            trace.to_string().contains("  2. no jar mapping\n"),
+            "{}",
+            trace
+        );
+
+        // Traces are only collected while explaining
+        path_finder.get_path(&current_file, "./helper.mjs").unwrap();
+        assert!(path_finder.trace.borrow().is_none());
+    }
+
+    #[test]
+    fn test_resolutions_are_cached_per_directory() {
+        let root = tempfile::tempdir().unwrap();
+        write_files(
+            root.path(),
+            &[
+                "widgets/moz-a/moz-a.mjs",
+                "widgets/moz-a/moz-a-item.mjs",
+                "widgets/moz-a/helper.mjs",
+                "widgets/moz-b/moz-b.mjs",
+            ],
+        );
+        let path_finder = PathFinder::new(JarResolver::default(), root.path());
+        let moz_a = root.path().join("widgets/moz-a/moz-a.mjs");
+        let moz_a_item = root.path().join("widgets/moz-a/moz-a-item.mjs");
+        let moz_b = root.path().join("widgets/moz-b/moz-b.mjs");
+
+        let first = path_finder.get_paths(&moz_a, &["./helper.mjs", "./missing.mjs"]);
+        assert!(first[0].is_ok());
+        assert!(
+            first[1]
+                .as_ref()
+                .unwrap_err()
+                .starts_with("File does not exist")
+        );
+        let second = path_finder.get_paths(&moz_a_item, &["./helper.mjs", "./missing.mjs"]);
+        assert_eq!(second[0].as_ref().unwrap(), first[0].as_ref().unwrap());
+        assert!(second[1].is_err());
+        assert!(path_finder.get_path(&moz_b, "./helper.mjs").is_err());
+        assert_eq!(
+            (path_finder.cache_hits.get(), path_finder.cache_misses.get()),
+            (1, 4)
+        );
+    }
+}
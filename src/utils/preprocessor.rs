@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::jar_resolver::{Conditionals, IfdefUnknown, JarResolverError, default_ifdef_config};
+
+/// Directives of the build's preprocessor. Other lines starting with `#`,
+/// like private class fields, are source code.
+const DIRECTIVES: &[&str] = &[
+    "#if",
+    "#ifdef",
+    "#ifndef",
+    "#elif",
+    "#elifdef",
+    "#elifndef",
+    "#else",
+    "#endif",
+    "#define",
+    "#undef",
+    "#include",
+    "#includesubst",
+    "#filter",
+    "#unfilter",
+    "#literal",
+    "#expand",
+    "#error",
+];
+
+/// Evaluates the preprocessor directives of sources that are listed with `*`
+/// in a jar.mn file, like the build does before packaging them, with the same
+/// conditions as jar.mn files.
+///
+/// Directive lines and excluded lines become empty lines, so the other lines
+/// keep their line numbers unless a file is included.
+#[derive(Debug, Clone)]
+pub struct Preprocessor {
+    ifdef_config: HashMap<String, bool>,
+    ifdef_unknown: IfdefUnknown,
+}
+
+impl Preprocessor {
+    /// Create a preprocessor for the default conditions of a desktop build,
+    /// extended or overridden by `ifdef_config`.
+    pub fn new(ifdef_config: &HashMap<String, bool>, ifdef_unknown: IfdefUnknown) -> Self {
+        let mut config = default_ifdef_config();
+        config.extend(ifdef_config.iter().map(|(k, v)| (k.clone(), *v)));
+        Self {
+            ifdef_config: config,
+            ifdef_unknown,
+        }
+    }
+
+    /// Preprocess the content of the file at `source_path`, which `#include`
+    /// paths are relative to.
+    pub fn process(&self, content: &str, source_path: &Path) -> Result<String, JarResolverError> {
+        let mut state = State {
+            conditionals: Conditionals::new(&self.ifdef_config, self.ifdef_unknown),
+            substitution: false,
+            included_files: vec![source_path.to_path_buf()],
+        };
+        let mut output = String::with_capacity(content.len());
+        process_into(content, source_path, &mut state, &mut output)?;
+        Ok(output)
+    }
+}
+
+/// Preprocessor state that carries over into included files.
+struct State {
+    conditionals: Conditionals,
+    /// Whether `#filter substitution` is active
+    substitution: bool,
+    /// The files being processed, to break include cycles
+    included_files: Vec<PathBuf>,
+}
+
+/// The directive and its argument if a line is a preprocessor directive.
+fn directive(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    let (directive, argument) = line
+        .split_once(char::is_whitespace)
+        .map_or((line, ""), |(directive, argument)| {
+            (directive, argument.trim())
+        });
+    DIRECTIVES
+        .contains(&directive)
+        .then_some((directive, argument))
+}
+
+fn process_into(
+    content: &str,
+    source_path: &Path,
+    state: &mut State,
+    output: &mut String,
+) -> Result<(), JarResolverError> {
+    for line in content.lines() {
+        let Some((directive, argument)) = directive(line) else {
+            if state.conditionals.included {
+                if state.substitution {
+                    output.push_str(&state.conditionals.defines.substitute(line));
+                } else {
+                    output.push_str(line);
+                }
+            }
+            output.push('\n');
+            continue;
+        };
+        if state.conditionals.apply(directive, argument)? || !state.conditionals.included {
+            output.push('\n');
+            continue;
+        }
+        match directive {
+            "#include" | "#includesubst" => {
+                let dir = source_path.parent().unwrap_or(Path::new(""));
+                let include_path = dir.join(argument);
+                if state.included_files.contains(&include_path) {
+                    log::debug!("Skipping recursive #include of {}", include_path.display());
+                    output.push('\n');
+                    continue;
+                }
+                let included = fs::read_to_string(&include_path).map_err(|_| {
+                    JarResolverError::IncludeFileNotFound(include_path.display().to_string())
+                })?;
+                state.included_files.push(include_path.clone());
+                let substitution = state.substitution;
+                state.substitution |= directive == "#includesubst";
+                process_into(&included, &include_path, state, output)?;
+                state.substitution = substitution;
+                state.included_files.pop();
+            }
+            "#expand" => {
+                output.push_str(&state.conditionals.defines.expand(argument));
+                output.push('\n');
+            }
+            "#literal" => {
+                output.push_str(argument);
+                output.push('\n');
+            }
+            "#filter" | "#unfilter" => {
+                if argument.split_whitespace().any(|f| f == "substitution") {
+                    state.substitution = directive == "#filter";
+                }
+                output.push('\n');
+            }
+            "#error" => return Err(JarResolverError::ErrorDirective(argument.to_string())),
+            _ => output.push('\n'),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("shared.inc.mjs"),
+            "export const shared = \"@APP@\";\n",
+        )
+        .unwrap();
+        let source = r#"#filter substitution
+#define APP browser
+const app = "@APP@";
+#ifdef MOZILLA_OFFICIAL
+const official = true;
+#else
+const official = false;
+#endif
+#if CUSTOM && !defined(ANDROID)
+const custom = true;
+#endif
+class A {
+  #private = 1;
+}
+#include shared.inc.mjs
+"#;
+        let preprocessor = Preprocessor::new(
+            &HashMap::from([("CUSTOM".to_string(), true)]),
+            IfdefUnknown::False,
+        );
+        let output = preprocessor
+            .process(source, &dir.path().join("a.mjs"))
+            .unwrap();
+        assert_eq!(
+            output,
+            r#"
+
+const app = "browser";
+
+const official = true;
+
+
+
+
+const custom = true;
+
+class A {
+  #private = 1;
+}
+export const shared = "browser";
+"#
+        );
+    }
+
+    #[test]
+    fn test_process_errors() {
+        let preprocessor = Preprocessor::new(&HashMap::new(), IfdefUnknown::Error);
+        let path = Path::new("a.mjs");
+        assert!(
+            preprocessor
+                .process("#ifdef UNKNOWN\n#endif\n", path)
+                .is_err()
+        );
+        assert!(preprocessor.process("#endif\n", path).is_err());
+        assert!(preprocessor.process("#error unsupported\n", path).is_err());
+        assert!(
+            preprocessor
+                .process("#ifdef ANDROID\n#error unsupported\n#endif\n", path)
+                .is_ok()
+        );
+    }
+}